@@ -0,0 +1,28 @@
+// Links the initramfs into the kernel
+//
+// `INITRAMFS` names a cpio "newc" archive, optionally gzip or LZ4 compressed. When it is set, the
+// kernel is built with `cfg(initramfs)` and finds the archive at `INITRAMFS_PATH`.
+
+use std::{env, path::Path};
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(initramfs)");
+    println!("cargo::rerun-if-env-changed=INITRAMFS");
+
+    let Some(archive) =
+        env::var_os("INITRAMFS").filter(|path| !path.is_empty())
+    else {
+        return;
+    };
+
+    // relative paths are relative to the crate
+    let path =
+        Path::new(&env::var_os("CARGO_MANIFEST_DIR").unwrap()).join(archive);
+    if !path.is_file() {
+        panic!("INITRAMFS: {} is not a file", path.display());
+    }
+
+    println!("cargo::rerun-if-changed={}", path.display());
+    println!("cargo::rustc-cfg=initramfs");
+    println!("cargo::rustc-env=INITRAMFS_PATH={}", path.display());
+}
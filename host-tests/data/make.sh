@@ -0,0 +1,19 @@
+#!/bin/sh
+# Make the test vectors of `src/decompress.rs` with the reference tools and the empty FAT images of
+# `src/fat.rs` with `mkfat.py`
+set -e
+cd "$(dirname "$0")"
+
+for file in words.txt random.bin; do
+    gzip -9 -n -c $file > $file.gz
+    # 64 KiB dependent blocks with checksums and the content size
+    lz4 -q -f -9 -B4 -BD -BX --content-size $file $file.lz4
+    lz4 -q -f -l $file $file.legacy.lz4
+done
+
+# FAT32 needs 65525 clusters and more
+for volume in "12 1024 4" "16 16384 4" "32 40960 1"; do
+    set -- $volume
+    python3 mkfat.py $1 $2 $3 fat$1.img
+    gzip -9 -n -f fat$1.img
+done
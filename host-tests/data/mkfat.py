@@ -0,0 +1,126 @@
+#!/usr/bin/env python3
+# An empty FAT volume laid out as mkfs.fat of dosfstools 4.2 does by default
+#
+# usage: mkfat.py BITS SIZE_KIB SECTORS_PER_CLUSTER IMAGE
+#
+# 512-byte sectors, two FATs, media 0xF8 and a volume label entry in the root directory. FAT12
+# and FAT16 have one reserved sector and 512 root entries, FAT32 has 32 reserved sectors with the
+# FSInfo sector at 1, the backup boot sector at 6 and the root directory in cluster 2. The volume
+# ID and the times are fixed, so the images are the same every time.
+
+import struct
+import sys
+
+SECTOR = 512
+FATS = 2
+LABEL = b"HOSTTEST   "
+VOLUME_ID = 0x1234ABCD
+
+
+def main():
+    bits, size_kib, per_cluster = (int(arg) for arg in sys.argv[1:4])
+    path = sys.argv[4]
+
+    sectors = size_kib * 1024 // SECTOR
+    reserved = 32 if bits == 32 else 1
+    root_sectors = 0 if bits == 32 else 512 * 32 // SECTOR
+
+    # the smallest FAT that holds every cluster
+    fat_sectors = 1
+    while True:
+        data_sectors = sectors - reserved - FATS * fat_sectors - root_sectors
+        clusters = data_sectors // per_cluster
+        if (clusters + 2) * bits <= fat_sectors * SECTOR * 8:
+            break
+        fat_sectors += 1
+
+    image = bytearray(sectors * SECTOR)
+    image[0:SECTOR] = boot_sector(bits, sectors, reserved, per_cluster, fat_sectors)
+
+    fat = bytearray(fat_sectors * SECTOR)
+    end = (1 << min(bits, 28)) - 1
+    set_entry(fat, bits, 0, end & ~0xFF | 0xF8)
+    set_entry(fat, bits, 1, end)
+    if bits == 32:
+        # the root directory
+        set_entry(fat, bits, 2, end)
+    for i in range(FATS):
+        start = (reserved + i * fat_sectors) * SECTOR
+        image[start : start + len(fat)] = fat
+
+    label = LABEL + b"\x08" + bytes(20)
+    root = (reserved + FATS * fat_sectors) * SECTOR
+    image[root : root + 32] = label
+
+    if bits == 32:
+        info = fs_info(clusters - 1, 3)
+        image[SECTOR : 2 * SECTOR] = info
+        image[6 * SECTOR : 7 * SECTOR] = image[0:SECTOR]
+        image[7 * SECTOR : 8 * SECTOR] = info
+
+    with open(path, "wb") as file:
+        file.write(image)
+
+
+def boot_sector(bits, sectors, reserved, per_cluster, fat_sectors):
+    sector = bytearray(SECTOR)
+    sector[0:11] = b"\xEB\x3C\x90mkfat.py"
+    small = sectors < 0x10000 and bits != 32
+    struct.pack_into(
+        "<HBHBHHBHHHII",
+        sector,
+        11,
+        SECTOR,
+        per_cluster,
+        reserved,
+        FATS,
+        0 if bits == 32 else 512,
+        sectors if small else 0,
+        0xF8,
+        0 if bits == 32 else fat_sectors,
+        32,
+        64,
+        0,
+        0 if small else sectors,
+    )
+
+    if bits == 32:
+        sector[0:2] = b"\xEB\x58"
+        # FAT size, flags, version, root cluster, FSInfo and backup boot sector
+        struct.pack_into("<IHHIHH", sector, 36, fat_sectors, 0, 0, 2, 1, 6)
+        extended = 64
+    else:
+        extended = 36
+    struct.pack_into("<BBBI", sector, extended, 0x80, 0, 0x29, VOLUME_ID)
+    sector[extended + 7 : extended + 18] = LABEL
+    sector[extended + 18 : extended + 26] = b"FAT%-5d" % bits
+    sector[510:512] = b"\x55\xAA"
+
+    return sector
+
+
+def fs_info(free, next_free):
+    sector = bytearray(SECTOR)
+    struct.pack_into("<I", sector, 0, 0x41615252)
+    struct.pack_into("<III", sector, 484, 0x61417272, free, next_free)
+    struct.pack_into("<I", sector, 508, 0xAA550000)
+
+    return sector
+
+
+def set_entry(fat, bits, cluster, value):
+    if bits == 12:
+        offset = cluster + cluster // 2
+        (pair,) = struct.unpack_from("<H", fat, offset)
+        if cluster & 1:
+            pair = pair & 0x000F | value << 4
+        else:
+            pair = pair & 0xF000 | value
+        struct.pack_into("<H", fat, offset, pair)
+    elif bits == 16:
+        struct.pack_into("<H", fat, cluster * 2, value)
+    else:
+        struct.pack_into("<I", fat, cluster * 4, value)
+
+
+main()
@@ -0,0 +1,345 @@
+// The buffer cache in front of a disk that records every request
+
+use crate::block::{cache::BufferCache, interface::BlockDevice, BlockError};
+use std::sync::Mutex;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const BLOCK_SIZE: usize = 512;
+
+const DISK_BLOCKS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    // first block, number of blocks
+    Read(u64, usize),
+    Write(u64, usize),
+    Flush,
+}
+
+// Block `n` starts out filled with `n`
+struct Disk {
+    block_size: usize,
+    bytes: Mutex<Vec<u8>>,
+    requests: Mutex<Vec<Request>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Disk {
+    fn new() -> Self {
+        Self::with_block_size(BLOCK_SIZE)
+    }
+
+    fn with_block_size(block_size: usize) -> Self {
+        let bytes = (0..DISK_BLOCKS)
+            .flat_map(|block| [block as u8; BLOCK_SIZE])
+            .collect();
+
+        Self {
+            block_size,
+            bytes: Mutex::new(bytes),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    // The requests since the last call
+    fn requests(&self) -> Vec<Request> {
+        std::mem::take(&mut *self.requests.lock().unwrap())
+    }
+
+    fn block(&self, block: u64) -> Vec<u8> {
+        let start = block as usize * BLOCK_SIZE;
+        self.bytes.lock().unwrap()[start..start + BLOCK_SIZE].to_vec()
+    }
+}
+
+impl BlockDevice for Disk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        DISK_BLOCKS as u64
+    }
+
+    fn read_blocks(
+        &self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), BlockError> {
+        let start = block as usize * BLOCK_SIZE;
+        let bytes = self.bytes.lock().unwrap();
+        buffer.copy_from_slice(&bytes[start..start + buffer.len()]);
+
+        let request = Request::Read(block, buffer.len() / BLOCK_SIZE);
+        self.requests.lock().unwrap().push(request);
+        Ok(())
+    }
+
+    fn write_blocks(
+        &self,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), BlockError> {
+        let start = block as usize * BLOCK_SIZE;
+        let mut bytes = self.bytes.lock().unwrap();
+        bytes[start..start + buffer.len()].copy_from_slice(buffer);
+
+        let request = Request::Write(block, buffer.len() / BLOCK_SIZE);
+        self.requests.lock().unwrap().push(request);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.requests.lock().unwrap().push(Request::Flush);
+        Ok(())
+    }
+}
+
+fn read<const N: usize>(
+    cache: &BufferCache<'_, N, BLOCK_SIZE>,
+    block: u64,
+    blocks: usize,
+) -> Vec<u8> {
+    let mut buffer = vec![0; blocks * BLOCK_SIZE];
+    cache.read_blocks(block, &mut buffer).unwrap();
+
+    buffer
+}
+
+fn write<const N: usize>(
+    cache: &BufferCache<'_, N, BLOCK_SIZE>,
+    block: u64,
+    blocks: usize,
+    fill: u8,
+) {
+    cache
+        .write_blocks(block, &vec![fill; blocks * BLOCK_SIZE])
+        .unwrap();
+}
+
+// As the disk starts out
+fn original(block: u64, blocks: usize) -> Vec<u8> {
+    (block..block + blocks as u64)
+        .flat_map(|block| [block as u8; BLOCK_SIZE])
+        .collect()
+}
+
+#[test]
+fn missing_blocks_are_read_in_runs() {
+    let disk = Disk::new();
+    let cache = BufferCache::<8, BLOCK_SIZE>::new(&disk);
+
+    assert_eq!(read(&cache, 2, 4), original(2, 4));
+    assert_eq!(disk.requests(), [Request::Read(2, 4)]);
+
+    // cached now
+    assert_eq!(read(&cache, 3, 2), original(3, 2));
+    assert_eq!(disk.requests(), []);
+
+    // only the gaps around the cached blocks
+    assert_eq!(read(&cache, 0, 8), original(0, 8));
+    assert_eq!(disk.requests(), [Request::Read(0, 2), Request::Read(6, 2)]);
+}
+
+#[test]
+fn least_recently_used_block_is_evicted() {
+    let disk = Disk::new();
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&disk);
+
+    for block in 0..4 {
+        read(&cache, block, 1);
+    }
+    // 1 is the least recently used block from now on
+    read(&cache, 0, 1);
+    disk.requests();
+
+    read(&cache, 4, 1);
+    assert_eq!(disk.requests(), [Request::Read(4, 1)]);
+
+    for block in [0, 2, 3, 4] {
+        read(&cache, block, 1);
+    }
+    assert_eq!(disk.requests(), []);
+
+    read(&cache, 1, 1);
+    assert_eq!(disk.requests(), [Request::Read(1, 1)]);
+
+    // 0 was used before 2, 3 and 4
+    read(&cache, 0, 1);
+    assert_eq!(disk.requests(), [Request::Read(0, 1)]);
+}
+
+#[test]
+fn writes_stay_in_the_cache() {
+    let disk = Disk::new();
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&disk);
+
+    write(&cache, 5, 2, 0xAA);
+
+    assert_eq!(disk.requests(), []);
+    assert_eq!(disk.block(5), original(5, 1));
+    assert_eq!(cache.dirty_blocks(), 2);
+    assert_eq!(read(&cache, 5, 2), vec![0xAA; 2 * BLOCK_SIZE]);
+}
+
+#[test]
+fn dirty_block_is_written_back_on_eviction() {
+    let disk = Disk::new();
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&disk);
+
+    write(&cache, 0, 1, 0xAA);
+    for block in 1..4 {
+        read(&cache, block, 1);
+    }
+    disk.requests();
+
+    read(&cache, 10, 1);
+
+    assert_eq!(
+        disk.requests(),
+        [Request::Read(10, 1), Request::Write(0, 1)]
+    );
+    assert_eq!(disk.block(0), vec![0xAA; BLOCK_SIZE]);
+    assert_eq!(cache.dirty_blocks(), 0);
+}
+
+#[test]
+fn eviction_writes_the_following_dirty_blocks_along() {
+    let disk = Disk::new();
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&disk);
+
+    write(&cache, 7, 1, 0xA7);
+    write(&cache, 8, 1, 0xA8);
+    write(&cache, 9, 1, 0xA9);
+    read(&cache, 20, 1);
+    disk.requests();
+
+    // evicts 7, the least recently used block
+    read(&cache, 21, 1);
+
+    assert_eq!(
+        disk.requests(),
+        [Request::Read(21, 1), Request::Write(7, 3)]
+    );
+    assert_eq!(disk.block(9), vec![0xA9; BLOCK_SIZE]);
+    assert_eq!(cache.dirty_blocks(), 0);
+}
+
+#[test]
+fn sync_merges_dirty_runs_in_block_order() {
+    let disk = Disk::new();
+    let cache = BufferCache::<16, BLOCK_SIZE>::new(&disk);
+
+    for block in [12, 9, 5, 7, 6] {
+        write(&cache, block, 1, 0xB0 + block as u8);
+    }
+    // clean blocks split runs
+    read(&cache, 10, 1);
+    disk.requests();
+
+    cache.sync().unwrap();
+
+    assert_eq!(
+        disk.requests(),
+        [
+            Request::Write(5, 3),
+            Request::Write(9, 1),
+            Request::Write(12, 1),
+            Request::Flush
+        ]
+    );
+    for block in [5, 6, 7, 9, 12] {
+        assert_eq!(disk.block(block), vec![0xB0 + block as u8; BLOCK_SIZE]);
+    }
+    assert_eq!(cache.dirty_blocks(), 0);
+
+    cache.sync().unwrap();
+    assert_eq!(disk.requests(), [Request::Flush]);
+}
+
+#[test]
+fn merged_writes_are_limited_in_length() {
+    let disk = Disk::new();
+    let cache = BufferCache::<32, BLOCK_SIZE>::new(&disk);
+
+    for block in 0..20 {
+        write(&cache, block, 1, 0xCC);
+    }
+    cache.sync().unwrap();
+
+    assert_eq!(
+        disk.requests(),
+        [Request::Write(0, 16), Request::Write(16, 4), Request::Flush]
+    );
+}
+
+#[test]
+fn large_write_bypasses_the_cache() {
+    let disk = Disk::new();
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&disk);
+
+    write(&cache, 1, 1, 0x11);
+    read(&cache, 30, 1);
+    disk.requests();
+
+    write(&cache, 0, 4, 0xEE);
+    assert_eq!(disk.requests(), [Request::Write(0, 4)]);
+    assert_eq!(disk.block(3), vec![0xEE; BLOCK_SIZE]);
+
+    // the cached copy follows the write and is no longer dirty
+    assert_eq!(read(&cache, 1, 1), vec![0xEE; BLOCK_SIZE]);
+    assert_eq!(cache.dirty_blocks(), 0);
+    // and the blocks cached before were not pushed out
+    read(&cache, 30, 1);
+    assert_eq!(disk.requests(), []);
+
+    cache.sync().unwrap();
+    assert_eq!(disk.requests(), [Request::Flush]);
+}
+
+#[test]
+fn invalidate_writes_back_and_forgets() {
+    let disk = Disk::new();
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&disk);
+
+    write(&cache, 2, 1, 0x22);
+    cache.invalidate().unwrap();
+    assert_eq!(disk.requests(), [Request::Write(2, 1), Request::Flush]);
+
+    assert_eq!(read(&cache, 2, 1), vec![0x22; BLOCK_SIZE]);
+    assert_eq!(disk.requests(), [Request::Read(2, 1)]);
+}
+
+#[test]
+fn bad_requests_do_not_reach_the_disk() {
+    let disk = Disk::new();
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&disk);
+    let mut buffer = vec![0; BLOCK_SIZE];
+
+    assert_eq!(
+        cache.read_blocks(0, &mut buffer[..100]),
+        Err(BlockError::InvalidLength)
+    );
+    assert_eq!(
+        cache.read_blocks(DISK_BLOCKS as u64, &mut buffer),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        cache.write_blocks(u64::MAX, &buffer),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(disk.requests(), []);
+
+    let other = Disk::with_block_size(4096);
+    let cache = BufferCache::<4, BLOCK_SIZE>::new(&other);
+    assert_eq!(
+        cache.read_blocks(0, &mut buffer),
+        Err(BlockError::BlockSizeMismatch)
+    );
+}
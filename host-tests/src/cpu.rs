@@ -0,0 +1,5 @@
+// The CPU functions of the kernel
+
+pub fn nop() {
+    core::hint::spin_loop();
+}
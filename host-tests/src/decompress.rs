@@ -0,0 +1,377 @@
+// Decompressors, fed with the output of the reference tools
+//
+// The vectors in `data` are made by `data/make.sh` with gzip and lz4 from the plain files next to
+// them.
+
+use crate::compress::{deflate, gzip, lz4, DecompressError};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const WORDS: &[u8] = include_bytes!("../data/words.txt");
+const WORDS_GZ: &[u8] = include_bytes!("../data/words.txt.gz");
+const WORDS_LZ4: &[u8] = include_bytes!("../data/words.txt.lz4");
+const WORDS_LEGACY_LZ4: &[u8] = include_bytes!("../data/words.txt.legacy.lz4");
+
+// Does not compress, so stored blocks and uncompressed LZ4 blocks
+const RANDOM: &[u8] = include_bytes!("../data/random.bin");
+const RANDOM_GZ: &[u8] = include_bytes!("../data/random.bin.gz");
+const RANDOM_LZ4: &[u8] = include_bytes!("../data/random.bin.lz4");
+const RANDOM_LEGACY_LZ4: &[u8] =
+    include_bytes!("../data/random.bin.legacy.lz4");
+
+// `Hello, Hello, Hello!` by zlib, a fixed Huffman block with a back-reference
+const HELLO_DEFLATE: [u8; 12] = [
+    0xF3, 0x48, 0xCD, 0xC9, 0xC9, 0xD7, 0x51, 0xF0, 0x40, 0xA2, 0x14, 0x01,
+];
+
+// gzip of nothing
+const EMPTY_GZ: [u8; 20] = [
+    0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x03, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// Offset of the LZ4 frame descriptor flags, after the magic
+const LZ4_FLAGS: usize = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+enum SinkError {
+    Full,
+    Decompress(DecompressError),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl From<DecompressError> for SinkError {
+    fn from(error: DecompressError) -> Self {
+        SinkError::Decompress(error)
+    }
+}
+
+// The output and the number of input bytes taken
+fn inflate(input: &[u8]) -> Result<(Vec<u8>, usize), DecompressError> {
+    let mut out = Vec::new();
+    let len = deflate::inflate(input, |data: &[u8]| collect(&mut out, data))?;
+
+    Ok((out, len))
+}
+
+fn gunzip(input: &[u8]) -> Result<(Vec<u8>, usize), DecompressError> {
+    let mut out = Vec::new();
+    let len = gzip::decompress(input, |data: &[u8]| collect(&mut out, data))?;
+
+    Ok((out, len))
+}
+
+fn unlz4(input: &[u8]) -> Result<(Vec<u8>, usize), DecompressError> {
+    let mut out = Vec::new();
+    let len = lz4::decompress(input, |data: &[u8]| collect(&mut out, data))?;
+
+    Ok((out, len))
+}
+
+fn collect(out: &mut Vec<u8>, data: &[u8]) -> Result<(), DecompressError> {
+    out.extend_from_slice(data);
+    Ok(())
+}
+
+fn flipped(input: &[u8], index: usize) -> Vec<u8> {
+    let mut input = input.to_vec();
+    input[index] ^= 0x01;
+
+    input
+}
+
+fn with(mut input: Vec<u8>, tail: &[u8]) -> Vec<u8> {
+    input.extend_from_slice(tail);
+
+    input
+}
+
+#[test]
+fn deflate_fixed_huffman_vector() {
+    assert_eq!(
+        inflate(&HELLO_DEFLATE),
+        Ok((b"Hello, Hello, Hello!".to_vec(), HELLO_DEFLATE.len()))
+    );
+}
+
+#[test]
+fn deflate_stops_at_the_last_block() {
+    let input = with(HELLO_DEFLATE.to_vec(), b"trailing");
+
+    assert_eq!(inflate(&input).unwrap().1, HELLO_DEFLATE.len());
+}
+
+#[test]
+fn deflate_stored_block() {
+    // last block, stored, then LEN and NLEN
+    let input = with(vec![0x01, 0x03, 0x00, 0xFC, 0xFF], b"abc");
+
+    assert_eq!(inflate(&input), Ok((b"abc".to_vec(), 8)));
+}
+
+#[test]
+fn deflate_rejects_bad_blocks() {
+    // block type 3 is reserved
+    assert_eq!(
+        inflate(&[0x07]),
+        Err(DecompressError::Corrupt("bad block type"))
+    );
+    // NLEN is not the complement of LEN
+    assert_eq!(
+        inflate(&with(vec![0x01, 0x03, 0x00, 0xFC, 0xFE], b"abc")),
+        Err(DecompressError::Corrupt("bad stored block length"))
+    );
+}
+
+#[test]
+fn gzip_vectors() {
+    for (plain, compressed) in [(WORDS, WORDS_GZ), (RANDOM, RANDOM_GZ)] {
+        assert_eq!(gunzip(compressed), Ok((plain.to_vec(), compressed.len())));
+    }
+    assert_eq!(gunzip(&EMPTY_GZ), Ok((Vec::new(), EMPTY_GZ.len())));
+}
+
+#[test]
+fn gzip_members_are_decompressed_one_at_a_time() {
+    let input = with(RANDOM_GZ.to_vec(), WORDS_GZ);
+
+    let (first, len) = gunzip(&input).unwrap();
+    assert_eq!((first.as_slice(), len), (RANDOM, RANDOM_GZ.len()));
+    assert_eq!(gunzip(&input[len..]).unwrap().0, WORDS);
+}
+
+#[test]
+fn gzip_header_fields_are_skipped() {
+    // extra field, name, comment and header CRC
+    let mut input = vec![0x1F, 0x8B, 0x08, 0x1E, 0, 0, 0, 0, 0x00, 0x03];
+    input.extend_from_slice(&[5, 0]);
+    input.extend_from_slice(b"extra");
+    input.extend_from_slice(b"random.bin\0a comment\0");
+    let mut crc = crate::checksum::Crc32::new();
+    crc.update(&input);
+    input.extend_from_slice(&(crc.finish() as u16).to_le_bytes());
+    let input = with(input, &RANDOM_GZ[10..]);
+
+    assert_eq!(gunzip(&input), Ok((RANDOM.to_vec(), input.len())));
+
+    // the header CRC covers the name
+    let name = input.iter().position(|&b| b == b'r').unwrap();
+    assert_eq!(
+        gunzip(&flipped(&input, name)),
+        Err(DecompressError::ChecksumMismatch)
+    );
+}
+
+#[test]
+fn gzip_rejects_bad_headers() {
+    assert_eq!(
+        gunzip(&flipped(WORDS_GZ, 0)),
+        Err(DecompressError::BadMagic)
+    );
+    // compression method
+    assert_eq!(
+        gunzip(&flipped(WORDS_GZ, 2)),
+        Err(DecompressError::Unsupported)
+    );
+    // reserved flag
+    let mut input = WORDS_GZ.to_vec();
+    input[3] |= 0x80;
+    assert_eq!(gunzip(&input), Err(DecompressError::Unsupported));
+}
+
+#[test]
+fn gzip_trailer_is_checked() {
+    let len = WORDS_GZ.len();
+
+    // CRC-32, then the size
+    for index in [len - 8, len - 1] {
+        assert_eq!(
+            gunzip(&flipped(WORDS_GZ, index)),
+            Err(DecompressError::ChecksumMismatch)
+        );
+    }
+}
+
+#[test]
+fn gzip_corrupt_data_is_an_error() {
+    for index in (20..WORDS_GZ.len() - 8).step_by(101) {
+        assert!(gunzip(&flipped(WORDS_GZ, index)).is_err(), "{}", index);
+    }
+}
+
+#[test]
+fn truncated_gzip_is_detected() {
+    for compressed in [WORDS_GZ, RANDOM_GZ, &EMPTY_GZ] {
+        for len in (0..compressed.len())
+            .step_by(37)
+            .chain([compressed.len() - 1])
+        {
+            assert_eq!(
+                gunzip(&compressed[..len]),
+                Err(DecompressError::Truncated),
+                "{}",
+                len
+            );
+        }
+    }
+}
+
+#[test]
+fn lz4_frame_vectors() {
+    for (plain, compressed) in [(WORDS, WORDS_LZ4), (RANDOM, RANDOM_LZ4)] {
+        assert!(lz4::is_lz4(compressed));
+        assert_eq!(unlz4(compressed), Ok((plain.to_vec(), compressed.len())));
+    }
+}
+
+#[test]
+fn lz4_legacy_vectors() {
+    for (plain, compressed) in
+        [(WORDS, WORDS_LEGACY_LZ4), (RANDOM, RANDOM_LEGACY_LZ4)]
+    {
+        assert!(lz4::is_lz4(compressed));
+        assert_eq!(unlz4(compressed), Ok((plain.to_vec(), compressed.len())));
+    }
+}
+
+#[test]
+fn lz4_legacy_frame_ends_at_padding_or_the_next_frame() {
+    let padded = with(RANDOM_LEGACY_LZ4.to_vec(), &[0; 8]);
+    assert_eq!(unlz4(&padded).unwrap().1, RANDOM_LEGACY_LZ4.len());
+
+    let followed = with(RANDOM_LEGACY_LZ4.to_vec(), WORDS_LZ4);
+    let (first, len) = unlz4(&followed).unwrap();
+    assert_eq!((first.as_slice(), len), (RANDOM, RANDOM_LEGACY_LZ4.len()));
+    assert_eq!(unlz4(&followed[len..]).unwrap().0, WORDS);
+}
+
+#[test]
+fn lz4_skippable_frames_are_skipped() {
+    let mut input = vec![0x5A, 0x2A, 0x4D, 0x18, 5, 0, 0, 0];
+    input.extend_from_slice(b"12345");
+    let input = with(input, RANDOM_LZ4);
+
+    assert!(lz4::is_lz4(&input));
+    assert_eq!(unlz4(&input), Ok((RANDOM.to_vec(), input.len())));
+}
+
+#[test]
+fn lz4_rejects_bad_frames() {
+    assert!(!lz4::is_lz4(b"\x04\x22\x4D\x19"));
+    assert_eq!(
+        unlz4(&flipped(WORDS_LZ4, 0)),
+        Err(DecompressError::BadMagic)
+    );
+    // dictionary
+    assert_eq!(
+        unlz4(&flipped(WORDS_LZ4, LZ4_FLAGS)),
+        Err(DecompressError::Unsupported)
+    );
+    // the header checksum covers the descriptor
+    let mut input = WORDS_LZ4.to_vec();
+    input[LZ4_FLAGS + 2] ^= 0x01;
+    assert_eq!(unlz4(&input), Err(DecompressError::ChecksumMismatch));
+}
+
+#[test]
+fn lz4_checksums_are_checked() {
+    // magic, descriptor with the content size and the header checksum
+    let block = 4 + 2 + 8 + 1;
+    let size =
+        u32::from_le_bytes(WORDS_LZ4[block..block + 4].try_into().unwrap());
+    let data = block + 4;
+    let block_checksum = data + (size & 0x7FFF_FFFF) as usize;
+
+    for index in [data, data + 100, block_checksum, WORDS_LZ4.len() - 1] {
+        assert_eq!(
+            unlz4(&flipped(WORDS_LZ4, index)),
+            Err(DecompressError::ChecksumMismatch),
+            "{}",
+            index
+        );
+    }
+}
+
+#[test]
+fn lz4_match_before_the_start_is_corrupt() {
+    // one literal, then a match 5 bytes back
+    let mut input = lz4::LEGACY_MAGIC.to_le_bytes().to_vec();
+    input.extend_from_slice(&4u32.to_le_bytes());
+    input.extend_from_slice(&[0x10, b'a', 0x05, 0x00]);
+
+    assert_eq!(
+        unlz4(&input),
+        Err(DecompressError::Corrupt("distance too far back"))
+    );
+}
+
+#[test]
+fn truncated_lz4_is_detected() {
+    for compressed in [WORDS_LZ4, RANDOM_LZ4] {
+        for len in (0..compressed.len())
+            .step_by(37)
+            .chain([compressed.len() - 1])
+        {
+            assert_eq!(
+                unlz4(&compressed[..len]),
+                Err(DecompressError::Truncated),
+                "{}",
+                len
+            );
+        }
+    }
+
+    // a legacy frame ends with the input, so only a cut block is noticed
+    let len = RANDOM_LEGACY_LZ4.len() - 1;
+    assert_eq!(
+        unlz4(&RANDOM_LEGACY_LZ4[..len]),
+        Err(DecompressError::Truncated)
+    );
+}
+
+#[test]
+fn sink_errors_are_passed_through() {
+    let mut calls = 0;
+    let full = |_: &[u8]| {
+        calls += 1;
+        Err(SinkError::Full)
+    };
+
+    assert_eq!(gzip::decompress(WORDS_GZ, full), Err(SinkError::Full));
+    assert_eq!(calls, 1);
+
+    assert_eq!(
+        lz4::decompress(WORDS_LZ4, |_: &[u8]| Err(SinkError::Full)),
+        Err(SinkError::Full)
+    );
+    assert_eq!(
+        lz4::decompress(&WORDS_LZ4[..100], |_: &[u8]| Ok(())),
+        Err(SinkError::Decompress(DecompressError::Truncated))
+    );
+}
+
+#[test]
+fn every_error_has_a_message() {
+    let errors = [
+        DecompressError::Truncated,
+        DecompressError::BadMagic,
+        DecompressError::Unsupported,
+        DecompressError::Corrupt("bad block type"),
+        DecompressError::ChecksumMismatch,
+    ];
+
+    for error in errors {
+        let text: &'static str = error.into();
+        assert!(!text.is_empty());
+        assert!(!error.to_string().is_empty());
+    }
+    assert_eq!(
+        DecompressError::Corrupt("bad block type").to_string(),
+        "Corrupt compressed data: bad block type"
+    );
+}
@@ -0,0 +1,318 @@
+// FAT file systems on empty volumes
+//
+// The images in `data` are made by `data/make.sh`, laid out as mkfs.fat from dosfstools does.
+// Images the tests have written to are checked with fsck.fat as well, if it is installed.
+
+use crate::{
+    block::{check_range, interface::BlockDevice, BlockError},
+    compress::{gzip, DecompressError},
+    fs::{
+        fat::{FatFileSystem, FatType},
+        FsError, Name,
+    },
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const SECTOR_SIZE: usize = 512;
+
+// 1 MiB with 4 sectors per cluster
+const FAT12_GZ: &[u8] = include_bytes!("../data/fat12.img.gz");
+// 16 MiB with 4 sectors per cluster
+const FAT16_GZ: &[u8] = include_bytes!("../data/fat16.img.gz");
+// 40 MiB with 1 sector per cluster, enough clusters to be FAT32 by the count as the specification
+// demands
+const FAT32_GZ: &[u8] = include_bytes!("../data/fat32.img.gz");
+
+// A disk image in memory, written back to its file for fsck.fat
+struct Image {
+    path: PathBuf,
+    bytes: Mutex<Vec<u8>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Image {
+    // A fresh copy of an image of `data`
+    fn unpack(name: &str, image_gz: &[u8]) -> Self {
+        let mut bytes = Vec::new();
+        gzip::decompress(image_gz, |data: &[u8]| {
+            bytes.extend_from_slice(data);
+            Ok::<_, DecompressError>(())
+        })
+        .unwrap();
+
+        Self {
+            path: env::temp_dir().join(format!(
+                "host-tests-{}-{}.img",
+                std::process::id(),
+                name
+            )),
+            bytes: Mutex::new(bytes),
+        }
+    }
+
+    // Let fsck.fat look at the image without changing it
+    fn fsck(&self) {
+        let Some(fsck) = tool("fsck.fat") else {
+            println!("fsck.fat not found, image not checked");
+            return;
+        };
+
+        fs::write(&self.path, &*self.bytes.lock().unwrap()).unwrap();
+        let output = Command::new(fsck)
+            .args(["-n", "-v"])
+            .arg(&self.path)
+            .output()
+            .expect("fsck.fat did not run");
+
+        assert!(
+            output.status.success(),
+            "fsck.fat found errors:\n{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl BlockDevice for Image {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.bytes.lock().unwrap().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_blocks(
+        &self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), BlockError> {
+        check_range(block, buffer.len(), SECTOR_SIZE, self.block_count())?;
+
+        let start = block as usize * SECTOR_SIZE;
+        let bytes = self.bytes.lock().unwrap();
+        buffer.copy_from_slice(&bytes[start..start + buffer.len()]);
+
+        Ok(())
+    }
+
+    fn write_blocks(
+        &self,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), BlockError> {
+        check_range(block, buffer.len(), SECTOR_SIZE, self.block_count())?;
+
+        let start = block as usize * SECTOR_SIZE;
+        let mut bytes = self.bytes.lock().unwrap();
+        bytes[start..start + buffer.len()].copy_from_slice(buffer);
+
+        Ok(())
+    }
+}
+
+// dosfstools installs to /sbin, which is not on the path of every user
+fn tool(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH").unwrap_or_default();
+
+    env::split_paths(&path)
+        .chain(["/sbin", "/usr/sbin", "/usr/local/sbin"].map(PathBuf::from))
+        .map(|dir| dir.join(name))
+        .find(|candidate| Path::is_file(candidate))
+}
+
+fn list(fs: &FatFileSystem, path: &str) -> Vec<(String, bool, u64)> {
+    let dir = fs.lookup(path).unwrap();
+    let mut position = 0;
+    let mut name = Name::empty();
+    let mut entries = Vec::new();
+
+    while let Some(entry) = fs.read_dir(&dir, &mut position, &mut name).unwrap()
+    {
+        entries.push((name.as_str().to_string(), entry.is_dir(), entry.size()));
+    }
+    entries.sort();
+
+    entries
+}
+
+fn read_file(fs: &FatFileSystem, path: &str) -> Vec<u8> {
+    let file = fs.lookup(path).unwrap();
+    let mut data = vec![0; file.size() as usize + 1000];
+    let mut len = 0;
+
+    // in odd pieces, to cross sector and cluster boundaries
+    loop {
+        let end = (len + 777).min(data.len());
+        match fs.read(&file, len as u64, &mut data[len..end]).unwrap() {
+            0 => break,
+            read => len += read,
+        }
+    }
+    data.truncate(len);
+
+    data
+}
+
+fn write_file(fs: &FatFileSystem, path: &str, data: &[u8]) {
+    let mut file = fs.create_file(path).unwrap();
+
+    for (i, chunk) in data.chunks(3001).enumerate() {
+        let written = fs.write(&mut file, (i * 3001) as u64, chunk).unwrap();
+        assert_eq!(written, chunk.len());
+    }
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len as u32).map(|i| (i * 7 + i / 300) as u8).collect()
+}
+
+// Fill a fresh volume, check it after a remount and empty it again
+fn exercise(image: &Image, fat_type: FatType) {
+    let fs = FatFileSystem::mount(image).unwrap();
+    assert_eq!(fs.fat_type(), fat_type);
+    // the volume label is not a file
+    assert_eq!(list(&fs, "/"), []);
+    let free = fs.free_space().unwrap();
+
+    let data = pattern(100_000);
+    fs.create_dir("/Long Directory Name").unwrap();
+    fs.create_dir("/long directory name/deeper").unwrap();
+    write_file(&fs, "/Long Directory Name/Data with ünïcode.bin", &data);
+    write_file(&fs, "/README.TXT", b"Hello, FAT!\n");
+    write_file(&fs, "/lower.txt", b"lower case short name\n");
+    for i in 0..40 {
+        fs.create_file(&format!("/long directory name/deeper/file {}", i))
+            .unwrap();
+    }
+    fs.sync().unwrap();
+    image.fsck();
+
+    let fs = FatFileSystem::mount(image).unwrap();
+    assert_eq!(
+        list(&fs, "/"),
+        [
+            ("Long Directory Name".to_string(), true, 0),
+            ("README.TXT".to_string(), false, 12),
+            ("lower.txt".to_string(), false, 22),
+        ]
+    );
+    assert_eq!(
+        read_file(&fs, "/LONG DIRECTORY NAME/data WITH ünïcode.BIN"),
+        data
+    );
+    assert_eq!(read_file(&fs, "/readme.txt"), b"Hello, FAT!\n");
+    // . and .. are listed as well
+    assert_eq!(list(&fs, "/long directory name/deeper").len(), 42);
+    assert!(fs
+        .lookup("/long directory name/deeper/..")
+        .unwrap()
+        .is_dir());
+    assert!(fs.lookup("/long directory name/..").unwrap().is_root());
+
+    // the file takes whole clusters
+    let cluster = fs.cluster_size() as u64;
+    assert!(
+        free - fs.free_space().unwrap()
+            >= data.len() as u64 / cluster * cluster
+    );
+
+    assert_eq!(
+        fs.remove("/long directory name").unwrap_err(),
+        FsError::DirectoryNotEmpty
+    );
+    for i in 0..40 {
+        fs.remove(&format!("/LONG DIRECTORY NAME/DEEPER/FILE {}", i))
+            .unwrap();
+    }
+    fs.remove("/long directory name/deeper").unwrap();
+    fs.remove("/long directory name/data with ünïcode.bin")
+        .unwrap();
+    fs.remove("/long directory name").unwrap();
+    fs.remove("/readme.txt").unwrap();
+    fs.remove("/lower.txt").unwrap();
+    fs.sync().unwrap();
+    image.fsck();
+
+    let fs = FatFileSystem::mount(image).unwrap();
+    assert_eq!(list(&fs, "/"), []);
+    assert_eq!(fs.free_space().unwrap(), free);
+}
+
+#[test]
+fn fat12() {
+    exercise(&Image::unpack("fat12", FAT12_GZ), FatType::Fat12);
+}
+
+#[test]
+fn fat16() {
+    exercise(&Image::unpack("fat16", FAT16_GZ), FatType::Fat16);
+}
+
+#[test]
+fn fat32() {
+    exercise(&Image::unpack("fat32", FAT32_GZ), FatType::Fat32);
+}
+
+#[test]
+fn sparse_write_and_truncate() {
+    let image = Image::unpack("truncate", FAT16_GZ);
+    let fs = FatFileSystem::mount(&image).unwrap();
+
+    let mut file = fs.create_file("/sparse").unwrap();
+    fs.write(&mut file, 5000, b"tail").unwrap();
+    let data = read_file(&fs, "/sparse");
+    assert_eq!(data.len(), 5004);
+    assert!(data[..5000].iter().all(|&byte| byte == 0));
+    assert_eq!(&data[5000..], b"tail");
+
+    fs.truncate(&mut file, 3).unwrap();
+    assert_eq!(read_file(&fs, "/sparse"), [0; 3]);
+    fs.truncate(&mut file, 0).unwrap();
+    assert_eq!(read_file(&fs, "/sparse"), b"");
+
+    fs.sync().unwrap();
+    image.fsck();
+}
+
+#[test]
+fn bad_paths_are_refused() {
+    let image = Image::unpack("paths", FAT12_GZ);
+    let fs = FatFileSystem::mount(&image).unwrap();
+    fs.create_file("/file.txt").unwrap();
+
+    assert_eq!(fs.lookup("/missing").unwrap_err(), FsError::NotFound);
+    assert_eq!(
+        fs.lookup("/file.txt/below").unwrap_err(),
+        FsError::NotADirectory
+    );
+    assert_eq!(
+        fs.create_file("/FILE.TXT").unwrap_err(),
+        FsError::AlreadyExists
+    );
+    assert_eq!(
+        fs.create_file("/bad?name").unwrap_err(),
+        FsError::InvalidName
+    );
+    assert_eq!(
+        fs.create_dir("/missing/dir").unwrap_err(),
+        FsError::NotFound
+    );
+}
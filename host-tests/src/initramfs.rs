@@ -0,0 +1,363 @@
+// Unpacking initramfs archives into a RAM file system
+//
+// The archives are made here, with compressed segments that store the data as it is: gzip members
+// of stored blocks and LZ4 frames of literals. Real compressed data is the business of
+// `decompress`.
+//
+// All tests share the one virtual file system, so each takes `VFS_LOCK` first.
+
+use crate::{
+    checksum::Crc32,
+    compress::{lz4, DecompressError},
+    fs::{
+        initramfs::{self, InitramfsError, Summary},
+        ramfs::RamFs,
+        vfs::{vfs, OpenFlags},
+        FileKind, FsError, Name,
+    },
+};
+use std::{collections::BTreeMap, sync::Mutex};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const WORDS: &[u8] = include_bytes!("../data/words.txt");
+
+const DIRECTORY: u32 = 0o040755;
+const FILE: u32 = 0o100644;
+const SYMLINK: u32 = 0o120777;
+const CHAR_DEVICE: u32 = 0o020666;
+
+// Each test unpacks into a file system of its own, mounted below this one
+type Root = RamFs<32, 4>;
+type Target = RamFs<64, 512>;
+
+// What came out, by path below the mount point. Directories have no data.
+type Tree = BTreeMap<String, Option<Vec<u8>>>;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static VFS_LOCK: Mutex<()> = Mutex::new(());
+
+static ROOT: Root = Root::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// A "newc" archive of `(path, mode, data)` entries with the trailer
+fn newc(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+    archive(entries, false)
+}
+
+// The same with the sum of the data bytes in each header
+fn newc_crc(entries: &[(&str, u32, &[u8])]) -> Vec<u8> {
+    archive(entries, true)
+}
+
+fn archive(entries: &[(&str, u32, &[u8])], check: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    let trailer: (&str, u32, &[u8]) = ("TRAILER!!!", 0, b"");
+
+    for (inode, &(path, mode, data)) in
+        entries.iter().chain([&trailer]).enumerate()
+    {
+        let sum = data.iter().map(|&b| b as u32).sum::<u32>();
+        #[rustfmt::skip]
+        let fields = [
+            inode as u32 + 1, mode, 0, 0, 1, 0, data.len() as u32, 0, 0, 0, 0,
+            path.len() as u32 + 1, if check { sum } else { 0 },
+        ];
+
+        out.extend_from_slice(if check { b"070702" } else { b"070701" });
+        for field in fields {
+            out.extend_from_slice(format!("{:08X}", field).as_bytes());
+        }
+        out.extend_from_slice(path.as_bytes());
+        out.push(0);
+        pad(&mut out, 4);
+        out.extend_from_slice(data);
+        pad(&mut out, 4);
+    }
+
+    out
+}
+
+fn pad(out: &mut Vec<u8>, to: usize) {
+    out.resize(out.len().next_multiple_of(to), 0);
+}
+
+// A gzip member of stored deflate blocks
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1F, 0x8B, 0x08, 0, 0, 0, 0, 0, 0, 0x03];
+
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    let mut crc = Crc32::new();
+    crc.update(data);
+    out.extend_from_slice(&crc.finish().to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    out
+}
+
+// A legacy LZ4 frame with one block of literals
+fn lz4(data: &[u8]) -> Vec<u8> {
+    let mut block = vec![0xF0];
+    let mut extra = data.len() - 15;
+    while extra >= 255 {
+        block.push(255);
+        extra -= 255;
+    }
+    block.push(extra as u8);
+    block.extend_from_slice(data);
+
+    let mut out = lz4::LEGACY_MAGIC.to_le_bytes().to_vec();
+    out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+    out.extend_from_slice(&block);
+
+    out
+}
+
+// Unpack `archive` into a new file system on `/<name>` and read back what is there
+fn unpack(
+    name: &str,
+    archive: &[u8],
+) -> (Result<Summary, InitramfsError>, Tree) {
+    // the other tests still run after one failed while holding the lock
+    let _guard = VFS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    if vfs().metadata("/").is_err() {
+        vfs().mount("/", &ROOT).unwrap();
+    }
+    let dir = format!("/{}", name);
+    vfs().create_dir(&dir).unwrap();
+    let target: &'static Target = Box::leak(Box::new(Target::new()));
+    vfs().mount(&dir, target).unwrap();
+
+    let result = initramfs::unpack(archive, &dir);
+    let mut tree = Tree::new();
+    walk(&dir, dir.len(), &mut tree);
+    vfs().unmount(&dir).unwrap();
+
+    (result, tree)
+}
+
+fn walk(path: &str, prefix: usize, tree: &mut Tree) {
+    let dir = vfs().open(path, OpenFlags::READ).unwrap();
+    let mut name = Name::empty();
+
+    while let Some(metadata) = vfs().read_dir(dir, &mut name).unwrap() {
+        if matches!(name.as_str(), "." | "..") {
+            continue;
+        }
+        let child = format!("{}/{}", path, name.as_str());
+        let key = child[prefix..].to_string();
+
+        if metadata.kind == FileKind::Directory {
+            tree.insert(key, None);
+            walk(&child, prefix, tree);
+        } else {
+            tree.insert(key, Some(read(&child)));
+        }
+    }
+    vfs().close(dir).unwrap();
+}
+
+fn read(path: &str) -> Vec<u8> {
+    let fd = vfs().open(path, OpenFlags::READ).unwrap();
+    let mut data = Vec::new();
+    let mut buffer = [0; 1000];
+
+    loop {
+        match vfs().read(fd, &mut buffer).unwrap() {
+            0 => break,
+            len => data.extend_from_slice(&buffer[..len]),
+        }
+    }
+    vfs().close(fd).unwrap();
+
+    data
+}
+
+fn tree(entries: &[(&str, Option<&[u8]>)]) -> Tree {
+    entries
+        .iter()
+        .map(|&(path, data)| (path.to_string(), data.map(<[u8]>::to_vec)))
+        .collect()
+}
+
+#[test]
+fn segments_of_every_kind_are_unpacked() {
+    let first = newc(&[
+        (".", DIRECTORY, b""),
+        ("etc", DIRECTORY, b""),
+        ("etc/hostname", FILE, b"pi\n"),
+    ]);
+    // spans the gzip member and the LZ4 frame
+    let second = newc(&[
+        ("usr/share/words.txt", FILE, WORDS),
+        ("etc/empty", FILE, b""),
+    ]);
+    let (head, tail) = second.split_at(second.len() / 2);
+    // two archives in one member
+    let mut third = newc(&[("odd", FILE, b"x")]);
+    third.extend_from_slice(&[0; 12]);
+    third.extend_from_slice(&newc(&[("bin", DIRECTORY, b"")]));
+
+    let mut archive = first;
+    pad(&mut archive, 512);
+    archive.extend_from_slice(&gzip(head));
+    archive.extend_from_slice(&lz4(tail));
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&gzip(&third));
+    archive.extend_from_slice(&[0; 100]);
+
+    let (summary, files) = unpack("segments", &archive);
+
+    assert_eq!(
+        summary,
+        Ok(Summary {
+            directories: 2,
+            files: 4,
+            bytes: WORDS.len() as u64 + 4,
+            skipped: 0,
+        })
+    );
+    assert_eq!(
+        files,
+        tree(&[
+            ("/bin", None),
+            ("/etc", None),
+            ("/etc/empty", Some(b"")),
+            ("/etc/hostname", Some(b"pi\n")),
+            ("/odd", Some(b"x")),
+            ("/usr", None),
+            ("/usr/share", None),
+            ("/usr/share/words.txt", Some(WORDS)),
+        ])
+    );
+}
+
+#[test]
+fn later_entries_replace_earlier_files() {
+    let mut archive = newc(&[("config.txt", FILE, b"a long first version")]);
+    archive.extend_from_slice(&gzip(&newc(&[("config.txt", FILE, b"short")])));
+
+    let (summary, files) = unpack("replace", &archive);
+
+    assert_eq!(summary.unwrap().files, 2);
+    assert_eq!(files, tree(&[("/config.txt", Some(b"short"))]));
+}
+
+#[test]
+fn only_files_and_directories_inside_the_root_are_made() {
+    let archive = newc(&[
+        ("link", SYMLINK, b"etc/hostname"),
+        ("dev/null", CHAR_DEVICE, b""),
+        ("../escaped", FILE, b"x"),
+        ("a/../../escaped", FILE, b"x"),
+        ("/absolute/./file", FILE, b"inside"),
+    ]);
+
+    let (summary, files) = unpack("skipped", &archive);
+
+    assert_eq!(summary.unwrap().skipped, 4);
+    assert_eq!(
+        files,
+        tree(&[("/absolute", None), ("/absolute/file", Some(b"inside"))])
+    );
+}
+
+#[test]
+fn checksums_of_070702_archives_are_checked() {
+    let entries: &[(&str, u32, &[u8])] = &[("words.txt", FILE, WORDS)];
+    let (summary, files) = unpack("checked", &newc_crc(entries));
+    assert!(summary.is_ok());
+    assert_eq!(files, tree(&[("/words.txt", Some(WORDS))]));
+
+    let mut archive = newc_crc(entries);
+    let middle = archive.len() / 2;
+    archive[middle] ^= 0x01;
+    let (summary, _) = unpack("checked-bad", &archive);
+    assert_eq!(
+        summary,
+        Err(InitramfsError::Fs(FsError::Corrupt(
+            "cpio: checksum mismatch"
+        )))
+    );
+}
+
+#[test]
+fn empty_input_unpacks_nothing() {
+    for (name, archive) in [("empty", &[][..]), ("zeros", &[0; 512][..])] {
+        assert_eq!(
+            unpack(name, archive),
+            (Ok(Summary::default()), Tree::new())
+        );
+    }
+}
+
+#[test]
+fn bad_archives_are_errors() {
+    let archive = newc(&[("words.txt", FILE, WORDS)]);
+    let cut = &archive[..archive.len() - 200];
+
+    let cases = [
+        (
+            "no-trailer",
+            cut.to_vec(),
+            InitramfsError::Fs(FsError::Corrupt(
+                "cpio: archive without trailer",
+            )),
+        ),
+        (
+            "cut-member",
+            gzip(&archive)[..1000].to_vec(),
+            InitramfsError::Decompress(DecompressError::Truncated),
+        ),
+        (
+            "cut-in-member",
+            gzip(cut),
+            InitramfsError::Fs(FsError::Corrupt(
+                "cpio: archive without trailer",
+            )),
+        ),
+        (
+            "garbage",
+            b"not an archive".to_vec(),
+            InitramfsError::Decompress(DecompressError::BadMagic),
+        ),
+        (
+            "bad-magic",
+            b"070707".iter().chain(&archive[6..]).copied().collect(),
+            InitramfsError::Fs(FsError::Corrupt("cpio: bad magic")),
+        ),
+    ];
+
+    for (name, archive, error) in cases {
+        assert_eq!(unpack(name, &archive).0, Err(error), "{}", name);
+    }
+}
+
+#[test]
+fn full_file_system_is_an_error() {
+    let big = vec![0x55; Target::new().free_space() as usize + 1];
+
+    let (summary, _) = unpack("full", &newc(&[("big", FILE, &big)]));
+
+    assert_eq!(summary, Err(InitramfsError::Fs(FsError::NoSpace)));
+}
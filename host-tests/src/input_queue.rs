@@ -0,0 +1,122 @@
+// The input event queue and its subscribers
+//
+// Each test has a queue of its own, leaked for the `'static` its subscribers need.
+
+use crate::input::{Event, InputQueue, Source, Subscriber};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// The ring of `input`
+const QUEUE_SIZE: u32 = 128;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn queue() -> &'static InputQueue {
+    Box::leak(Box::new(InputQueue::new()))
+}
+
+// Event `n` of a test, told apart by the button number and the source
+fn event(n: u32) -> Event {
+    Event::Button {
+        button: n as u8,
+        pressed: n & 0x100 != 0,
+    }
+}
+
+fn push(queue: &InputQueue, events: impl IntoIterator<Item = u32>) {
+    for n in events {
+        queue.push(Source::Usb((n >> 9) as u8), event(n));
+    }
+}
+
+// Event `n` as `subscriber` reads it next
+fn assert_next(subscriber: &mut Subscriber, n: u32) {
+    let next = subscriber.try_next().expect("no event");
+    assert_eq!(
+        (next.source, next.event),
+        (Source::Usb((n >> 9) as u8), event(n)),
+        "event {}",
+        n
+    );
+}
+
+#[test]
+fn subscribers_see_what_is_pushed_after_they_subscribed() {
+    let queue = queue();
+    push(queue, 0..3);
+
+    let mut first = queue.subscribe();
+    push(queue, 3..6);
+    let mut second = queue.subscribe();
+    push(queue, 6..8);
+
+    for n in 3..8 {
+        assert_next(&mut first, n);
+    }
+    for n in 6..8 {
+        assert_next(&mut second, n);
+    }
+    assert_eq!(first.try_next(), None);
+    assert_eq!(second.try_next(), None);
+    assert_eq!((first.lost(), second.lost()), (0, 0));
+}
+
+#[test]
+fn events_stay_in_order_around_the_ring() {
+    let queue = queue();
+    let mut subscriber = queue.subscribe();
+
+    // never more than the ring behind
+    for start in (0..5 * QUEUE_SIZE).step_by(100) {
+        push(queue, start..start + 100);
+        for n in start..start + 100 {
+            assert_next(&mut subscriber, n);
+        }
+    }
+
+    assert_eq!(subscriber.try_next(), None);
+    assert_eq!(subscriber.lost(), 0);
+}
+
+#[test]
+fn subscribers_falling_behind_lose_the_oldest_events() {
+    let queue = queue();
+    let mut slow = queue.subscribe();
+    let mut fast = queue.subscribe();
+
+    push(queue, 0..QUEUE_SIZE);
+    assert_next(&mut fast, 0);
+    push(queue, QUEUE_SIZE..QUEUE_SIZE + 10);
+
+    // picks up with the oldest event in the ring
+    assert_next(&mut slow, 10);
+    assert_eq!(slow.lost(), 10);
+    assert_next(&mut fast, 10);
+    assert_eq!(fast.lost(), 9);
+
+    for n in 11..QUEUE_SIZE + 10 {
+        assert_next(&mut slow, n);
+    }
+    assert_eq!(slow.try_next(), None);
+    assert_eq!(slow.lost(), 10);
+
+    // more than a ring later, what was pushed before the last ring is gone
+    push(queue, QUEUE_SIZE + 10..4 * QUEUE_SIZE);
+    assert_next(&mut slow, 3 * QUEUE_SIZE);
+    assert_eq!(slow.lost(), 10 + (2 * QUEUE_SIZE - 10) as u64);
+}
+
+#[test]
+fn wait_returns_what_is_there() {
+    let queue = queue();
+    let mut subscriber = queue.subscribe();
+    push(queue, 0..2);
+
+    assert_eq!(subscriber.wait().event, event(0));
+    assert_eq!(subscriber.wait().event, event(1));
+    assert_eq!(subscriber.try_next(), None);
+}
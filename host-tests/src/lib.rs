@@ -0,0 +1,55 @@
+// Tests of kernel modules that run on the build machine
+//
+// Modules that do not touch the hardware are compiled from the kernel sources as they are and
+// tested here with the standard test harness:
+//
+//     $ cd host-tests && cargo test
+//
+// The kernel itself is built for the Pi only and has no test target. The parts of the kernel these
+// modules use are replaced by stand-ins, see `print`, `synchronization` and the others below.
+
+//--------------------------------------------------------------------------------------------------
+// Kernel modules
+//--------------------------------------------------------------------------------------------------
+
+// Mounted at the crate root, where the modules expect each other
+#[path = "../../src"]
+mod kernel {
+    pub mod block;
+    pub mod checksum;
+    pub mod compress;
+    pub mod fs;
+    pub mod input;
+}
+
+pub use kernel::{block, checksum, compress, fs, input};
+
+#[path = "../../src/bsp/raspberrypi/mailbox/property.rs"]
+pub mod property;
+
+//--------------------------------------------------------------------------------------------------
+// Stand-ins
+//--------------------------------------------------------------------------------------------------
+
+pub mod cpu;
+pub mod print;
+pub mod synchronization;
+pub mod time;
+pub mod usb;
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod block_cache;
+#[cfg(test)]
+mod decompress;
+#[cfg(test)]
+mod fat;
+#[cfg(test)]
+mod initramfs;
+#[cfg(test)]
+mod input_queue;
+#[cfg(test)]
+mod mailbox;
@@ -0,0 +1,308 @@
+// Mailbox property messages, answered by a fake firmware
+
+use crate::property::{self, interface::Tag, *};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// First tag of a message, after the buffer size and the request code
+const FIRST_TAG: usize = 2;
+
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+const TAG_RESPONSE: u32 = 0x8000_0000;
+
+fn word(msg: &mut PropertyMessage, index: usize) -> &mut u32 {
+    assert!(index < MESSAGE_WORDS);
+    // the message is only touched through this reference while it lives
+    unsafe { &mut *msg.as_mut_ptr().add(index) }
+}
+
+// Answer the tag at `offset` with `values` the way the firmware does
+fn answer(msg: &mut PropertyMessage, offset: usize, values: &[u32]) {
+    answer_with_len(msg, offset, values, values.len() * 4);
+}
+
+fn answer_with_len(
+    msg: &mut PropertyMessage,
+    offset: usize,
+    values: &[u32],
+    len: usize,
+) {
+    *word(msg, 1) = RESPONSE_SUCCESS;
+    *word(msg, offset + 2) = TAG_RESPONSE | len as u32;
+    for (i, value) in values.iter().enumerate() {
+        *word(msg, offset + 3 + i) = *value;
+    }
+}
+
+#[test]
+fn empty_message() {
+    let msg = PropertyMessage::new();
+
+    // buffer size in bytes, request code, end tag
+    assert_eq!(msg.words(), &[12, 0, 0]);
+    assert!(!msg.is_answered());
+}
+
+#[test]
+fn message_is_16_byte_aligned() {
+    let messages = [PropertyMessage::new(), PropertyMessage::new()];
+
+    assert_eq!(core::mem::align_of::<PropertyMessage>(), 16);
+    for msg in messages.iter() {
+        assert_eq!(msg.words().as_ptr() as usize % 16, 0);
+    }
+}
+
+#[test]
+fn tags_are_laid_out_back_to_back() {
+    let mut msg = PropertyMessage::new();
+    msg.push(GetBoardRevision).unwrap();
+    msg.push(SetClockRate {
+        clock: 3,
+        rate: 600_000_000,
+        skip_turbo: true,
+    })
+    .unwrap();
+
+    #[rustfmt::skip]
+    let expected = [
+        // header
+        (2 + 4 + 6 + 1) * 4, 0,
+        // identifier, value buffer size, request code, values
+        0x0001_0002, 4, 0, 0,
+        0x0003_8002, 12, 0, 3, 600_000_000, 1,
+        // end tag
+        0,
+    ];
+    assert_eq!(msg.words(), &expected);
+}
+
+#[test]
+fn value_buffer_fits_request_and_response() {
+    let mut msg = PropertyMessage::new();
+    msg.push(GetClockRate { clock: 4 }).unwrap();
+
+    // answered with the clock id and the rate
+    assert_eq!(msg.words()[FIRST_TAG + 1], 8);
+    assert_eq!(&msg.words()[FIRST_TAG + 3..FIRST_TAG + 5], &[4, 0]);
+}
+
+#[test]
+fn command_line_buffer_is_word_sized() {
+    let mut msg = PropertyMessage::new();
+    msg.push(GetCommandLine).unwrap();
+
+    assert_eq!(msg.words()[FIRST_TAG + 1] as usize, COMMAND_LINE_SIZE);
+    assert_eq!(msg.words().len(), FIRST_TAG + 3 + COMMAND_LINE_SIZE / 4 + 1);
+}
+
+#[test]
+fn full_message_is_rejected_unchanged() {
+    let mut msg = PropertyMessage::new();
+    msg.push(GetCommandLine).unwrap();
+    let before = msg.words().to_vec();
+
+    assert_eq!(
+        msg.push(GetCommandLine).err(),
+        Some(MailBoxError::MessageFull)
+    );
+    assert_eq!(msg.words(), &before[..]);
+
+    // smaller tags still fit
+    msg.push(GetBoardModel).unwrap();
+}
+
+#[test]
+fn message_is_filled_up_to_the_end_tag() {
+    let mut msg = PropertyMessage::new();
+
+    // 4 words per tag, the header and the end tag take 3
+    let tags = (MESSAGE_WORDS - 3) / 4;
+    for _ in 0..tags {
+        msg.push(GetBoardModel).unwrap();
+    }
+
+    assert_eq!(
+        msg.push(GetBoardModel).err(),
+        Some(MailBoxError::MessageFull)
+    );
+    assert_eq!(msg.words().len(), 2 + tags * 4 + 1);
+    assert_eq!(*msg.words().last().unwrap(), 0);
+}
+
+#[test]
+fn responses_are_decoded() {
+    let mut msg = PropertyMessage::new();
+    let mac = msg.push(GetBoardMacAddress).unwrap();
+    let serial = msg.push(GetBoardSerial).unwrap();
+    let memory = msg.push(GetArmMemory).unwrap();
+    let power = msg.push(GetPowerState { device: 3 }).unwrap();
+
+    answer(&mut msg, FIRST_TAG, &[0x4433_2211, 0x6655]);
+    answer(&mut msg, FIRST_TAG + 5, &[0x89AB_CDEF, 0x0123_4567]);
+    answer(&mut msg, FIRST_TAG + 10, &[0, 0x3B40_0000]);
+    answer(&mut msg, FIRST_TAG + 15, &[3, 0b10]);
+
+    assert!(msg.is_answered());
+    assert_eq!(msg.status(), Ok(()));
+    assert_eq!(msg.get(&mac), Ok([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]));
+    assert_eq!(msg.get(&serial), Ok(0x0123_4567_89AB_CDEF));
+    assert_eq!(
+        msg.get(&memory),
+        Ok(MemoryRegion {
+            base: 0,
+            size: 0x3B40_0000
+        })
+    );
+    assert_eq!(
+        msg.get(&power),
+        Ok(PowerState {
+            on: false,
+            exists: false
+        })
+    );
+}
+
+#[test]
+fn command_line_stops_at_nul_or_length() {
+    let mut words = [0u32; COMMAND_LINE_SIZE / 4];
+    let text = b"console=uart baud=115200\0garbage";
+    for (word, chunk) in words.iter_mut().zip(text.chunks(4)) {
+        let mut bytes = [0; 4];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        *word = u32::from_le_bytes(bytes);
+    }
+
+    let terminated = GetCommandLine::decode(&words, text.len()).unwrap();
+    assert_eq!(terminated.as_str(), "console=uart baud=115200");
+
+    let cut = GetCommandLine::decode(&words, 12).unwrap();
+    assert_eq!(cut.as_str(), "console=uart");
+
+    let empty = GetCommandLine::decode(&words, 0).unwrap();
+    assert_eq!(empty.as_str(), "");
+}
+
+#[test]
+fn unanswered_message_is_an_unexpected_response() {
+    let mut msg = PropertyMessage::new();
+    let handle = msg.push(GetFirmwareRevision).unwrap();
+
+    assert_eq!(msg.status(), Err(MailBoxError::UnexpectedResponse(0)));
+    assert_eq!(msg.get(&handle), Err(MailBoxError::UnexpectedResponse(0)));
+}
+
+#[test]
+fn parse_error_is_reported() {
+    let mut msg = PropertyMessage::new();
+    let handle = msg.push(GetFirmwareRevision).unwrap();
+    *word(&mut msg, 1) = 0x8000_0001;
+
+    assert!(msg.is_answered());
+    assert_eq!(msg.status(), Err(MailBoxError::ParseError));
+    assert_eq!(msg.get(&handle), Err(MailBoxError::ParseError));
+}
+
+#[test]
+fn unknown_response_code_is_reported() {
+    let mut msg = PropertyMessage::new();
+    msg.push(GetFirmwareRevision).unwrap();
+    *word(&mut msg, 1) = 0x1234;
+
+    assert_eq!(msg.status(), Err(MailBoxError::UnexpectedResponse(0x1234)));
+}
+
+#[test]
+fn tag_without_response_bit_is_not_handled() {
+    let mut msg = PropertyMessage::new();
+    let handled = msg.push(GetBoardModel).unwrap();
+    let ignored = msg.push(GetBoardRevision).unwrap();
+    answer(&mut msg, FIRST_TAG, &[0x11]);
+
+    assert_eq!(msg.get(&handled), Ok(0x11));
+    assert_eq!(
+        msg.get(&ignored),
+        Err(MailBoxError::TagNotHandled(GetBoardRevision::ID))
+    );
+}
+
+#[test]
+fn response_larger_than_the_buffer_is_rejected() {
+    let mut msg = PropertyMessage::new();
+    let handle = msg.push(GetPitch).unwrap();
+    answer_with_len(&mut msg, FIRST_TAG, &[4096], 8);
+
+    assert_eq!(
+        msg.get(&handle),
+        Err(MailBoxError::ResponseTooLarge(GetPitch::ID))
+    );
+}
+
+#[test]
+fn short_response_is_invalid() {
+    let mut msg = PropertyMessage::new();
+    let handle = msg.push(GetClockRate { clock: 4 }).unwrap();
+    answer_with_len(&mut msg, FIRST_TAG, &[4, 250_000_000], 4);
+
+    assert_eq!(
+        msg.get(&handle),
+        Err(MailBoxError::InvalidResponse(GetClockRate::ID))
+    );
+}
+
+#[test]
+fn overwritten_tag_is_invalid() {
+    let mut msg = PropertyMessage::new();
+    let handle = msg.push(GetTemperature).unwrap();
+    answer(&mut msg, FIRST_TAG, &[0, 45_000]);
+    *word(&mut msg, FIRST_TAG) = GetMaxTemperature::ID;
+
+    assert_eq!(
+        msg.get(&handle),
+        Err(MailBoxError::InvalidResponse(GetTemperature::ID))
+    );
+}
+
+#[test]
+fn mail_carries_bus_address_and_channel() {
+    assert_eq!(property::mail(0x0008_1230, 8), Ok(0xC008_1238));
+    assert_eq!(property::mail(0, 1), Ok(0xC000_0001));
+}
+
+#[test]
+fn unaligned_or_unreachable_message_is_rejected() {
+    for address in [0x0008_1234, 0x0008_1238, 0x4000_0000, 0x1_0000_0000] {
+        assert_eq!(
+            property::mail(address, 8),
+            Err(MailBoxError::NotAligned),
+            "{:#x}",
+            address
+        );
+    }
+}
+
+#[test]
+fn every_error_has_a_message() {
+    let errors = [
+        MailBoxError::NotAligned,
+        MailBoxError::MessageFull,
+        MailBoxError::Timeout,
+        MailBoxError::ParseError,
+        MailBoxError::UnexpectedResponse(1),
+        MailBoxError::TagNotHandled(2),
+        MailBoxError::ResponseTooLarge(3),
+        MailBoxError::InvalidResponse(4),
+    ];
+
+    for error in errors {
+        let text: &'static str = error.into();
+        assert!(!text.is_empty());
+        assert!(!error.to_string().is_empty());
+    }
+    assert_eq!(
+        MailBoxError::TagNotHandled(0x30002).to_string(),
+        "Tag 0x00030002 is not handled"
+    );
+}
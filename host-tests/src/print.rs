@@ -0,0 +1,15 @@
+// The kernel log goes to the test output
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        std::println!($($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        std::println!($($arg)*)
+    };
+}
@@ -0,0 +1,29 @@
+// The kernel timer, counting from the first time it is read
+
+use std::{sync::OnceLock, time::Instant};
+
+pub mod interface {
+    use core::time::Duration;
+
+    pub trait TimeManager {
+        fn uptime(&self) -> Duration;
+    }
+}
+
+pub struct TimeManager {
+    start: OnceLock<Instant>,
+}
+
+static TIME_MANAGER: TimeManager = TimeManager {
+    start: OnceLock::new(),
+};
+
+pub fn time_manager() -> &'static TimeManager {
+    &TIME_MANAGER
+}
+
+impl interface::TimeManager for TimeManager {
+    fn uptime(&self) -> core::time::Duration {
+        self.start.get_or_init(Instant::now).elapsed()
+    }
+}
@@ -1,4 +1,5 @@
-use cortex_a::asm;
+use core::arch::asm;
+use cortex_a::asm::{self, barrier};
 
 //-------------------------------------------------------------------------------------------------
 // Archtectural Public Reexports
@@ -12,3 +13,20 @@ pub fn wait_forever() -> ! {
         asm::wfe();
     }
 }
+
+// Write the cache lines of `len` bytes at `start` back to memory and drop them, so that a DMA
+// engine sees what the CPU wrote and the CPU sees what the DMA engine writes afterwards
+pub fn clean_and_invalidate_dcache(start: usize, len: usize) {
+    let ctr: u64;
+    // the smallest data cache line is 4 << CTR_EL0.DminLine bytes
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    let line = 4 << ((ctr >> 16) & 0xF);
+
+    let mut address = start & !(line - 1);
+    while address < start + len {
+        unsafe { asm!("dc civac, {}", in(reg) address) };
+        address += line;
+    }
+
+    unsafe { barrier::dsb(barrier::SY) };
+}
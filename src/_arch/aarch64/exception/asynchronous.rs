@@ -56,8 +56,9 @@ fn is_masked<T: DaifField>() -> bool {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+// True if IRQs are masked on the executing core
 pub fn is_local_irq_masked() -> bool {
-    !is_masked::<IRQ>()
+    is_masked::<IRQ>()
 }
 
 #[inline(always)]
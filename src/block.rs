@@ -0,0 +1,108 @@
+// Block devices
+//
+// Storage drivers, partitions and RAM disks all look the same to file systems: a number of
+// equally sized blocks that are read and written whole. `cache::BufferCache` sits in front of a
+// slow device and keeps recently used blocks in memory, `partition::PartitionTable` splits a disk
+// into its partitions.
+
+pub mod cache;
+pub mod partition;
+pub mod ramdisk;
+
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    // past the last block of the device
+    OutOfRange,
+    // the buffer is not a whole number of blocks
+    InvalidLength,
+    // the device has another block size than its user expects
+    BlockSizeMismatch,
+    ReadOnly,
+    // reported by the driver
+    Device(&'static str),
+}
+
+// Block device interfaces
+pub mod interface {
+    use super::BlockError;
+
+    // Requests may come with IRQs masked, as the buffer cache and the file systems hold their
+    // lock while they wait for the device. A driver must finish them without its interrupt then.
+    pub trait BlockDevice {
+        // size of a block in bytes
+        fn block_size(&self) -> usize;
+
+        // number of blocks on the device
+        fn block_count(&self) -> u64;
+
+        // read whole blocks from `block` on into `buffer`
+        fn read_blocks(
+            &self,
+            block: u64,
+            buffer: &mut [u8],
+        ) -> Result<(), BlockError>;
+
+        // write whole blocks from `block` on from `buffer`
+        fn write_blocks(
+            &self,
+            block: u64,
+            buffer: &[u8],
+        ) -> Result<(), BlockError>;
+
+        // make written blocks persistent
+        fn flush(&self) -> Result<(), BlockError> {
+            Ok(())
+        }
+
+        // size of the device in bytes
+        fn size(&self) -> u64 {
+            self.block_count() * self.block_size() as u64
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str((*self).into())
+    }
+}
+
+impl From<BlockError> for &'static str {
+    fn from(error: BlockError) -> Self {
+        match error {
+            BlockError::OutOfRange => "Block out of range",
+            BlockError::InvalidLength => "Not a whole number of blocks",
+            BlockError::BlockSizeMismatch => "Unexpected block size",
+            BlockError::ReadOnly => "Read-only block device",
+            BlockError::Device(message) => message,
+        }
+    }
+}
+
+// Number of blocks in `len` bytes if they are on a device of `block_count` blocks from `block` on
+pub fn check_range(
+    block: u64,
+    len: usize,
+    block_size: usize,
+    block_count: u64,
+) -> Result<u64, BlockError> {
+    if !len.is_multiple_of(block_size) {
+        return Err(BlockError::InvalidLength);
+    }
+
+    let blocks = (len / block_size) as u64;
+    match block.checked_add(blocks) {
+        Some(end) if end <= block_count => Ok(blocks),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
@@ -0,0 +1,332 @@
+// Buffer cache in front of a block device
+//
+// The cache keeps the `N` most recently used blocks. Writes only go to the cache and reach the
+// device when their block is evicted or at `sync()`. Requests are merged for sequential IO: runs
+// of missing blocks are read with one device request, runs of dirty blocks are written with one
+// request, and writes of at least a cache full skip the cache.
+//
+// The device is called under the cache lock, so that two requests for the same block cannot pass
+// each other. It has to work with IRQs masked, see `interface::BlockDevice`.
+
+use super::{check_range, interface::BlockDevice, BlockError};
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Most blocks written back with one request
+const MAX_MERGE: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    block: u64,
+    valid: bool,
+    dirty: bool,
+    last_used: u32,
+}
+
+struct CacheInner<const N: usize, const BLOCK_SIZE: usize> {
+    entries: [Entry; N],
+    data: [[u8; BLOCK_SIZE]; N],
+    // dirty runs are gathered here to be written with one request
+    merge_buffer: [[u8; BLOCK_SIZE]; MAX_MERGE],
+    clock: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// `N` blocks of `BLOCK_SIZE` bytes from `device`, which must have this block size
+pub struct BufferCache<'a, const N: usize, const BLOCK_SIZE: usize> {
+    device: &'a (dyn BlockDevice + Sync),
+    inner: IRQSafeNullLock<CacheInner<N, BLOCK_SIZE>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a, const N: usize, const BLOCK_SIZE: usize>
+    BufferCache<'a, N, BLOCK_SIZE>
+{
+    pub const fn new(device: &'a (dyn BlockDevice + Sync)) -> Self {
+        Self {
+            device,
+            inner: IRQSafeNullLock::new(CacheInner::new()),
+        }
+    }
+
+    pub fn device(&self) -> &'a (dyn BlockDevice + Sync) {
+        self.device
+    }
+
+    // Write all dirty blocks back and flush the device
+    pub fn sync(&self) -> Result<(), BlockError> {
+        self.inner.lock(|inner| inner.sync(self.device))?;
+
+        self.device.flush()
+    }
+
+    // Write all dirty blocks back and forget every block, e.g. before the medium is swapped
+    pub fn invalidate(&self) -> Result<(), BlockError> {
+        self.sync()?;
+        self.inner.lock(|inner| {
+            for entry in inner.entries.iter_mut() {
+                entry.valid = false;
+            }
+        });
+
+        Ok(())
+    }
+
+    // Number of dirty blocks in the cache
+    pub fn dirty_blocks(&self) -> usize {
+        self.inner.lock(|inner| {
+            inner
+                .entries
+                .iter()
+                .filter(|entry| entry.valid && entry.dirty)
+                .count()
+        })
+    }
+
+    fn check(&self, block: u64, len: usize) -> Result<(), BlockError> {
+        if self.device.block_size() != BLOCK_SIZE {
+            return Err(BlockError::BlockSizeMismatch);
+        }
+
+        check_range(block, len, BLOCK_SIZE, self.device.block_count())
+            .map(|_| ())
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl<const N: usize, const BLOCK_SIZE: usize> BlockDevice
+    for BufferCache<'_, N, BLOCK_SIZE>
+{
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_blocks(
+        &self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), BlockError> {
+        self.check(block, buffer.len())?;
+
+        self.inner
+            .lock(|inner| inner.read(self.device, block, buffer))
+    }
+
+    fn write_blocks(
+        &self,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), BlockError> {
+        self.check(block, buffer.len())?;
+
+        self.inner
+            .lock(|inner| inner.write(self.device, block, buffer))
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.sync()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<const N: usize, const BLOCK_SIZE: usize> CacheInner<N, BLOCK_SIZE> {
+    const fn new() -> Self {
+        let unused = Entry {
+            block: 0,
+            valid: false,
+            dirty: false,
+            last_used: 0,
+        };
+
+        Self {
+            entries: [unused; N],
+            data: [[0; BLOCK_SIZE]; N],
+            merge_buffer: [[0; BLOCK_SIZE]; MAX_MERGE],
+            clock: 0,
+        }
+    }
+
+    fn read(
+        &mut self,
+        device: &dyn BlockDevice,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), BlockError> {
+        let blocks = buffer.len() / BLOCK_SIZE;
+        let mut i = 0;
+
+        while i < blocks {
+            if let Some(index) = self.lookup(block + i as u64) {
+                buffer[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE]
+                    .copy_from_slice(&self.data[index]);
+                i += 1;
+                continue;
+            }
+
+            // read the run of missing blocks at once, straight into the buffer
+            let mut end = i + 1;
+            while end < blocks && self.find(block + end as u64).is_none() {
+                end += 1;
+            }
+            let run = &mut buffer[i * BLOCK_SIZE..end * BLOCK_SIZE];
+            device.read_blocks(block + i as u64, run)?;
+
+            for (j, bytes) in run.chunks(BLOCK_SIZE).enumerate() {
+                let index = self.allocate(device, block + (i + j) as u64)?;
+                self.data[index].copy_from_slice(bytes);
+            }
+            i = end;
+        }
+
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        device: &dyn BlockDevice,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), BlockError> {
+        // a write of a cache full would only push everything else out
+        if buffer.len() >= N * BLOCK_SIZE {
+            device.write_blocks(block, buffer)?;
+
+            for (i, bytes) in buffer.chunks(BLOCK_SIZE).enumerate() {
+                if let Some(index) = self.find(block + i as u64) {
+                    self.data[index].copy_from_slice(bytes);
+                    self.entries[index].dirty = false;
+                }
+            }
+
+            return Ok(());
+        }
+
+        for (i, bytes) in buffer.chunks(BLOCK_SIZE).enumerate() {
+            let block = block + i as u64;
+            let index = match self.lookup(block) {
+                Some(index) => index,
+                None => self.allocate(device, block)?,
+            };
+
+            self.data[index].copy_from_slice(bytes);
+            self.entries[index].dirty = true;
+        }
+
+        Ok(())
+    }
+
+    // Write back the dirty blocks in order of their block number
+    fn sync(&mut self, device: &dyn BlockDevice) -> Result<(), BlockError> {
+        while let Some(index) = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.valid && entry.dirty)
+            .min_by_key(|(_, entry)| entry.block)
+            .map(|(i, _)| i)
+        {
+            self.write_back(device, index)?;
+        }
+
+        Ok(())
+    }
+
+    // Write the dirty block in `index` back, along with the dirty blocks that follow it
+    fn write_back(
+        &mut self,
+        device: &dyn BlockDevice,
+        index: usize,
+    ) -> Result<(), BlockError> {
+        let start = self.entries[index].block;
+        let mut run = [0; MAX_MERGE];
+        let mut len = 0;
+
+        while len < MAX_MERGE {
+            match self.find(start + len as u64) {
+                Some(i) if self.entries[i].dirty => {
+                    self.merge_buffer[len].copy_from_slice(&self.data[i]);
+                    run[len] = i;
+                    len += 1;
+                }
+                _ => break,
+            }
+        }
+
+        device.write_blocks(start, self.merge_buffer[..len].as_flattened())?;
+        for i in &run[..len] {
+            self.entries[*i].dirty = false;
+        }
+
+        Ok(())
+    }
+
+    fn tick(&mut self) -> u32 {
+        self.clock = self.clock.wrapping_add(1);
+        self.clock
+    }
+
+    fn find(&self, block: u64) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.valid && entry.block == block)
+    }
+
+    // Find a block and mark it as used
+    fn lookup(&mut self, block: u64) -> Option<usize> {
+        let index = self.find(block)?;
+        self.entries[index].last_used = self.tick();
+
+        Some(index)
+    }
+
+    // Make room for `block`, writing the least recently used block back if it is dirty
+    fn allocate(
+        &mut self,
+        device: &dyn BlockDevice,
+        block: u64,
+    ) -> Result<usize, BlockError> {
+        let index = match self.entries.iter().position(|entry| !entry.valid) {
+            Some(free) => free,
+            None => self
+                .entries
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, entry)| {
+                    self.clock.wrapping_sub(entry.last_used)
+                })
+                .map_or(0, |(i, _)| i),
+        };
+
+        if self.entries[index].valid && self.entries[index].dirty {
+            self.write_back(device, index)?;
+        }
+
+        self.entries[index] = Entry {
+            block,
+            valid: true,
+            dirty: false,
+            last_used: self.tick(),
+        };
+
+        Ok(index)
+    }
+}
@@ -0,0 +1,607 @@
+// MBR and GPT partition tables
+//
+// A disk with a protective MBR entry is read as GPT, any other MBR as MBR with its logical
+// partitions. Every partition becomes a block device of its own, named by its type and its index
+// in the table, e.g. `fat32-1` or `linux-2`. Logical partitions count from 5 as on Linux.
+//
+// https://en.wikipedia.org/wiki/Master_boot_record
+// https://uefi.org/specs/UEFI/2.10/05_GUID_Partition_Table_Format.html
+
+use super::{check_range, interface::BlockDevice, BlockError};
+use crate::checksum::Crc32;
+use core::fmt::{self, Write};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Largest block size the scanner reads
+const MAX_BLOCK_SIZE: usize = 4096;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+
+// MBR partition types
+const MBR_EMPTY: u8 = 0x00;
+const MBR_GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+// Index of the first logical partition
+const FIRST_LOGICAL_INDEX: u32 = 5;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+
+// Partition type GUIDs
+const GUID_UNUSED: [u8; 16] = [0; 16];
+const GUID_EFI_SYSTEM: [u8; 16] = guid(
+    0xC12A_7328,
+    0xF81F,
+    0x11D2,
+    [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B],
+);
+const GUID_BASIC_DATA: [u8; 16] = guid(
+    0xEBD0_A0A2,
+    0xB9E5,
+    0x4433,
+    [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7],
+);
+const GUID_LINUX_FILESYSTEM: [u8; 16] = guid(
+    0x0FC6_3DAF,
+    0x8483,
+    0x4772,
+    [0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4],
+);
+const GUID_LINUX_SWAP: [u8; 16] = guid(
+    0x0657_FD6D,
+    0xA4AB,
+    0x43C4,
+    [0x84, 0xE5, 0x09, 0x33, 0xC8, 0x4B, 0x4F, 0x4F],
+);
+
+// Longest partition name, "unknown-128". Longer names are cut, e.g. of GPT entry 1000 and up.
+const MAX_NAME_LEN: usize = 12;
+
+// An MBR or EBR entry
+#[derive(Clone, Copy)]
+struct MbrEntry {
+    kind: u8,
+    start: u64,
+    blocks: u64,
+}
+
+// The fields of a GPT header the scanner uses
+struct GptHeader {
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const MAX_PARTITIONS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Mbr,
+    Gpt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    Fat12,
+    Fat16,
+    Fat32,
+    // GPT basic data, FAT or NTFS
+    BasicData,
+    EfiSystem,
+    Linux,
+    LinuxSwap,
+    Unknown,
+}
+
+#[derive(Clone, Copy)]
+pub struct PartitionName {
+    bytes: [u8; MAX_NAME_LEN],
+    len: usize,
+}
+
+// A partition as a block device
+#[derive(Clone, Copy)]
+pub struct Partition<'a> {
+    device: &'a (dyn BlockDevice + Sync),
+    start: u64,
+    blocks: u64,
+    kind: PartitionKind,
+    index: u32,
+    name: PartitionName,
+}
+
+pub struct PartitionTable<'a> {
+    scheme: Scheme,
+    partitions: [Option<Partition<'a>>; MAX_PARTITIONS],
+    count: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PartitionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PartitionKind::Fat12 => "fat12",
+            PartitionKind::Fat16 => "fat16",
+            PartitionKind::Fat32 => "fat32",
+            PartitionKind::BasicData => "data",
+            PartitionKind::EfiSystem => "efi",
+            PartitionKind::Linux => "linux",
+            PartitionKind::LinuxSwap => "swap",
+            PartitionKind::Unknown => "unknown",
+        }
+    }
+
+    // May hold a FAT file system
+    pub fn is_fat(&self) -> bool {
+        matches!(
+            self,
+            PartitionKind::Fat12
+                | PartitionKind::Fat16
+                | PartitionKind::Fat32
+                | PartitionKind::BasicData
+                | PartitionKind::EfiSystem
+        )
+    }
+}
+
+impl PartitionName {
+    pub fn as_str(&self) -> &str {
+        // only ASCII is written
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Display for PartitionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'a> Partition<'a> {
+    // The device the partition is on
+    pub fn device(&self) -> &'a (dyn BlockDevice + Sync) {
+        self.device
+    }
+
+    // First block on the device
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn kind(&self) -> PartitionKind {
+        self.kind
+    }
+
+    // Index in the partition table, from 1
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+}
+
+impl fmt::Display for Partition<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: blocks {}..{}, {} MiB",
+            self.name,
+            self.start,
+            self.start + self.blocks,
+            self.size() / (1024 * 1024)
+        )
+    }
+}
+
+impl<'a> PartitionTable<'a> {
+    // Read the partition table of `device`
+    pub fn scan(
+        device: &'a (dyn BlockDevice + Sync),
+    ) -> Result<Self, &'static str> {
+        let block_size = device.block_size();
+        if !(512..=MAX_BLOCK_SIZE).contains(&block_size)
+            || !block_size.is_power_of_two()
+        {
+            return Err("Partition table: unsupported block size");
+        }
+
+        let mut buffer = [0; MAX_BLOCK_SIZE];
+        let block = &mut buffer[..block_size];
+        device.read_blocks(0, block)?;
+
+        if block[510..512] != MBR_SIGNATURE {
+            return Err("MBR: no partition table");
+        }
+
+        let entries = [0, 1, 2, 3].map(|i| mbr_entry(block, i));
+        let mut table = Self {
+            scheme: Scheme::Mbr,
+            partitions: [None; MAX_PARTITIONS],
+            count: 0,
+        };
+
+        if entries.iter().any(|entry| entry.kind == MBR_GPT_PROTECTIVE) {
+            table.scheme = Scheme::Gpt;
+            table.scan_gpt(device)?;
+        } else {
+            table.scan_mbr(device, &entries)?;
+        }
+
+        Ok(table)
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Partition<'a>> {
+        self.partitions[..self.count].iter().flatten()
+    }
+
+    // The partition named `name`, e.g. `fat32-1`
+    pub fn find(&self, name: &str) -> Option<Partition<'a>> {
+        self.iter()
+            .find(|partition| partition.name() == name)
+            .copied()
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl BlockDevice for Partition<'_> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(
+        &self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), BlockError> {
+        check_range(block, buffer.len(), self.block_size(), self.blocks)?;
+
+        self.device.read_blocks(self.start + block, buffer)
+    }
+
+    fn write_blocks(
+        &self,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), BlockError> {
+        check_range(block, buffer.len(), self.block_size(), self.blocks)?;
+
+        self.device.write_blocks(self.start + block, buffer)
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.device.flush()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a> PartitionTable<'a> {
+    fn scan_mbr(
+        &mut self,
+        device: &'a (dyn BlockDevice + Sync),
+        entries: &[MbrEntry; 4],
+    ) -> Result<(), &'static str> {
+        let mut extended = None;
+
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.kind == MBR_EMPTY || entry.blocks == 0 {
+                continue;
+            }
+
+            if MBR_EXTENDED.contains(&entry.kind) {
+                extended = Some(entry.start);
+            } else {
+                self.push(
+                    device,
+                    i as u32 + 1,
+                    mbr_kind(entry.kind),
+                    entry.start,
+                    entry.blocks,
+                )?;
+            }
+        }
+
+        match extended {
+            Some(start) => self.scan_extended(device, start),
+            None => Ok(()),
+        }
+    }
+
+    // Follow the chain of EBRs in the extended partition at `extended_start`
+    fn scan_extended(
+        &mut self,
+        device: &'a (dyn BlockDevice + Sync),
+        extended_start: u64,
+    ) -> Result<(), &'static str> {
+        let mut buffer = [0; MAX_BLOCK_SIZE];
+        let block = &mut buffer[..device.block_size()];
+        let mut ebr = extended_start;
+
+        // a chain running in a circle ends at the partition limit
+        for index in
+            FIRST_LOGICAL_INDEX..FIRST_LOGICAL_INDEX + MAX_PARTITIONS as u32
+        {
+            if ebr >= device.block_count() {
+                return Err(
+                    "MBR: extended partition past the end of the device",
+                );
+            }
+            device.read_blocks(ebr, block)?;
+            if block[510..512] != MBR_SIGNATURE {
+                return Err("MBR: bad extended boot record");
+            }
+
+            // the partition is relative to its EBR, the next EBR to the extended partition
+            let partition = mbr_entry(block, 0);
+            let next = mbr_entry(block, 1);
+
+            if partition.kind != MBR_EMPTY && partition.blocks != 0 {
+                self.push(
+                    device,
+                    index,
+                    mbr_kind(partition.kind),
+                    ebr + partition.start,
+                    partition.blocks,
+                )?;
+            }
+
+            if next.blocks == 0 || !MBR_EXTENDED.contains(&next.kind) {
+                return Ok(());
+            }
+            ebr = extended_start + next.start;
+        }
+
+        Err("MBR: too many logical partitions")
+    }
+
+    fn scan_gpt(
+        &mut self,
+        device: &'a (dyn BlockDevice + Sync),
+    ) -> Result<(), &'static str> {
+        let mut buffer = [0; MAX_BLOCK_SIZE];
+        let block = &mut buffer[..device.block_size()];
+
+        // the backup header is in the last block
+        let header = match gpt_header(device, 1, block) {
+            Ok(header) => header,
+            Err(e) => gpt_header(
+                device,
+                device.block_count().saturating_sub(1),
+                block,
+            )
+            .map_err(|_| e)?,
+        };
+
+        let entries_per_block = block.len() / header.entry_size;
+        let mut crc = Crc32::new();
+        let mut remaining = header.entry_count;
+        let mut lba = header.entries_lba;
+
+        while remaining > 0 {
+            device.read_blocks(lba, block)?;
+
+            let count = remaining.min(entries_per_block);
+            crc.update(&block[..count * header.entry_size]);
+
+            for (i, entry) in
+                block.chunks(header.entry_size).take(count).enumerate()
+            {
+                let type_guid: [u8; 16] = entry[0..16].try_into().unwrap();
+                if type_guid == GUID_UNUSED {
+                    continue;
+                }
+
+                let first =
+                    u64::from_le_bytes(entry[32..40].try_into().unwrap());
+                let last =
+                    u64::from_le_bytes(entry[40..48].try_into().unwrap());
+                if last < first {
+                    return Err("GPT: bad partition entry");
+                }
+
+                let index = header.entry_count - remaining + i + 1;
+                self.push(
+                    device,
+                    index as u32,
+                    gpt_kind(&type_guid),
+                    first,
+                    last - first + 1,
+                )?;
+            }
+
+            remaining -= count;
+            lba += 1;
+        }
+
+        if crc.finish() != header.entries_crc {
+            return Err("GPT: bad partition entry CRC");
+        }
+
+        Ok(())
+    }
+
+    fn push(
+        &mut self,
+        device: &'a (dyn BlockDevice + Sync),
+        index: u32,
+        kind: PartitionKind,
+        start: u64,
+        blocks: u64,
+    ) -> Result<(), &'static str> {
+        if start.saturating_add(blocks) > device.block_count() {
+            return Err(
+                "Partition table: partition past the end of the device",
+            );
+        }
+        if self.count == MAX_PARTITIONS {
+            return Err("Partition table: too many partitions");
+        }
+
+        let mut name = PartitionName {
+            bytes: [0; MAX_NAME_LEN],
+            len: 0,
+        };
+        let _ = write!(name, "{}-{}", kind.name(), index);
+
+        self.partitions[self.count] = Some(Partition {
+            device,
+            start,
+            blocks,
+            kind,
+            index,
+            name,
+        });
+        self.count += 1;
+
+        Ok(())
+    }
+}
+
+// Keeps what fits, the names are ASCII
+impl Write for PartitionName {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(MAX_NAME_LEN - self.len);
+
+        self.bytes[self.len..self.len + len]
+            .copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        Ok(())
+    }
+}
+
+// GUID in its on-disk byte order, the first three fields are little endian
+const fn guid(a: u32, b: u16, c: u16, d: [u8; 8]) -> [u8; 16] {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3],
+        d[4], d[5], d[6], d[7],
+    ]
+}
+
+fn mbr_entry(block: &[u8], i: usize) -> MbrEntry {
+    let entry =
+        &block[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+
+    MbrEntry {
+        kind: entry[4],
+        start: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64,
+        blocks: u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64,
+    }
+}
+
+fn mbr_kind(kind: u8) -> PartitionKind {
+    match kind {
+        0x01 => PartitionKind::Fat12,
+        0x04 | 0x06 | 0x0E => PartitionKind::Fat16,
+        0x0B | 0x0C => PartitionKind::Fat32,
+        0x82 => PartitionKind::LinuxSwap,
+        0x83 => PartitionKind::Linux,
+        0xEF => PartitionKind::EfiSystem,
+        _ => PartitionKind::Unknown,
+    }
+}
+
+fn gpt_kind(type_guid: &[u8; 16]) -> PartitionKind {
+    match *type_guid {
+        GUID_EFI_SYSTEM => PartitionKind::EfiSystem,
+        GUID_BASIC_DATA => PartitionKind::BasicData,
+        GUID_LINUX_FILESYSTEM => PartitionKind::Linux,
+        GUID_LINUX_SWAP => PartitionKind::LinuxSwap,
+        _ => PartitionKind::Unknown,
+    }
+}
+
+// Read and check the GPT header in `lba`
+fn gpt_header(
+    device: &dyn BlockDevice,
+    lba: u64,
+    block: &mut [u8],
+) -> Result<GptHeader, &'static str> {
+    device.read_blocks(lba, block)?;
+
+    if &block[0..8] != GPT_SIGNATURE {
+        return Err("GPT: no header");
+    }
+
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+    };
+    let u64_at = |offset: usize| {
+        u64::from_le_bytes(block[offset..offset + 8].try_into().unwrap())
+    };
+
+    let header_size = u32_at(12) as usize;
+    if !(GPT_HEADER_MIN_SIZE..=block.len()).contains(&header_size) {
+        return Err("GPT: bad header size");
+    }
+
+    // the CRC is computed with its own field zeroed
+    let mut crc = Crc32::new();
+    crc.update(&block[0..16]);
+    crc.update(&[0; 4]);
+    crc.update(&block[20..header_size]);
+    if crc.finish() != u32_at(16) {
+        return Err("GPT: bad header CRC");
+    }
+
+    if u64_at(24) != lba {
+        return Err("GPT: header in the wrong block");
+    }
+
+    let entry_size = u32_at(84) as usize;
+    if entry_size < GPT_MIN_ENTRY_SIZE
+        || !entry_size.is_power_of_two()
+        || entry_size > block.len()
+    {
+        return Err("GPT: bad partition entry size");
+    }
+
+    Ok(GptHeader {
+        entries_lba: u64_at(72),
+        entry_count: u32_at(80) as usize,
+        entry_size,
+        entries_crc: u32_at(88),
+    })
+}
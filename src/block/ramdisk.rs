@@ -0,0 +1,98 @@
+// A block device in memory
+//
+// The disk is a plain array, so that a static RAM disk starts out zeroed in .bss.
+
+use super::{check_range, interface::BlockDevice, BlockError};
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// `BLOCKS` blocks of `BLOCK_SIZE` bytes
+pub struct RamDisk<const BLOCKS: usize, const BLOCK_SIZE: usize> {
+    blocks: IRQSafeNullLock<[[u8; BLOCK_SIZE]; BLOCKS]>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<const BLOCKS: usize, const BLOCK_SIZE: usize> RamDisk<BLOCKS, BLOCK_SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            blocks: IRQSafeNullLock::new([[0; BLOCK_SIZE]; BLOCKS]),
+        }
+    }
+
+    // Copy an image to the disk from byte `offset` on
+    pub fn load(&self, offset: usize, image: &[u8]) -> Result<(), BlockError> {
+        if offset.saturating_add(image.len()) > BLOCKS * BLOCK_SIZE {
+            return Err(BlockError::OutOfRange);
+        }
+
+        self.blocks.lock(|blocks| {
+            blocks.as_flattened_mut()[offset..offset + image.len()]
+                .copy_from_slice(image)
+        });
+
+        Ok(())
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl<const BLOCKS: usize, const BLOCK_SIZE: usize> BlockDevice
+    for RamDisk<BLOCKS, BLOCK_SIZE>
+{
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        BLOCKS as u64
+    }
+
+    fn read_blocks(
+        &self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), BlockError> {
+        check_range(block, buffer.len(), BLOCK_SIZE, BLOCKS as u64)?;
+
+        let start = block as usize * BLOCK_SIZE;
+        self.blocks.lock(|blocks| {
+            buffer.copy_from_slice(
+                &blocks.as_flattened()[start..start + buffer.len()],
+            )
+        });
+
+        Ok(())
+    }
+
+    fn write_blocks(
+        &self,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), BlockError> {
+        check_range(block, buffer.len(), BLOCK_SIZE, BLOCKS as u64)?;
+
+        let start = block as usize * BLOCK_SIZE;
+        self.blocks.lock(|blocks| {
+            blocks.as_flattened_mut()[start..start + buffer.len()]
+                .copy_from_slice(buffer)
+        });
+
+        Ok(())
+    }
+}
+
+impl<const BLOCKS: usize, const BLOCK_SIZE: usize> Default
+    for RamDisk<BLOCKS, BLOCK_SIZE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,9 +1,19 @@
 #[cfg(feature = "bsp_rpi3")]
 mod bcm2xx_interrupt_controller;
+mod bcm2xxx_dwc2;
 mod bcm2xxx_gpio;
+mod bcm2xxx_i2c;
 mod bcm2xxx_pl011_uart;
+mod bcm2xxx_pwm;
+mod bcm2xxx_sdhci;
+mod bcm2xxx_spi;
 
 #[cfg(feature = "bsp_rpi3")]
 pub use bcm2xx_interrupt_controller::*;
+pub use bcm2xxx_dwc2::*;
 pub use bcm2xxx_gpio::*;
+pub use bcm2xxx_i2c::*;
 pub use bcm2xxx_pl011_uart::*;
+pub use bcm2xxx_pwm::*;
+pub use bcm2xxx_sdhci::*;
+pub use bcm2xxx_spi::*;
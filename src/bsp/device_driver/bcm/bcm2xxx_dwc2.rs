@@ -0,0 +1,959 @@
+// USB host controller driver for the Synopsys DesignWare USB 2.0 OTG core (DWC2)
+//
+// The core drives the USB ports of the Pi 3, all behind the hub of its LAN9514 on the root port.
+// On the Pi 4 it serves the USB-C port only. It is used in host mode with its DMA engine and
+// without interrupts: a transfer takes a free channel, which moves the packets from and to a
+// bounce buffer of its own, and polls the channel until it halts.
+//
+// Full- and low-speed devices behind a high-speed hub are reached by split transactions, one
+// packet at a time: the start split hands the packet to the transaction translator of the hub,
+// the complete split fetches the result.
+//
+// https://github.com/raspberrypi/linux/tree/rpi-6.6.y/drivers/usb/host/dwc_otg (no public data
+// sheet exists, the register descriptions follow the Linux driver)
+
+use crate::{
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    clock, cpu, driver,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+    usb::{
+        self, Direction, Endpoint, SetupPacket, Speed, TransferType, UsbError,
+    },
+};
+use core::time::Duration;
+use tock_registers::{
+    fields::FieldValue,
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{InMemoryRegister, ReadOnly, ReadWrite},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// DWC2 registers
+register_bitfields! {
+    u32,
+
+    // AHB configuration
+    GAHBCFG [
+        DMA_EN OFFSET(5) NUMBITS(1) [],
+        GLBL_INTR_EN OFFSET(0) NUMBITS(1) []
+    ],
+
+    // USB configuration
+    GUSBCFG [
+        FORCE_DEVICE_MODE OFFSET(30) NUMBITS(1) [],
+        FORCE_HOST_MODE OFFSET(29) NUMBITS(1) [],
+        TERM_SEL_DL_PULSE OFFSET(22) NUMBITS(1) [],
+        ULPI_EXT_VBUS_DRV OFFSET(20) NUMBITS(1) [],
+        HNP_CAP OFFSET(9) NUMBITS(1) [],
+        SRP_CAP OFFSET(8) NUMBITS(1) [],
+
+        // UTMI+ PHY if clear
+        ULPI_UTMI_SEL OFFSET(4) NUMBITS(1) [],
+
+        // 8-bit PHY interface if clear
+        PHYIF OFFSET(3) NUMBITS(1) []
+    ],
+
+    // Reset
+    GRSTCTL [
+        // The AHB master is idle
+        AHB_IDLE OFFSET(31) NUMBITS(1) [],
+
+        // Transmit FIFO to flush
+        TX_FNUM OFFSET(6) NUMBITS(5) [
+            All = 0x10
+        ],
+
+        TX_FIFO_FLUSH OFFSET(5) NUMBITS(1) [],
+        RX_FIFO_FLUSH OFFSET(4) NUMBITS(1) [],
+        CORE_SOFT_RESET OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Hardware configuration 2
+    GHWCFG2 [
+        // Host channels, minus one
+        NUM_HOST_CHAN OFFSET(14) NUMBITS(4) []
+    ],
+
+    // Host configuration
+    HCFG [
+        // Clock of the full- and low-speed PHY
+        FSLS_PCLK_SEL OFFSET(0) NUMBITS(2) [
+            Clock30_60MHz = 0
+        ]
+    ],
+
+    // Host frame number
+    HFNUM [
+        // Frames at full speed, microframes at high speed
+        FRNUM OFFSET(0) NUMBITS(16) []
+    ],
+
+    // Host port control and status, the change bits are cleared by writing 1
+    HPRT [
+        PRT_SPD OFFSET(17) NUMBITS(2) [
+            High = 0,
+            Full = 1,
+            Low = 2
+        ],
+
+        PRT_PWR OFFSET(12) NUMBITS(1) [],
+        PRT_RST OFFSET(8) NUMBITS(1) [],
+        PRT_OVRCURR_CHNG OFFSET(5) NUMBITS(1) [],
+        PRT_ENCHNG OFFSET(3) NUMBITS(1) [],
+
+        // Enabled after a reset, disabled by writing 1
+        PRT_ENA OFFSET(2) NUMBITS(1) [],
+
+        PRT_CONN_DET OFFSET(1) NUMBITS(1) [],
+        PRT_CONN_STS OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Channel characteristics
+    HCCHAR [
+        CH_ENA OFFSET(31) NUMBITS(1) [],
+        CH_DIS OFFSET(30) NUMBITS(1) [],
+
+        // Run a periodic transfer in an odd frame
+        ODD_FRM OFFSET(29) NUMBITS(1) [],
+
+        DEV_ADDR OFFSET(22) NUMBITS(7) [],
+
+        // Transactions per microframe
+        MC OFFSET(20) NUMBITS(2) [],
+
+        EP_TYPE OFFSET(18) NUMBITS(2) [
+            Control = 0,
+            Isochronous = 1,
+            Bulk = 2,
+            Interrupt = 3
+        ],
+
+        LSPD_DEV OFFSET(17) NUMBITS(1) [],
+
+        EP_DIR OFFSET(15) NUMBITS(1) [
+            Out = 0,
+            In = 1
+        ],
+
+        EP_NUM OFFSET(11) NUMBITS(4) [],
+        MPS OFFSET(0) NUMBITS(11) []
+    ],
+
+    // Channel split control
+    HCSPLT [
+        SPLT_ENA OFFSET(31) NUMBITS(1) [],
+
+        // Complete split, start split if clear
+        COMP_SPLT OFFSET(16) NUMBITS(1) [],
+
+        XACT_POS OFFSET(14) NUMBITS(2) [
+            All = 3
+        ],
+
+        HUB_ADDR OFFSET(7) NUMBITS(7) [],
+        PRT_ADDR OFFSET(0) NUMBITS(7) []
+    ],
+
+    // Channel interrupts, cleared by writing 1
+    HCINT [
+        DATA_TGL_ERR OFFSET(10) NUMBITS(1) [],
+        FRM_OVRUN OFFSET(9) NUMBITS(1) [],
+        BBL_ERR OFFSET(8) NUMBITS(1) [],
+        XACT_ERR OFFSET(7) NUMBITS(1) [],
+        NYET OFFSET(6) NUMBITS(1) [],
+        ACK OFFSET(5) NUMBITS(1) [],
+        NAK OFFSET(4) NUMBITS(1) [],
+        STALL OFFSET(3) NUMBITS(1) [],
+        AHB_ERR OFFSET(2) NUMBITS(1) [],
+        CH_HLTD OFFSET(1) NUMBITS(1) [],
+        XFER_COMPL OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Channel transfer size
+    HCTSIZ [
+        // The PID of the next packet, updated by the core
+        PID OFFSET(29) NUMBITS(2) [
+            Data0 = 0,
+            Data2 = 1,
+            Data1 = 2,
+            Setup = 3
+        ],
+
+        // Packets left
+        PKT_CNT OFFSET(19) NUMBITS(10) [],
+
+        // Bytes left
+        XFER_SIZE OFFSET(0) NUMBITS(19) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    ChannelRegisterBlock {
+        (0x00 => HCCHAR: ReadWrite<u32, HCCHAR::Register>),
+        (0x04 => HCSPLT: ReadWrite<u32, HCSPLT::Register>),
+        (0x08 => HCINT: ReadWrite<u32, HCINT::Register>),
+        (0x0C => HCINTMSK: ReadWrite<u32, HCINT::Register>),
+        (0x10 => HCTSIZ: ReadWrite<u32, HCTSIZ::Register>),
+        (0x14 => HCDMA: ReadWrite<u32>),
+        (0x18 => _reserved1),
+        (0x20 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => _reserved1),
+        (0x008 => GAHBCFG: ReadWrite<u32, GAHBCFG::Register>),
+        (0x00C => GUSBCFG: ReadWrite<u32, GUSBCFG::Register>),
+        (0x010 => GRSTCTL: ReadWrite<u32, GRSTCTL::Register>),
+        (0x014 => _reserved2),
+        (0x024 => GRXFSIZ: ReadWrite<u32>),
+        (0x028 => GNPTXFSIZ: ReadWrite<u32>),
+        (0x02C => _reserved3),
+        (0x040 => GSNPSID: ReadOnly<u32>),
+        (0x044 => _reserved4),
+        (0x048 => GHWCFG2: ReadOnly<u32, GHWCFG2::Register>),
+        (0x04C => _reserved5),
+        (0x100 => HPTXFSIZ: ReadWrite<u32>),
+        (0x104 => _reserved6),
+        (0x400 => HCFG: ReadWrite<u32, HCFG::Register>),
+        (0x404 => _reserved7),
+        (0x408 => HFNUM: ReadOnly<u32, HFNUM::Register>),
+        (0x40C => _reserved8),
+        (0x440 => HPRT: ReadWrite<u32, HPRT::Register>),
+        (0x444 => _reserved9),
+        (0x500 => HC: [ChannelRegisterBlock; MAX_CHANNELS]),
+        (0x700 => _reserved10),
+        (0xE00 => PCGCCTL: ReadWrite<u32>),
+        (0xE04 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+// Core IDs are "OT" and the version
+const SNPSID_MASK: u32 = 0xFFFF_F000;
+const SNPSID_DWC2: u32 = 0x4F54_2000;
+
+const MAX_CHANNELS: usize = 16;
+
+// FIFO sizes in 32-bit words, the FIFOs share 4096 words
+const RX_FIFO_SIZE: u32 = 1024;
+const NON_PERIODIC_TX_FIFO_SIZE: u32 = 1024;
+const PERIODIC_TX_FIFO_SIZE: u32 = 1024;
+
+// Uncached alias of memory as seen by the DMA engine
+const BUS_ALIAS: u32 = 0xC000_0000;
+
+// Largest piece of a transfer a channel moves at once
+const DMA_BUFFER_SIZE: usize = 4096;
+
+const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+// A mode forced in GUSBCFG takes effect after 25 ms
+const FORCE_MODE_DELAY: Duration = Duration::from_millis(25);
+const HALT_TIMEOUT: Duration = Duration::from_millis(10);
+
+// A device must show up within 510 ms after the port has been powered and be stable for 100 ms
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(510);
+const DEBOUNCE: Duration = Duration::from_millis(100);
+const PORT_RESET: Duration = Duration::from_millis(50);
+const PORT_RESET_RECOVERY: Duration = Duration::from_millis(20);
+
+// Longest time a device may answer NAK before a transfer fails
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(1);
+// Longest time a hub may answer NYET to complete splits before the start split is repeated
+const SPLIT_TIMEOUT: Duration = Duration::from_millis(20);
+
+// Transaction errors in a row before a transfer fails
+const MAX_ERRORS: usize = 3;
+
+// HPRT bits cleared by writing 1, written as 0 when changing others
+const HPRT_WRITE_CLEAR: u32 = 0b10_1110;
+
+// Bounce buffers of the channels, on cache lines of their own
+#[repr(align(64))]
+struct DmaBuffers([[u8; DMA_BUFFER_SIZE]; MAX_CHANNELS]);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pid {
+    Data0,
+    Data1,
+    Setup,
+}
+
+// How a transaction was answered
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Handshake {
+    // all packets have been moved
+    Complete,
+    // the start split was taken by the hub
+    Ack,
+    Nak,
+    // the hub has no result for the complete split yet
+    Nyet,
+}
+
+// What a halted channel reports
+struct Halt {
+    interrupts: InMemoryRegister<u32, HCINT::Register>,
+    // bytes moved
+    len: usize,
+    // the PID of the next packet was DATA1
+    toggle: bool,
+}
+
+struct DWC2Inner {
+    registers: Registers,
+    channels: usize,
+    // bit n is set while channel n runs a transfer
+    busy: u16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Representation of the USB host controller
+pub struct DWC2 {
+    inner: IRQSafeNullLock<DWC2Inner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+// Each channel only touches its own buffer while it is busy
+static mut DMA_BUFFERS: DmaBuffers =
+    DmaBuffers([[0; DMA_BUFFER_SIZE]; MAX_CHANNELS]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DWC2 {
+    pub const COMPATIBLE: &'static str = "DWC2 USB host";
+
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(DWC2Inner::new(mmio_start_addr)),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl driver::interface::DeviceDriver for DWC2 {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        use bsp::clock::{clock_manager, PowerDomain};
+        use clock::interface::ClockManager;
+
+        clock_manager().set_power(PowerDomain::UsbHcd, true)?;
+        self.inner.lock(|inner| inner.reset())
+    }
+}
+
+impl usb::interface::HostController for DWC2 {
+    fn reset_root_port(&self) -> Result<Speed, UsbError> {
+        self.inner.lock(|inner| inner.set_port(HPRT::PRT_PWR::SET));
+
+        self.wait_until(CONNECT_TIMEOUT, |inner| {
+            inner.registers.HPRT.is_set(HPRT::PRT_CONN_STS)
+        })
+        .map_err(|_| UsbError::NoDevice)?;
+        time::time_manager().spin_for(DEBOUNCE);
+
+        self.inner.lock(|inner| inner.set_port(HPRT::PRT_RST::SET));
+        time::time_manager().spin_for(PORT_RESET);
+        self.inner
+            .lock(|inner| inner.set_port(HPRT::PRT_RST::CLEAR));
+        time::time_manager().spin_for(PORT_RESET_RECOVERY);
+
+        self.inner.lock(|inner| {
+            let port = &inner.registers.HPRT;
+            if !port.is_set(HPRT::PRT_CONN_STS) || !port.is_set(HPRT::PRT_ENA) {
+                return Err(UsbError::NoDevice);
+            }
+
+            Ok(match port.read_as_enum(HPRT::PRT_SPD) {
+                Some(HPRT::PRT_SPD::Value::Low) => Speed::Low,
+                Some(HPRT::PRT_SPD::Value::Full) => Speed::Full,
+                _ => Speed::High,
+            })
+        })
+    }
+
+    fn control(
+        &self,
+        endpoint: &mut Endpoint,
+        setup: SetupPacket,
+        data: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        self.with_channel(|channel| {
+            let mut packet = setup.to_bytes();
+            self.stage(
+                channel,
+                endpoint,
+                Direction::Out,
+                Some(Pid::Setup),
+                &mut packet,
+                true,
+            )?;
+
+            let len = data.len().min(setup.length as usize);
+            let direction = setup.direction();
+            endpoint.toggle = true;
+            let len = match len {
+                0 => 0,
+                _ => self
+                    .stage(
+                        channel,
+                        endpoint,
+                        direction,
+                        None,
+                        &mut data[..len],
+                        true,
+                    )?
+                    .unwrap_or(0),
+            };
+
+            // the status stage goes the other way, or in when there is no data stage
+            let status = match (len, direction) {
+                (0, _) | (_, Direction::Out) => Direction::In,
+                _ => Direction::Out,
+            };
+            endpoint.toggle = true;
+            self.stage(channel, endpoint, status, None, &mut [], true)?;
+
+            Ok(len)
+        })
+    }
+
+    fn bulk(
+        &self,
+        endpoint: &mut Endpoint,
+        data: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        self.with_channel(|channel| {
+            let direction = endpoint.direction;
+            let len =
+                self.stage(channel, endpoint, direction, None, data, true)?;
+
+            Ok(len.unwrap_or(0))
+        })
+    }
+
+    fn interrupt(
+        &self,
+        endpoint: &mut Endpoint,
+        data: &mut [u8],
+    ) -> Result<Option<usize>, UsbError> {
+        self.with_channel(|channel| {
+            let direction = endpoint.direction;
+            self.stage(channel, endpoint, direction, None, data, false)
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl DWC2 {
+    // Run `f` on a free channel
+    fn with_channel<R>(
+        &self,
+        f: impl FnOnce(usize) -> Result<R, UsbError>,
+    ) -> Result<R, UsbError> {
+        let channel = self.inner.lock(|inner| {
+            let channel = (0..inner.channels)
+                .find(|&channel| inner.busy & (1 << channel) == 0)
+                .ok_or(UsbError::NoChannel)?;
+            inner.busy |= 1 << channel;
+
+            Ok(channel)
+        })?;
+
+        let result = f(channel);
+        self.inner.lock(|inner| inner.busy &= !(1 << channel));
+
+        result
+    }
+
+    // Move `data` in one direction, in pieces that fit the bounce buffer. The PID is the data
+    // toggle of the endpoint unless given. Returns the bytes moved, fewer after a short packet,
+    // or `None` if the device answered NAK and `wait` is false.
+    fn stage(
+        &self,
+        channel: usize,
+        endpoint: &mut Endpoint,
+        direction: Direction,
+        pid: Option<Pid>,
+        data: &mut [u8],
+        wait: bool,
+    ) -> Result<Option<usize>, UsbError> {
+        let max_packet_size = endpoint.max_packet_size.max(1) as usize;
+        // splits move one packet at a time
+        let max_len = match endpoint.translator {
+            Some(_) => max_packet_size,
+            None => DMA_BUFFER_SIZE / max_packet_size * max_packet_size,
+        };
+
+        let deadline = time::time_manager().uptime() + TRANSFER_TIMEOUT;
+        let mut done = 0;
+        let mut errors = 0;
+
+        loop {
+            let len = (data.len() - done).min(max_len);
+            let pid = pid.unwrap_or(match endpoint.toggle {
+                true => Pid::Data1,
+                false => Pid::Data0,
+            });
+
+            let result = match endpoint.translator {
+                Some(_) => self.split(
+                    channel,
+                    endpoint,
+                    direction,
+                    pid,
+                    &mut data[done..done + len],
+                ),
+                None => self.transaction(
+                    channel,
+                    endpoint,
+                    direction,
+                    pid,
+                    &mut data[done..done + len],
+                    false,
+                ),
+            };
+            let halt = result?;
+            done += halt.len;
+            if pid != Pid::Setup {
+                endpoint.toggle = halt.toggle;
+            }
+
+            let handshake = match handshake(&halt.interrupts) {
+                Err(UsbError::TransactionError) if errors < MAX_ERRORS => {
+                    errors += 1;
+                    continue;
+                }
+                handshake => handshake?,
+            };
+            errors = 0;
+
+            match handshake {
+                Handshake::Complete | Handshake::Ack => {
+                    // a short packet ends the transfer
+                    if done == data.len() || halt.len < len {
+                        return Ok(Some(done));
+                    }
+                }
+                Handshake::Nak | Handshake::Nyet => {
+                    if !wait && done == 0 {
+                        return Ok(None);
+                    }
+                    if time::time_manager().uptime() > deadline {
+                        return Err(UsbError::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    // One packet through the transaction translator of a hub. Ends with the complete split unless
+    // the start split was not taken.
+    fn split(
+        &self,
+        channel: usize,
+        endpoint: &Endpoint,
+        direction: Direction,
+        pid: Pid,
+        data: &mut [u8],
+    ) -> Result<Halt, UsbError> {
+        let start =
+            self.transaction(channel, endpoint, direction, pid, data, false)?;
+        if handshake(&start.interrupts) != Ok(Handshake::Ack) {
+            return Ok(start);
+        }
+
+        let deadline = time::time_manager().uptime() + SPLIT_TIMEOUT;
+        loop {
+            let mut complete = self
+                .transaction(channel, endpoint, direction, pid, data, true)?;
+            match handshake(&complete.interrupts) {
+                Ok(Handshake::Nyet)
+                    if time::time_manager().uptime() <= deadline => {}
+                // an OUT packet is acknowledged
+                Ok(Handshake::Ack) if direction == Direction::Out => {
+                    complete.interrupts.modify(HCINT::XFER_COMPL::SET);
+                    complete.len = data.len();
+                    return Ok(complete);
+                }
+                _ => return Ok(complete),
+            }
+        }
+    }
+
+    // Run the channel once and wait until it halts
+    fn transaction(
+        &self,
+        channel: usize,
+        endpoint: &Endpoint,
+        direction: Direction,
+        pid: Pid,
+        data: &mut [u8],
+        complete_split: bool,
+    ) -> Result<Halt, UsbError> {
+        // the channel owns its buffer while it is busy
+        let buffer = unsafe {
+            &mut (&mut *core::ptr::addr_of_mut!(DMA_BUFFERS.0[channel]))
+                [..data.len()]
+        };
+        if direction == Direction::Out {
+            buffer.copy_from_slice(data);
+        }
+        cpu::clean_and_invalidate_dcache(
+            buffer.as_ptr() as usize,
+            buffer.len(),
+        );
+
+        self.inner.lock(|inner| {
+            inner.start(
+                channel,
+                endpoint,
+                direction,
+                pid,
+                buffer,
+                complete_split,
+            )
+        });
+
+        let timeout = match endpoint.transfer_type {
+            // the core retries NAKed bulk and control packets by itself
+            TransferType::Bulk | TransferType::Control => TRANSFER_TIMEOUT,
+            _ => HALT_TIMEOUT,
+        };
+        let halted = self.wait_until(timeout, |inner| {
+            inner.registers.HC[channel].HCINT.is_set(HCINT::CH_HLTD)
+        });
+        let halt = self.inner.lock(|inner| match halted {
+            Ok(()) => Ok(inner.halt(channel, direction, data.len())),
+            Err(()) => {
+                inner.stop(channel);
+                Err(UsbError::Timeout)
+            }
+        })?;
+
+        if direction == Direction::In {
+            cpu::clean_and_invalidate_dcache(
+                buffer.as_ptr() as usize,
+                buffer.len(),
+            );
+            data[..halt.len].copy_from_slice(&buffer[..halt.len]);
+        }
+
+        Ok(halt)
+    }
+
+    // Spin until `condition` holds, `Err` after `timeout`
+    fn wait_until(
+        &self,
+        timeout: Duration,
+        mut condition: impl FnMut(&mut DWC2Inner) -> bool,
+    ) -> Result<(), ()> {
+        let deadline = time::time_manager().uptime() + timeout;
+
+        loop {
+            if self.inner.lock(&mut condition) {
+                return Ok(());
+            }
+            if time::time_manager().uptime() > deadline {
+                return Err(());
+            }
+            cpu::nop();
+        }
+    }
+}
+
+impl DWC2Inner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            channels: 0,
+            busy: 0,
+        }
+    }
+
+    // Reset the core into host mode, its ports unpowered
+    fn reset(&mut self) -> Result<(), &'static str> {
+        if self.registers.GSNPSID.get() & SNPSID_MASK != SNPSID_DWC2 {
+            return Err("DWC2 USB core not found");
+        }
+
+        // transfers are polled
+        self.registers.GAHBCFG.modify(GAHBCFG::GLBL_INTR_EN::CLEAR);
+        self.registers.GUSBCFG.modify(
+            GUSBCFG::ULPI_EXT_VBUS_DRV::CLEAR
+                + GUSBCFG::TERM_SEL_DL_PULSE::CLEAR,
+        );
+
+        let reset = self.wait(RESET_TIMEOUT, |registers| {
+            registers.GRSTCTL.is_set(GRSTCTL::AHB_IDLE)
+        }) && {
+            self.registers.GRSTCTL.write(GRSTCTL::CORE_SOFT_RESET::SET);
+            self.wait(RESET_TIMEOUT, |registers| {
+                !registers.GRSTCTL.is_set(GRSTCTL::CORE_SOFT_RESET)
+                    && registers.GRSTCTL.is_set(GRSTCTL::AHB_IDLE)
+            })
+        };
+        if !reset {
+            return Err("DWC2 USB core reset timeout");
+        }
+        time::time_manager().spin_for(RESET_TIMEOUT);
+
+        // the internal UTMI+ PHY
+        self.registers.GUSBCFG.modify(
+            GUSBCFG::ULPI_UTMI_SEL::CLEAR
+                + GUSBCFG::PHYIF::CLEAR
+                + GUSBCFG::HNP_CAP::CLEAR
+                + GUSBCFG::SRP_CAP::CLEAR
+                + GUSBCFG::FORCE_DEVICE_MODE::CLEAR
+                + GUSBCFG::FORCE_HOST_MODE::SET,
+        );
+        time::time_manager().spin_for(FORCE_MODE_DELAY);
+
+        self.registers.GAHBCFG.modify(GAHBCFG::DMA_EN::SET);
+        // ungate the clocks
+        self.registers.PCGCCTL.set(0);
+        self.registers
+            .HCFG
+            .modify(HCFG::FSLS_PCLK_SEL::Clock30_60MHz);
+
+        // receive FIFO, then the transmit FIFOs, as depth << 16 | start
+        self.registers.GRXFSIZ.set(RX_FIFO_SIZE);
+        self.registers
+            .GNPTXFSIZ
+            .set(NON_PERIODIC_TX_FIFO_SIZE << 16 | RX_FIFO_SIZE);
+        self.registers.HPTXFSIZ.set(
+            PERIODIC_TX_FIFO_SIZE << 16
+                | (RX_FIFO_SIZE + NON_PERIODIC_TX_FIFO_SIZE),
+        );
+        self.flush_fifos()?;
+
+        self.channels =
+            (self.registers.GHWCFG2.read(GHWCFG2::NUM_HOST_CHAN) as usize + 1)
+                .min(MAX_CHANNELS);
+        self.busy = 0;
+        for channel in 0..self.channels {
+            self.stop(channel);
+        }
+
+        Ok(())
+    }
+
+    fn flush_fifos(&self) -> Result<(), &'static str> {
+        self.registers
+            .GRSTCTL
+            .write(GRSTCTL::TX_FIFO_FLUSH::SET + GRSTCTL::TX_FNUM::All);
+        let tx = self.wait(RESET_TIMEOUT, |registers| {
+            !registers.GRSTCTL.is_set(GRSTCTL::TX_FIFO_FLUSH)
+        });
+
+        self.registers.GRSTCTL.write(GRSTCTL::RX_FIFO_FLUSH::SET);
+        let rx = self.wait(RESET_TIMEOUT, |registers| {
+            !registers.GRSTCTL.is_set(GRSTCTL::RX_FIFO_FLUSH)
+        });
+
+        match tx && rx {
+            true => Ok(()),
+            false => Err("DWC2 USB FIFO flush timeout"),
+        }
+    }
+
+    // Change bits of HPRT without clearing its change flags or disabling the port
+    fn set_port(&mut self, field: FieldValue<u32, HPRT::Register>) {
+        let port = InMemoryRegister::<u32, HPRT::Register>::new(
+            self.registers.HPRT.get() & !HPRT_WRITE_CLEAR,
+        );
+        port.modify(field);
+        self.registers.HPRT.set(port.get());
+    }
+
+    // Program a channel for `buffer` and enable it
+    fn start(
+        &mut self,
+        channel: usize,
+        endpoint: &Endpoint,
+        direction: Direction,
+        pid: Pid,
+        buffer: &[u8],
+        complete_split: bool,
+    ) {
+        let registers = &self.registers.HC[channel];
+        let max_packet_size = endpoint.max_packet_size.max(1) as usize;
+        let packets = buffer.len().div_ceil(max_packet_size).max(1);
+
+        let transfer_type = match endpoint.transfer_type {
+            TransferType::Control => HCCHAR::EP_TYPE::Control,
+            TransferType::Isochronous => HCCHAR::EP_TYPE::Isochronous,
+            TransferType::Bulk => HCCHAR::EP_TYPE::Bulk,
+            TransferType::Interrupt => HCCHAR::EP_TYPE::Interrupt,
+        };
+        let characteristics = HCCHAR::DEV_ADDR.val(endpoint.device as u32)
+            + HCCHAR::EP_NUM.val(endpoint.number as u32)
+            + HCCHAR::MPS.val(max_packet_size as u32)
+            + HCCHAR::MC.val(1)
+            + transfer_type
+            + match direction {
+                Direction::Out => HCCHAR::EP_DIR::Out,
+                Direction::In => HCCHAR::EP_DIR::In,
+            }
+            + match endpoint.speed {
+                Speed::Low => HCCHAR::LSPD_DEV::SET,
+                _ => HCCHAR::LSPD_DEV::CLEAR,
+            };
+
+        registers.HCSPLT.set(0);
+        if let Some(translator) = endpoint.translator {
+            registers.HCSPLT.write(
+                HCSPLT::SPLT_ENA::SET
+                    + HCSPLT::XACT_POS::All
+                    + HCSPLT::HUB_ADDR.val(translator.hub as u32)
+                    + HCSPLT::PRT_ADDR.val(translator.port as u32)
+                    + match complete_split {
+                        true => HCSPLT::COMP_SPLT::SET,
+                        false => HCSPLT::COMP_SPLT::CLEAR,
+                    },
+            );
+        }
+
+        registers.HCINT.set(0xFFFF_FFFF);
+        registers.HCINTMSK.set(0);
+        registers.HCTSIZ.write(
+            HCTSIZ::XFER_SIZE.val(buffer.len() as u32)
+                + HCTSIZ::PKT_CNT.val(packets as u32)
+                + match pid {
+                    Pid::Data0 => HCTSIZ::PID::Data0,
+                    Pid::Data1 => HCTSIZ::PID::Data1,
+                    Pid::Setup => HCTSIZ::PID::Setup,
+                },
+        );
+        registers.HCDMA.set(buffer.as_ptr() as u32 | BUS_ALIAS);
+
+        // periodic transfers run in the next frame
+        let odd_frame = match endpoint.transfer_type {
+            TransferType::Interrupt | TransferType::Isochronous => {
+                (self.registers.HFNUM.read(HFNUM::FRNUM) + 1) & 1
+            }
+            _ => 0,
+        };
+        registers.HCCHAR.write(
+            characteristics
+                + HCCHAR::ODD_FRM.val(odd_frame)
+                + HCCHAR::CH_DIS::CLEAR
+                + HCCHAR::CH_ENA::SET,
+        );
+    }
+
+    // What a halted channel has done, `len` bytes were asked for
+    fn halt(&self, channel: usize, direction: Direction, len: usize) -> Halt {
+        let registers = &self.registers.HC[channel];
+        let size = registers.HCTSIZ.extract();
+        let max_packet_size =
+            registers.HCCHAR.read(HCCHAR::MPS).max(1) as usize;
+        let interrupts = InMemoryRegister::new(registers.HCINT.get());
+        registers.HCINT.set(0xFFFF_FFFF);
+
+        let moved = match direction {
+            // the size left counts down by what arrived
+            Direction::In => {
+                len - (size.read(HCTSIZ::XFER_SIZE) as usize).min(len)
+            }
+            // whole packets, the last one may be short
+            Direction::Out if interrupts.is_set(HCINT::XFER_COMPL) => len,
+            Direction::Out => {
+                let packets = len.div_ceil(max_packet_size).max(1);
+                let left = size.read(HCTSIZ::PKT_CNT) as usize;
+                (packets.saturating_sub(left) * max_packet_size).min(len)
+            }
+        };
+
+        Halt {
+            interrupts,
+            len: moved,
+            toggle: size.matches_all(HCTSIZ::PID::Data1),
+        }
+    }
+
+    // Disable a channel and wait until it halts
+    fn stop(&self, channel: usize) {
+        let registers = &self.registers.HC[channel];
+        if registers.HCCHAR.is_set(HCCHAR::CH_ENA) {
+            registers
+                .HCCHAR
+                .modify(HCCHAR::CH_DIS::SET + HCCHAR::CH_ENA::SET);
+            let _ = self.wait(HALT_TIMEOUT, |registers| {
+                !registers.HC[channel].HCCHAR.is_set(HCCHAR::CH_ENA)
+            });
+        }
+        self.registers.HC[channel].HCINT.set(0xFFFF_FFFF);
+    }
+
+    fn wait(
+        &self,
+        timeout: Duration,
+        condition: impl Fn(&Registers) -> bool,
+    ) -> bool {
+        let deadline = time::time_manager().uptime() + timeout;
+
+        while !condition(&self.registers) {
+            if time::time_manager().uptime() > deadline {
+                return false;
+            }
+            cpu::nop();
+        }
+
+        true
+    }
+}
+
+// How the last transaction of a halted channel was answered
+fn handshake(
+    interrupts: &InMemoryRegister<u32, HCINT::Register>,
+) -> Result<Handshake, UsbError> {
+    if interrupts.is_set(HCINT::STALL) {
+        Err(UsbError::Stall)
+    } else if interrupts.is_set(HCINT::AHB_ERR) {
+        Err(UsbError::DmaError)
+    } else if interrupts.is_set(HCINT::BBL_ERR) {
+        Err(UsbError::Babble)
+    } else if interrupts.is_set(HCINT::DATA_TGL_ERR) {
+        Err(UsbError::DataToggleError)
+    } else if interrupts.is_set(HCINT::XFER_COMPL) {
+        Ok(Handshake::Complete)
+    } else if interrupts.is_set(HCINT::NYET) {
+        Ok(Handshake::Nyet)
+    } else if interrupts.is_set(HCINT::NAK) {
+        Ok(Handshake::Nak)
+    } else if interrupts.is_set(HCINT::ACK) {
+        Ok(Handshake::Ack)
+    } else {
+        // a transaction or frame overrun error
+        Err(UsbError::TransactionError)
+    }
+}
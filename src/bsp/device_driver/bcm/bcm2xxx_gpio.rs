@@ -1,14 +1,30 @@
 // GPIO Driver
+//
+// Pins are used through a `Pin` handle, which is claimed from the driver and released when it is
+// dropped. A pin can only be claimed once at a time, so two drivers cannot drive the same pin by
+// accident.
+//
+// A claimed pin can have a handler that is called from the GPIO interrupts. Events closer to
+// the previous one than the debounce time of the pin are dropped, which filters out the bouncing
+// of buttons and switches. To keep a handler for good, `core::mem::forget` its pin.
+//
+// Events of pins without a handler are latched by the interrupt handler until they are polled with
+// `event_detected()` and cleared. A level trigger is turned off when it fires and turned on again
+// once the handler has run or the event has been cleared, so that a lasting level does not keep
+// the CPU in the interrupt handler.
 
 use crate::{
+    bsp,
     bsp::device_driver::common::MMIODerefWrapper,
-    driver,
+    driver, exception, fs,
     synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
 };
+use core::{fmt, time::Duration};
 use tock_registers::{
-    interfaces::{ReadWriteable, Writeable},
+    interfaces::{Readable, Writeable},
     register_bitfields, register_structs,
-    registers::ReadWrite,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
 // Descriptions taken from
@@ -26,24 +42,6 @@ use tock_registers::{
 register_bitfields! {
     u32,
 
-    GPFSEL1 [
-        // Pin 15
-        FSEL15 OFFSET(15) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100
-        ],
-
-
-        // Pin 14
-        FSEL14 OFFSET(12) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100
-        ]
-    ],
-
-
     // BCM2837 Only
     GPPUD [
         PUD OFFSET(0) NUMBITS(2) [
@@ -51,61 +49,149 @@ register_bitfields! {
             PullDown = 0b01,
             PullUp = 0b10
         ]
-    ],
-
-
-    GPPUDLK0 [
-        // Pin15
-        PUDLC15 OFFSET(15) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1
-        ],
-
-        // Pin14
-        PUDLC14 OFFSET(14) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1
-        ]
-    ],
-
-    // BCM2837 Only
-    GPIO_PUP_CNTRL_REG0 [
-        // Pin 15
-        GPIO_PUP_PDN_CNTRL15 OFFSET(30) NUMBITS(2) [
-            NoRegister = 0b00,
-            PullUp = 0b01
-        ],
-
-        // Pin 14
-        GPIO_PUP_PDN_CNTRL14 OFFSET(28) NUMBITS(2) [
-            NoRegister = 0b00,
-            PullUp = 0b01
-        ]
     ]
-
 }
 
+// Most registers hold one bit per pin in two banks of 32 pins. The function select registers hold
+// 3 bits for 10 pins each, the BCM2711 pull registers 2 bits for 16 pins each.
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => _reserved1),
-        (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => _reserved2),
+        (0x00 => GPFSEL: [ReadWrite<u32>; 6]),
+        (0x18 => _reserved1),
+        (0x1C => GPSET: [WriteOnly<u32>; 2]),
+        (0x24 => _reserved2),
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
+        (0x30 => _reserved3),
+        (0x34 => GPLEV: [ReadOnly<u32>; 2]),
+        (0x3C => _reserved4),
+        (0x40 => GPEDS: [ReadWrite<u32>; 2]),
+        (0x48 => _reserved5),
+        (0x4C => GPREN: [ReadWrite<u32>; 2]),
+        (0x54 => _reserved6),
+        (0x58 => GPFEN: [ReadWrite<u32>; 2]),
+        (0x60 => _reserved7),
+        (0x64 => GPHEN: [ReadWrite<u32>; 2]),
+        (0x6C => _reserved8),
+        (0x70 => GPLEN: [ReadWrite<u32>; 2]),
+        (0x78 => _reserved9),
+        (0x7C => GPAREN: [ReadWrite<u32>; 2]),
+        (0x84 => _reserved10),
+        (0x88 => GPAFEN: [ReadWrite<u32>; 2]),
+        (0x90 => _reserved11),
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
-        (0x98 => GPPUDLK0: ReadWrite<u32, GPPUDLK0::Register>),
-        (0xE4 => GPIO_PUP_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_CNTRL_REG0::Register>),
-        (0xE8 => @END),
+        (0x98 => GPPUDCLK: [ReadWrite<u32>; 2]),
+        (0xA0 => _reserved12),
+        (0xE4 => GPIO_PUP_PDN_CNTRL: [ReadWrite<u32>; 4]),
+        (0xF4 => @END),
     }
 }
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+// Pins of the BCM2837 / BCM2711
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi3ap"))]
+const NUM_PINS: u32 = 54;
+#[cfg(feature = "bsp_rpi4")]
+const NUM_PINS: u32 = 58;
+
+// PL011 UART TX and RX
+const PL011_PINS: [u32; 2] = [14, 15];
+
+// BSC1 SDA and SCL
+const I2C1_PINS: [u32; 2] = [2, 3];
+
+// SPI0 CE1, CE0, MISO, MOSI and SCLK
+const SPI0_PINS: [u32; 5] = [7, 8, 9, 10, 11];
+
+// PWM outputs of the headphone jack
+const AUDIO_PINS: [u32; 2] = [40, 41];
+
+// SD card CLK, CMD and DAT0-3. The Pi 4 has dedicated pins for EMMC2.
+#[cfg(feature = "bsp_rpi3")]
+const SD_CARD_PINS: [u32; 6] = [48, 49, 50, 51, 52, 53];
+
+// Handler of a pin and its debouncing state
+#[derive(Clone, Copy)]
+struct PinIRQ {
+    handler: Option<&'static (dyn interface::PinHandler + Sync)>,
+    debounce: Duration,
+    last_event: Option<Duration>,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GPIOError {
+    // there is no pin with this number
+    InvalidPin(u32),
+    // the pin has been claimed before and not released
+    AlreadyClaimed(u32),
+}
+
+// Function select values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Input = 0b000,
+    Output = 0b001,
+    Alt0 = 0b100,
+    Alt1 = 0b101,
+    Alt2 = 0b110,
+    Alt3 = 0b111,
+    Alt4 = 0b011,
+    Alt5 = 0b010,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+// What sets the event status of a pin. The synchronous edges are sampled with the system clock,
+// the asynchronous ones catch shorter pulses. A level keeps the event set for as long as it lasts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    None,
+    RisingEdge,
+    FallingEdge,
+    BothEdges,
+    AsyncRisingEdge,
+    AsyncFallingEdge,
+    AsyncBothEdges,
+    High,
+    Low,
+}
+
+// An event passed to a pin handler
+#[derive(Debug, Clone, Copy)]
+pub struct PinEvent {
+    pub pin: u32,
+    // level of the pin when the interrupt was handled
+    pub high: bool,
+    pub uptime: Duration,
+}
+
+// GPIO interfaces
+pub mod interface {
+    pub trait PinHandler {
+        // called in interrupt context
+        fn handle(&self, event: super::PinEvent);
+    }
+}
+
 pub struct GPIOInner {
     registers: Registers,
+    // one bit per claimed pin
+    claimed: u64,
+    // one bit per pin whose event was taken by the interrupt handler and not polled yet
+    latched: u64,
+    // level triggers turned off until their event has been dealt with, per bank
+    masked_high: [u32; 2],
+    masked_low: [u32; 2],
 }
 
 // Export the inner struct so that BSPs can use it for the panic handler
@@ -114,75 +200,408 @@ pub use GPIOInner as PanicGPIO;
 // Representation of the GPIO HW
 pub struct GPIO {
     inner: IRQSafeNullLock<GPIOInner>,
+    handlers: IRQSafeNullLock<[PinIRQ; NUM_PINS as usize]>,
+    // the interrupts of pins 0-27, 28-45 and 46-53, the handler looks at all of them either way
+    irq_numbers: [bsp::device_driver::IRQNumber; 3],
+}
+
+// An exclusively claimed pin, released on drop
+pub struct Pin {
+    gpio: &'static GPIO,
+    number: u32,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl fmt::Display for GPIOError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GPIOError::InvalidPin(pin) => write!(f, "No GPIO pin {}", pin),
+            GPIOError::AlreadyClaimed(pin) => {
+                write!(f, "GPIO pin {} is already claimed", pin)
+            }
+        }
+    }
+}
+
+impl From<GPIOError> for &'static str {
+    fn from(error: GPIOError) -> Self {
+        match error {
+            GPIOError::InvalidPin(_) => "No such GPIO pin",
+            GPIOError::AlreadyClaimed(_) => "GPIO pin is already claimed",
+        }
+    }
+}
+
 impl GPIOInner {
     // create an instance
     pub const unsafe fn new(mmio_star_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_star_addr),
+            claimed: 0,
+            latched: 0,
+            masked_high: [0; 2],
+            masked_low: [0; 2],
+        }
+    }
+
+    // Map PL011 UART as standard output
+    pub fn map_pl011_uart(&mut self) {
+        for pin in PL011_PINS {
+            self.set_function(pin, Function::Alt0);
+            self.set_pull(pin, Pull::None);
+        }
+    }
+
+    // Map BSC1 to GPIO2/3, which have pull-ups on the board
+    pub fn map_i2c1(&mut self) {
+        for pin in I2C1_PINS {
+            self.set_function(pin, Function::Alt0);
+            self.set_pull(pin, Pull::None);
+        }
+    }
+
+    // Map SPI0 to GPIO7-11
+    pub fn map_spi0(&mut self) {
+        for pin in SPI0_PINS {
+            self.set_function(pin, Function::Alt0);
+            self.set_pull(pin, Pull::None);
+        }
+    }
+
+    // Map the PWM channels of the headphone jack to GPIO40/41
+    pub fn map_audio(&mut self) {
+        for pin in AUDIO_PINS {
+            self.set_function(pin, Function::Alt0);
+            self.set_pull(pin, Pull::None);
+        }
+    }
+
+    // Map the EMMC controller to the SD card slot, the firmware leaves it on SDHOST
+    #[cfg(feature = "bsp_rpi3")]
+    pub fn map_sd_card(&mut self) {
+        for pin in SD_CARD_PINS {
+            self.set_function(pin, Function::Alt3);
+            // CLK is driven by the controller, CMD and DAT need pull-ups
+            let pull = if pin == SD_CARD_PINS[0] {
+                Pull::None
+            } else {
+                Pull::Up
+            };
+            self.set_pull(pin, pull);
+        }
+    }
+
+    pub fn set_function(&mut self, pin: u32, function: Function) {
+        let (index, shift) = ((pin / 10) as usize, (pin % 10) * 3);
+        let value = self.registers.GPFSEL[index].get();
+
+        self.registers.GPFSEL[index]
+            .set((value & !(0b111 << shift)) | ((function as u32) << shift));
+    }
+
+    pub fn function(&self, pin: u32) -> Function {
+        let (index, shift) = ((pin / 10) as usize, (pin % 10) * 3);
+
+        match (self.registers.GPFSEL[index].get() >> shift) & 0b111 {
+            0b000 => Function::Input,
+            0b001 => Function::Output,
+            0b100 => Function::Alt0,
+            0b101 => Function::Alt1,
+            0b110 => Function::Alt2,
+            0b111 => Function::Alt3,
+            0b011 => Function::Alt4,
+            _ => Function::Alt5,
         }
     }
 
-    // disable pull-up/down on pins 14 and 15
     #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi3ap"))]
-    fn disable_pud_14_15_bcm2837(&mut self) {
+    pub fn set_pull(&mut self, pin: u32, pull: Pull) {
         use crate::{time, time::interface::TimeManager};
         use core::time::Duration;
 
         // The Linux 2837 GPIO 1 us between the steps
         const DELAY: Duration = Duration::from_micros(1);
 
-        self.registers.GPPUD.write(GPPUD::PUD::Off);
+        let (bank, bit) = bank_bit(pin);
+        let pud = match pull {
+            Pull::None => GPPUD::PUD::Off,
+            Pull::Up => GPPUD::PUD::PullUp,
+            Pull::Down => GPPUD::PUD::PullDown,
+        };
+
+        // the control signal is clocked into the pins selected in GPPUDCLK
+        self.registers.GPPUD.write(pud);
         time::time_manager().spin_for(DELAY);
 
-        self.registers.GPPUDLK0.write(
-            GPPUDLK0::PUDLC15::AssertClock + GPPUDLK0::PUDLC14::AssertClock,
-        );
+        self.registers.GPPUDCLK[bank].set(bit);
         time::time_manager().spin_for(DELAY);
 
         self.registers.GPPUD.write(GPPUD::PUD::Off);
-        self.registers.GPPUDLK0.set(0);
+        self.registers.GPPUDCLK[bank].set(0);
     }
 
-    // disable pull-up/down on pins 14 and 15
-    #[cfg(any(feature = "bsp_rpi4"))]
-    fn disable_pud_14_15_bcm2711(&mut self) {
-        self.registers.GPIO_PUP_CNTRL_REG0.write(
-            GPIO_PUP_CNTRL_REG0::GPIO_PUP_PDN_CNTRL15::PullUp
-                + GPIO_PUP_CNTRL_REG0::GPIO_PUP_PDN_CNTRL14::PullUp,
-        )
+    #[cfg(feature = "bsp_rpi4")]
+    pub fn set_pull(&mut self, pin: u32, pull: Pull) {
+        let (index, shift) = ((pin / 16) as usize, (pin % 16) * 2);
+        let pull = match pull {
+            Pull::None => 0b00,
+            Pull::Up => 0b01,
+            Pull::Down => 0b10,
+        };
+        let value = self.registers.GPIO_PUP_PDN_CNTRL[index].get();
+
+        self.registers.GPIO_PUP_PDN_CNTRL[index]
+            .set((value & !(0b11 << shift)) | (pull << shift));
     }
 
-    // Map PL011 UART as standard output
-    pub fn map_pl011_uart(&mut self) {
-        self.registers
-            .GPFSEL1
-            .modify(GPFSEL1::FSEL14::AltFunc0 + GPFSEL1::FSEL14::AltFunc0);
+    pub fn is_high(&self, pin: u32) -> bool {
+        let (bank, bit) = bank_bit(pin);
+
+        (self.registers.GPLEV[bank].get() & bit) != 0
+    }
+
+    // Drive an output pin
+    pub fn write(&mut self, pin: u32, high: bool) {
+        let (bank, bit) = bank_bit(pin);
+
+        if high {
+            self.registers.GPSET[bank].set(bit);
+        } else {
+            self.registers.GPCLR[bank].set(bit);
+        }
+    }
+
+    pub fn set_trigger(&mut self, pin: u32, trigger: Trigger) {
+        let (bank, bit) = bank_bit(pin);
+
+        // the new trigger replaces one that is turned off for now
+        self.masked_high[bank] &= !bit;
+        self.masked_low[bank] &= !bit;
+
+        let registers = [
+            (&self.registers.GPREN[bank], Trigger::RisingEdge),
+            (&self.registers.GPFEN[bank], Trigger::FallingEdge),
+            (&self.registers.GPAREN[bank], Trigger::AsyncRisingEdge),
+            (&self.registers.GPAFEN[bank], Trigger::AsyncFallingEdge),
+            (&self.registers.GPHEN[bank], Trigger::High),
+            (&self.registers.GPLEN[bank], Trigger::Low),
+        ];
+
+        for (register, enabled_by) in registers {
+            let enable = match trigger {
+                Trigger::BothEdges => matches!(
+                    enabled_by,
+                    Trigger::RisingEdge | Trigger::FallingEdge
+                ),
+                Trigger::AsyncBothEdges => matches!(
+                    enabled_by,
+                    Trigger::AsyncRisingEdge | Trigger::AsyncFallingEdge
+                ),
+                _ => trigger == enabled_by,
+            };
+
+            let value = register.get() & !bit;
+            register.set(if enable { value | bit } else { value });
+        }
+
+        // an event from the old setting would show up as a new one
+        self.clear_event(pin);
+    }
 
-        #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi3ap"))]
-        self.disable_pud_14_15_bcm2837();
+    // True if the trigger has fired since the event was cleared
+    pub fn event_detected(&self, pin: u32) -> bool {
+        let (bank, bit) = bank_bit(pin);
 
-        #[cfg(feature = "bsp_rpi4")]
-        self.disable_pud_14_15_bcm2711();
+        is_set(self.latched, pin)
+            || (self.registers.GPEDS[bank].get() & bit) != 0
+    }
+
+    // Event status bits are cleared by writing 1. A level trigger turned off by the event fires
+    // again from now on.
+    pub fn clear_event(&mut self, pin: u32) {
+        let (bank, bit) = bank_bit(pin);
+
+        self.latched &= !(1 << pin);
+        self.registers.GPEDS[bank].set(bit);
+        self.rearm(pin);
     }
 }
 
 impl GPIO {
-    // Create an instance
-    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+    pub const COMPATIBLE: &'static str = "BCM GPIO";
+
+    // Create an instance, `irq_numbers` are the three interrupts of the pins
+    pub const unsafe fn new(
+        mmio_start_addr: usize,
+        irq_numbers: [bsp::device_driver::IRQNumber; 3],
+    ) -> Self {
         Self {
             inner: IRQSafeNullLock::new(GPIOInner::new(mmio_start_addr)),
+            handlers: IRQSafeNullLock::new([PinIRQ::NONE; NUM_PINS as usize]),
+            irq_numbers,
         }
     }
 
-    // Concurency safe version of `GPIOInner.map_pl011_uart()`
+    // Concurency safe version of `GPIOInner.map_pl011_uart()`. The pins stay claimed for good.
     pub fn map_pl011_uart(&self) {
-        self.inner.lock(|inner| inner.map_pl011_uart());
+        self.inner.lock(|inner| {
+            for pin in PL011_PINS {
+                inner.claimed |= 1 << pin;
+            }
+            inner.map_pl011_uart()
+        });
+    }
+
+    // Concurency safe version of `GPIOInner.map_i2c1()`. The pins stay claimed for good.
+    pub fn map_i2c1(&self) {
+        self.inner.lock(|inner| {
+            for pin in I2C1_PINS {
+                inner.claimed |= 1 << pin;
+            }
+            inner.map_i2c1()
+        });
+    }
+
+    // Concurency safe version of `GPIOInner.map_spi0()`. The pins stay claimed for good.
+    pub fn map_spi0(&self) {
+        self.inner.lock(|inner| {
+            for pin in SPI0_PINS {
+                inner.claimed |= 1 << pin;
+            }
+            inner.map_spi0()
+        });
+    }
+
+    // Concurency safe version of `GPIOInner.map_audio()`. The pins stay claimed for good.
+    pub fn map_audio(&self) {
+        self.inner.lock(|inner| {
+            for pin in AUDIO_PINS {
+                inner.claimed |= 1 << pin;
+            }
+            inner.map_audio()
+        });
+    }
+
+    // Concurency safe version of `GPIOInner.map_sd_card()`. The pins stay claimed for good.
+    #[cfg(feature = "bsp_rpi3")]
+    pub fn map_sd_card(&self) {
+        self.inner.lock(|inner| {
+            for pin in SD_CARD_PINS {
+                inner.claimed |= 1 << pin;
+            }
+            inner.map_sd_card()
+        });
+    }
+
+    // Claim a pin for exclusive use
+    pub fn claim(&'static self, number: u32) -> Result<Pin, GPIOError> {
+        if number >= NUM_PINS {
+            return Err(GPIOError::InvalidPin(number));
+        }
+
+        self.inner.lock(|inner| {
+            if is_set(inner.claimed, number) {
+                return Err(GPIOError::AlreadyClaimed(number));
+            }
+
+            inner.claimed |= 1 << number;
+
+            Ok(Pin { gpio: self, number })
+        })
+    }
+
+    pub fn is_claimed(&self, number: u32) -> bool {
+        self.inner.lock(|inner| is_set(inner.claimed, number))
+    }
+}
+
+impl Pin {
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn set_function(&self, function: Function) {
+        self.lock(|inner, pin| inner.set_function(pin, function));
+    }
+
+    pub fn function(&self) -> Function {
+        self.lock(|inner, pin| inner.function(pin))
+    }
+
+    pub fn set_pull(&self, pull: Pull) {
+        self.lock(|inner, pin| inner.set_pull(pin, pull));
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.lock(|inner, pin| inner.is_high(pin))
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+
+    pub fn set_high(&self) {
+        self.lock(|inner, pin| inner.write(pin, true));
+    }
+
+    pub fn set_low(&self) {
+        self.lock(|inner, pin| inner.write(pin, false));
+    }
+
+    pub fn set_trigger(&self, trigger: Trigger) {
+        self.lock(|inner, pin| inner.set_trigger(pin, trigger));
+    }
+
+    // Call `handler` whenever `trigger` fires, but not again within `debounce`
+    pub fn set_handler(
+        &self,
+        trigger: Trigger,
+        debounce: Duration,
+        handler: &'static (dyn interface::PinHandler + Sync),
+    ) {
+        self.gpio.handlers.lock(|handlers| {
+            handlers[self.number as usize] = PinIRQ {
+                handler: Some(handler),
+                debounce,
+                last_event: None,
+            }
+        });
+        self.set_trigger(trigger);
+    }
+
+    pub fn set_debounce(&self, debounce: Duration) {
+        self.gpio.handlers.lock(|handlers| {
+            handlers[self.number as usize].debounce = debounce
+        });
+    }
+
+    pub fn remove_handler(&self) {
+        self.set_trigger(Trigger::None);
+        self.gpio
+            .handlers
+            .lock(|handlers| handlers[self.number as usize] = PinIRQ::NONE);
+    }
+
+    pub fn event_detected(&self) -> bool {
+        self.lock(|inner, pin| inner.event_detected(pin))
+    }
+
+    pub fn clear_event(&self) {
+        self.lock(|inner, pin| inner.clear_event(pin));
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        self.remove_handler();
+
+        let number = self.number;
+        self.gpio
+            .inner
+            .lock(|inner| inner.claimed &= !(1 << number));
     }
 }
 
@@ -191,6 +610,210 @@ impl GPIO {
 //------------------------------------------------------------------------------
 impl driver::interface::DeviceDriver for GPIO {
     fn compatible(&self) -> &'static str {
-        "BCM GPIO"
+        Self::COMPATIBLE
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQDescriptor};
+
+        for irq_number in self.irq_numbers {
+            let descriptor = IRQDescriptor {
+                name: Self::COMPATIBLE,
+                handler: self,
+            };
+
+            irq_manager().register_handler(irq_number, descriptor)?;
+            irq_manager().enable(irq_number);
+        }
+
+        Ok(())
+    }
+}
+
+// `/dev/gpio`, one byte per pin from the pin at the offset on. Reads give the level of each pin as
+// `0` or `1`, writing `0` or `1` makes a pin an output at that level unless a driver claimed it.
+impl fs::devfs::interface::CharDevice for GPIO {
+    fn read(
+        &self,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, fs::FsError> {
+        let first = offset.min(NUM_PINS as u64) as u32;
+        let len = buffer.len().min((NUM_PINS - first) as usize);
+
+        self.inner.lock(|inner| {
+            for (i, byte) in buffer[..len].iter_mut().enumerate() {
+                *byte = if inner.is_high(first + i as u32) {
+                    b'1'
+                } else {
+                    b'0'
+                };
+            }
+        });
+
+        Ok(len)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<usize, fs::FsError> {
+        if offset + data.len() as u64 > NUM_PINS as u64 {
+            return Err(fs::FsError::NoSpace);
+        }
+
+        self.inner.lock(|inner| {
+            // check everything first, so that a bad write changes no pin
+            for (i, &byte) in data.iter().enumerate() {
+                if !matches!(byte, b'0' | b'1') {
+                    return Err(fs::FsError::InvalidArgument);
+                }
+                if is_set(inner.claimed, offset as u32 + i as u32) {
+                    return Err(fs::FsError::Busy);
+                }
+            }
+
+            for (i, &byte) in data.iter().enumerate() {
+                let pin = offset as u32 + i as u32;
+                inner.write(pin, byte == b'1');
+                inner.set_function(pin, Function::Output);
+            }
+
+            Ok(data.len())
+        })
+    }
+
+    fn size(&self) -> u64 {
+        NUM_PINS as u64
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for GPIO {
+    fn handle(&self) -> Result<(), &'static str> {
+        let uptime = time::time_manager().uptime();
+
+        for bank in 0..2 {
+            let (pending, levels) = self.inner.lock(|inner| {
+                let handled =
+                    self.handlers.lock(|handlers| handled_mask(handlers, bank));
+                let pending = inner.take_events(bank);
+
+                // events of pins without a handler are left for polling
+                inner.latched |= ((pending & !handled) as u64) << (bank * 32);
+
+                (pending & handled, inner.registers.GPLEV[bank].get())
+            });
+
+            for bit in (0..32).filter(|bit| (pending & (1 << bit)) != 0) {
+                let pin = bank as u32 * 32 + bit;
+                let event = PinEvent {
+                    pin,
+                    high: (levels & (1 << bit)) != 0,
+                    uptime,
+                };
+
+                // the handler is called without the lock, so it can use its pin
+                if let Some(handler) = self
+                    .handlers
+                    .lock(|handlers| handlers[pin as usize].accept(uptime))
+                {
+                    handler.handle(event);
+                }
+
+                self.inner.lock(|inner| inner.rearm(pin));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl PinIRQ {
+    const NONE: Self = Self {
+        handler: None,
+        debounce: Duration::ZERO,
+        last_event: None,
+    };
+
+    // The handler to call for an event at `uptime`, `None` while bouncing
+    fn accept(
+        &mut self,
+        uptime: Duration,
+    ) -> Option<&'static (dyn interface::PinHandler + Sync)> {
+        let handler = self.handler?;
+
+        if let Some(last_event) = self.last_event {
+            if uptime.saturating_sub(last_event) < self.debounce {
+                return None;
+            }
+        }
+        self.last_event = Some(uptime);
+
+        Some(handler)
+    }
+}
+
+impl GPIOInner {
+    // Clear the event status of `bank` and return the pins that had an event. The level triggers
+    // among them are turned off, as their event would be set again right away.
+    fn take_events(&mut self, bank: usize) -> u32 {
+        let pending = self.registers.GPEDS[bank].get();
+        let high = self.registers.GPHEN[bank].get();
+        let low = self.registers.GPLEN[bank].get();
+
+        self.registers.GPHEN[bank].set(high & !pending);
+        self.registers.GPLEN[bank].set(low & !pending);
+        self.masked_high[bank] |= high & pending;
+        self.masked_low[bank] |= low & pending;
+
+        self.registers.GPEDS[bank].set(pending);
+
+        pending
     }
+
+    // Turn the level trigger of `pin` back on if an event turned it off
+    fn rearm(&mut self, pin: u32) {
+        let (bank, bit) = bank_bit(pin);
+
+        if (self.masked_high[bank] & bit) != 0 {
+            let value = self.registers.GPHEN[bank].get();
+            self.registers.GPHEN[bank].set(value | bit);
+        }
+        if (self.masked_low[bank] & bit) != 0 {
+            let value = self.registers.GPLEN[bank].get();
+            self.registers.GPLEN[bank].set(value | bit);
+        }
+
+        self.masked_high[bank] &= !bit;
+        self.masked_low[bank] &= !bit;
+    }
+}
+
+impl Pin {
+    fn lock<R>(&self, f: impl FnOnce(&mut GPIOInner, u32) -> R) -> R {
+        self.gpio.inner.lock(|inner| f(inner, self.number))
+    }
+}
+
+// Pins of `bank` that have a handler
+fn handled_mask(handlers: &[PinIRQ], bank: usize) -> u32 {
+    handlers
+        .iter()
+        .enumerate()
+        .skip(bank * 32)
+        .take(32)
+        .filter(|(_, irq)| irq.handler.is_some())
+        .fold(0, |mask, (pin, _)| mask | (1 << (pin % 32)))
+}
+
+fn is_set(claimed: u64, pin: u32) -> bool {
+    (claimed & (1 << pin)) != 0
+}
+
+// Register bank and bit of a pin in the one-bit-per-pin registers
+fn bank_bit(pin: u32) -> (usize, u32) {
+    ((pin / 32) as usize, 1 << (pin % 32))
 }
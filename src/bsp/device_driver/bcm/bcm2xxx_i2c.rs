@@ -0,0 +1,648 @@
+// BSC (I2C) master driver
+//
+// The controller moves the bytes of a transfer from its interrupt handler while the caller waits
+// for the result. With interrupts masked, e.g. during kernel init, the caller runs the handler
+// itself, so transfers work from any context.
+//
+// A transaction is sent as one message per run of reads or writes. Messages are chained with a
+// repeated start by starting the next one as soon as the last byte of the previous one is in the
+// FIFO. This only works after writes, so reads must come last.
+//
+// The bus is used through `embedded-hal`'s `I2c` trait, implemented for `&I2C`, so that device
+// crates can be given `bsp::driver::i2c()`.
+
+use crate::{
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    clock, cpu, driver, exception,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+};
+use core::{fmt, time::Duration};
+use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource, Operation};
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+// Descriptions taken from
+// raspberypi 3ap
+// - https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+// raspberypi 4b
+// - https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// BSC registers
+register_bitfields! {
+    u32,
+
+    // Control
+    C [
+        // I2C enable
+        I2CEN OFFSET(15) NUMBITS(1) [],
+
+        // Interrupt on RX, while the FIFO is 3/4 full
+        INTR OFFSET(10) NUMBITS(1) [],
+
+        // Interrupt on TX, while the FIFO is less than 1/4 full
+        INTT OFFSET(9) NUMBITS(1) [],
+
+        // Interrupt on done
+        INTD OFFSET(8) NUMBITS(1) [],
+
+        // Start a new transfer, a repeated start if one is active
+        ST OFFSET(7) NUMBITS(1) [],
+
+        // Clear the FIFO
+        CLEAR OFFSET(4) NUMBITS(2) [
+            All = 0b01
+        ],
+
+        // Read transfer
+        READ OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Status
+    S [
+        // Slave clock stretch timeout, write 1 to clear
+        CLKT OFFSET(9) NUMBITS(1) [],
+
+        // Slave address not acknowledged, write 1 to clear
+        ERR OFFSET(8) NUMBITS(1) [],
+
+        // FIFO contains data
+        RXD OFFSET(5) NUMBITS(1) [],
+
+        // FIFO can accept data
+        TXD OFFSET(4) NUMBITS(1) [],
+
+        // FIFO needs reading
+        RXR OFFSET(3) NUMBITS(1) [],
+
+        // FIFO needs writing
+        TXW OFFSET(2) NUMBITS(1) [],
+
+        // Transfer done, write 1 to clear
+        DONE OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Data length
+    DLEN [
+        DLEN OFFSET(0) NUMBITS(16) []
+    ],
+
+    // Slave address
+    A [
+        ADDR OFFSET(0) NUMBITS(7) []
+    ],
+
+    // Data FIFO
+    FIFO [
+        DATA OFFSET(0) NUMBITS(8) []
+    ],
+
+    // Clock divider, always even
+    DIV [
+        CDIV OFFSET(0) NUMBITS(16) []
+    ],
+
+    // Data delay in core clock cycles
+    DEL [
+        // Falling edge delay
+        FEDL OFFSET(16) NUMBITS(16) [],
+
+        // Rising edge delay
+        REDL OFFSET(0) NUMBITS(16) []
+    ],
+
+    // Clock stretch timeout
+    CLKT [
+        // SCL cycles to wait for the slave, 0 disables the timeout
+        TOUT OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => C: ReadWrite<u32, C::Register>),
+        (0x04 => S: ReadWrite<u32, S::Register>),
+        (0x08 => DLEN: ReadWrite<u32, DLEN::Register>),
+        (0x0C => A: ReadWrite<u32, A::Register>),
+        (0x10 => FIFO: ReadWrite<u32, FIFO::Register>),
+        (0x14 => DIV: ReadWrite<u32, DIV::Register>),
+        (0x18 => DEL: ReadWrite<u32, DEL::Register>),
+        (0x1C => CLKT: ReadWrite<u32, CLKT::Register>),
+        (0x20 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+// A transaction in flight. The operations belong to the caller, which waits until the transfer
+// is finished or taken back before it returns.
+struct Transfer {
+    operations: *mut [Operation<'static>],
+    // operation and byte to transfer next
+    operation: usize,
+    position: usize,
+    // the message on the bus ends before this operation
+    message_end: usize,
+    result: Option<Result<(), I2CError>>,
+}
+
+// Only touched by the caller and the interrupt handler, under the lock
+unsafe impl Send for Transfer {}
+
+struct I2CInner {
+    registers: Registers,
+    core_clock_rate: u32,
+    bus_speed: u32,
+    transfer: Option<Transfer>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2CError {
+    // the address or a byte was not acknowledged
+    NoAcknowledge,
+    // the slave held SCL low for longer than the clock stretch timeout
+    ClockStretchTimeout,
+    // the transfer did not finish in time
+    Timeout,
+    // the controller stopped before every byte was transferred
+    Incomplete,
+    // another transfer is in progress
+    Busy,
+    InvalidAddress,
+    MessageTooLong,
+    // a read is followed by a write
+    Unsupported,
+}
+
+// Representation of the BSC controller
+pub struct I2C {
+    inner: IRQSafeNullLock<I2CInner>,
+    irq_number: bsp::device_driver::IRQNumber,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for I2CError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str((*self).into())
+    }
+}
+
+impl From<I2CError> for &'static str {
+    fn from(error: I2CError) -> Self {
+        match error {
+            I2CError::NoAcknowledge => "I2C no acknowledge",
+            I2CError::ClockStretchTimeout => "I2C clock stretch timeout",
+            I2CError::Timeout => "I2C transfer timeout",
+            I2CError::Incomplete => "I2C transfer ended early",
+            I2CError::Busy => "I2C bus busy",
+            I2CError::InvalidAddress => "I2C invalid address",
+            I2CError::MessageTooLong => "I2C message too long",
+            I2CError::Unsupported => "I2C reads must come after all writes",
+        }
+    }
+}
+
+impl I2C {
+    pub const COMPATIBLE: &'static str = "BCM BSC I2C";
+
+    // 100 kbit/s, the speed after init
+    pub const STANDARD_MODE: u32 = 100_000;
+
+    // 400 kbit/s
+    pub const FAST_MODE: u32 = 400_000;
+
+    pub const unsafe fn new(
+        mmio_start_addr: usize,
+        irq_number: bsp::device_driver::IRQNumber,
+    ) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(I2CInner::new(mmio_start_addr)),
+            irq_number,
+        }
+    }
+
+    // Set the SCL frequency in Hz, it is derived from the core clock and may end up lower
+    pub fn set_bus_speed(&self, speed: u32) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.set_bus_speed(speed))
+    }
+
+    pub fn bus_speed(&self) -> u32 {
+        self.inner.lock(|inner| inner.bus_speed)
+    }
+
+    // How many SCL cycles a slave may stretch the clock, 0 waits forever
+    pub fn set_clock_stretch_timeout(&self, scl_cycles: u16) {
+        self.inner.lock(|inner| {
+            inner
+                .registers
+                .CLKT
+                .write(CLKT::TOUT.val(scl_cycles as u32))
+        });
+    }
+
+    // Run `operations` on the slave at the 7-bit `address`
+    pub fn transaction(
+        &self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), I2CError> {
+        validate(address, operations)?;
+        if operations.is_empty() {
+            return Ok(());
+        }
+
+        let timeout = self.inner.lock(|inner| {
+            if inner.transfer.is_some() {
+                return Err(I2CError::Busy);
+            }
+
+            inner.start(address, operations);
+            Ok(inner.timeout(operations))
+        })?;
+        let deadline = time::time_manager().uptime() + timeout;
+
+        loop {
+            // with IRQs masked the interrupt handler cannot run, so stand in for it
+            if exception::asynchronous::is_local_irq_masked() {
+                self.inner.lock(|inner| inner.handle_interrupt());
+            }
+
+            if let Some(result) = self.inner.lock(|inner| inner.finished()) {
+                return result;
+            }
+
+            if time::time_manager().uptime() > deadline {
+                // the operations must not be touched once we return
+                return self.inner.lock(|inner| {
+                    inner.finish(Err(I2CError::Timeout));
+                    inner.finished().unwrap_or(Err(I2CError::Timeout))
+                });
+            }
+
+            cpu::nop();
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl driver::interface::DeviceDriver for I2C {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        use bsp::clock::{clock_manager, Clock, PowerDomain};
+        use clock::interface::ClockManager;
+
+        let core_clock_rate =
+            clock_manager().enable(PowerDomain::I2c1, Clock::Core)?;
+
+        self.inner.lock(|inner| {
+            inner.core_clock_rate = core_clock_rate;
+            inner.init()
+        })
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: Self::COMPATIBLE,
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for I2C {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.handle_interrupt());
+
+        Ok(())
+    }
+}
+
+impl embedded_hal::i2c::Error for I2CError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            I2CError::NoAcknowledge => {
+                ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_hal::i2c::ErrorType for &I2C {
+    type Error = I2CError;
+}
+
+impl embedded_hal::i2c::I2c for &I2C {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), I2CError> {
+        I2C::transaction(self, address, operations)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl I2CInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            core_clock_rate: 0,
+            bus_speed: 0,
+            transfer: None,
+        }
+    }
+
+    fn init(&mut self) -> Result<(), &'static str> {
+        self.registers.C.write(C::CLEAR::All);
+        self.clear_status();
+
+        self.set_bus_speed(I2C::STANDARD_MODE)
+    }
+
+    fn set_bus_speed(&mut self, speed: u32) -> Result<(), &'static str> {
+        if speed == 0 {
+            return Err("I2C bus speed must not be 0");
+        }
+
+        // round up to the next even divider, so the speed is never exceeded
+        let divider = self.core_clock_rate.div_ceil(speed);
+        let divider = divider + (divider & 1);
+        if !(2..=0xFFFE).contains(&divider) {
+            return Err("I2C clock does not support the bus speed");
+        }
+
+        // sample and drive data away from the SCL edges
+        let falling_delay = (divider / 16).max(1);
+        let rising_delay = (divider / 4).max(1);
+
+        self.registers.DIV.write(DIV::CDIV.val(divider));
+        self.registers
+            .DEL
+            .write(DEL::FEDL.val(falling_delay) + DEL::REDL.val(rising_delay));
+        self.bus_speed = self.core_clock_rate / divider;
+
+        Ok(())
+    }
+
+    fn start(&mut self, address: u8, operations: &mut [Operation<'_>]) {
+        self.registers.C.write(C::CLEAR::All);
+        self.clear_status();
+        self.registers.A.write(A::ADDR.val(address as u32));
+
+        self.transfer = Some(Transfer {
+            operations: operations as *mut [Operation<'_>] as *mut _,
+            operation: 0,
+            position: 0,
+            message_end: 0,
+            result: None,
+        });
+        self.start_message();
+    }
+
+    // Put the message from the next operation on the bus
+    fn start_message(&mut self) {
+        let transfer = match &mut self.transfer {
+            Some(transfer) => transfer,
+            None => return,
+        };
+
+        let operations = unsafe { &*transfer.operations };
+        let start = transfer.message_end;
+        let end = message_end(operations, start);
+        let read = matches!(operations[start], Operation::Read(_));
+
+        transfer.operation = start;
+        transfer.position = 0;
+        transfer.message_end = end;
+
+        self.registers
+            .DLEN
+            .write(DLEN::DLEN.val(message_len(&operations[start..end]) as u32));
+        let control = C::I2CEN::SET + C::ST::SET + C::INTD::SET;
+        if read {
+            self.registers
+                .C
+                .write(control + C::READ::SET + C::INTR::SET);
+        } else {
+            self.registers.C.write(control + C::INTT::SET);
+        }
+    }
+
+    fn handle_interrupt(&mut self) {
+        if self.transfer.is_none() {
+            return;
+        }
+
+        let status = self.registers.S.extract();
+
+        if status.is_set(S::ERR) {
+            self.finish(Err(I2CError::NoAcknowledge));
+        } else if status.is_set(S::CLKT) {
+            self.finish(Err(I2CError::ClockStretchTimeout));
+        } else if status.is_set(S::DONE) {
+            self.read_fifo();
+            let complete = self
+                .transfer
+                .as_mut()
+                .is_some_and(|transfer| transfer.is_complete());
+            self.finish(if complete {
+                Ok(())
+            } else {
+                Err(I2CError::Incomplete)
+            });
+        } else if status.is_set(S::RXR) {
+            self.read_fifo();
+        } else if status.is_set(S::TXW) {
+            self.write_fifo();
+        }
+    }
+
+    fn write_fifo(&mut self) {
+        let transfer = match &mut self.transfer {
+            Some(transfer) => transfer,
+            None => return,
+        };
+
+        while self.registers.S.is_set(S::TXD) {
+            match transfer.next_byte() {
+                Some((Operation::Write(bytes), position)) => self
+                    .registers
+                    .FIFO
+                    .write(FIFO::DATA.val(bytes[position] as u32)),
+                _ => break,
+            }
+        }
+
+        if !transfer.is_message_sent() {
+            return;
+        }
+
+        if transfer.is_complete() {
+            self.registers.C.modify(C::INTT::CLEAR);
+        } else {
+            // repeated start once the FIFO has been sent
+            self.start_message();
+        }
+    }
+
+    fn read_fifo(&mut self) {
+        while self.registers.S.is_set(S::RXD) {
+            let byte = self.registers.FIFO.read(FIFO::DATA) as u8;
+
+            if let Some((Operation::Read(bytes), position)) = self
+                .transfer
+                .as_mut()
+                .and_then(|transfer| transfer.next_byte())
+            {
+                bytes[position] = byte;
+            }
+        }
+    }
+
+    // Stop the controller and keep `result` for the caller
+    fn finish(&mut self, result: Result<(), I2CError>) {
+        self.registers.C.write(C::CLEAR::All);
+        self.clear_status();
+
+        if let Some(transfer) = &mut self.transfer {
+            transfer.result.get_or_insert(result);
+        }
+    }
+
+    // The result of the transfer, which is given up
+    fn finished(&mut self) -> Option<Result<(), I2CError>> {
+        let result = self.transfer.as_ref()?.result?;
+        self.transfer = None;
+
+        Some(result)
+    }
+
+    // Twice the time the transaction takes on the bus, plus some slack for clock stretching
+    fn timeout(&self, operations: &[Operation<'_>]) -> Duration {
+        // address and data bytes, 9 clocks each
+        let bytes = message_len(operations) + operations.len();
+        let micros =
+            bytes as u64 * 9 * 2_000_000 / self.bus_speed.max(1) as u64;
+
+        Duration::from_micros(micros) + Duration::from_millis(10)
+    }
+
+    fn clear_status(&self) {
+        self.registers
+            .S
+            .write(S::CLKT::SET + S::ERR::SET + S::DONE::SET);
+    }
+}
+
+impl Transfer {
+    // The operation and position of the next byte of the message on the bus
+    fn next_byte(&mut self) -> Option<(&mut Operation<'static>, usize)> {
+        if self.is_message_sent() {
+            return None;
+        }
+
+        let position = self.position;
+        self.position += 1;
+
+        Some((unsafe { &mut (*self.operations)[self.operation] }, position))
+    }
+
+    // True once every byte of the message has been transferred, skips empty operations
+    fn is_message_sent(&mut self) -> bool {
+        let operations = unsafe { &*self.operations };
+
+        while self.operation < self.message_end
+            && self.position >= operation_len(&operations[self.operation])
+        {
+            self.operation += 1;
+            self.position = 0;
+        }
+
+        self.operation == self.message_end
+    }
+
+    fn is_complete(&mut self) -> bool {
+        self.is_message_sent()
+            && self.message_end == unsafe { &*self.operations }.len()
+    }
+}
+
+fn validate(address: u8, operations: &[Operation<'_>]) -> Result<(), I2CError> {
+    if address > 0x7F {
+        return Err(I2CError::InvalidAddress);
+    }
+
+    let mut start = 0;
+    while start < operations.len() {
+        let end = message_end(operations, start);
+
+        if message_len(&operations[start..end]) > 0xFFFF {
+            return Err(I2CError::MessageTooLong);
+        }
+        if matches!(operations[start], Operation::Read(_))
+            && end < operations.len()
+        {
+            return Err(I2CError::Unsupported);
+        }
+
+        start = end;
+    }
+
+    Ok(())
+}
+
+// A message is a run of reads or writes
+fn message_end(operations: &[Operation<'_>], start: usize) -> usize {
+    let read =
+        |operation: &Operation<'_>| matches!(operation, Operation::Read(_));
+
+    operations[start..]
+        .iter()
+        .position(|operation| read(operation) != read(&operations[start]))
+        .map_or(operations.len(), |len| start + len)
+}
+
+fn message_len(operations: &[Operation<'_>]) -> usize {
+    operations.iter().map(operation_len).sum()
+}
+
+fn operation_len(operation: &Operation<'_>) -> usize {
+    match operation {
+        Operation::Read(bytes) => bytes.len(),
+        Operation::Write(bytes) => bytes.len(),
+    }
+}
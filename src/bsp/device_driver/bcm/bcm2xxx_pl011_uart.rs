@@ -1,6 +1,7 @@
 use crate::{
-    bsp, bsp::device_driver::common::MMIODerefWrapper, console, cpu, driver,
-    exception, synchronization, synchronization::IRQSafeNullLock,
+    bsp, bsp::device_driver::common::MMIODerefWrapper, clock, cmdline, console,
+    cpu, driver, exception, fs, input, screen, synchronization,
+    synchronization::IRQSafeNullLock, usb, warn,
 };
 use core::fmt;
 use tock_registers::{
@@ -169,12 +170,6 @@ register_structs! {
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
-#[derive(PartialEq)]
-enum BlockingMode {
-    Blocking,
-    NonBlocking,
-}
-
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -192,6 +187,7 @@ pub use PL011UartInner as PanicUart;
 pub struct PL011Uart {
     inner: IRQSafeNullLock<PL011UartInner>,
     irq_number: bsp::device_driver::IRQNumber,
+    baud_rate: &'static cmdline::Param<u32>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -199,6 +195,11 @@ pub struct PL011Uart {
 //--------------------------------------------------------------------------------------------------
 
 impl PL011UartInner {
+    // UART clock set up by the firmware, used when it cannot be asked
+    pub const DEFAULT_CLOCK_RATE: u32 = 48_000_000;
+
+    pub const DEFAULT_BAUD_RATE: u32 = 921_600;
+
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
@@ -207,7 +208,12 @@ impl PL011UartInner {
         }
     }
 
-    pub fn init(&mut self) {
+    // `clock_rate` is the rate of the UART clock in Hz. A baud rate the clock cannot make is
+    // replaced by the closest one it can, which is returned.
+    pub fn init(&mut self, clock_rate: u32, baud_rate: u32) -> u32 {
+        let divisor = baud_rate_divisor(clock_rate, baud_rate);
+        let (integer, fraction) = (divisor >> 6, divisor & 0x3F);
+
         self.flush();
 
         // Turn the UART off temporarily.
@@ -216,8 +222,8 @@ impl PL011UartInner {
         self.registers.ICR.write(ICR::ALL::CLEAR);
 
         // Set the baud rate, 8N1 and FIFO enabled.
-        self.registers.IBRD.write(IBRD::BOUD_DIVINT.val(3));
-        self.registers.FBRD.write(FBRD::BOUD_DIVFRAC.val(16));
+        self.registers.IBRD.write(IBRD::BOUD_DIVINT.val(integer));
+        self.registers.FBRD.write(FBRD::BOUD_DIVFRAC.val(fraction));
         self.registers
             .LCR_H
             .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
@@ -234,6 +240,8 @@ impl PL011UartInner {
         self.registers
             .CR
             .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
+
+        ((clock_rate as u64 * 4 + divisor as u64 / 2) / divisor as u64) as u32
     }
 
     pub fn write_char(&mut self, c: char) {
@@ -252,20 +260,17 @@ impl PL011UartInner {
         }
     }
 
-    fn read_char_converting(
-        &mut self,
-        blocking_mode: BlockingMode,
-    ) -> Option<char> {
-        // 受信FIFOが空の場合
+    // Publish a received character and pass it through the key bindings, echoing it back
+    fn receive(&mut self, c: char) {
+        input::input_queue()
+            .push(input::Source::Serial, input::Event::Character(c));
+        screen::vt::filter_input(c, true, |c| self.write_char(c));
+    }
+
+    fn read_char_converting(&mut self) -> Option<char> {
+        // 受信FIFOが空の場合は、即座にreturn
         if self.registers.FR.matches_all(FR::RXFE::SET) {
-            // ノンブロッキングモードの場合は、即座にreturn
-            if blocking_mode == BlockingMode::NonBlocking {
-                return None;
-            }
-            // ブロッキングモードの場合は、charを受信するまで待つ
-            while self.registers.FR.matches_all(FR::RXFE::SET) {
-                cpu::nop();
-            }
+            return None;
         }
 
         // charをFIFOから読み取る
@@ -294,13 +299,16 @@ impl fmt::Write for PL011UartInner {
 impl PL011Uart {
     pub const COMPATIBLE: &'static str = "BCM PL011 UART";
 
+    // `baud_rate` is read when the driver is initialized
     pub const unsafe fn new(
         mmio_start_addr: usize,
         irq_number: bsp::device_driver::IRQNumber,
+        baud_rate: &'static cmdline::Param<u32>,
     ) -> Self {
         Self {
             inner: IRQSafeNullLock::new(PL011UartInner::new(mmio_start_addr)),
             irq_number,
+            baud_rate,
         }
     }
 }
@@ -317,7 +325,31 @@ impl driver::interface::DeviceDriver for PL011Uart {
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
-        self.inner.lock(|inner| inner.init());
+        use bsp::clock::{clock_manager, Clock, PowerDomain};
+        use clock::interface::ClockManager;
+
+        // the console must come up even if the firmware does not answer
+        let clock = clock_manager()
+            .enable(PowerDomain::Uart0, Clock::Uart)
+            .and_then(|rate| match rate {
+                0 => Err("UART clock does not exist"),
+                rate => Ok(rate),
+            });
+        let clock_rate = clock.unwrap_or(PL011UartInner::DEFAULT_CLOCK_RATE);
+
+        let baud_rate = self.baud_rate.get();
+        let actual = self.inner.lock(|inner| inner.init(clock_rate, baud_rate));
+
+        if let Err(e) = clock {
+            warn!("UART: {}, assuming a {} Hz clock", e, clock_rate);
+        }
+        // receivers cope with about 3% off
+        if actual.abs_diff(baud_rate) > baud_rate / 32 {
+            warn!(
+                "UART: {} baud is out of reach of the {} Hz clock, using {}",
+                baud_rate, clock_rate, actual
+            );
+        }
 
         Ok(())
     }
@@ -354,21 +386,29 @@ impl console::interface::Write for PL011Uart {
 }
 
 impl console::interface::Read for PL011Uart {
-    /// 受信FIFO(RX)から一文字を読み取る
+    /// コンソールの入力バッファから一文字を読み取る
+    ///
+    /// USB キーボードの入力もここに届く。メインループは待っている間止まるので、USB のクラス
+    /// ドライバはここでポーリングする。割り込みが無効な間は受信FIFO(RX)も自分で読む。
     fn read_char(&self) -> char {
-        self.inner.lock(|inner| {
-            inner.read_char_converting(BlockingMode::Blocking).unwrap()
-        })
+        loop {
+            if let Some(c) = console::input_buffer().pop() {
+                return c;
+            }
+            self.poll_input();
+        }
     }
 
-    /// 受信FIFO(RX)を空にする
+    /// 受信FIFO(RX)と入力バッファを空にする
     fn clear_rx(&self) {
         // FIFO の中身が空になるまで読み取る
         while self
             .inner
-            .lock(|inner| inner.read_char_converting(BlockingMode::NonBlocking))
+            .lock(|inner| inner.read_char_converting())
             .is_some()
         {}
+
+        console::input_buffer().clear();
     }
 }
 
@@ -384,6 +424,50 @@ impl console::interface::Statistics for PL011Uart {
 
 impl console::interface::All for PL011Uart {}
 
+// `/dev/uart0`. A read takes the console input like `read_char`: it waits for the first
+// character and returns what has arrived up to then as UTF-8. Writes go out unchanged.
+impl fs::devfs::interface::CharDevice for PL011Uart {
+    fn read(
+        &self,
+        _offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, fs::FsError> {
+        let mut len = 0;
+
+        while len < buffer.len() {
+            let room = buffer.len() - len;
+            // whole characters only, except one longer than the whole buffer
+            let Some(c) = console::input_buffer()
+                .pop_if(|c| len == 0 || c.len_utf8() <= room)
+            else {
+                if len > 0 {
+                    break;
+                }
+                self.poll_input();
+                continue;
+            };
+
+            let mut utf8 = [0; 4];
+            let bytes = c.encode_utf8(&mut utf8).as_bytes();
+            let n = bytes.len().min(room);
+            buffer[len..len + n].copy_from_slice(&bytes[..n]);
+            len += n;
+        }
+
+        Ok(len)
+    }
+
+    fn write(&self, _offset: u64, data: &[u8]) -> Result<usize, fs::FsError> {
+        self.inner.lock(|inner| {
+            for &byte in data {
+                inner.write_char(byte as char);
+            }
+        });
+
+        Ok(data.len())
+    }
+}
+
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
     fn handle(&self) -> Result<(), &'static str> {
         self.inner.lock(|inner| {
@@ -392,10 +476,8 @@ impl exception::asynchronous::interface::IRQHandler for PL011Uart {
             inner.registers.ICR.write(ICR::ALL::CLEAR);
 
             if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
-                while let Some(c) =
-                    inner.read_char_converting(BlockingMode::NonBlocking)
-                {
-                    inner.write_char(c)
+                while let Some(c) = inner.read_char_converting() {
+                    inner.receive(c);
                 }
             }
         });
@@ -403,3 +485,41 @@ impl exception::asynchronous::interface::IRQHandler for PL011Uart {
         Ok(())
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl PL011Uart {
+    // Move one character from the RX FIFO to the console input, else give the USB keyboards a
+    // turn. The lock is taken for the one character only, the IRQ handler does the same while
+    // interrupts are on.
+    fn poll_input(&self) {
+        let received =
+            self.inner.lock(|inner| match inner.read_char_converting() {
+                Some(c) => {
+                    inner.receive(c);
+                    true
+                }
+                None => false,
+            });
+
+        if !received {
+            // the drivers keep to the poll interval of their devices
+            usb::usb().poll();
+            cpu::nop();
+        }
+    }
+}
+
+// Divisor of the UART clock closest to `baud_rate`, in 1/64ths
+//   divisor = clock_rate / (16 * baud_rate)
+// IBRD takes 1 to 65535, so the divisor is clamped to what the registers can hold.
+fn baud_rate_divisor(clock_rate: u32, baud_rate: u32) -> u32 {
+    let baud_rate = baud_rate.max(1) as u64;
+
+    // rounded to nearest
+    let divisor = (clock_rate as u64 * 4 + baud_rate / 2) / baud_rate;
+
+    divisor.clamp(1 << 6, 0xFFFF << 6 | 0x3F) as u32
+}
@@ -0,0 +1,497 @@
+// PWM Driver
+//
+// The PWM clock is made from PLLD by the clock manager, at `CLOCK_RATE` for every controller. A
+// channel counts `range` clock ticks per period and is high for `data` of them, either in one
+// block (mark-space) or spread evenly over the period (balanced).
+//
+// Audio is played through the FIFO, which feeds both channels in turn with one sample each.
+// Playing takes both channels of the controller over.
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu, driver,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use core::time::Duration;
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+// Descriptions taken from
+// raspberypi 3ap
+// - https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+// raspberypi 4b
+// - https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// PWM registers
+register_bitfields! {
+    u32,
+
+    // Control
+    CTL [
+        // Channel 2 mark-space mode, balanced if clear
+        MSEN2 OFFSET(15) NUMBITS(1) [],
+
+        // Channel 2 reads the FIFO
+        USEF2 OFFSET(13) NUMBITS(1) [],
+
+        // Channel 2 output inverted
+        POLA2 OFFSET(12) NUMBITS(1) [],
+
+        // Channel 2 enable
+        PWEN2 OFFSET(8) NUMBITS(1) [],
+
+        // Channel 1 mark-space mode, balanced if clear
+        MSEN1 OFFSET(7) NUMBITS(1) [],
+
+        // Clear the FIFO
+        CLRF1 OFFSET(6) NUMBITS(1) [],
+
+        // Channel 1 reads the FIFO
+        USEF1 OFFSET(5) NUMBITS(1) [],
+
+        // Channel 1 output inverted
+        POLA1 OFFSET(4) NUMBITS(1) [],
+
+        // Channel 1 enable
+        PWEN1 OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Status
+    STA [
+        // Bus error, write 1 to clear
+        BERR OFFSET(8) NUMBITS(1) [],
+
+        // Channel 2 gap, write 1 to clear
+        GAPO2 OFFSET(5) NUMBITS(1) [],
+
+        // Channel 1 gap, write 1 to clear
+        GAPO1 OFFSET(4) NUMBITS(1) [],
+
+        // FIFO read while empty, write 1 to clear
+        RERR1 OFFSET(3) NUMBITS(1) [],
+
+        // FIFO written while full, write 1 to clear
+        WERR1 OFFSET(2) NUMBITS(1) [],
+
+        // FIFO empty
+        EMPT1 OFFSET(1) NUMBITS(1) [],
+
+        // FIFO full
+        FULL1 OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Clock manager control
+    CM_CTL [
+        // Must be written with every change
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Key = 0x5A
+        ],
+
+        // Noise shaping of the fractional divider
+        MASH OFFSET(9) NUMBITS(2) [
+            Integer = 0,
+            OneStage = 1
+        ],
+
+        // Generator running
+        BUSY OFFSET(7) NUMBITS(1) [],
+
+        ENAB OFFSET(4) NUMBITS(1) [],
+
+        SRC OFFSET(0) NUMBITS(4) [
+            PllD = 6
+        ]
+    ],
+
+    // Clock manager divisor
+    CM_DIV [
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Key = 0x5A
+        ],
+
+        DIVI OFFSET(12) NUMBITS(12) [],
+
+        DIVF OFFSET(0) NUMBITS(12) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CTL: ReadWrite<u32, CTL::Register>),
+        (0x04 => STA: ReadWrite<u32, STA::Register>),
+        (0x08 => _reserved1),
+        (0x10 => RNG1: ReadWrite<u32>),
+        (0x14 => DAT1: ReadWrite<u32>),
+        (0x18 => FIF1: ReadWrite<u32>),
+        (0x1C => _reserved2),
+        (0x20 => RNG2: ReadWrite<u32>),
+        (0x24 => DAT2: ReadWrite<u32>),
+        (0x28 => @END),
+    }
+}
+
+// CM_PWMCTL and CM_PWMDIV of the clock manager
+register_structs! {
+    #[allow(non_snake_case)]
+    ClockRegisterBlock {
+        (0x00 => CTL: ReadWrite<u32, CM_CTL::Register>),
+        (0x04 => DIV: ReadWrite<u32, CM_DIV::Register>),
+        (0x08 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+type ClockRegisters = MMIODerefWrapper<ClockRegisterBlock>;
+
+struct PWMInner {
+    registers: Registers,
+    clock_registers: ClockRegisters,
+    pll_rate: u32,
+    // actual rate of the PWM clock
+    clock_rate: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    One,
+    Two,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PWMMode {
+    // high for `data` ticks, then low for the rest of the period
+    MarkSpace,
+    // `data` high ticks spread evenly over the period
+    Balanced,
+}
+
+// Representation of a PWM controller
+pub struct PWM {
+    inner: IRQSafeNullLock<PWMInner>,
+}
+
+// One channel of a controller
+pub struct PWMChannel {
+    pwm: &'static PWM,
+    channel: Channel,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PWM {
+    pub const COMPATIBLE: &'static str = "BCM PWM";
+
+    // PWM clock in Hz
+    pub const CLOCK_RATE: u32 = 100_000_000;
+
+    // `pll_rate` is the rate of PLLD in Hz, the clock manager makes the PWM clock from it
+    pub const unsafe fn new(
+        mmio_start_addr: usize,
+        clock_mmio_start_addr: usize,
+        pll_rate: u32,
+    ) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(PWMInner::new(
+                mmio_start_addr,
+                clock_mmio_start_addr,
+                pll_rate,
+            )),
+        }
+    }
+
+    pub fn channel(&'static self, channel: Channel) -> PWMChannel {
+        PWMChannel { pwm: self, channel }
+    }
+
+    // Rate of the PWM clock in Hz
+    pub fn clock_rate(&self) -> u32 {
+        self.inner.lock(|inner| inner.clock_rate)
+    }
+
+    // Play signed 16-bit PCM `samples`, interleaved left and right if `stereo`. Returns once the
+    // last sample has been played, with both channels off.
+    pub fn play(
+        &self,
+        samples: &[i16],
+        sample_rate: u32,
+        stereo: bool,
+    ) -> Result<(), &'static str> {
+        let range = self.inner.lock(|inner| inner.start_audio(sample_rate))?;
+        // mono samples go to both channels
+        let channels = if stereo { 1 } else { 2 };
+
+        for &sample in samples {
+            let value = ((sample as i32 + 0x8000) as u64 * range as u64) >> 16;
+
+            for _ in 0..channels {
+                while !self.inner.lock(|inner| inner.push_sample(value as u32))
+                {
+                    cpu::nop();
+                }
+            }
+        }
+
+        while !self
+            .inner
+            .lock(|inner| inner.registers.STA.is_set(STA::EMPT1))
+        {
+            cpu::nop();
+        }
+        self.inner.lock(|inner| inner.registers.CTL.set(0));
+
+        Ok(())
+    }
+}
+
+impl PWMChannel {
+    // Set the mode and the period, `frequency` in Hz. The duty cycle starts at 0.
+    pub fn configure(
+        &self,
+        mode: PWMMode,
+        frequency: u32,
+        inverted: bool,
+    ) -> Result<(), &'static str> {
+        self.pwm.inner.lock(|inner| {
+            inner.configure(self.channel, mode, frequency, inverted)
+        })
+    }
+
+    // Clock ticks per period
+    pub fn range(&self) -> u32 {
+        self.pwm.inner.lock(|inner| match self.channel {
+            Channel::One => inner.registers.RNG1.get(),
+            Channel::Two => inner.registers.RNG2.get(),
+        })
+    }
+
+    // High clock ticks per period, at most `range()`
+    pub fn set_duty(&self, data: u32) {
+        self.pwm.inner.lock(|inner| match self.channel {
+            Channel::One => inner.registers.DAT1.set(data),
+            Channel::Two => inner.registers.DAT2.set(data),
+        });
+    }
+
+    // High time per period, for servos
+    pub fn set_pulse_width(&self, width: Duration) {
+        let ticks =
+            width.as_nanos() * self.pwm.clock_rate() as u128 / 1_000_000_000;
+        self.set_duty(ticks.min(self.range() as u128) as u32);
+    }
+
+    pub fn enable(&self) {
+        self.pwm
+            .inner
+            .lock(|inner| inner.enable(self.channel, true));
+    }
+
+    pub fn disable(&self) {
+        self.pwm
+            .inner
+            .lock(|inner| inner.enable(self.channel, false));
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl driver::interface::DeviceDriver for PWM {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.init())
+    }
+}
+
+impl embedded_hal::pwm::ErrorType for PWMChannel {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_hal::pwm::SetDutyCycle for PWMChannel {
+    fn max_duty_cycle(&self) -> u16 {
+        self.range().min(u16::MAX as u32) as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        let max = self.max_duty_cycle().max(1) as u64;
+        let data = duty.min(max as u16) as u64 * self.range() as u64 / max;
+        self.set_duty(data as u32);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl PWMInner {
+    const unsafe fn new(
+        mmio_start_addr: usize,
+        clock_mmio_start_addr: usize,
+        pll_rate: u32,
+    ) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            clock_registers: ClockRegisters::new(clock_mmio_start_addr),
+            pll_rate,
+            clock_rate: 0,
+        }
+    }
+
+    fn init(&mut self) -> Result<(), &'static str> {
+        self.registers.CTL.set(0);
+        self.clear_status();
+
+        self.set_clock_rate(PWM::CLOCK_RATE)
+    }
+
+    // The clock must not be changed while the generator is running
+    fn set_clock_rate(&mut self, rate: u32) -> Result<(), &'static str> {
+        // in 1/4096ths
+        let divisor = ((self.pll_rate as u64) << 12) / rate as u64;
+        let (integer, fraction) = (divisor >> 12, divisor & 0xFFF);
+        if !(2..=0xFFF).contains(&integer) {
+            return Err("PWM clock rate not supported");
+        }
+
+        let clock = &self.clock_registers;
+        clock.CTL.write(CM_CTL::PASSWD::Key + CM_CTL::SRC::PllD);
+        while clock.CTL.is_set(CM_CTL::BUSY) {
+            cpu::nop();
+        }
+
+        clock.DIV.write(
+            CM_DIV::PASSWD::Key
+                + CM_DIV::DIVI.val(integer as u32)
+                + CM_DIV::DIVF.val(fraction as u32),
+        );
+        let mash = if fraction == 0 {
+            CM_CTL::MASH::Integer
+        } else {
+            CM_CTL::MASH::OneStage
+        };
+        clock
+            .CTL
+            .write(CM_CTL::PASSWD::Key + CM_CTL::SRC::PllD + mash);
+        clock.CTL.write(
+            CM_CTL::PASSWD::Key + CM_CTL::SRC::PllD + mash + CM_CTL::ENAB::SET,
+        );
+        while !clock.CTL.is_set(CM_CTL::BUSY) {
+            cpu::nop();
+        }
+
+        self.clock_rate = (((self.pll_rate as u64) << 12) / divisor) as u32;
+
+        Ok(())
+    }
+
+    fn configure(
+        &mut self,
+        channel: Channel,
+        mode: PWMMode,
+        frequency: u32,
+        inverted: bool,
+    ) -> Result<(), &'static str> {
+        if frequency == 0 || frequency > self.clock_rate / 2 {
+            return Err("PWM frequency not supported");
+        }
+
+        let range = self.clock_rate / frequency;
+        let mark_space = (mode == PWMMode::MarkSpace) as u32;
+        let inverted = inverted as u32;
+
+        match channel {
+            Channel::One => {
+                self.registers.RNG1.set(range);
+                self.registers.DAT1.set(0);
+                self.registers.CTL.modify(
+                    CTL::MSEN1.val(mark_space)
+                        + CTL::POLA1.val(inverted)
+                        + CTL::USEF1::CLEAR,
+                );
+            }
+            Channel::Two => {
+                self.registers.RNG2.set(range);
+                self.registers.DAT2.set(0);
+                self.registers.CTL.modify(
+                    CTL::MSEN2.val(mark_space)
+                        + CTL::POLA2.val(inverted)
+                        + CTL::USEF2::CLEAR,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn enable(&mut self, channel: Channel, enable: bool) {
+        match channel {
+            Channel::One => {
+                self.registers.CTL.modify(CTL::PWEN1.val(enable as u32))
+            }
+            Channel::Two => {
+                self.registers.CTL.modify(CTL::PWEN2.val(enable as u32))
+            }
+        }
+    }
+
+    // Set both channels up for samples from the FIFO and return the range of a sample
+    fn start_audio(&mut self, sample_rate: u32) -> Result<u32, &'static str> {
+        if sample_rate == 0 || sample_rate > self.clock_rate / 256 {
+            return Err("PWM sample rate not supported");
+        }
+        let range = self.clock_rate / sample_rate;
+
+        self.registers.CTL.set(0);
+        self.clear_status();
+        self.registers.RNG1.set(range);
+        self.registers.RNG2.set(range);
+        self.registers.CTL.write(CTL::CLRF1::SET);
+        self.registers.CTL.write(
+            CTL::USEF1::SET
+                + CTL::PWEN1::SET
+                + CTL::USEF2::SET
+                + CTL::PWEN2::SET,
+        );
+
+        Ok(range)
+    }
+
+    // False while the FIFO is full
+    fn push_sample(&mut self, value: u32) -> bool {
+        if self.registers.STA.is_set(STA::FULL1) {
+            return false;
+        }
+
+        self.registers.FIF1.set(value);
+        true
+    }
+
+    fn clear_status(&self) {
+        self.registers.STA.write(
+            STA::BERR::SET
+                + STA::GAPO1::SET
+                + STA::GAPO2::SET
+                + STA::RERR1::SET
+                + STA::WERR1::SET,
+        );
+    }
+}
@@ -0,0 +1,1138 @@
+// SD card driver for the SDHCI controllers, the Arasan EMMC of the Pi 3 and EMMC2 of the Pi 4
+//
+// The card is identified once its pins are mapped and again after it has been swapped.
+// Data moves through the DATA register. The driver is polled: the file systems and the buffer
+// cache call it with IRQs masked, see `block::interface::BlockDevice`, so it watches the interrupt
+// flags of the controller instead of taking its interrupt.
+//
+// Failed commands reset the command or data line of the controller and are retried.
+//
+// https://www.sdcard.org/downloads/pls/ (Physical Layer and Host Controller Simplified
+// Specifications)
+
+use crate::{
+    block::{self, interface::BlockDevice},
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    clock, cpu, driver, info,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+    warn,
+};
+use core::{fmt, time::Duration};
+use tock_registers::{
+    fields::Field,
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{InMemoryRegister, ReadOnly, ReadWrite},
+};
+
+// Descriptions taken from
+// raspberypi 3ap
+// - https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+// raspberypi 4b
+// - https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// SDHCI registers
+register_bitfields! {
+    u32,
+
+    // Block size and count
+    BLKSIZECNT [
+        BLKCNT OFFSET(16) NUMBITS(16) [],
+        BLKSIZE OFFSET(0) NUMBITS(10) []
+    ],
+
+    // Command and transfer mode
+    CMDTM [
+        CMD_INDEX OFFSET(24) NUMBITS(6) [],
+
+        // Data transfer
+        CMD_ISDATA OFFSET(21) NUMBITS(1) [],
+
+        // Check the index of the response
+        CMD_IXCHK_EN OFFSET(20) NUMBITS(1) [],
+
+        // Check the CRC of the response
+        CMD_CRCCHK_EN OFFSET(19) NUMBITS(1) [],
+
+        CMD_RSPNS_TYPE OFFSET(16) NUMBITS(2) [
+            None = 0b00,
+            Bits136 = 0b01,
+            Bits48 = 0b10,
+            // 48 bits, the card is busy on DAT0 afterwards
+            Bits48Busy = 0b11
+        ],
+
+        TM_MULTI_BLOCK OFFSET(5) NUMBITS(1) [],
+
+        TM_DAT_DIR OFFSET(4) NUMBITS(1) [
+            HostToCard = 0,
+            CardToHost = 1
+        ],
+
+        TM_AUTO_CMD_EN OFFSET(2) NUMBITS(2) [
+            None = 0b00,
+            // stop a multi-block transfer by itself
+            Cmd12 = 0b01
+        ],
+
+        TM_BLKCNT_EN OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Status
+    STATUS [
+        // Data lines in use
+        DAT_INHIBIT OFFSET(1) NUMBITS(1) [],
+
+        // Command line in use
+        CMD_INHIBIT OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Host configuration 0
+    CONTROL0 [
+        // SD bus voltage, the Pi 4 only
+        SD_BUS_VOLTAGE OFFSET(9) NUMBITS(3) [
+            V3_3 = 0b111
+        ],
+
+        // SD bus power, the Pi 4 only
+        SD_BUS_POWER OFFSET(8) NUMBITS(1) [],
+
+        // High speed mode
+        HCTL_HS_EN OFFSET(2) NUMBITS(1) [],
+
+        // 4-bit data bus
+        HCTL_DWIDTH OFFSET(1) NUMBITS(1) []
+    ],
+
+    // Host configuration 1
+    CONTROL1 [
+        // Reset the data line
+        SRST_DATA OFFSET(26) NUMBITS(1) [],
+
+        // Reset the command line
+        SRST_CMD OFFSET(25) NUMBITS(1) [],
+
+        // Reset the whole controller
+        SRST_HC OFFSET(24) NUMBITS(1) [],
+
+        // Data timeout, TMCLK * 2^(13 + DATA_TOUNIT)
+        DATA_TOUNIT OFFSET(16) NUMBITS(4) [
+            Max = 0b1110
+        ],
+
+        // Clock divider, low 8 bits
+        CLK_FREQ8 OFFSET(8) NUMBITS(8) [],
+
+        // Clock divider, high 2 bits
+        CLK_FREQ_MS2 OFFSET(6) NUMBITS(2) [],
+
+        // SD clock enable
+        CLK_EN OFFSET(2) NUMBITS(1) [],
+
+        // Internal clock stable
+        CLK_STABLE OFFSET(1) NUMBITS(1) [],
+
+        // Internal clock enable
+        CLK_INTLEN OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Interrupt flags, write 1 to clear
+    INTERRUPT [
+        // Auto CMD12 error
+        ACMD_ERR OFFSET(24) NUMBITS(1) [],
+
+        // Data end bit error
+        DEND_ERR OFFSET(22) NUMBITS(1) [],
+
+        // Data CRC error
+        DCRC_ERR OFFSET(21) NUMBITS(1) [],
+
+        // Data timeout
+        DTO_ERR OFFSET(20) NUMBITS(1) [],
+
+        // Command index error
+        CBAD_ERR OFFSET(19) NUMBITS(1) [],
+
+        // Command end bit error
+        CEND_ERR OFFSET(18) NUMBITS(1) [],
+
+        // Command CRC error
+        CCRC_ERR OFFSET(17) NUMBITS(1) [],
+
+        // Command timeout
+        CTO_ERR OFFSET(16) NUMBITS(1) [],
+
+        // Any error
+        ERR OFFSET(15) NUMBITS(1) [],
+
+        // The DATA register can be read
+        READ_RDY OFFSET(5) NUMBITS(1) [],
+
+        // The DATA register can be written
+        WRITE_RDY OFFSET(4) NUMBITS(1) [],
+
+        // Data transfer done
+        DATA_DONE OFFSET(1) NUMBITS(1) [],
+
+        // Command done
+        CMD_DONE OFFSET(0) NUMBITS(1) []
+    ],
+
+    // Slot interrupt status and version
+    SLOTISR_VER [
+        // Host Controller specification version, 2 is 3.00
+        SDVERSION OFFSET(16) NUMBITS(8) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => ARG2: ReadWrite<u32>),
+        (0x04 => BLKSIZECNT: ReadWrite<u32, BLKSIZECNT::Register>),
+        (0x08 => ARG1: ReadWrite<u32>),
+        (0x0C => CMDTM: ReadWrite<u32, CMDTM::Register>),
+        (0x10 => RESP: [ReadOnly<u32>; 4]),
+        (0x20 => DATA: ReadWrite<u32>),
+        (0x24 => STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x28 => CONTROL0: ReadWrite<u32, CONTROL0::Register>),
+        (0x2C => CONTROL1: ReadWrite<u32, CONTROL1::Register>),
+        (0x30 => INTERRUPT: ReadWrite<u32, INTERRUPT::Register>),
+        (0x34 => IRPT_MASK: ReadWrite<u32, INTERRUPT::Register>),
+        (0x38 => IRPT_EN: ReadWrite<u32, INTERRUPT::Register>),
+        (0x3C => _reserved1),
+        (0xFC => SLOTISR_VER: ReadOnly<u32, SLOTISR_VER::Register>),
+        (0x100 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+// Clock rates in Hz
+const IDENTIFICATION_CLOCK_RATE: u32 = 400_000;
+const DEFAULT_SPEED_CLOCK_RATE: u32 = 25_000_000;
+const HIGH_SPEED_CLOCK_RATE: u32 = 50_000_000;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
+const DATA_TIMEOUT: Duration = Duration::from_millis(500);
+// ACMD41 keeps the card busy for up to a second
+const POWER_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Attempts of a read or write
+const RETRIES: usize = 3;
+
+// Blocks of one read or write command
+const MAX_BLOCKS_PER_COMMAND: usize = 0xFFFF;
+
+// Commands
+const GO_IDLE_STATE: u32 = 0;
+const ALL_SEND_CID: u32 = 2;
+const SEND_RELATIVE_ADDR: u32 = 3;
+const SWITCH_FUNC: u32 = 6;
+const SELECT_CARD: u32 = 7;
+const SEND_IF_COND: u32 = 8;
+const SEND_CSD: u32 = 9;
+const STOP_TRANSMISSION: u32 = 12;
+const SET_BLOCKLEN: u32 = 16;
+const READ_SINGLE_BLOCK: u32 = 17;
+const READ_MULTIPLE_BLOCK: u32 = 18;
+const WRITE_BLOCK: u32 = 24;
+const WRITE_MULTIPLE_BLOCK: u32 = 25;
+const APP_CMD: u32 = 55;
+
+// Application commands, after APP_CMD
+const SET_BUS_WIDTH: u32 = 6;
+const SD_SEND_OP_COND: u32 = 41;
+const SEND_SCR: u32 = 51;
+
+// SEND_IF_COND argument, 2.7-3.6V and a check pattern the card echoes
+const IF_COND_CHECK: u32 = 0x1AA;
+
+// SD_SEND_OP_COND bits
+const OCR_BUSY: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+const OCR_VOLTAGE_WINDOW: u32 = 0x00FF_8000;
+
+// SWITCH_FUNC argument, switch function group 1 to high speed
+const SWITCH_HIGH_SPEED: u32 = 0x80FF_FFF1;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Response {
+    None,
+    R1,
+    // R1 and busy on DAT0
+    R1b,
+    // CID or CSD
+    R2,
+    // OCR, without CRC
+    R3,
+    R6,
+    R7,
+}
+
+// Data of a command, a whole number of blocks
+enum Data<'a> {
+    None,
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+struct SDHCIInner {
+    registers: Registers,
+    // rate of the controller clock
+    base_clock_rate: u32,
+    // interrupt flags seen and not yet waited for
+    events: InMemoryRegister<u32, INTERRUPT::Register>,
+    card: Option<Card>,
+    // relative card address
+    rca: u32,
+    // a command sequence is running
+    busy: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SDError {
+    NoCard,
+    CommandTimeout,
+    CommandError,
+    DataTimeout,
+    DataError,
+    // past the end of the card or not a whole number of blocks
+    OutOfRange,
+    // another command sequence is running
+    Busy,
+    Unsupported,
+}
+
+// An identified card
+#[derive(Debug, Clone, Copy)]
+pub struct Card {
+    pub blocks: u64,
+    // SDHC or SDXC, addressed by block instead of byte
+    pub high_capacity: bool,
+    pub bus_width: u8,
+    pub high_speed: bool,
+}
+
+// Representation of the SD card controller
+pub struct SDHCI {
+    inner: IRQSafeNullLock<SDHCIInner>,
+    clock: bsp::clock::Clock,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for SDError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str((*self).into())
+    }
+}
+
+impl From<SDError> for &'static str {
+    fn from(error: SDError) -> Self {
+        match error {
+            SDError::NoCard => "No SD card",
+            SDError::CommandTimeout => "SD command timeout",
+            SDError::CommandError => "SD command error",
+            SDError::DataTimeout => "SD data timeout",
+            SDError::DataError => "SD data error",
+            SDError::OutOfRange => "SD block out of range",
+            SDError::Busy => "SD card busy",
+            SDError::Unsupported => "SD card not supported",
+        }
+    }
+}
+
+impl From<SDError> for block::BlockError {
+    fn from(error: SDError) -> Self {
+        match error {
+            SDError::OutOfRange => block::BlockError::OutOfRange,
+            _ => block::BlockError::Device(error.into()),
+        }
+    }
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} MiB {}, {}-bit, {}",
+            self.blocks * SDHCI::BLOCK_SIZE as u64 / (1024 * 1024),
+            if self.high_capacity {
+                "SDHC/SDXC"
+            } else {
+                "SDSC"
+            },
+            self.bus_width,
+            if self.high_speed {
+                "high speed"
+            } else {
+                "default speed"
+            }
+        )
+    }
+}
+
+impl SDHCI {
+    pub const COMPATIBLE: &'static str = "SDHCI SD card";
+
+    pub const BLOCK_SIZE: usize = 512;
+
+    // `clock` is the firmware clock of the controller
+    pub const unsafe fn new(
+        mmio_start_addr: usize,
+        clock: bsp::clock::Clock,
+    ) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(SDHCIInner::new(mmio_start_addr)),
+            clock,
+        }
+    }
+
+    // The card, `None` if there is none or it could not be identified
+    pub fn card(&self) -> Option<Card> {
+        self.inner.lock(|inner| inner.card)
+    }
+
+    // Identify the card once its pins are mapped. A missing card must not stop the kernel.
+    pub fn detect(&self) -> Option<Card> {
+        match self.exclusive(|| self.identify()) {
+            Ok(card) => {
+                info!("SD card: {}", card);
+                Some(card)
+            }
+            Err(e) => {
+                warn!("SD card: {}", e);
+                None
+            }
+        }
+    }
+
+    // Read whole blocks from `block` on into `buffer`
+    pub fn read_blocks(
+        &self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), SDError> {
+        self.exclusive(|| {
+            let card = self.check_range(block, buffer.len())?;
+
+            for (i, chunk) in buffer
+                .chunks_mut(MAX_BLOCKS_PER_COMMAND * Self::BLOCK_SIZE)
+                .enumerate()
+            {
+                let block = block + (i * MAX_BLOCKS_PER_COMMAND) as u64;
+                let command = if chunk.len() > Self::BLOCK_SIZE {
+                    READ_MULTIPLE_BLOCK
+                } else {
+                    READ_SINGLE_BLOCK
+                };
+
+                self.retry(|| {
+                    self.command(
+                        command,
+                        card.address(block),
+                        Response::R1,
+                        Data::Read(&mut *chunk),
+                    )
+                })?;
+            }
+
+            Ok(())
+        })
+    }
+
+    // Write whole blocks from `block` on from `buffer`
+    pub fn write_blocks(
+        &self,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), SDError> {
+        self.exclusive(|| {
+            let card = self.check_range(block, buffer.len())?;
+
+            for (i, chunk) in buffer
+                .chunks(MAX_BLOCKS_PER_COMMAND * Self::BLOCK_SIZE)
+                .enumerate()
+            {
+                let block = block + (i * MAX_BLOCKS_PER_COMMAND) as u64;
+                let command = if chunk.len() > Self::BLOCK_SIZE {
+                    WRITE_MULTIPLE_BLOCK
+                } else {
+                    WRITE_BLOCK
+                };
+
+                self.retry(|| {
+                    self.command(
+                        command,
+                        card.address(block),
+                        Response::R1,
+                        Data::Write(chunk),
+                    )
+                })?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl driver::interface::DeviceDriver for SDHCI {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        use bsp::clock::{clock_manager, PowerDomain};
+        use clock::interface::ClockManager;
+
+        let base_clock_rate =
+            clock_manager().enable(PowerDomain::SdCard, self.clock)?;
+        self.inner.lock(|inner| {
+            inner.base_clock_rate = base_clock_rate;
+            inner.reset()
+        })?;
+
+        Ok(())
+    }
+}
+
+// The card as a block device, empty while there is none
+impl BlockDevice for SDHCI {
+    fn block_size(&self) -> usize {
+        Self::BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.card().map_or(0, |card| card.blocks)
+    }
+
+    fn read_blocks(
+        &self,
+        block: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), block::BlockError> {
+        SDHCI::read_blocks(self, block, buffer).map_err(Into::into)
+    }
+
+    fn write_blocks(
+        &self,
+        block: u64,
+        buffer: &[u8],
+    ) -> Result<(), block::BlockError> {
+        SDHCI::write_blocks(self, block, buffer).map_err(Into::into)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Card {
+    // Argument of the read and write commands
+    fn address(&self, block: u64) -> u32 {
+        if self.high_capacity {
+            block as u32
+        } else {
+            (block * SDHCI::BLOCK_SIZE as u64) as u32
+        }
+    }
+}
+
+impl SDHCI {
+    // Run a command sequence, one at a time
+    fn exclusive<R>(
+        &self,
+        f: impl FnOnce() -> Result<R, SDError>,
+    ) -> Result<R, SDError> {
+        self.inner.lock(|inner| {
+            if inner.busy {
+                return Err(SDError::Busy);
+            }
+            inner.busy = true;
+            Ok(())
+        })?;
+
+        let result = f();
+        self.inner.lock(|inner| inner.busy = false);
+
+        result
+    }
+
+    // The card, identified if it is not known yet, if `len` bytes from `block` are on it
+    fn check_range(&self, block: u64, len: usize) -> Result<Card, SDError> {
+        let card = match self.card() {
+            Some(card) => card,
+            None => self.identify()?,
+        };
+
+        let blocks = (len / Self::BLOCK_SIZE) as u64;
+        if !len.is_multiple_of(Self::BLOCK_SIZE) || block + blocks > card.blocks
+        {
+            return Err(SDError::OutOfRange);
+        }
+
+        Ok(card)
+    }
+
+    // Run `f` again after errors, identifying the card again when it stops answering
+    fn retry(
+        &self,
+        mut f: impl FnMut() -> Result<[u32; 4], SDError>,
+    ) -> Result<(), SDError> {
+        let mut result = Ok([0; 4]);
+
+        for _ in 0..RETRIES {
+            result = f();
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(SDError::CommandTimeout) => {
+                    self.inner.lock(|inner| inner.card = None);
+                    self.identify()?;
+                }
+                Err(_) => {}
+            }
+        }
+
+        result.map(|_| ())
+    }
+
+    // Reset the controller and bring the card to the transfer state
+    fn identify(&self) -> Result<Card, SDError> {
+        self.inner.lock(|inner| {
+            inner.card = None;
+            inner.reset()
+        })?;
+        self.set_clock(IDENTIFICATION_CLOCK_RATE)?;
+
+        self.command(GO_IDLE_STATE, 0, Response::None, Data::None)?;
+
+        // version 2 cards echo the check pattern, version 1 cards do not answer
+        let version2 = match self.command(
+            SEND_IF_COND,
+            IF_COND_CHECK,
+            Response::R7,
+            Data::None,
+        ) {
+            Ok(response) if response[0] & 0xFFF == IF_COND_CHECK => true,
+            Ok(_) => return Err(SDError::Unsupported),
+            Err(SDError::CommandTimeout) => false,
+            Err(e) => return Err(e),
+        };
+
+        let ocr = self.power_up(version2)?;
+        let high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+
+        self.command(ALL_SEND_CID, 0, Response::R2, Data::None)?;
+        let response =
+            self.command(SEND_RELATIVE_ADDR, 0, Response::R6, Data::None)?;
+        let rca = response[0] & 0xFFFF_0000;
+        self.inner.lock(|inner| inner.rca = rca);
+
+        let csd = self.command(SEND_CSD, rca, Response::R2, Data::None)?;
+        let blocks = csd_blocks(&csd).ok_or(SDError::Unsupported)?;
+
+        self.command(SELECT_CARD, rca, Response::R1b, Data::None)?;
+        self.set_clock(DEFAULT_SPEED_CLOCK_RATE)?;
+
+        if !high_capacity {
+            self.command(
+                SET_BLOCKLEN,
+                Self::BLOCK_SIZE as u32,
+                Response::R1,
+                Data::None,
+            )?;
+        }
+
+        let mut scr = [0; 8];
+        self.app_command(SEND_SCR, 0, Response::R1, Data::Read(&mut scr))?;
+        let scr = u64::from_be_bytes(scr);
+
+        let mut card = Card {
+            blocks,
+            high_capacity,
+            bus_width: 1,
+            high_speed: false,
+        };
+
+        // SD_BUS_WIDTHS
+        if (scr >> 48) & 0b0100 != 0 {
+            self.app_command(SET_BUS_WIDTH, 2, Response::R1, Data::None)?;
+            self.inner.lock(|inner| {
+                inner.registers.CONTROL0.modify(CONTROL0::HCTL_DWIDTH::SET)
+            });
+            card.bus_width = 4;
+        }
+
+        // SD_SPEC, SWITCH_FUNC is there from version 1.10 on
+        if (scr >> 56) & 0xF >= 1 {
+            card.high_speed = self.switch_to_high_speed()?;
+        }
+
+        self.inner.lock(|inner| inner.card = Some(card));
+
+        Ok(card)
+    }
+
+    // Wait until the card has powered up and return its OCR
+    fn power_up(&self, version2: bool) -> Result<u32, SDError> {
+        let argument = if version2 {
+            OCR_VOLTAGE_WINDOW | OCR_HIGH_CAPACITY
+        } else {
+            OCR_VOLTAGE_WINDOW
+        };
+        let deadline = time::time_manager().uptime() + POWER_UP_TIMEOUT;
+
+        loop {
+            let response = self.app_command(
+                SD_SEND_OP_COND,
+                argument,
+                Response::R3,
+                Data::None,
+            )?;
+            if response[0] & OCR_BUSY != 0 {
+                return Ok(response[0]);
+            }
+
+            if time::time_manager().uptime() > deadline {
+                return Err(SDError::CommandTimeout);
+            }
+            time::time_manager().spin_for(Duration::from_millis(10));
+        }
+    }
+
+    // True if the card and the controller now run at high speed
+    fn switch_to_high_speed(&self) -> Result<bool, SDError> {
+        let mut status = [0; 64];
+        self.command(
+            SWITCH_FUNC,
+            SWITCH_HIGH_SPEED,
+            Response::R1,
+            Data::Read(&mut status),
+        )?;
+
+        // the function selected for group 1
+        if status[16] & 0xF != 1 {
+            return Ok(false);
+        }
+
+        self.inner.lock(|inner| {
+            inner.registers.CONTROL0.modify(CONTROL0::HCTL_HS_EN::SET)
+        });
+        self.set_clock(HIGH_SPEED_CLOCK_RATE)?;
+
+        Ok(true)
+    }
+
+    fn app_command(
+        &self,
+        index: u32,
+        argument: u32,
+        response: Response,
+        data: Data<'_>,
+    ) -> Result<[u32; 4], SDError> {
+        let rca = self.inner.lock(|inner| inner.rca);
+
+        self.command(APP_CMD, rca, Response::R1, Data::None)?;
+        self.command(index, argument, response, data)
+    }
+
+    // Send a command, move its data and return its response. Errors leave the controller ready for
+    // the next command.
+    fn command(
+        &self,
+        index: u32,
+        argument: u32,
+        response: Response,
+        data: Data<'_>,
+    ) -> Result<[u32; 4], SDError> {
+        let result = self.try_command(index, argument, response, data);
+
+        if result.is_err() {
+            let multi_block =
+                index == READ_MULTIPLE_BLOCK || index == WRITE_MULTIPLE_BLOCK;
+
+            self.inner.lock(|inner| inner.recover());
+            if multi_block {
+                // the card may still be sending or receiving
+                let _ = self.try_command(
+                    STOP_TRANSMISSION,
+                    0,
+                    Response::R1b,
+                    Data::None,
+                );
+                self.inner.lock(|inner| inner.recover());
+            }
+        }
+
+        result
+    }
+
+    fn try_command(
+        &self,
+        index: u32,
+        argument: u32,
+        response: Response,
+        mut data: Data<'_>,
+    ) -> Result<[u32; 4], SDError> {
+        let uses_data =
+            !matches!(data, Data::None) || response == Response::R1b;
+
+        self.wait_until(COMMAND_TIMEOUT, |inner| inner.is_idle(uses_data))
+            .map_err(|_| SDError::CommandTimeout)?;
+        self.inner
+            .lock(|inner| inner.send_command(index, argument, response, &data));
+
+        self.wait_event(INTERRUPT::CMD_DONE, COMMAND_TIMEOUT)?;
+        let response = self.inner.lock(|inner| inner.response());
+
+        match &mut data {
+            Data::None => {}
+            Data::Read(buffer) => {
+                for block in buffer.chunks_mut(block_size(buffer.len())) {
+                    self.wait_event(INTERRUPT::READ_RDY, DATA_TIMEOUT)?;
+                    self.inner.lock(|inner| inner.read_block(block));
+                }
+            }
+            Data::Write(buffer) => {
+                for block in buffer.chunks(block_size(buffer.len())) {
+                    self.wait_event(INTERRUPT::WRITE_RDY, DATA_TIMEOUT)?;
+                    self.inner.lock(|inner| inner.write_block(block));
+                }
+            }
+        }
+
+        if !matches!(data, Data::None) {
+            self.wait_event(INTERRUPT::DATA_DONE, DATA_TIMEOUT)?;
+        }
+
+        Ok(response)
+    }
+
+    // Set the SD clock to at most `rate`
+    fn set_clock(&self, rate: u32) -> Result<(), SDError> {
+        self.wait_until(COMMAND_TIMEOUT, |inner| inner.is_idle(true))
+            .map_err(|_| SDError::CommandTimeout)?;
+        self.inner.lock(|inner| inner.start_clock(rate));
+
+        self.wait_until(COMMAND_TIMEOUT, |inner| {
+            inner.registers.CONTROL1.is_set(CONTROL1::CLK_STABLE)
+        })
+        .map_err(|_| SDError::Unsupported)?;
+        self.inner.lock(|inner| {
+            inner.registers.CONTROL1.modify(CONTROL1::CLK_EN::SET)
+        });
+
+        Ok(())
+    }
+
+    // Poll for an interrupt flag and take it
+    fn wait_event(
+        &self,
+        event: Field<u32, INTERRUPT::Register>,
+        timeout: Duration,
+    ) -> Result<(), SDError> {
+        let timeout_error = if timeout == DATA_TIMEOUT {
+            SDError::DataTimeout
+        } else {
+            SDError::CommandTimeout
+        };
+
+        self.wait_until(timeout, |inner| {
+            inner.collect_events();
+            inner.events.is_set(event) || inner.events.is_set(INTERRUPT::ERR)
+        })
+        .map_err(|_| timeout_error)?;
+
+        self.inner.lock(|inner| {
+            inner.error()?;
+            inner.events.modify(event.val(0));
+            Ok(())
+        })
+    }
+
+    // Spin until `condition` holds, `Err` after `timeout`
+    fn wait_until(
+        &self,
+        timeout: Duration,
+        mut condition: impl FnMut(&mut SDHCIInner) -> bool,
+    ) -> Result<(), ()> {
+        let deadline = time::time_manager().uptime() + timeout;
+
+        loop {
+            if self.inner.lock(&mut condition) {
+                return Ok(());
+            }
+            if time::time_manager().uptime() > deadline {
+                return Err(());
+            }
+            cpu::nop();
+        }
+    }
+}
+
+impl SDHCIInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            base_clock_rate: 0,
+            events: InMemoryRegister::new(0),
+            card: None,
+            rca: 0,
+            busy: false,
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), SDError> {
+        self.registers.CONTROL0.set(0);
+        self.registers.CONTROL1.write(CONTROL1::SRST_HC::SET);
+        if !self.wait_for_reset() {
+            return Err(SDError::Unsupported);
+        }
+
+        #[cfg(feature = "bsp_rpi4")]
+        self.registers.CONTROL0.write(
+            CONTROL0::SD_BUS_POWER::SET + CONTROL0::SD_BUS_VOLTAGE::V3_3,
+        );
+
+        // every flag is latched, none raises the interrupt line
+        self.registers.IRPT_MASK.set(0xFFFF_FFFF);
+        self.registers.IRPT_EN.set(0);
+        self.registers.INTERRUPT.set(0xFFFF_FFFF);
+        self.events.set(0);
+        self.rca = 0;
+
+        Ok(())
+    }
+
+    // Reset the lines an error came from and forget the events
+    fn recover(&mut self) {
+        self.collect_events();
+
+        let command_errors = INTERRUPT::CTO_ERR::SET
+            + INTERRUPT::CCRC_ERR::SET
+            + INTERRUPT::CEND_ERR::SET
+            + INTERRUPT::CBAD_ERR::SET;
+        let reset = if self.events.matches_any(command_errors) {
+            CONTROL1::SRST_CMD::SET + CONTROL1::SRST_DATA::SET
+        } else {
+            CONTROL1::SRST_DATA::SET
+        };
+
+        self.registers.CONTROL1.modify(reset);
+        // the reset takes a few controller clocks
+        let _ = self.wait_for_reset();
+
+        self.registers.INTERRUPT.set(0xFFFF_FFFF);
+        self.events.set(0);
+    }
+
+    fn wait_for_reset(&self) -> bool {
+        let deadline = time::time_manager().uptime() + COMMAND_TIMEOUT;
+
+        while self.registers.CONTROL1.matches_any(
+            CONTROL1::SRST_HC::SET
+                + CONTROL1::SRST_CMD::SET
+                + CONTROL1::SRST_DATA::SET,
+        ) {
+            if time::time_manager().uptime() > deadline {
+                return false;
+            }
+            cpu::nop();
+        }
+
+        true
+    }
+
+    // Stop the SD clock and start the internal clock at the new rate
+    fn start_clock(&mut self, rate: u32) {
+        let version = self.registers.SLOTISR_VER.read(SLOTISR_VER::SDVERSION);
+        let divider = clock_divider(self.base_clock_rate, rate, version >= 2);
+
+        self.registers.CONTROL1.modify(CONTROL1::CLK_EN::CLEAR);
+        self.registers.CONTROL1.modify(
+            CONTROL1::CLK_FREQ8.val(divider & 0xFF)
+                + CONTROL1::CLK_FREQ_MS2.val(divider >> 8)
+                + CONTROL1::DATA_TOUNIT::Max
+                + CONTROL1::CLK_INTLEN::SET,
+        );
+    }
+
+    fn is_idle(&self, uses_data: bool) -> bool {
+        let status = self.registers.STATUS.extract();
+
+        !status.is_set(STATUS::CMD_INHIBIT)
+            && (!uses_data || !status.is_set(STATUS::DAT_INHIBIT))
+    }
+
+    fn send_command(
+        &mut self,
+        index: u32,
+        argument: u32,
+        response: Response,
+        data: &Data<'_>,
+    ) {
+        let response_type = match response {
+            Response::None => CMDTM::CMD_RSPNS_TYPE::None,
+            Response::R2 => {
+                CMDTM::CMD_RSPNS_TYPE::Bits136 + CMDTM::CMD_CRCCHK_EN::SET
+            }
+            Response::R3 => CMDTM::CMD_RSPNS_TYPE::Bits48,
+            Response::R1b => {
+                CMDTM::CMD_RSPNS_TYPE::Bits48Busy
+                    + CMDTM::CMD_CRCCHK_EN::SET
+                    + CMDTM::CMD_IXCHK_EN::SET
+            }
+            Response::R1 | Response::R6 | Response::R7 => {
+                CMDTM::CMD_RSPNS_TYPE::Bits48
+                    + CMDTM::CMD_CRCCHK_EN::SET
+                    + CMDTM::CMD_IXCHK_EN::SET
+            }
+        };
+
+        let (len, direction) = match data {
+            Data::None => (0, CMDTM::TM_DAT_DIR::HostToCard),
+            Data::Read(buffer) => (buffer.len(), CMDTM::TM_DAT_DIR::CardToHost),
+            Data::Write(buffer) => {
+                (buffer.len(), CMDTM::TM_DAT_DIR::HostToCard)
+            }
+        };
+        let mut command = CMDTM::CMD_INDEX.val(index) + response_type;
+
+        if len > 0 {
+            let block_size = block_size(len);
+            let blocks = len / block_size;
+
+            self.registers.BLKSIZECNT.write(
+                BLKSIZECNT::BLKSIZE.val(block_size as u32)
+                    + BLKSIZECNT::BLKCNT.val(blocks as u32),
+            );
+            command +=
+                CMDTM::CMD_ISDATA::SET + CMDTM::TM_BLKCNT_EN::SET + direction;
+            if blocks > 1 {
+                command +=
+                    CMDTM::TM_MULTI_BLOCK::SET + CMDTM::TM_AUTO_CMD_EN::Cmd12;
+            }
+        }
+
+        self.registers.ARG1.set(argument);
+        self.registers.CMDTM.write(command);
+    }
+
+    // RESP0-3, 136-bit responses without their CRC are shifted into place
+    fn response(&self) -> [u32; 4] {
+        let mut response = [0; 4];
+        for (word, register) in response.iter_mut().zip(&self.registers.RESP) {
+            *word = register.get();
+        }
+
+        response
+    }
+
+    fn read_block(&mut self, block: &mut [u8]) {
+        for bytes in block.chunks_mut(4) {
+            let word = self.registers.DATA.get().to_le_bytes();
+            bytes.copy_from_slice(&word[..bytes.len()]);
+        }
+    }
+
+    fn write_block(&mut self, block: &[u8]) {
+        for bytes in block.chunks(4) {
+            let mut word = [0; 4];
+            word[..bytes.len()].copy_from_slice(bytes);
+            self.registers.DATA.set(u32::from_le_bytes(word));
+        }
+    }
+
+    // Move the interrupt flags into `events` and acknowledge them
+    fn collect_events(&mut self) {
+        let flags = self.registers.INTERRUPT.get();
+
+        self.registers.INTERRUPT.set(flags);
+        self.events.set(self.events.get() | flags);
+    }
+
+    fn error(&self) -> Result<(), SDError> {
+        let events = &self.events;
+
+        if !events.is_set(INTERRUPT::ERR) {
+            Ok(())
+        } else if events.is_set(INTERRUPT::CTO_ERR) {
+            Err(SDError::CommandTimeout)
+        } else if events.is_set(INTERRUPT::DTO_ERR) {
+            Err(SDError::DataTimeout)
+        } else if events.matches_any(
+            INTERRUPT::DCRC_ERR::SET
+                + INTERRUPT::DEND_ERR::SET
+                + INTERRUPT::ACMD_ERR::SET,
+        ) {
+            Err(SDError::DataError)
+        } else {
+            Err(SDError::CommandError)
+        }
+    }
+}
+
+// Data moves in 512-byte blocks, shorter transfers are one block
+fn block_size(len: usize) -> usize {
+    len.min(SDHCI::BLOCK_SIZE)
+}
+
+// CLK_FREQ8 and CLK_FREQ_MS2 for at most `rate`, `base_rate / (2 * divider)`. Controllers before
+// version 3.00 divide by powers of two only.
+fn clock_divider(base_rate: u32, rate: u32, ten_bit: bool) -> u32 {
+    if base_rate <= rate {
+        return 0;
+    }
+
+    let divider = base_rate.div_ceil(2 * rate);
+    if ten_bit {
+        divider.min(0x3FF)
+    } else {
+        divider.next_power_of_two().min(0x80)
+    }
+}
+
+// Card size in 512-byte blocks from the CSD in a 136-bit response
+fn csd_blocks(response: &[u32; 4]) -> Option<u64> {
+    // the response lacks the CRC byte of the CSD
+    let csd = ((response[3] as u128) << 96
+        | (response[2] as u128) << 64
+        | (response[1] as u128) << 32
+        | response[0] as u128)
+        << 8;
+    let bits = |high: u32, low: u32| {
+        ((csd >> low) & ((1u128 << (high - low + 1)) - 1)) as u64
+    };
+
+    match bits(127, 126) {
+        // SDSC
+        0 => {
+            let block_len = bits(83, 80);
+            let size = bits(73, 62);
+            let multiplier = bits(49, 47);
+
+            Some((size + 1) << (multiplier + 2) << block_len >> 9)
+        }
+        // SDHC and SDXC, in 512 KiB units
+        1 => Some((bits(69, 48) + 1) * 1024),
+        _ => None,
+    }
+}
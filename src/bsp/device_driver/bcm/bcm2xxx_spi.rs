@@ -0,0 +1,630 @@
+// SPI0 master driver
+//
+// Transfers are full duplex: every byte sent clocks one in. A transfer is either polled by the
+// caller, or moved by the interrupt handler while the caller waits for it. With interrupts
+// masked the caller does the work in both cases.
+//
+// Devices on the bus are used through `SPIDevice`, which carries the mode, clock rate and chip
+// select of one device and implements `embedded-hal`'s `SpiDevice`. The chip select stays asserted
+// for a whole transaction, delays included. With `ChipSelect::None` no chip select line is driven,
+// for devices selected by a GPIO. There is no `SpiBus`, which must leave the chip select alone:
+// every transfer here is a transaction of one device.
+//
+// The DMA request lines of the controller are left off until there is a DMA driver.
+
+use crate::{
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    clock, cpu, driver, exception,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+};
+use core::{fmt, time::Duration};
+use embedded_hal::spi::{ErrorKind, Mode, Operation, Phase, Polarity};
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+// Descriptions taken from
+// raspberypi 3ap
+// - https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+// raspberypi 4b
+// - https://datasheets.raspberrypi.org/bcm2711/bcm2711-peripherals.pdf
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// SPI registers
+register_bitfields! {
+    u32,
+
+    // Control and Status
+    CS [
+        // Chip select 1 active high
+        CSPOL1 OFFSET(22) NUMBITS(1) [],
+
+        // Chip select 0 active high
+        CSPOL0 OFFSET(21) NUMBITS(1) [],
+
+        // RX FIFO contains data
+        RXD OFFSET(17) NUMBITS(1) [],
+
+        // TX FIFO can accept data
+        TXD OFFSET(18) NUMBITS(1) [],
+
+        // Transfer done, the TX FIFO is empty
+        DONE OFFSET(16) NUMBITS(1) [],
+
+        // Interrupt while the RX FIFO is 3/4 full
+        INTR OFFSET(10) NUMBITS(1) [],
+
+        // Interrupt on done
+        INTD OFFSET(9) NUMBITS(1) [],
+
+        // Transfer active, the chip select is asserted while set
+        TA OFFSET(7) NUMBITS(1) [],
+
+        // Chip select polarity
+        CSPOL OFFSET(6) NUMBITS(1) [],
+
+        // Clear the FIFOs
+        CLEAR OFFSET(4) NUMBITS(2) [
+            Both = 0b11
+        ],
+
+        // Clock polarity, idle high if set
+        CPOL OFFSET(3) NUMBITS(1) [],
+
+        // Clock phase, sample on the second edge if set
+        CPHA OFFSET(2) NUMBITS(1) [],
+
+        // Chip select line
+        CS OFFSET(0) NUMBITS(2) [
+            Ce0 = 0b00,
+            Ce1 = 0b01,
+            // not wired to a pin
+            None = 0b10
+        ]
+    ],
+
+    // TX and RX FIFO
+    FIFO [
+        DATA OFFSET(0) NUMBITS(8) []
+    ],
+
+    // Clock divider, always even
+    CLK [
+        CDIV OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => FIFO: ReadWrite<u32, FIFO::Register>),
+        (0x08 => CLK: ReadWrite<u32, CLK::Register>),
+        (0x0C => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+// Where a transfer is in its operations
+#[derive(Clone, Copy)]
+struct Cursor {
+    operation: usize,
+    position: usize,
+}
+
+// A run of operations in flight, without delays. The operations belong to the caller, which
+// waits until the transfer is finished or taken back before it returns. Bytes are sent ahead of
+// the ones received by up to a FIFO.
+struct Transfer {
+    operations: *mut [Operation<'static, u8>],
+    sent: Cursor,
+    received: Cursor,
+    finished: bool,
+}
+
+// Only touched by the caller and the interrupt handler, under the lock
+unsafe impl Send for Transfer {}
+
+struct SPIInner {
+    registers: Registers,
+    core_clock_rate: u32,
+    transfer: Option<Transfer>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SPIError {
+    // another transfer is in progress
+    Busy,
+    // the transfer did not finish in time
+    Timeout,
+    // the core clock cannot be divided down to the clock rate
+    InvalidClockRate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChipSelect {
+    Ce0,
+    Ce1,
+    // no line is driven
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    // the caller moves the bytes
+    Polled,
+    // the interrupt handler moves the bytes
+    Interrupt,
+}
+
+// Settings of a device on the bus
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub mode: Mode,
+    // SCLK in Hz, the clock may end up slower
+    pub clock_rate: u32,
+    pub chip_select: ChipSelect,
+    pub chip_select_active_high: bool,
+    pub transfer_mode: TransferMode,
+}
+
+// A device on the bus
+pub struct SPIDevice {
+    spi: &'static SPI,
+    config: Config,
+    // even divider of the core clock
+    divider: u32,
+}
+
+// Representation of the SPI0 controller
+pub struct SPI {
+    inner: IRQSafeNullLock<SPIInner>,
+    irq_number: bsp::device_driver::IRQNumber,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for SPIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str((*self).into())
+    }
+}
+
+impl From<SPIError> for &'static str {
+    fn from(error: SPIError) -> Self {
+        match error {
+            SPIError::Busy => "SPI bus busy",
+            SPIError::Timeout => "SPI transfer timeout",
+            SPIError::InvalidClockRate => "SPI clock rate not supported",
+        }
+    }
+}
+
+impl Config {
+    // Mode 0 at `clock_rate` on CE0, active low, polled
+    pub const fn new(clock_rate: u32) -> Self {
+        Self {
+            mode: embedded_hal::spi::MODE_0,
+            clock_rate,
+            chip_select: ChipSelect::Ce0,
+            chip_select_active_high: false,
+            transfer_mode: TransferMode::Polled,
+        }
+    }
+}
+
+impl SPI {
+    pub const COMPATIBLE: &'static str = "BCM SPI0";
+
+    pub const unsafe fn new(
+        mmio_start_addr: usize,
+        irq_number: bsp::device_driver::IRQNumber,
+    ) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(SPIInner::new(mmio_start_addr)),
+            irq_number,
+        }
+    }
+
+    // A device on the bus with `config`
+    pub fn device(
+        &'static self,
+        config: Config,
+    ) -> Result<SPIDevice, SPIError> {
+        let core_clock_rate = self.inner.lock(|inner| inner.core_clock_rate);
+        if config.clock_rate == 0 {
+            return Err(SPIError::InvalidClockRate);
+        }
+
+        // round up to the next even divider, so the clock rate is never exceeded
+        let divider = core_clock_rate.div_ceil(config.clock_rate);
+        let divider = divider + (divider & 1);
+        if !(2..=0xFFFE).contains(&divider) {
+            return Err(SPIError::InvalidClockRate);
+        }
+
+        Ok(SPIDevice {
+            spi: self,
+            config,
+            divider,
+        })
+    }
+
+    // Run `operations` with the chip select of `device` asserted
+    fn transaction(
+        &self,
+        device: &SPIDevice,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), SPIError> {
+        self.inner.lock(|inner| {
+            if inner.transfer.is_some() {
+                return Err(SPIError::Busy);
+            }
+
+            inner.select(device);
+            Ok(())
+        })?;
+
+        let result = self.run_with_delays(device, operations);
+
+        self.inner.lock(|inner| inner.deselect());
+
+        result
+    }
+
+    // Run the operations between the delays one after another
+    fn run_with_delays(
+        &self,
+        device: &SPIDevice,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), SPIError> {
+        let mut start = 0;
+
+        loop {
+            let end = operations[start..]
+                .iter()
+                .position(|operation| {
+                    matches!(operation, Operation::DelayNs(_))
+                })
+                .map_or(operations.len(), |len| start + len);
+
+            self.run(device, &mut operations[start..end])?;
+
+            match operations.get(end) {
+                Some(Operation::DelayNs(ns)) => time::time_manager()
+                    .spin_for(Duration::from_nanos(*ns as u64)),
+                _ => return Ok(()),
+            }
+            start = end + 1;
+        }
+    }
+
+    // Transfer a run of operations without delays and wait for it
+    fn run(
+        &self,
+        device: &SPIDevice,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), SPIError> {
+        // with IRQs masked the interrupt handler cannot run, so the caller moves the bytes and the
+        // controller is not asked for interrupts nobody takes
+        let polled = device.config.transfer_mode == TransferMode::Polled
+            || exception::asynchronous::is_local_irq_masked();
+        let bytes: usize = operations.iter().map(operation_len).sum();
+        if bytes == 0 {
+            return Ok(());
+        }
+
+        self.inner.lock(|inner| inner.start(operations, !polled));
+
+        // twice the time on the bus, plus some slack
+        let clock_rate = device.clock_rate() as u64;
+        let deadline = time::time_manager().uptime()
+            + Duration::from_micros(bytes as u64 * 8 * 2_000_000 / clock_rate)
+            + Duration::from_millis(10);
+
+        loop {
+            if polled {
+                self.inner.lock(|inner| inner.service());
+            }
+
+            if self.inner.lock(|inner| inner.finished()) {
+                return Ok(());
+            }
+
+            if time::time_manager().uptime() > deadline {
+                // the operations must not be touched once we return
+                return self.inner.lock(|inner| {
+                    inner.abort();
+                    Err(SPIError::Timeout)
+                });
+            }
+
+            cpu::nop();
+        }
+    }
+}
+
+impl SPIDevice {
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // The rate SCLK actually runs at
+    pub fn clock_rate(&self) -> u32 {
+        self.spi.inner.lock(|inner| inner.core_clock_rate) / self.divider
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl driver::interface::DeviceDriver for SPI {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        use bsp::clock::{clock_manager, Clock, PowerDomain};
+        use clock::interface::ClockManager;
+
+        let core_clock_rate =
+            clock_manager().enable(PowerDomain::Spi, Clock::Core)?;
+
+        self.inner.lock(|inner| {
+            inner.core_clock_rate = core_clock_rate;
+            inner.registers.CS.write(CS::CLEAR::Both + CS::CS::None);
+        });
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: Self::COMPATIBLE,
+            handler: self,
+        };
+
+        irq_manager().register_handler(self.irq_number, descriptor)?;
+        irq_manager().enable(self.irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for SPI {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.service());
+
+        Ok(())
+    }
+}
+
+impl embedded_hal::spi::Error for SPIError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl embedded_hal::spi::ErrorType for SPIDevice {
+    type Error = SPIError;
+}
+
+impl embedded_hal::spi::SpiDevice for SPIDevice {
+    fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), SPIError> {
+        self.spi.transaction(self, operations)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl SPIInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            core_clock_rate: 0,
+            transfer: None,
+        }
+    }
+
+    // Set up the bus for `device` and assert its chip select
+    fn select(&mut self, device: &SPIDevice) {
+        let config = &device.config;
+        let active_high = config.chip_select_active_high as u32;
+
+        let chip_select = match config.chip_select {
+            ChipSelect::Ce0 => CS::CS::Ce0 + CS::CSPOL0.val(active_high),
+            ChipSelect::Ce1 => CS::CS::Ce1 + CS::CSPOL1.val(active_high),
+            ChipSelect::None => CS::CS::None,
+        };
+        let clock_polarity = match config.mode.polarity {
+            Polarity::IdleLow => CS::CPOL::CLEAR,
+            Polarity::IdleHigh => CS::CPOL::SET,
+        };
+        let clock_phase = match config.mode.phase {
+            Phase::CaptureOnFirstTransition => CS::CPHA::CLEAR,
+            Phase::CaptureOnSecondTransition => CS::CPHA::SET,
+        };
+
+        self.registers.CLK.write(CLK::CDIV.val(device.divider));
+        // set the idle levels before the chip select goes active
+        self.registers.CS.write(
+            chip_select
+                + CS::CSPOL.val(active_high)
+                + clock_polarity
+                + clock_phase
+                + CS::CLEAR::Both,
+        );
+        self.registers.CS.modify(CS::TA::SET);
+    }
+
+    fn deselect(&mut self) {
+        self.registers.CS.modify(CS::TA::CLEAR);
+    }
+
+    fn start(&mut self, operations: &mut [Operation<'_, u8>], interrupt: bool) {
+        let start = Cursor {
+            operation: 0,
+            position: 0,
+        };
+
+        self.transfer = Some(Transfer {
+            operations: operations as *mut [Operation<'_, u8>] as *mut _,
+            sent: start,
+            received: start,
+            finished: false,
+        });
+
+        if interrupt {
+            // DONE is set while the TX FIFO is empty, so this fires right away
+            self.registers.CS.modify(CS::INTD::SET + CS::INTR::SET);
+        }
+    }
+
+    // Move bytes between the FIFOs and the operations
+    fn service(&mut self) {
+        let transfer = match &mut self.transfer {
+            Some(transfer) if !transfer.finished => transfer,
+            _ => return,
+        };
+
+        // read first, so the bytes in flight always fit the RX FIFO
+        while self.registers.CS.is_set(CS::RXD) {
+            let byte = self.registers.FIFO.read(FIFO::DATA) as u8;
+            transfer.receive(byte);
+        }
+
+        while self.registers.CS.is_set(CS::TXD) {
+            match transfer.next_to_send() {
+                Some(byte) => {
+                    self.registers.FIFO.write(FIFO::DATA.val(byte as u32))
+                }
+                None => break,
+            }
+        }
+
+        if transfer.is_received() {
+            transfer.finished = true;
+            self.registers.CS.modify(CS::INTD::CLEAR + CS::INTR::CLEAR);
+        }
+    }
+
+    // True once the transfer is finished, which is then given up
+    fn finished(&mut self) -> bool {
+        match &self.transfer {
+            Some(transfer) if transfer.finished => {
+                self.transfer = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn abort(&mut self) {
+        self.registers
+            .CS
+            .modify(CS::INTD::CLEAR + CS::INTR::CLEAR + CS::CLEAR::Both);
+        self.transfer = None;
+    }
+}
+
+impl Transfer {
+    fn operations(&mut self) -> &mut [Operation<'static, u8>] {
+        unsafe { &mut *self.operations }
+    }
+
+    // The next byte to send, 0 while reading only
+    fn next_to_send(&mut self) -> Option<u8> {
+        let mut cursor = self.sent;
+        let operations = self.operations();
+
+        let byte = match cursor.next(operations)? {
+            Operation::Write(bytes) | Operation::Transfer(_, bytes) => {
+                bytes.get(cursor.position).copied().unwrap_or(0)
+            }
+            Operation::TransferInPlace(bytes) => bytes[cursor.position],
+            _ => 0,
+        };
+
+        cursor.position += 1;
+        self.sent = cursor;
+
+        Some(byte)
+    }
+
+    // Store a received byte, bytes of writes are dropped
+    fn receive(&mut self, byte: u8) {
+        let mut cursor = self.received;
+        let operations = self.operations();
+
+        match cursor.next(operations) {
+            Some(Operation::Read(bytes))
+            | Some(Operation::Transfer(bytes, _))
+            | Some(Operation::TransferInPlace(bytes)) => {
+                if let Some(slot) = bytes.get_mut(cursor.position) {
+                    *slot = byte;
+                }
+            }
+            _ => {}
+        }
+
+        cursor.position += 1;
+        self.received = cursor;
+    }
+
+    fn is_received(&mut self) -> bool {
+        let mut cursor = self.received;
+        let operations = self.operations();
+
+        cursor.next(operations).is_none()
+    }
+}
+
+impl Cursor {
+    // Skip finished operations and return the one the cursor is in
+    fn next<'a>(
+        &mut self,
+        operations: &'a mut [Operation<'static, u8>],
+    ) -> Option<&'a mut Operation<'static, u8>> {
+        while self.position >= operation_len(operations.get(self.operation)?) {
+            self.operation += 1;
+            self.position = 0;
+        }
+
+        operations.get_mut(self.operation)
+    }
+}
+
+// Bytes clocked by an operation
+fn operation_len(operation: &Operation<'_, u8>) -> usize {
+    match operation {
+        Operation::Read(bytes) => bytes.len(),
+        Operation::Write(bytes) => bytes.len(),
+        Operation::Transfer(read, write) => read.len().max(write.len()),
+        Operation::TransferInPlace(bytes) => bytes.len(),
+        Operation::DelayNs(_) => 0,
+    }
+}
@@ -1,10 +1,15 @@
+pub mod board;
+pub mod clock;
+pub mod cmdline;
 pub mod console;
 pub mod cpu;
 pub mod driver;
 pub mod exception;
 pub mod frame_buffer;
+pub mod fs;
 pub mod mailbox;
 pub mod memory;
+pub mod thermal;
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -0,0 +1,355 @@
+// Board identification through the firmware
+//
+// `bsp::board_name()` only knows what the kernel was built for. The firmware knows which board it
+// is actually running on, so ask it once at boot and keep the answer.
+//
+// https://www.raspberrypi.com/documentation/computers/raspberry-pi.html#raspberry-pi-revision-codes
+
+use super::driver::MAILBOX;
+use super::mailbox::{property::*, MailBoxError};
+use crate::synchronization::{interface::ReadWriteEx, InitStateLock};
+use core::fmt;
+use tock_registers::{
+    interfaces::Readable, register_bitfields, registers::InMemoryRegister,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// New-style revision code
+//  31      26 25 24 23 22  20 19   16 15   12 11          4 3   0
+// |----------|--|--|--|------|-------|-------|-------------|-----|
+// |          |W |  |N |memory| manuf.| proc. |    type     | rev |
+register_bitfields! {u32,
+    REVISION [
+        // the warranty bit of boards that were overclocked
+        WARRANTY_VOID OFFSET(25) NUMBITS(1) [],
+
+        // set for new-style codes, old-style codes are a plain number
+        NEW_STYLE OFFSET(23) NUMBITS(1) [],
+
+        // 256 MiB << MEMORY
+        MEMORY OFFSET(20) NUMBITS(3) [],
+
+        MANUFACTURER OFFSET(16) NUMBITS(4) [],
+
+        PROCESSOR OFFSET(12) NUMBITS(4) [],
+
+        TYPE OFFSET(4) NUMBITS(8) [],
+
+        // the minor number of the PCB revision 1.x
+        PCB_REVISION OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+// Old-style codes are the low 24 bits, with the warranty bit above them
+const OLD_STYLE_MASK: u32 = 0x00FF_FFFF;
+const OLD_STYLE_WARRANTY_VOID: u32 = 1 << 24;
+
+const MIB: u64 = 1024 * 1024;
+
+// A board with an old-style revision code
+struct OldStyleBoard {
+    code: u32,
+    board_type: &'static str,
+    pcb_revision: (u8, u8),
+    memory_mib: u64,
+    manufacturer: &'static str,
+}
+
+// The first boards, all with a BCM2835
+const OLD_STYLE_BOARDS: [OldStyleBoard; 17] = [
+    OldStyleBoard::new(0x02, "Model B", (1, 0), 256, "Egoman"),
+    OldStyleBoard::new(0x03, "Model B", (1, 0), 256, "Egoman"),
+    OldStyleBoard::new(0x04, "Model B", (2, 0), 256, "Sony UK"),
+    OldStyleBoard::new(0x05, "Model B", (2, 0), 256, "Qisda"),
+    OldStyleBoard::new(0x06, "Model B", (2, 0), 256, "Egoman"),
+    OldStyleBoard::new(0x07, "Model A", (2, 0), 256, "Egoman"),
+    OldStyleBoard::new(0x08, "Model A", (2, 0), 256, "Sony UK"),
+    OldStyleBoard::new(0x09, "Model A", (2, 0), 256, "Qisda"),
+    OldStyleBoard::new(0x0D, "Model B", (2, 0), 512, "Egoman"),
+    OldStyleBoard::new(0x0E, "Model B", (2, 0), 512, "Sony UK"),
+    OldStyleBoard::new(0x0F, "Model B", (2, 0), 512, "Egoman"),
+    OldStyleBoard::new(0x10, "Model B+", (1, 2), 512, "Sony UK"),
+    OldStyleBoard::new(0x11, "Compute Module 1", (1, 0), 512, "Sony UK"),
+    OldStyleBoard::new(0x12, "Model A+", (1, 1), 256, "Sony UK"),
+    OldStyleBoard::new(0x13, "Model B+", (1, 2), 512, "Embest"),
+    OldStyleBoard::new(0x14, "Compute Module 1", (1, 0), 512, "Embest"),
+    OldStyleBoard::new(0x15, "Model A+", (1, 1), 256, "Embest"),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Revision code of the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Revision {
+    code: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+// What the firmware told about the board. Tags the firmware does not handle, as under QEMU, are
+// left as `None`.
+#[derive(Debug, Clone, Copy)]
+pub struct BoardInfo {
+    pub model: Option<u32>,
+    pub revision: Option<Revision>,
+    pub serial: Option<u64>,
+    pub mac_address: Option<MacAddress>,
+    pub firmware_revision: Option<u32>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static BOARD_INFO: InitStateLock<Option<BoardInfo>> = InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Ask the firmware about the board. Must be called during kernel init.
+pub fn init() -> Result<(), MailBoxError> {
+    let info = BoardInfo::query()?;
+    BOARD_INFO.write(|board| *board = Some(info));
+
+    Ok(())
+}
+
+// The board information, `None` before `init()` or when the firmware did not answer
+pub fn board_info() -> Option<BoardInfo> {
+    BOARD_INFO.read(|board| *board)
+}
+
+impl BoardInfo {
+    // Query all values in one message
+    pub fn query() -> Result<Self, MailBoxError> {
+        let mut msg = PropertyMessage::new();
+        let model = msg.push(GetBoardModel)?;
+        let revision = msg.push(GetBoardRevision)?;
+        let serial = msg.push(GetBoardSerial)?;
+        let mac_address = msg.push(GetBoardMacAddress)?;
+        let firmware_revision = msg.push(GetFirmwareRevision)?;
+
+        MAILBOX.call(&mut msg)?;
+
+        Ok(Self {
+            model: msg.get(&model).ok(),
+            revision: msg.get(&revision).ok().map(Revision::new),
+            serial: msg.get(&serial).ok(),
+            mac_address: msg.get(&mac_address).ok().map(MacAddress),
+            firmware_revision: msg.get(&firmware_revision).ok(),
+        })
+    }
+
+    pub fn print(&self) {
+        use crate::info;
+
+        match self.revision {
+            Some(revision) => info!("      {}", revision),
+            None => info!("      Unknown board"),
+        }
+        if let Some(serial) = self.serial {
+            info!("      Serial: {:016x}", serial);
+        }
+        if let Some(mac_address) = self.mac_address {
+            info!("      MAC address: {}", mac_address);
+        }
+        if let Some(firmware_revision) = self.firmware_revision {
+            info!("      Firmware revision: {:#010x}", firmware_revision);
+        }
+        if let Some(model) = self.model {
+            info!("      Model: {:#x}", model);
+        }
+    }
+}
+
+impl Revision {
+    pub const fn new(code: u32) -> Self {
+        Self { code }
+    }
+
+    // The raw revision code
+    pub fn code(&self) -> u32 {
+        self.code
+    }
+
+    pub fn is_new_style(&self) -> bool {
+        self.field().is_set(REVISION::NEW_STYLE)
+    }
+
+    pub fn is_warranty_void(&self) -> bool {
+        if !self.is_new_style() {
+            return self.code & OLD_STYLE_WARRANTY_VOID != 0;
+        }
+
+        self.field().is_set(REVISION::WARRANTY_VOID)
+    }
+
+    // Board type, e.g. "4 Model B"
+    pub fn board_type(&self) -> Option<&'static str> {
+        if !self.is_new_style() {
+            return self.old_style().map(|board| board.board_type);
+        }
+
+        let name = match self.field().read(REVISION::TYPE) {
+            0x00 => "Model A",
+            0x01 => "Model B",
+            0x02 => "Model A+",
+            0x03 => "Model B+",
+            0x04 => "2 Model B",
+            0x05 => "Alpha",
+            0x06 => "Compute Module 1",
+            0x08 => "3 Model B",
+            0x09 => "Zero",
+            0x0A => "Compute Module 3",
+            0x0C => "Zero W",
+            0x0D => "3 Model B+",
+            0x0E => "3 Model A+",
+            0x10 => "Compute Module 3+",
+            0x11 => "4 Model B",
+            0x12 => "Zero 2 W",
+            0x13 => "400",
+            0x14 => "Compute Module 4",
+            0x15 => "Compute Module 4S",
+            0x17 => "5",
+            0x18 => "Compute Module 5",
+            0x19 => "500",
+            0x1A => "Compute Module 5 Lite",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+
+    // PCB revision as (major, minor)
+    pub fn pcb_revision(&self) -> Option<(u8, u8)> {
+        if !self.is_new_style() {
+            return self.old_style().map(|board| board.pcb_revision);
+        }
+
+        Some((1, self.field().read(REVISION::PCB_REVISION) as u8))
+    }
+
+    // Size of the RAM in bytes
+    pub fn memory_size(&self) -> Option<u64> {
+        if !self.is_new_style() {
+            return self.old_style().map(|board| board.memory_mib * MIB);
+        }
+
+        match self.field().read(REVISION::MEMORY) {
+            memory @ 0..=6 => Some((256 * MIB) << memory),
+            _ => None,
+        }
+    }
+
+    pub fn manufacturer(&self) -> Option<&'static str> {
+        if !self.is_new_style() {
+            return self.old_style().map(|board| board.manufacturer);
+        }
+
+        let name = match self.field().read(REVISION::MANUFACTURER) {
+            0 => "Sony UK",
+            1 => "Egoman",
+            2 | 4 => "Embest",
+            3 => "Sony Japan",
+            5 => "Stadium",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+
+    // Old-style codes are only used by boards with a BCM2835
+    pub fn processor(&self) -> Option<&'static str> {
+        if !self.is_new_style() {
+            return self.old_style().map(|_| "BCM2835");
+        }
+
+        let name = match self.field().read(REVISION::PROCESSOR) {
+            0 => "BCM2835",
+            1 => "BCM2836",
+            2 => "BCM2837",
+            3 => "BCM2711",
+            4 => "BCM2712",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+}
+
+// e.g. `Raspberry Pi 4 Model B rev 1.4, 4096 MiB, BCM2711, Sony UK`
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let board_type = match self.board_type() {
+            Some(board_type) => board_type,
+            None => return write!(f, "Unknown board {:#010x}", self.code),
+        };
+
+        write!(f, "Raspberry Pi {}", board_type)?;
+        if let Some((major, minor)) = self.pcb_revision() {
+            write!(f, " rev {}.{}", major, minor)?;
+        }
+        if let Some(size) = self.memory_size() {
+            write!(f, ", {} MiB", size / MIB)?;
+        }
+        if let Some(processor) = self.processor() {
+            write!(f, ", {}", processor)?;
+        }
+        if let Some(manufacturer) = self.manufacturer() {
+            write!(f, ", {}", manufacturer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Revision {
+    fn field(&self) -> InMemoryRegister<u32, REVISION::Register> {
+        InMemoryRegister::new(self.code)
+    }
+
+    fn old_style(&self) -> Option<&'static OldStyleBoard> {
+        let code = self.code & OLD_STYLE_MASK;
+        OLD_STYLE_BOARDS.iter().find(|board| board.code == code)
+    }
+}
+
+impl OldStyleBoard {
+    const fn new(
+        code: u32,
+        board_type: &'static str,
+        pcb_revision: (u8, u8),
+        memory_mib: u64,
+        manufacturer: &'static str,
+    ) -> Self {
+        Self {
+            code,
+            board_type,
+            pcb_revision,
+            memory_mib,
+            manufacturer,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// Clocks and power domains, managed by the firmware
+//
+// https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface
+
+use super::driver::MAILBOX;
+use super::mailbox::property::*;
+use crate::clock::{self, CpuPolicy};
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// PLLD, the source of the clocks the clock manager makes for devices
+#[cfg(feature = "bsp_rpi3")]
+pub const PLLD_RATE: u32 = 500_000_000;
+#[cfg(feature = "bsp_rpi4")]
+pub const PLLD_RATE: u32 = 750_000_000;
+
+// Clock ids of the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Hevc = 11,
+    Emmc2 = 12,
+    M2mc = 13,
+    PixelBvb = 14,
+}
+
+// Device ids of the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerDomain {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+pub struct ClockManager;
+
+// Switches the ARM clock between its lowest and highest rate
+pub struct CpuGovernor {
+    policy: IRQSafeNullLock<Option<CpuPolicy>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static CLOCK_MANAGER: ClockManager = ClockManager;
+
+static CPU_GOVERNOR: CpuGovernor = CpuGovernor::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the clock manager
+pub fn clock_manager() -> &'static impl clock::interface::ClockManager<
+    Clock = Clock,
+    PowerDomain = PowerDomain,
+> {
+    &CLOCK_MANAGER
+}
+
+// Return a reference to the CPU frequency governor
+pub fn cpu_governor() -> &'static impl clock::interface::CpuGovernor {
+    &CPU_GOVERNOR
+}
+
+impl CpuGovernor {
+    const fn new() -> Self {
+        Self {
+            policy: IRQSafeNullLock::new(None),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+use clock::interface::ClockManager as _;
+
+impl clock::interface::ClockManager for ClockManager {
+    type Clock = Clock;
+    type PowerDomain = PowerDomain;
+
+    fn rate(&self, clock: Clock) -> Result<u32, &'static str> {
+        existing(MAILBOX.request(GetClockRate {
+            clock: clock as u32,
+        })?)
+    }
+
+    fn min_rate(&self, clock: Clock) -> Result<u32, &'static str> {
+        existing(MAILBOX.request(GetMinClockRate {
+            clock: clock as u32,
+        })?)
+    }
+
+    fn max_rate(&self, clock: Clock) -> Result<u32, &'static str> {
+        existing(MAILBOX.request(GetMaxClockRate {
+            clock: clock as u32,
+        })?)
+    }
+
+    fn set_rate(&self, clock: Clock, rate: u32) -> Result<u32, &'static str> {
+        existing(MAILBOX.request(SetClockRate {
+            clock: clock as u32,
+            rate,
+            skip_turbo: false,
+        })?)
+    }
+
+    fn is_powered(&self, domain: PowerDomain) -> Result<bool, &'static str> {
+        let state = MAILBOX.request(GetPowerState {
+            device: domain as u32,
+        })?;
+        if !state.exists {
+            return Err("Power domain does not exist");
+        }
+
+        Ok(state.on)
+    }
+
+    fn set_power(
+        &self,
+        domain: PowerDomain,
+        on: bool,
+    ) -> Result<(), &'static str> {
+        let state = MAILBOX.request(SetPowerState {
+            device: domain as u32,
+            on,
+            wait: true,
+        })?;
+        if !state.exists {
+            return Err("Power domain does not exist");
+        }
+        if state.on != on {
+            return Err("Power state did not change");
+        }
+
+        Ok(())
+    }
+}
+
+impl clock::interface::CpuGovernor for CpuGovernor {
+    fn policy(&self) -> Option<CpuPolicy> {
+        self.policy.lock(|policy| *policy)
+    }
+
+    fn set_policy(&self, policy: CpuPolicy) -> Result<u32, &'static str> {
+        let rate = match policy {
+            CpuPolicy::PowerSave => CLOCK_MANAGER.min_rate(Clock::Arm)?,
+            CpuPolicy::Performance => CLOCK_MANAGER.max_rate(Clock::Arm)?,
+        };
+        let rate = CLOCK_MANAGER.set_rate(Clock::Arm, rate)?;
+
+        self.policy.lock(|current| *current = Some(policy));
+
+        Ok(rate)
+    }
+
+    fn cpu_rate(&self) -> Result<u32, &'static str> {
+        CLOCK_MANAGER.rate(Clock::Arm)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// The firmware answers with a rate of 0 for clocks it does not know
+fn existing(rate: u32) -> Result<u32, &'static str> {
+    if rate == 0 {
+        return Err("Clock does not exist");
+    }
+
+    Ok(rate)
+}
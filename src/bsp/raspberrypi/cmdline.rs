@@ -0,0 +1,58 @@
+// Kernel command line from the firmware
+//
+// The firmware reads `cmdline.txt` from the boot partition and puts its own parameters for Linux in
+// front of it, which are not warned about.
+
+use super::driver::MAILBOX;
+use super::mailbox::{property::GetCommandLine, MailBoxError};
+use super::{console, frame_buffer};
+use crate::{cmdline, font, print, usb};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Every parameter of the kernel
+static PARAMS: [&(dyn cmdline::interface::Param + Sync); 6] = [
+    &print::LOGLEVEL,
+    &print::CONSOLE,
+    &console::BAUD_RATE,
+    &frame_buffer::MODE,
+    &font::file::FONTS,
+    &usb::keyboard::keymap::KEYMAP,
+];
+
+// Prefixes of the keys the firmware adds
+const FIRMWARE_KEYS: [&str; 13] = [
+    "coherent_pool",
+    "8250.",
+    "bcm2708_fb.",
+    "bcm2709.",
+    "bcm2835_",
+    "dwc_otg.",
+    "smsc95xx.",
+    "snd_bcm2835.",
+    "vc_mem.",
+    "video",
+    "cma",
+    "root",
+    "fsck.",
+];
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Read the command line from the firmware and set the parameters. Must be called during kernel
+// init, before the drivers are initialized.
+pub fn init() -> Result<(), MailBoxError> {
+    let line = MAILBOX.request(GetCommandLine)?;
+    cmdline::init(line.as_str(), &PARAMS);
+
+    Ok(())
+}
+
+// Warn about unknown parameters and invalid values
+pub fn report() {
+    cmdline::report(&PARAMS, &FIRMWARE_KEYS);
+}
@@ -1,8 +1,16 @@
 use super::memory;
 use crate::bsp::device_driver;
-use crate::console;
+use crate::{cmdline, console};
 use core::fmt;
 
+//-------------------------------------------------------------------------------------------------
+// Global instances
+//-------------------------------------------------------------------------------------------------
+
+// Baud rate of the UART console, e.g. `baud=115200`
+pub static BAUD_RATE: cmdline::Param<u32> =
+    cmdline::Param::new("baud", device_driver::PanicUart::DEFAULT_BAUD_RATE);
+
 //-------------------------------------------------------------------------------------------------
 // Public Code
 //-------------------------------------------------------------------------------------------------
@@ -16,7 +24,10 @@ pub unsafe fn panic_console_out() -> impl fmt::Write {
         device_driver::PanicUart::new(memory::map::mmio::PL011_UART_START);
 
     panic_gpio.map_pl011_uart();
-    panic_uart.init();
+    panic_uart.init(
+        device_driver::PanicUart::DEFAULT_CLOCK_RATE,
+        BAUD_RATE.get(),
+    );
     panic_uart
 }
 
@@ -1,5 +1,5 @@
 use super::{
-    exception,
+    clock, exception,
     frame_buffer::{self},
     memory::map::mmio,
 };
@@ -13,9 +13,14 @@ use driver::interface::DeviceDriver;
 
 // Device Driver Manager type
 struct BSPDriverManager {
-    device_drivers: [&'static (dyn DeviceDriver + Sync); 4],
+    device_drivers: [&'static (dyn DeviceDriver + Sync); NUM_DRIVERS],
 }
 
+#[cfg(feature = "bsp_rpi3")]
+const NUM_DRIVERS: usize = 9;
+#[cfg(feature = "bsp_rpi4")]
+const NUM_DRIVERS: usize = 10;
+
 //--------------------------------------------------------------------------------------------------
 // Global instaces
 //--------------------------------------------------------------------------------------------------
@@ -23,11 +28,63 @@ pub(super) static PL011_UART: device_driver::PL011Uart = unsafe {
     device_driver::PL011Uart::new(
         mmio::PL011_UART_START,
         exception::asynchronous::irq_map::PL011_UART,
+        &super::console::BAUD_RATE,
+    )
+};
+
+pub(super) static GPIO: device_driver::GPIO = unsafe {
+    device_driver::GPIO::new(
+        mmio::GPIO_START,
+        [
+            exception::asynchronous::irq_map::GPIO_BANK0,
+            exception::asynchronous::irq_map::GPIO_BANK1,
+            exception::asynchronous::irq_map::GPIO_BANK2,
+        ],
+    )
+};
+
+static I2C: device_driver::I2C = unsafe {
+    device_driver::I2C::new(
+        mmio::I2C1_START,
+        exception::asynchronous::irq_map::I2C,
+    )
+};
+
+static SPI: device_driver::SPI = unsafe {
+    device_driver::SPI::new(
+        mmio::SPI0_START,
+        exception::asynchronous::irq_map::SPI,
+    )
+};
+
+static PWM: device_driver::PWM = unsafe {
+    device_driver::PWM::new(
+        mmio::PWM0_START,
+        mmio::CM_PWM_START,
+        clock::PLLD_RATE,
     )
 };
 
-static GPIO: device_driver::GPIO =
-    unsafe { device_driver::GPIO::new(mmio::GPIO_START) };
+// The Pi 4 has a controller of its own for the headphone jack
+#[cfg(feature = "bsp_rpi4")]
+static AUDIO_PWM: device_driver::PWM = unsafe {
+    device_driver::PWM::new(
+        mmio::PWM1_START,
+        mmio::CM_PWM_START,
+        clock::PLLD_RATE,
+    )
+};
+
+#[cfg(feature = "bsp_rpi3")]
+static SD_CARD: device_driver::SDHCI =
+    unsafe { device_driver::SDHCI::new(mmio::EMMC_START, clock::Clock::Emmc) };
+
+#[cfg(feature = "bsp_rpi4")]
+static SD_CARD: device_driver::SDHCI =
+    unsafe { device_driver::SDHCI::new(mmio::EMMC_START, clock::Clock::Emmc2) };
+
+static USB_HOST: device_driver::DWC2 =
+    unsafe { device_driver::DWC2::new(mmio::USB_START) };
 
 #[cfg(feature = "bsp_rpi3")]
 pub(super) static INTERRUPT_CONTROLLER: device_driver::InterruptController = unsafe {
@@ -47,7 +104,19 @@ pub(super) static MAILBOX: super::mailbox::MailBox =
     unsafe { super::mailbox::MailBox::new(mmio::MAILBOX_START) };
 
 static BSP_DRIVER_MANAGER: BSPDriverManager = BSPDriverManager {
-    device_drivers: [&PL011_UART, &GPIO, &FRAMEBUFFER, &INTERRUPT_CONTROLLER],
+    device_drivers: [
+        &PL011_UART,
+        &GPIO,
+        &I2C,
+        &SPI,
+        &PWM,
+        #[cfg(feature = "bsp_rpi4")]
+        &AUDIO_PWM,
+        &SD_CARD,
+        &USB_HOST,
+        &FRAMEBUFFER,
+        &INTERRUPT_CONTROLLER,
+    ],
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -58,6 +127,50 @@ pub fn driver_manager() -> &'static impl driver::interface::DriverManager {
     &BSP_DRIVER_MANAGER
 }
 
+// Return a reference to the GPIO driver, pins are claimed from it
+pub fn gpio() -> &'static device_driver::GPIO {
+    &GPIO
+}
+
+// Return a reference to the I2C bus on GPIO2/3, usable as an `embedded_hal::i2c::I2c`
+pub fn i2c() -> &'static device_driver::I2C {
+    &I2C
+}
+
+// Return a reference to SPI0 on GPIO7-11, devices on it are made with `device()`
+pub fn spi() -> &'static device_driver::SPI {
+    &SPI
+}
+
+// Return a reference to the PWM controller of GPIO12/13/18/19, whose pins are claimed by the user
+pub fn pwm() -> &'static device_driver::PWM {
+    &PWM
+}
+
+// Return a reference to the PWM controller of the headphone jack, which is the one of `pwm()` on
+// the Pi 3
+pub fn audio() -> &'static device_driver::PWM {
+    #[cfg(feature = "bsp_rpi3")]
+    {
+        &PWM
+    }
+
+    #[cfg(feature = "bsp_rpi4")]
+    {
+        &AUDIO_PWM
+    }
+}
+
+// Return a reference to the SD card slot
+pub fn sd_card() -> &'static device_driver::SDHCI {
+    &SD_CARD
+}
+
+// Return a reference to the USB host controller, the devices behind it are enumerated by `usb`
+pub fn usb_host() -> &'static device_driver::DWC2 {
+    &USB_HOST
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -69,5 +182,11 @@ impl driver::interface::DriverManager for BSPDriverManager {
 
     fn post_device_driver_init(&self) {
         GPIO.map_pl011_uart();
+        GPIO.map_i2c1();
+        GPIO.map_spi0();
+        GPIO.map_audio();
+        #[cfg(feature = "bsp_rpi3")]
+        GPIO.map_sd_card();
+        SD_CARD.detect();
     }
 }
@@ -8,6 +8,14 @@ use crate::{bsp, bsp::driver, exception};
 pub(in crate::bsp) mod irq_map {
     use super::bsp::device_driver::{IRQNumber, PeripheralIRQ};
 
+    pub const GPIO_BANK0: IRQNumber =
+        IRQNumber::Peripheral(PeripheralIRQ::new(49));
+    pub const GPIO_BANK1: IRQNumber =
+        IRQNumber::Peripheral(PeripheralIRQ::new(50));
+    pub const GPIO_BANK2: IRQNumber =
+        IRQNumber::Peripheral(PeripheralIRQ::new(51));
+    pub const I2C: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(53));
+    pub const SPI: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(54));
     pub const PL011_UART: IRQNumber =
         IRQNumber::Peripheral(PeripheralIRQ::new(57));
 }
@@ -16,6 +24,11 @@ pub(in crate::bsp) mod irq_map {
 pub(in crate::bsp) mod irq_map {
     use super::bsp::device_driver::IRQNumber;
 
+    pub const GPIO_BANK0: IRQNumber = IRQNumber::new(145);
+    pub const GPIO_BANK1: IRQNumber = IRQNumber::new(146);
+    pub const GPIO_BANK2: IRQNumber = IRQNumber::new(147);
+    pub const I2C: IRQNumber = IRQNumber::new(149);
+    pub const SPI: IRQNumber = IRQNumber::new(150);
     pub const PL011_UART: IRQNumber = IRQNumber::new(153);
 }
 
@@ -1,43 +1,60 @@
 //! https://github.com/RaspberryPI/firmware/wiki/Mailbox-framebuffer-interface
 
 use super::driver::{FRAMEBUFFER, MAILBOX};
-use super::mailbox::*;
+use super::mailbox::property::*;
+use crate::cmdline;
 use crate::driver;
+use crate::font;
+use crate::fs;
 use crate::screen;
 use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
 use core::fmt;
-use noto_sans_mono_bitmap::{get_bitmap, BitmapHeight, FontWeight};
 
 //--------------------------------------------------------------------------------------------------
 // Global Definitions
 //--------------------------------------------------------------------------------------------------
-pub const BUFFER_WIDTH: usize = 1024;
-pub const BUFFER_HEIGHT: usize = 768;
-pub const FONT_WIDTH: usize = 8;
-pub const FONT_HEIGHT: usize = 16;
+// Used when neither the command line nor the firmware provide a usable mode
+pub const DEFAULT_MODE: DisplayMode = DisplayMode::new(1024, 768, 32);
+
+// Preferred mode from the command line, e.g. `fb.mode=1280x720-32`
+pub static MODE: cmdline::Param<Option<DisplayMode>> =
+    cmdline::Param::new("fb.mode", None);
+
+// Largest resolution accepted from the firmware or the command line
+const MAX_WIDTH: u32 = 4096;
+const MAX_HEIGHT: u32 = 4096;
+
+// The pixel routines below only know how to draw 32-bit pixels
+const SUPPORTED_DEPTH: u32 = 32;
+
+// Alignment of the frame buffer requested from the firmware
+const BUFFER_ALIGNMENT: u32 = 4096;
+
+// The firmware returns a VideoCore bus address
+const BUS_ADDRESS_MASK: u32 = 0x3FFF_FFFF;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct FrameBufferInner {
-    phyis_width: u32,
-    phyis_height: u32,
-    width: u32,
-    heigth: u32,
+    mode: DisplayMode,
     pitch: u32,
-    depth: u32,
-    _x_offset: u32,
-    _y_offset: u32,
     addr: u32,
     size: u32,
-    #[allow(dead_code)]
-    row: usize,
-    #[allow(dead_code)]
-    col: usize,
-    row_position: usize,
-    column_position: usize,
+    // text console geometry, derived from the allocated buffer and the font
+    cell_width: usize,
+    cell_height: usize,
+    columns: usize,
+    rows: usize,
 }
 
 pub struct FrameBuffer {
@@ -64,100 +81,197 @@ pub struct RGBColor {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl DisplayMode {
+    pub const fn new(width: u32, height: u32, depth: u32) -> Self {
+        Self {
+            width,
+            height,
+            depth,
+        }
+    }
+
+    // Parse `<width>x<height>` or `<width>x<height>-<depth>`
+    pub fn parse(s: &str) -> Option<Self> {
+        let (resolution, depth) = match s.split_once('-') {
+            Some((resolution, depth)) => (resolution, depth.parse().ok()?),
+            None => (s, SUPPORTED_DEPTH),
+        };
+        let (width, height) = resolution.split_once('x')?;
+
+        Some(Self::new(width.parse().ok()?, height.parse().ok()?, depth))
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Frame buffer size is zero");
+        }
+
+        if self.width > MAX_WIDTH || self.height > MAX_HEIGHT {
+            return Err("Frame buffer size is too large");
+        }
+
+        if self.depth != SUPPORTED_DEPTH {
+            return Err("Unsupported frame buffer depth");
+        }
+
+        Ok(())
+    }
+}
+
+impl cmdline::interface::Value for DisplayMode {
+    fn parse(s: &str) -> Option<Self> {
+        DisplayMode::parse(s)
+    }
+}
+
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}-{}", self.width, self.height, self.depth)
+    }
+}
+
 impl FrameBufferInner {
     pub const fn new() -> Self {
         Self {
-            phyis_width: BUFFER_WIDTH as u32,
-            phyis_height: BUFFER_HEIGHT as u32,
-            width: 840,
-            heigth: 480,
+            mode: DEFAULT_MODE,
             pitch: 0,
-            depth: 32,
-            _x_offset: 0,
-            _y_offset: 0,
             addr: 0,
             size: 0,
-            row: 0,
-            col: 0,
-            row_position: BUFFER_HEIGHT - FONT_HEIGHT,
-            column_position: 0,
+            cell_width: 0,
+            cell_height: 0,
+            columns: 0,
+            rows: 0,
         }
     }
 
-    unsafe fn init(&mut self) -> Result<(), &'static str> {
-        // send a message via property channel 8
-        let mut msg = Messege::new(8);
-        // init message for frame buffer
-        self.init_msg(&mut msg);
+    fn init(&mut self) -> Result<(), &'static str> {
+        // The command line wins over the size of the attached display
+        let mode = match Self::preferred_mode() {
+            Some(mode) => mode,
+            None => Self::physical_display().unwrap_or(DEFAULT_MODE),
+        };
+
+        self.allocate(mode)
+    }
+
+    // Ask the firmware for the resolution of the attached display
+    fn physical_display() -> Option<DisplayMode> {
+        let (width, height) = MAILBOX.request(GetPhysicalSize).ok()?;
+
+        let mode = DisplayMode::new(width, height, SUPPORTED_DEPTH);
+        mode.validate().ok()?;
+
+        Some(mode)
+    }
+
+    // `fb.mode=` from the kernel command line
+    fn preferred_mode() -> Option<DisplayMode> {
+        let mode = MODE.get()?;
+        mode.validate().ok()?;
+
+        Some(mode)
+    }
 
-        // send a messeage
-        if let Err(_e) = MAILBOX.mailbox_call(&mut msg) {
-            return Err("MailBox Error");
+    fn allocate(&mut self, mode: DisplayMode) -> Result<(), &'static str> {
+        let mut msg = PropertyMessage::new();
+        msg.push(SetPhysicalSize {
+            width: mode.width,
+            height: mode.height,
+        })?;
+        let virtual_size = msg.push(SetVirtualSize {
+            width: mode.width,
+            height: mode.height,
+        })?;
+        let depth = msg.push(SetDepth { depth: mode.depth })?;
+        let pitch = msg.push(GetPitch)?;
+        let buffer = msg.push(AllocateBuffer {
+            alignment: BUFFER_ALIGNMENT,
+        })?;
+
+        MAILBOX.call(&mut msg)?;
+
+        // the firmware may adjust the requested mode, so use what it returns
+        let (width, height) = msg.get(&virtual_size)?;
+        let mode = DisplayMode::new(width, height, msg.get(&depth)?);
+        mode.validate()?;
+
+        let pitch = msg.get(&pitch)?;
+        let (addr, size) = msg.get(&buffer)?;
+        let addr = addr & BUS_ADDRESS_MASK;
+
+        if pitch < mode.width * (mode.depth >> 3) {
+            return Err("Frame buffer pitch is smaller than a row");
         }
 
-        // set a settings
-        self.depth = msg.data[15].read();
-        self.pitch = msg.data[19].read(); // pitch
-        self.addr = msg.data[23].read(); // buffer address
-        self.size = msg.data[24].read(); // buffer size
+        if addr == 0 || (size as u64) < pitch as u64 * mode.height as u64 {
+            return Err("Frame buffer allocation failed");
+        }
+
+        self.mode = mode;
+        self.pitch = pitch;
+        self.addr = addr;
+        self.size = size;
+
+        self.set_cell_size(font::font_manager().cell_size());
+        if self.columns == 0 || self.rows == 0 {
+            return Err("Frame buffer is smaller than a character");
+        }
 
-        // crate::info!("addr: {:x}, size: {:x}", self.addr, self.size);
         Ok(())
     }
 
-    fn init_msg(&self, msg: &mut Messege) {
-        // all bytes of messeage data
-        msg.data[0].write(26 * 4);
+    // Follow the cell size of the font chain, which may change after the mode has been set
+    fn sync_cells(&mut self) {
+        let cell_size = font::font_manager().cell_size();
+        if self.is_allocated()
+            && cell_size != (self.cell_width, self.cell_height)
+        {
+            self.set_cell_size(cell_size);
+        }
+    }
 
-        // request
-        msg.data[1].write(0x0);
+    // A font bigger than the display leaves no cells, like a display that is not ready
+    fn set_cell_size(&mut self, (cell_width, cell_height): (usize, usize)) {
+        self.cell_width = cell_width;
+        self.cell_height = cell_height;
+        self.columns = self.mode.width as usize / cell_width;
+        self.rows = self.mode.height as usize / cell_height;
+    }
 
-        // physical display settings
-        msg.data[2].write(0x4_8003); // tag indetity
-        msg.data[3].write(8); // value buffer size
-        msg.data[4].write(8); // respronse: 1 request: 0
-                              // value buffer is u8 array
-        msg.data[5].write(self.phyis_width);
-        msg.data[6].write(self.phyis_height);
+    fn is_allocated(&self) -> bool {
+        self.addr != 0
+    }
 
-        // virtual display settings
-        msg.data[7].write(0x4_8004);
-        msg.data[8].write(8);
-        msg.data[9].write(8);
-        msg.data[10].write(self.width);
-        msg.data[11].write(self.heigth);
+    // Bytes of pixel memory, without the padding the firmware may add after the last row
+    fn memory_size(&self) -> u64 {
+        if !self.is_allocated() {
+            return 0;
+        }
 
-        // depth settings
-        msg.data[12].write(0x4_8005);
-        msg.data[13].write(4);
-        msg.data[14].write(4);
-        msg.data[15].write(self.depth);
+        self.pitch as u64 * self.mode.height as u64
+    }
 
-        // pitch settings
-        msg.data[16].write(0x4_0008);
-        msg.data[17].write(4);
-        msg.data[18].write(4);
-        msg.data[19].write(self.pitch);
+    fn pixel_ptr(&self, y: usize, x: usize) -> Option<*mut u32> {
+        if y >= self.mode.height as usize || x >= self.mode.width as usize {
+            return None;
+        }
 
-        // allocate frame buffer
-        msg.data[20].write(0x4_0001);
-        msg.data[21].write(8);
-        msg.data[22].write(8);
-        msg.data[23].write(0); // frame buffer address
-        msg.data[24].write(0); // frame buffer size
+        // self.depth + 7は下位４bitを繰り上げている
+        let offset =
+            y * self.pitch as usize + x * ((self.mode.depth as usize + 7) >> 3);
 
-        // Last buffer
-        msg.data[25].write(0);
+        Some((self.addr as usize + offset) as *mut u32)
     }
 
     fn read_pixel(&self, y: usize, x: usize) -> RGBColor {
-        let ptr = (self.addr
-            + y as u32 * self.pitch
-            + x as u32 * ((self.depth + 7) >> 3)) as *mut u32;
-        let ch = unsafe { core::ptr::read_volatile(ptr) };
-        let r = (ch & 0b11111111_00000000_00000000) >> 16;
+        let ch = match self.pixel_ptr(y, x) {
+            Some(ptr) => unsafe { core::ptr::read_volatile(ptr) },
+            None => 0,
+        };
+        // same layout as `write_pixel`
+        let b = (ch & 0b11111111_00000000_00000000) >> 16;
         let g = (ch & 0b11111111_00000000) >> 8;
-        let b = ch & 0b11111111;
+        let r = ch & 0b11111111;
         RGBColor {
             r: r as u8,
             g: g as u8,
@@ -166,10 +280,10 @@ impl FrameBufferInner {
     }
 
     fn write_pixel(&self, y: usize, x: usize, c: RGBColor) {
-        // self.depth + 7は下位４bitを繰り上げている
-        let ptr = (self.addr
-            + y as u32 * self.pitch
-            + x as u32 * ((self.depth + 7) >> 3)) as *mut u32;
+        let ptr = match self.pixel_ptr(y, x) {
+            Some(ptr) => ptr,
+            None => return,
+        };
         // print!("{:?}\n", ptr);
         unsafe {
             core::ptr::write_volatile(
@@ -179,65 +293,118 @@ impl FrameBufferInner {
         }
     }
 
-    fn _write_char(&self, y: usize, x: usize, c: char) {
-        let bitmap_char =
-            get_bitmap(c, FontWeight::Regular, BitmapHeight::Size16)
-                .expect("unsupported char");
-        for (row_i, row) in bitmap_char.bitmap().iter().enumerate() {
-            for (col_i, intensity) in row.iter().enumerate() {
-                let (r, g, b) =
-                    (*intensity as u8, *intensity as u8, *intensity as u8);
-                // let (r, g, b) = (255 - r, 255 - g, 255 - b);
-                // let (r, g, b) = (255 - r, 255 - g, 255 - b);
-                let rgb_32 = RGBColor { r: r, g: g, b: b };
-                self.write_pixel(y + row_i, x + col_i, rgb_32);
+    fn fill(
+        &self,
+        y: usize,
+        x: usize,
+        height: usize,
+        width: usize,
+        c: RGBColor,
+    ) {
+        for row in y..y + height {
+            for col in x..x + width {
+                self.write_pixel(row, col, c);
             }
         }
     }
 
-    pub fn write_char(&mut self, c: char) {
-        match c {
-            '\n' => self.new_line(),
-            _ => {
-                if self.column_position >= BUFFER_WIDTH - FONT_WIDTH {
-                    self.new_line();
-                }
-
-                self._write_char(self.row_position, self.column_position, c);
-                self.column_position += FONT_WIDTH;
-            }
-        }
+    fn char_cells(&self, c: char) -> usize {
+        let (cell_width, _) = font::font_manager().cell_size();
+        font::font_manager().with_glyph(c, |glyph| glyph.cells(cell_width))
     }
 
-    pub fn new_line(&mut self) {
-        for row in FONT_HEIGHT..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let color = self.read_pixel(row, col);
-                self.write_pixel(row - FONT_HEIGHT, col, color);
-            }
+    // Draw `c` into the cell at (row, column), blending between the attribute colors
+    fn draw_char(
+        &self,
+        row: usize,
+        column: usize,
+        c: char,
+        attr: screen::Attribute,
+    ) {
+        if !self.is_allocated() || row >= self.rows || column >= self.columns {
+            return;
         }
 
-        for row in 0..FONT_HEIGHT {
-            self.clear_row(BUFFER_HEIGHT - row);
-        }
-        self.column_position = 0;
+        let (y, x) = (row * self.cell_height, column * self.cell_width);
+        let (fg, bg) = (RGBColor::from(attr.fg), RGBColor::from(attr.bg));
+        font::font_manager().with_glyph(c, |glyph| {
+            let width = glyph.cells(self.cell_width) * self.cell_width;
+            for row_i in 0..self.cell_height {
+                for col_i in 0..width {
+                    let intensity = glyph.get(col_i, row_i);
+                    self.write_pixel(
+                        y + row_i,
+                        x + col_i,
+                        bg.blend(fg, intensity),
+                    );
+                }
+            }
+        });
     }
 
-    pub fn clear_row(&mut self, y: usize) {
-        let color = RGBColor { r: 0, g: 0, b: 0 };
-        for col in 0..BUFFER_WIDTH {
-            self.write_pixel(y, col, color);
+    fn clear_cells(
+        &self,
+        row: usize,
+        column: usize,
+        count: usize,
+        attr: screen::Attribute,
+    ) {
+        if !self.is_allocated() || row >= self.rows {
+            return;
         }
+
+        let count = count.min(self.columns.saturating_sub(column));
+        self.fill(
+            row * self.cell_height,
+            column * self.cell_width,
+            self.cell_height,
+            count * self.cell_width,
+            RGBColor::from(attr.bg),
+        );
     }
 }
 
-impl fmt::Write for FrameBufferInner {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            self.write_char(c);
+impl RGBColor {
+    // Mix `self` and `other`, with `amount` 255 giving `other`
+    fn blend(self, other: RGBColor, amount: u8) -> RGBColor {
+        let mix = |a: u8, b: u8| {
+            let (a, b, t) = (a as u32, b as u32, amount as u32);
+            ((a * (255 - t) + b * t) / 255) as u8
+        };
+
+        RGBColor {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
         }
+    }
+}
 
-        Ok(())
+// VGA text mode palette
+impl From<screen::Color> for RGBColor {
+    fn from(color: screen::Color) -> Self {
+        use screen::Color::*;
+
+        let (r, g, b) = match color {
+            Black => (0x00, 0x00, 0x00),
+            Red => (0xAA, 0x00, 0x00),
+            Green => (0x00, 0xAA, 0x00),
+            Yellow => (0xAA, 0x55, 0x00),
+            Blue => (0x00, 0x00, 0xAA),
+            Magenta => (0xAA, 0x00, 0xAA),
+            Cyan => (0x00, 0xAA, 0xAA),
+            White => (0xAA, 0xAA, 0xAA),
+            BrightBlack => (0x55, 0x55, 0x55),
+            BrightRed => (0xFF, 0x55, 0x55),
+            BrightGreen => (0x55, 0xFF, 0x55),
+            BrightYellow => (0xFF, 0xFF, 0x55),
+            BrightBlue => (0x55, 0x55, 0xFF),
+            BrightMagenta => (0xFF, 0x55, 0xFF),
+            BrightCyan => (0x55, 0xFF, 0xFF),
+            BrightWhite => (0xFF, 0xFF, 0xFF),
+        };
+
+        RGBColor { r, g, b }
     }
 }
 
@@ -256,12 +423,9 @@ impl FrameBuffer {
         self.inner.lock(|buff| buff.write_pixel(y, x, c))
     }
 
-    pub fn write_char(&self, _y: usize, _x: usize, c: char) {
-        self.inner.lock(|buff| buff.write_char(c));
-    }
-
-    pub fn clear_row(&self, y: usize) {
-        self.inner.lock(|buff| buff.clear_row(y));
+    // The mode negotiated with the firmware
+    pub fn mode(&self) -> DisplayMode {
+        self.inner.lock(|buff| buff.mode)
     }
 }
 
@@ -282,14 +446,126 @@ impl driver::interface::DeviceDriver for FrameBuffer {
     }
 }
 
-impl screen::interface::Write for FrameBuffer {
-    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
-        self.inner.lock(|buff| fmt::Write::write_fmt(buff, args))
+impl screen::interface::TextDisplay for FrameBuffer {
+    fn text_size(&self) -> (usize, usize) {
+        self.inner.lock(|buff| {
+            buff.sync_cells();
+            (buff.columns, buff.rows)
+        })
+    }
+
+    fn char_cells(&self, c: char) -> usize {
+        self.inner.lock(|buff| buff.char_cells(c))
+    }
+
+    fn draw_char(
+        &self,
+        row: usize,
+        column: usize,
+        c: char,
+        attr: screen::Attribute,
+    ) {
+        self.inner.lock(|buff| {
+            buff.sync_cells();
+            buff.draw_char(row, column, c, attr)
+        });
+    }
+
+    fn clear_cells(
+        &self,
+        row: usize,
+        column: usize,
+        count: usize,
+        attr: screen::Attribute,
+    ) {
+        self.inner.lock(|buff| {
+            buff.sync_cells();
+            buff.clear_cells(row, column, count, attr)
+        });
     }
 }
 
-impl screen::interface::All for FrameBuffer {}
+impl screen::interface::ReadPixels for FrameBuffer {
+    fn pixel_size(&self) -> (usize, usize) {
+        self.inner.lock(|buff| {
+            if !buff.is_allocated() {
+                return (0, 0);
+            }
+
+            (buff.mode.width as usize, buff.mode.height as usize)
+        })
+    }
+
+    // Reads a run of pixels under one lock, which is much faster than `read_pixel`
+    fn read_pixels(&self, y: usize, x: usize, out: &mut [u8]) -> usize {
+        self.inner.lock(|buff| {
+            if !buff.is_allocated() || y >= buff.mode.height as usize {
+                return 0;
+            }
+
+            let count = (out.len() / 3)
+                .min((buff.mode.width as usize).saturating_sub(x));
+            for (i, rgb) in out.chunks_exact_mut(3).take(count).enumerate() {
+                let c = buff.read_pixel(y, x + i);
+                rgb.copy_from_slice(&[c.r, c.g, c.b]);
+            }
+
+            count
+        })
+    }
+}
+
+// `/dev/fb0`, the raw pixel memory row by row with `pitch` bytes per row
+impl fs::devfs::interface::CharDevice for FrameBuffer {
+    fn read(
+        &self,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, fs::FsError> {
+        self.inner.lock(|buff| {
+            let size = buff.memory_size();
+            let offset = offset.min(size);
+            let len = buffer.len().min((size - offset) as usize);
+
+            let base = (buff.addr as usize + offset as usize) as *const u8;
+            for (i, byte) in buffer[..len].iter_mut().enumerate() {
+                *byte = unsafe { core::ptr::read_volatile(base.add(i)) };
+            }
+
+            Ok(len)
+        })
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<usize, fs::FsError> {
+        self.inner.lock(|buff| {
+            if data.is_empty() {
+                return Ok(0);
+            }
+
+            let size = buff.memory_size();
+            if offset >= size {
+                return Err(fs::FsError::NoSpace);
+            }
+            let len = data.len().min((size - offset) as usize);
+
+            let base = (buff.addr as usize + offset as usize) as *mut u8;
+            for (i, &byte) in data[..len].iter().enumerate() {
+                unsafe { core::ptr::write_volatile(base.add(i), byte) };
+            }
+
+            Ok(len)
+        })
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.lock(|buff| buff.memory_size())
+    }
+}
+
+pub fn screen() -> &'static impl screen::interface::TextDisplay {
+    &FRAMEBUFFER
+}
 
-pub fn screen() -> &'static impl screen::interface::Write {
+pub fn pixels() -> &'static impl screen::interface::ReadPixels {
     &FRAMEBUFFER
 }
@@ -0,0 +1,98 @@
+// File systems of the board
+//
+// `/` is a RAM file system that holds the contents of the initramfs, `/tmp` is another one for
+// scratch files and `/dev` has the device files of the drivers.
+//
+// The initramfs is linked into the kernel when it is built with `INITRAMFS=<archive>`, see
+// `build.rs`.
+
+use super::driver::{FRAMEBUFFER, GPIO, PL011_UART};
+use crate::fs::{
+    devfs::{DevFs, Device},
+    ramfs::RamFs,
+    vfs::vfs,
+    FileKind, FsError,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+// 2 MiB
+static ROOT_FS: RamFs<256, 4096> = RamFs::new();
+
+// 512 KiB
+static TMP_FS: RamFs<64, 1024> = RamFs::new();
+
+#[cfg(initramfs)]
+static INITRAMFS: &[u8] = include_bytes!(env!("INITRAMFS_PATH"));
+
+static DEV_FS: DevFs<3> = DevFs::new([
+    Device {
+        name: "uart0",
+        device: &PL011_UART,
+    },
+    Device {
+        name: "fb0",
+        device: &FRAMEBUFFER,
+    },
+    Device {
+        name: "gpio",
+        device: &GPIO,
+    },
+]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Mount the file systems, after the drivers have been initialized
+pub fn init() -> Result<(), FsError> {
+    let vfs = vfs();
+
+    vfs.mount("/", &ROOT_FS)?;
+
+    #[cfg(initramfs)]
+    unpack_initramfs();
+
+    create_mount_point("/dev")?;
+    vfs.mount("/dev", &DEV_FS)?;
+
+    create_mount_point("/tmp")?;
+    vfs.mount("/tmp", &TMP_FS)?;
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// A broken initramfs leaves what has been unpacked so far, the kernel runs without it
+#[cfg(initramfs)]
+fn unpack_initramfs() {
+    use crate::{fs::initramfs, info, warn};
+
+    match initramfs::unpack(INITRAMFS, "/") {
+        Ok(summary) => info!(
+            "Initramfs: {} directories, {} files, {} bytes unpacked, {} entries skipped",
+            summary.directories,
+            summary.files,
+            summary.bytes,
+            summary.skipped
+        ),
+        Err(e) => warn!("Initramfs: {}", e),
+    }
+}
+
+// The initramfs may already have it
+fn create_mount_point(path: &str) -> Result<(), FsError> {
+    match vfs().create_dir(path) {
+        Err(FsError::AlreadyExists)
+            if vfs().metadata(path)?.kind == FileKind::Directory =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
@@ -1,14 +1,21 @@
+pub mod property;
+
 use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
     synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+};
+use core::{
+    sync::atomic::{fence, Ordering},
+    time::Duration,
 };
-
 use tock_registers::{
     interfaces::{Readable, Writeable},
     register_bitfields, register_structs,
     registers::{InMemoryRegister, ReadOnly},
 };
-use volatile::Volatile;
+
+pub use property::{interface::Tag, MailBoxError, PropertyMessage};
 
 // Descriptions taken from
 // raspberypi 3ap
@@ -43,22 +50,16 @@ register_structs! {
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
-#[derive(Debug, Clone, Copy)]
-pub enum MailBoxError {
-    NotAligned,
-}
+// Channel of the property interface, ARM to VideoCore
+const PROPERTY_CHANNEL: u32 = 8;
+
+// Longest time the firmware may take to answer, allocating a frame buffer takes a few ms
+const TIMEOUT: Duration = Duration::from_secs(1);
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-#[derive(Debug, Clone)]
-#[repr(C, align(16))]
-pub struct Messege {
-    pub data: [Volatile<u32>; 36],
-    pub channel: u32,
-}
-
 pub struct MailBoxInner {
     registers: Registers,
 }
@@ -70,53 +71,6 @@ pub struct MailBox {
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
-impl Messege {
-    // dataでアドレスを送るときは16byte境界にあラインされている必要がある
-    pub unsafe fn new(channel: u32) -> Self {
-        let data = [
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-            Volatile::new(0u32),
-        ];
-        Self {
-            data: data,
-            channel,
-        }
-    }
-}
 
 impl MailBoxInner {
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
@@ -125,44 +79,40 @@ impl MailBoxInner {
         }
     }
 
-    pub unsafe fn mailbox_call(
-        &self,
-        msg: &mut Messege,
-    ) -> Result<(), MailBoxError> {
-        let ptr = msg.data.as_ptr() as u32;
+    // Send a message on the property channel and wait for the answer
+    pub fn call(&self, msg: &mut PropertyMessage) -> Result<(), MailBoxError> {
+        let mail = property::mail(msg.as_mut_ptr() as usize, PROPERTY_CHANNEL)?;
+        let deadline = time::time_manager().uptime() + TIMEOUT;
 
-        // Check alignment
-        if ptr & 0x0F != 0 {
-            return Err(MailBoxError::NotAligned);
-        }
-
-        // mailbox interface
-        let data = (ptr | msg.channel) + 0xC000_0000;
-        let response = 0x8000_0000;
+        // the message must be in memory before the firmware is told about it
+        fence(Ordering::SeqCst);
 
         // wait until mailbox is empty
-        while self.registers.STATUS.matches_all(STATUS::FULL::SET) {
-            // wait here
-        }
+        wait_until(deadline, || {
+            !self.registers.STATUS.matches_all(STATUS::FULL::SET)
+        })?;
 
         // send a message via mailbox
-        self.registers.WRITE.set(data);
+        self.registers.WRITE.set(mail);
 
-        // wait until messeage is sended
+        // mails for other channels are dropped
         loop {
-            while self.registers.STATUS.matches_all(STATUS::EMPTY::SET) {
-                // wait here
-            }
-
-            let received_data = self.registers.READ.get();
+            wait_until(deadline, || {
+                !self.registers.STATUS.matches_all(STATUS::EMPTY::SET)
+            })?;
 
-            if received_data == data {
-                // この呼び出しがないとmsg.data[1]の値が最適化で定数値にされる？
-                if msg.data[1].read() == response {
-                    return Ok(());
-                }
+            if self.registers.READ.get() == mail {
+                break;
             }
         }
+
+        // the answer may become visible after the mail
+        wait_until(deadline, || {
+            fence(Ordering::SeqCst);
+            msg.is_answered()
+        })?;
+
+        msg.status()
     }
 }
 
@@ -173,10 +123,33 @@ impl MailBox {
         }
     }
 
-    pub unsafe fn mailbox_call(
-        &self,
-        msg: &mut Messege,
-    ) -> Result<(), MailBoxError> {
-        self.inner.lock(|mbox| mbox.mailbox_call(msg))
+    pub fn call(&self, msg: &mut PropertyMessage) -> Result<(), MailBoxError> {
+        self.inner.lock(|mbox| mbox.call(msg))
     }
+
+    // Send a message with a single tag and return its response
+    pub fn request<T: Tag>(&self, tag: T) -> Result<T::Response, MailBoxError> {
+        let mut msg = PropertyMessage::new();
+        let handle = msg.push(tag)?;
+        self.call(&mut msg)?;
+
+        msg.get(&handle)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn wait_until(
+    deadline: Duration,
+    mut done: impl FnMut() -> bool,
+) -> Result<(), MailBoxError> {
+    while !done() {
+        if time::time_manager().uptime() > deadline {
+            return Err(MailBoxError::Timeout);
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,811 @@
+// Messages of the mailbox property channel
+//
+// A message is a list of tags, each of which asks the firmware for a value or an action. Pushing a
+// typed tag onto a `PropertyMessage` returns a handle, and once the firmware has answered the
+// handle reads the typed response back. Sizes, padding and the end tag are taken care of here.
+//
+// This module only encodes and decodes the message words and does not touch the hardware, so it
+// builds and runs on the host as well.
+//
+// https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface
+
+use core::{fmt, marker::PhantomData};
+
+//--------------------------------------------------------------------------------------------------
+// Global Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Number of 32-bit words in a message, including the header and the end tag
+pub const MESSAGE_WORDS: usize = 512;
+
+// Largest kernel command line that can be read
+pub const COMMAND_LINE_SIZE: usize = 1024;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Request codes of the message and of every tag
+const REQUEST: u32 = 0;
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+const RESPONSE_PARSE_ERROR: u32 = 0x8000_0001;
+
+// Set in the code of a tag the firmware has answered, the rest is the response length in bytes
+const TAG_RESPONSE: u32 = 0x8000_0000;
+
+const END_TAG: u32 = 0;
+
+// buffer size, request code
+const HEADER_WORDS: usize = 2;
+
+// identifier, value buffer size, request code
+const TAG_HEADER_WORDS: usize = 3;
+
+// The channel is in the low 4 bits of a mail, so messages must be 16-byte aligned
+const CHANNEL_MASK: usize = 0x0F;
+
+// Uncached alias of the message address as seen by the VideoCore
+const BUS_ALIAS: u32 = 0xC000_0000;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailBoxError {
+    // the message is not 16-byte aligned or out of reach of the VideoCore
+    NotAligned,
+    // the tags do not fit into the message
+    MessageFull,
+    // the firmware did not answer in time
+    Timeout,
+    // the firmware could not parse the message
+    ParseError,
+    // the message came back with an unknown code
+    UnexpectedResponse(u32),
+    // the firmware does not know the tag with this identifier
+    TagNotHandled(u32),
+    // the response of the tag is larger than its value buffer
+    ResponseTooLarge(u32),
+    // the response of the tag is too short or malformed
+    InvalidResponse(u32),
+}
+
+// Property tag interfaces
+pub mod interface {
+    pub trait Tag {
+        // tag identifier
+        const ID: u32;
+
+        type Response;
+
+        // size of the value buffer in words, large enough for the request and the response
+        fn value_words(&self) -> usize;
+
+        // write the request values into the zeroed value buffer
+        fn encode(&self, _values: &mut [u32]) {}
+
+        // read the response from the value buffer, `len` is its length in bytes
+        fn decode(values: &[u32], len: usize) -> Option<Self::Response>;
+    }
+}
+
+// A property message with 16-byte alignment, as required by the mailbox
+#[derive(Clone)]
+#[repr(C, align(16))]
+pub struct PropertyMessage {
+    words: [u32; MESSAGE_WORDS],
+    // end of the last tag
+    len: usize,
+}
+
+// Where a tag was placed in a message, used to read its response
+pub struct TagHandle<T> {
+    offset: usize,
+    value_words: usize,
+    _tag: PhantomData<T>,
+}
+
+// Start and size of a memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32,
+}
+
+// Power state of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerState {
+    pub on: bool,
+    // false if the firmware does not know the device
+    pub exists: bool,
+}
+
+// Kernel command line set up by the firmware
+#[derive(Clone, Copy)]
+pub struct CommandLine {
+    bytes: [u8; COMMAND_LINE_SIZE],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for MailBoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailBoxError::NotAligned => write!(f, "Message is not aligned"),
+            MailBoxError::MessageFull => write!(f, "Message is full"),
+            MailBoxError::Timeout => write!(f, "Firmware did not answer"),
+            MailBoxError::ParseError => {
+                write!(f, "Firmware could not parse message")
+            }
+            MailBoxError::UnexpectedResponse(code) => {
+                write!(f, "Unexpected response code {:#010x}", code)
+            }
+            MailBoxError::TagNotHandled(id) => {
+                write!(f, "Tag {:#010x} is not handled", id)
+            }
+            MailBoxError::ResponseTooLarge(id) => {
+                write!(f, "Response of tag {:#010x} is too large", id)
+            }
+            MailBoxError::InvalidResponse(id) => {
+                write!(f, "Response of tag {:#010x} is invalid", id)
+            }
+        }
+    }
+}
+
+impl From<MailBoxError> for &'static str {
+    fn from(error: MailBoxError) -> Self {
+        match error {
+            MailBoxError::NotAligned => "Mailbox message is not aligned",
+            MailBoxError::MessageFull => "Mailbox message is full",
+            MailBoxError::Timeout => "Mailbox call timed out",
+            MailBoxError::ParseError => "Mailbox message could not be parsed",
+            MailBoxError::UnexpectedResponse(_) => {
+                "Unexpected mailbox response"
+            }
+            MailBoxError::TagNotHandled(_) => "Mailbox tag is not handled",
+            MailBoxError::ResponseTooLarge(_) => {
+                "Mailbox response is too large"
+            }
+            MailBoxError::InvalidResponse(_) => "Invalid mailbox response",
+        }
+    }
+}
+
+// The mail that hands the message at `address` to the firmware on `channel`
+pub fn mail(address: usize, channel: u32) -> Result<u32, MailBoxError> {
+    if address & CHANNEL_MASK != 0 {
+        return Err(MailBoxError::NotAligned);
+    }
+
+    u32::try_from(address)
+        .ok()
+        .and_then(|address| address.checked_add(BUS_ALIAS))
+        .map(|mail| mail | channel & CHANNEL_MASK as u32)
+        .ok_or(MailBoxError::NotAligned)
+}
+
+impl PropertyMessage {
+    pub const fn new() -> Self {
+        let mut words = [0; MESSAGE_WORDS];
+        words[0] = ((HEADER_WORDS + 1) * 4) as u32;
+
+        Self {
+            words,
+            len: HEADER_WORDS,
+        }
+    }
+
+    // Append a tag, the returned handle reads its response after the call
+    pub fn push<T: interface::Tag>(
+        &mut self,
+        tag: T,
+    ) -> Result<TagHandle<T>, MailBoxError> {
+        let offset = self.len;
+        let value_words = tag.value_words();
+        let end = offset + TAG_HEADER_WORDS + value_words;
+
+        // keep a word for the end tag
+        if end >= MESSAGE_WORDS {
+            return Err(MailBoxError::MessageFull);
+        }
+
+        self.words[offset] = T::ID;
+        self.words[offset + 1] = (value_words * 4) as u32;
+        self.words[offset + 2] = REQUEST;
+
+        let values = &mut self.words[offset + TAG_HEADER_WORDS..end];
+        values.fill(0);
+        tag.encode(values);
+
+        self.len = end;
+        self.words[end] = END_TAG;
+        self.words[0] = ((end + 1) * 4) as u32;
+        self.words[1] = REQUEST;
+
+        Ok(TagHandle {
+            offset,
+            value_words,
+            _tag: PhantomData,
+        })
+    }
+
+    // The words handed to the firmware
+    pub fn as_mut_ptr(&mut self) -> *mut u32 {
+        self.words.as_mut_ptr()
+    }
+
+    // The used part of the message
+    pub fn words(&self) -> &[u32] {
+        &self.words[..self.len + 1]
+    }
+
+    // True once the firmware has replaced the request code
+    pub fn is_answered(&self) -> bool {
+        self.words[1] != REQUEST
+    }
+
+    // Result of the whole message
+    pub fn status(&self) -> Result<(), MailBoxError> {
+        match self.words[1] {
+            RESPONSE_SUCCESS => Ok(()),
+            RESPONSE_PARSE_ERROR => Err(MailBoxError::ParseError),
+            code => Err(MailBoxError::UnexpectedResponse(code)),
+        }
+    }
+
+    // Response of a tag pushed onto this message
+    pub fn get<T: interface::Tag>(
+        &self,
+        handle: &TagHandle<T>,
+    ) -> Result<T::Response, MailBoxError> {
+        self.status()?;
+
+        let offset = handle.offset;
+        if self.words.get(offset) != Some(&T::ID) {
+            return Err(MailBoxError::InvalidResponse(T::ID));
+        }
+
+        let code = self.words[offset + 2];
+        if code & TAG_RESPONSE == 0 {
+            return Err(MailBoxError::TagNotHandled(T::ID));
+        }
+
+        let len = (code & !TAG_RESPONSE) as usize;
+        if len > handle.value_words * 4 {
+            return Err(MailBoxError::ResponseTooLarge(T::ID));
+        }
+
+        let start = offset + TAG_HEADER_WORDS;
+        T::decode(&self.words[start..start + handle.value_words], len)
+            .ok_or(MailBoxError::InvalidResponse(T::ID))
+    }
+}
+
+impl Default for PropertyMessage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PowerState {
+    // bit 0: on, bit 1: the device does not exist
+    fn new(state: u32) -> Self {
+        Self {
+            on: state & 0b01 != 0,
+            exists: state & 0b10 == 0,
+        }
+    }
+}
+
+impl CommandLine {
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tags
+//--------------------------------------------------------------------------------------------------
+
+// Firmware revision
+pub struct GetFirmwareRevision;
+
+// Board model
+pub struct GetBoardModel;
+
+// Board revision code
+pub struct GetBoardRevision;
+
+// MAC address of the on-board network interface
+pub struct GetBoardMacAddress;
+
+// Board serial number
+pub struct GetBoardSerial;
+
+// Memory split between the ARM and the VideoCore
+pub struct GetArmMemory;
+pub struct GetVcMemory;
+
+// Command line passed to the kernel
+pub struct GetCommandLine;
+
+// Power state of a device, identified by its firmware device id
+pub struct GetPowerState {
+    pub device: u32,
+}
+
+// Switch a device on or off, `wait` waits until the power is stable
+pub struct SetPowerState {
+    pub device: u32,
+    pub on: bool,
+    pub wait: bool,
+}
+
+// Rates of a clock in Hz, identified by its firmware clock id. A rate of 0 means the clock does not
+// exist.
+pub struct GetClockRate {
+    pub clock: u32,
+}
+
+pub struct GetMaxClockRate {
+    pub clock: u32,
+}
+
+pub struct GetMinClockRate {
+    pub clock: u32,
+}
+
+// Set a clock, answered with the rate that was actually set. Setting the ARM clock above its
+// default also raises the other turbo clocks unless `skip_turbo` is set.
+pub struct SetClockRate {
+    pub clock: u32,
+    pub rate: u32,
+    pub skip_turbo: bool,
+}
+
+// SoC temperature in thousandths of a degree Celsius
+pub struct GetTemperature;
+
+// Temperature at which the firmware starts throttling on its own
+pub struct GetMaxTemperature;
+
+// Voltage in microvolts, identified by its firmware voltage id
+pub struct GetVoltage {
+    pub voltage: u32,
+}
+
+// Under-voltage and throttling flags
+pub struct GetThrottled;
+
+// Allocate the frame buffer, answered with (bus address, size)
+pub struct AllocateBuffer {
+    pub alignment: u32,
+}
+
+// Display size as (width, height)
+pub struct GetPhysicalSize;
+
+pub struct SetPhysicalSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+// Size of the frame buffer, which may be larger than the display
+pub struct SetVirtualSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+// Bits per pixel
+pub struct SetDepth {
+    pub depth: u32,
+}
+
+// Bytes per frame buffer row
+pub struct GetPitch;
+
+impl interface::Tag for GetFirmwareRevision {
+    const ID: u32 = 0x0000_0001;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        1
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<1>(values, len).map(|[revision]| revision)
+    }
+}
+
+impl interface::Tag for GetBoardModel {
+    const ID: u32 = 0x0001_0001;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        1
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<1>(values, len).map(|[model]| model)
+    }
+}
+
+impl interface::Tag for GetBoardRevision {
+    const ID: u32 = 0x0001_0002;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        1
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<1>(values, len).map(|[revision]| revision)
+    }
+}
+
+impl interface::Tag for GetBoardMacAddress {
+    const ID: u32 = 0x0001_0003;
+    type Response = [u8; 6];
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    // the address is sent in network byte order
+    fn decode(values: &[u32], len: usize) -> Option<[u8; 6]> {
+        let [low, high] = response::<2>(values, len)?;
+        let (low, high) = (low.to_le_bytes(), high.to_le_bytes());
+
+        Some([low[0], low[1], low[2], low[3], high[0], high[1]])
+    }
+}
+
+impl interface::Tag for GetBoardSerial {
+    const ID: u32 = 0x0001_0004;
+    type Response = u64;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u64> {
+        let [low, high] = response::<2>(values, len)?;
+
+        Some(((high as u64) << 32) | low as u64)
+    }
+}
+
+impl interface::Tag for GetArmMemory {
+    const ID: u32 = 0x0001_0005;
+    type Response = MemoryRegion;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<MemoryRegion> {
+        response::<2>(values, len)
+            .map(|[base, size]| MemoryRegion { base, size })
+    }
+}
+
+impl interface::Tag for GetVcMemory {
+    const ID: u32 = 0x0001_0006;
+    type Response = MemoryRegion;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<MemoryRegion> {
+        response::<2>(values, len)
+            .map(|[base, size]| MemoryRegion { base, size })
+    }
+}
+
+impl interface::Tag for GetCommandLine {
+    const ID: u32 = 0x0005_0001;
+    type Response = CommandLine;
+
+    fn value_words(&self) -> usize {
+        COMMAND_LINE_SIZE / 4
+    }
+
+    // a string of `len` bytes, which may or may not be terminated by NUL
+    fn decode(values: &[u32], len: usize) -> Option<CommandLine> {
+        let mut cmdline = CommandLine {
+            bytes: [0; COMMAND_LINE_SIZE],
+            len: 0,
+        };
+
+        for (chunk, word) in cmdline.bytes.chunks_mut(4).zip(values.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        let len = len.min(COMMAND_LINE_SIZE);
+        cmdline.len = cmdline.bytes[..len]
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(len);
+
+        Some(cmdline)
+    }
+}
+
+impl interface::Tag for GetPowerState {
+    const ID: u32 = 0x0002_0001;
+    type Response = PowerState;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.device;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<PowerState> {
+        response::<2>(values, len).map(|[_, state]| PowerState::new(state))
+    }
+}
+
+impl interface::Tag for SetPowerState {
+    const ID: u32 = 0x0002_8001;
+    type Response = PowerState;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.device;
+        values[1] = self.on as u32 | (self.wait as u32) << 1;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<PowerState> {
+        response::<2>(values, len).map(|[_, state]| PowerState::new(state))
+    }
+}
+
+impl interface::Tag for GetClockRate {
+    const ID: u32 = 0x0003_0002;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.clock;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<2>(values, len).map(|[_, rate]| rate)
+    }
+}
+
+impl interface::Tag for GetMaxClockRate {
+    const ID: u32 = 0x0003_0004;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.clock;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<2>(values, len).map(|[_, rate]| rate)
+    }
+}
+
+impl interface::Tag for GetMinClockRate {
+    const ID: u32 = 0x0003_0007;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.clock;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<2>(values, len).map(|[_, rate]| rate)
+    }
+}
+
+impl interface::Tag for SetClockRate {
+    const ID: u32 = 0x0003_8002;
+    type Response = u32;
+
+    // clock id, rate and the turbo flag, answered with clock id and rate
+    fn value_words(&self) -> usize {
+        3
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.clock;
+        values[1] = self.rate;
+        values[2] = self.skip_turbo as u32;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<2>(values, len).map(|[_, rate]| rate)
+    }
+}
+
+impl interface::Tag for GetTemperature {
+    const ID: u32 = 0x0003_0006;
+    type Response = u32;
+
+    // temperature id, always 0
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<2>(values, len).map(|[_, temperature]| temperature)
+    }
+}
+
+impl interface::Tag for GetMaxTemperature {
+    const ID: u32 = 0x0003_000A;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<2>(values, len).map(|[_, temperature]| temperature)
+    }
+}
+
+impl interface::Tag for GetVoltage {
+    const ID: u32 = 0x0003_0003;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.voltage;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<2>(values, len).map(|[_, voltage]| voltage)
+    }
+}
+
+impl interface::Tag for GetThrottled {
+    const ID: u32 = 0x0003_0046;
+    type Response = u32;
+
+    // the request value selects sticky flags to clear, none are
+    fn value_words(&self) -> usize {
+        1
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<1>(values, len).map(|[flags]| flags)
+    }
+}
+
+impl interface::Tag for AllocateBuffer {
+    const ID: u32 = 0x0004_0001;
+    type Response = (u32, u32);
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.alignment;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<(u32, u32)> {
+        response::<2>(values, len).map(|[addr, size]| (addr, size))
+    }
+}
+
+impl interface::Tag for GetPhysicalSize {
+    const ID: u32 = 0x0004_0003;
+    type Response = (u32, u32);
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<(u32, u32)> {
+        response::<2>(values, len).map(|[width, height]| (width, height))
+    }
+}
+
+impl interface::Tag for SetPhysicalSize {
+    const ID: u32 = 0x0004_8003;
+    type Response = (u32, u32);
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.width;
+        values[1] = self.height;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<(u32, u32)> {
+        response::<2>(values, len).map(|[width, height]| (width, height))
+    }
+}
+
+impl interface::Tag for SetVirtualSize {
+    const ID: u32 = 0x0004_8004;
+    type Response = (u32, u32);
+
+    fn value_words(&self) -> usize {
+        2
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.width;
+        values[1] = self.height;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<(u32, u32)> {
+        response::<2>(values, len).map(|[width, height]| (width, height))
+    }
+}
+
+impl interface::Tag for SetDepth {
+    const ID: u32 = 0x0004_8005;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        1
+    }
+
+    fn encode(&self, values: &mut [u32]) {
+        values[0] = self.depth;
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<1>(values, len).map(|[depth]| depth)
+    }
+}
+
+impl interface::Tag for GetPitch {
+    const ID: u32 = 0x0004_0008;
+    type Response = u32;
+
+    fn value_words(&self) -> usize {
+        1
+    }
+
+    fn decode(values: &[u32], len: usize) -> Option<u32> {
+        response::<1>(values, len).map(|[pitch]| pitch)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// The first `N` response words, if the response is long enough
+fn response<const N: usize>(values: &[u32], len: usize) -> Option<[u32; N]> {
+    if len < N * 4 {
+        return None;
+    }
+
+    values.get(..N)?.try_into().ok()
+}
@@ -56,6 +56,11 @@ pub(super) mod map {
     pub const GPIO_OFFSET:    usize  = 0x0020_0000;
     pub const UART_OFFSET:    usize  = 0x0020_1000;
     pub const MAILBOX_OFFSET: usize  = 0x0000_B880;
+    pub const SPI0_OFFSET:    usize  = 0x0020_4000;
+    pub const PWM0_OFFSET:    usize  = 0x0020_C000;
+    pub const CM_PWM_OFFSET:  usize  = 0x0010_10A0;
+    pub const I2C1_OFFSET:    usize  = 0x0080_4000;
+    pub const USB_OFFSET:     usize  = 0x0098_0000;
 
     #[cfg(feature = "bsp_rpi3")]
     pub mod mmio {
@@ -66,6 +71,12 @@ pub(super) mod map {
         pub const GPIO_START:       usize   = START + GPIO_OFFSET;
         pub const PL011_UART_START: usize   = START + UART_OFFSET;
         pub const MAILBOX_START:    usize   = START + MAILBOX_OFFSET;
+        pub const SPI0_START:       usize   = START + SPI0_OFFSET;
+        pub const I2C1_START:       usize   = START + I2C1_OFFSET;
+        pub const PWM0_START:       usize   = START + PWM0_OFFSET;
+        pub const CM_PWM_START:     usize   = START + CM_PWM_OFFSET;
+        pub const EMMC_START:       usize   = START + 0x0030_0000;
+        pub const USB_START:        usize   = START + USB_OFFSET;
         pub const END_INCLUSIVE:    usize   =         0x4000_FFFF;
     }
 
@@ -79,6 +90,16 @@ pub(super) mod map {
         pub const GICD_START:       usize =           0xFF84_1000;
         pub const GICC_START:       usize =           0xFF84_2000;
         pub const MAILBOX_START:    usize   = START + MAILBOX_OFFSET;
+        pub const SPI0_START:       usize   = START + SPI0_OFFSET;
+        pub const I2C1_START:       usize   = START + I2C1_OFFSET;
+        pub const PWM0_START:       usize   = START + PWM0_OFFSET;
+        // the headphone jack
+        pub const PWM1_START:       usize   = START + 0x0020_C800;
+        pub const CM_PWM_START:     usize   = START + CM_PWM_OFFSET;
+        // EMMC2, wired to the SD card slot
+        pub const EMMC_START:       usize   = START + 0x0034_0000;
+        // the USB-C port
+        pub const USB_START:        usize   = START + USB_OFFSET;
         pub const END_INCLUSIVE:    usize   =         0xFF84_FFFF;
     }
 }
@@ -0,0 +1,376 @@
+// SoC temperature and voltage monitoring
+//
+// The firmware is sampled every `SAMPLE_INTERVAL` from `poll()`. Crossing a threshold is logged,
+// and when the chip gets hot the ARM clock is lowered through the CPU governor until it has cooled
+// down again. The firmware throttles on its own at the maximum temperature, this only starts
+// earlier.
+//
+// https://www.raspberrypi.com/documentation/computers/os.html#get_throttled
+
+use super::clock::cpu_governor;
+use super::driver::MAILBOX;
+use super::mailbox::{property::*, MailBoxError};
+use crate::{
+    clock::{interface::CpuGovernor, CpuPolicy},
+    info,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+    warn,
+};
+use core::{fmt, time::Duration};
+use tock_registers::{
+    interfaces::Readable, register_bitfields, registers::InMemoryRegister,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Flags of GET_THROTTLED, the upper half is sticky since boot
+const CURRENT_FLAGS: u32 = 0xFFFF;
+
+register_bitfields! {u32,
+    THROTTLED [
+        SOFT_TEMPERATURE_LIMIT_OCCURRED OFFSET(19) NUMBITS(1) [],
+        THROTTLED_OCCURRED OFFSET(18) NUMBITS(1) [],
+        FREQUENCY_CAPPED_OCCURRED OFFSET(17) NUMBITS(1) [],
+        UNDER_VOLTAGE_OCCURRED OFFSET(16) NUMBITS(1) [],
+
+        SOFT_TEMPERATURE_LIMIT OFFSET(3) NUMBITS(1) [],
+        THROTTLED OFFSET(2) NUMBITS(1) [],
+        FREQUENCY_CAPPED OFFSET(1) NUMBITS(1) [],
+        UNDER_VOLTAGE OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Temperatures in thousandths of a degree Celsius
+const WARN_TEMPERATURE: u32 = 70_000;
+const THROTTLE_TEMPERATURE: u32 = 80_000;
+
+// Throttling starts at least this far below the firmware limit
+const FIRMWARE_MARGIN: u32 = 5_000;
+
+// How far the temperature has to fall before the level goes down again
+const HYSTERESIS: u32 = 5_000;
+
+// Voltage id of the core
+const CORE_VOLTAGE: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Normal,
+    Warm,
+    Hot,
+}
+
+struct ThermalMonitorInner {
+    readings: Option<Readings>,
+    level: Level,
+    // the policy to go back to when the chip has cooled down, set while throttling
+    saved_policy: Option<CpuPolicy>,
+    next_sample: Duration,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Under-voltage and throttling flags reported by the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled(u32);
+
+// The last sample. Values the firmware did not report are `None`.
+#[derive(Debug, Clone, Copy)]
+pub struct Readings {
+    // when the sample was taken
+    pub uptime: Duration,
+    // thousandths of a degree Celsius
+    pub temperature: Option<u32>,
+    pub max_temperature: Option<u32>,
+    // microvolts
+    pub core_voltage: Option<u32>,
+    pub throttled: Option<Throttled>,
+    // the monitor has lowered the ARM clock
+    pub throttling: bool,
+}
+
+pub struct ThermalMonitor {
+    inner: IRQSafeNullLock<ThermalMonitorInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static THERMAL_MONITOR: ThermalMonitor = ThermalMonitor::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the thermal monitor
+pub fn thermal_monitor() -> &'static ThermalMonitor {
+    &THERMAL_MONITOR
+}
+
+impl ThermalMonitor {
+    const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(ThermalMonitorInner {
+                readings: None,
+                level: Level::Normal,
+                saved_policy: None,
+                next_sample: Duration::ZERO,
+            }),
+        }
+    }
+
+    // Take a sample if it is time to, called from the main loop
+    pub fn poll(&self) {
+        let now = time::time_manager().uptime();
+        let due = self.inner.lock(|inner| {
+            if now < inner.next_sample {
+                return false;
+            }
+
+            inner.next_sample = now + SAMPLE_INTERVAL;
+            true
+        });
+
+        if due {
+            if let Err(e) = self.sample(now) {
+                warn!("Thermal: sampling failed: {}", e);
+            }
+        }
+    }
+
+    // The last sample, `None` before the first one
+    pub fn readings(&self) -> Option<Readings> {
+        self.inner.lock(|inner| inner.readings)
+    }
+}
+
+impl Throttled {
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_under_voltage(&self) -> bool {
+        self.field().is_set(THROTTLED::UNDER_VOLTAGE)
+    }
+
+    pub fn is_frequency_capped(&self) -> bool {
+        self.field().is_set(THROTTLED::FREQUENCY_CAPPED)
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.field().is_set(THROTTLED::THROTTLED)
+    }
+
+    pub fn is_soft_temperature_limit(&self) -> bool {
+        self.field().is_set(THROTTLED::SOFT_TEMPERATURE_LIMIT)
+    }
+
+    // Any of the flags has been set since boot
+    pub fn has_occurred(&self) -> bool {
+        self.field().matches_any(
+            THROTTLED::UNDER_VOLTAGE_OCCURRED::SET
+                + THROTTLED::FREQUENCY_CAPPED_OCCURRED::SET
+                + THROTTLED::THROTTLED_OCCURRED::SET
+                + THROTTLED::SOFT_TEMPERATURE_LIMIT_OCCURRED::SET,
+        )
+    }
+}
+
+// The flags that are set now, e.g. `under-voltage, throttled`
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.is_under_voltage(), "under-voltage"),
+            (self.is_frequency_capped(), "frequency capped"),
+            (self.is_throttled(), "throttled"),
+            (self.is_soft_temperature_limit(), "soft temperature limit"),
+        ];
+
+        let mut first = true;
+        for (_, name) in flags.iter().filter(|(set, _)| *set) {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", name)?;
+            first = false;
+        }
+        if first {
+            write!(f, "ok")?;
+        }
+
+        Ok(())
+    }
+}
+
+// One line for a status line, e.g. `54.2 C 0.88 V ok`
+impl fmt::Display for Readings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.temperature {
+            Some(t) => write!(f, "{}.{} C", t / 1000, t % 1000 / 100)?,
+            None => write!(f, "-- C")?,
+        }
+        match self.core_voltage {
+            Some(v) => {
+                write!(f, " {}.{:02} V", v / 1_000_000, v % 1_000_000 / 10_000)?
+            }
+            None => write!(f, " -- V")?,
+        }
+        if let Some(throttled) = self.throttled {
+            write!(f, " {}", throttled)?;
+        }
+        if self.throttling {
+            write!(f, " (cooling down)")?;
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ThermalMonitor {
+    fn sample(&self, now: Duration) -> Result<(), MailBoxError> {
+        let mut msg = PropertyMessage::new();
+        let temperature = msg.push(GetTemperature)?;
+        let max_temperature = msg.push(GetMaxTemperature)?;
+        let core_voltage = msg.push(GetVoltage {
+            voltage: CORE_VOLTAGE,
+        })?;
+        let throttled = msg.push(GetThrottled)?;
+
+        MAILBOX.call(&mut msg)?;
+
+        let mut readings = Readings {
+            uptime: now,
+            temperature: msg.get(&temperature).ok(),
+            max_temperature: msg.get(&max_temperature).ok(),
+            core_voltage: msg.get(&core_voltage).ok(),
+            throttled: msg.get(&throttled).ok().map(Throttled),
+            throttling: false,
+        };
+
+        self.inner.lock(|inner| {
+            let previous = inner.readings.and_then(|r| r.throttled);
+            report_flags(previous, readings.throttled);
+
+            if let Some(temperature) = readings.temperature {
+                inner.update_level(temperature, readings.max_temperature);
+            }
+
+            readings.throttling = inner.saved_policy.is_some();
+            inner.readings = Some(readings);
+        });
+
+        Ok(())
+    }
+}
+
+impl ThermalMonitorInner {
+    fn update_level(&mut self, temperature: u32, max_temperature: Option<u32>) {
+        let throttle = match max_temperature {
+            Some(max) => {
+                THROTTLE_TEMPERATURE.min(max.saturating_sub(FIRMWARE_MARGIN))
+            }
+            None => THROTTLE_TEMPERATURE,
+        };
+        let warn = WARN_TEMPERATURE.min(throttle);
+
+        // go up right away, but only down once the temperature is below the threshold by the
+        // hysteresis
+        let level = match self.level {
+            _ if temperature >= throttle => Level::Hot,
+            Level::Hot if temperature + HYSTERESIS > throttle => Level::Hot,
+            _ if temperature >= warn => Level::Warm,
+            Level::Hot | Level::Warm if temperature + HYSTERESIS > warn => {
+                Level::Warm
+            }
+            _ => Level::Normal,
+        };
+
+        if level == self.level {
+            // lowering the clock is tried again with every sample until it works
+            if level == Level::Hot && self.saved_policy.is_none() {
+                self.throttle();
+            }
+            return;
+        }
+
+        let degrees = temperature / 1000;
+        match level {
+            Level::Hot => {
+                warn!("Thermal: {} C, lowering the CPU clock", degrees)
+            }
+            Level::Warm if level > self.level => {
+                warn!("Thermal: {} C", degrees)
+            }
+            _ => info!("Thermal: cooled down to {} C", degrees),
+        }
+
+        if level == Level::Hot {
+            self.throttle();
+        } else if self.level == Level::Hot {
+            self.restore();
+        }
+        self.level = level;
+    }
+
+    fn throttle(&mut self) {
+        if self.saved_policy.is_some() {
+            return;
+        }
+
+        let policy = cpu_governor().policy().unwrap_or(CpuPolicy::Performance);
+        match cpu_governor().set_policy(CpuPolicy::PowerSave) {
+            Ok(rate) => {
+                info!("Thermal: CPU clock is {} MHz", rate / 1_000_000);
+                self.saved_policy = Some(policy);
+            }
+            Err(e) => warn!("Thermal: lowering the CPU clock failed: {}", e),
+        }
+    }
+
+    fn restore(&mut self) {
+        let policy = match self.saved_policy.take() {
+            Some(policy) => policy,
+            None => return,
+        };
+
+        match cpu_governor().set_policy(policy) {
+            Ok(rate) => info!("Thermal: CPU clock is {} MHz", rate / 1_000_000),
+            Err(e) => warn!("Thermal: restoring the CPU clock failed: {}", e),
+        }
+    }
+}
+
+impl Throttled {
+    fn field(&self) -> InMemoryRegister<u32, THROTTLED::Register> {
+        InMemoryRegister::new(self.0)
+    }
+}
+
+// Warn about flags that were not set in the previous sample
+fn report_flags(previous: Option<Throttled>, current: Option<Throttled>) {
+    let current = match current {
+        Some(current) => current,
+        None => return,
+    };
+    let previous = previous.map(|p| p.bits()).unwrap_or(0);
+
+    let new = Throttled(current.bits() & !previous & CURRENT_FLAGS);
+    if new.bits() != 0 {
+        warn!("Thermal: {}", new);
+    }
+    if previous & CURRENT_FLAGS != 0 && current.bits() & CURRENT_FLAGS == 0 {
+        info!("Thermal: no longer throttled");
+    }
+}
@@ -0,0 +1,223 @@
+// Checksums used by file formats and transfer framing
+//
+// All checksums are computed incrementally, so data can be fed in pieces as it is produced.
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Reflected CRC-32 polynomial (ISO-HDLC), as used by PNG, gzip and zip
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+// Largest prime below 2^16
+const ADLER32_MODULUS: u32 = 65521;
+
+// Number of bytes that can be summed before the Adler-32 sums must be reduced
+const ADLER32_BLOCK: usize = 5552;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+// xxHash32 primes
+const XXH32_PRIME1: u32 = 2_654_435_761;
+const XXH32_PRIME2: u32 = 2_246_822_519;
+const XXH32_PRIME3: u32 = 3_266_489_917;
+const XXH32_PRIME4: u32 = 668_265_263;
+const XXH32_PRIME5: u32 = 374_761_393;
+
+// xxHash32 consumes the input in stripes of four words
+const XXH32_STRIPE: usize = 16;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    value: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+// xxHash32, as used by LZ4 frames
+#[derive(Debug, Clone, Copy)]
+pub struct XxHash32 {
+    seed: u32,
+    accumulators: [u32; 4],
+    // the start of a stripe that is not complete yet
+    stripe: [u8; XXH32_STRIPE],
+    stripe_len: usize,
+    total_len: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self { value: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data.iter() {
+            let index = (self.value ^ *byte as u32) & 0xFF;
+            self.value = CRC32_TABLE[index as usize] ^ (self.value >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.value
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Adler32 {
+    pub const fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for block in data.chunks(ADLER32_BLOCK) {
+            for byte in block.iter() {
+                self.a += *byte as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER32_MODULUS;
+            self.b %= ADLER32_MODULUS;
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XxHash32 {
+    pub const fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            accumulators: [
+                seed.wrapping_add(XXH32_PRIME1).wrapping_add(XXH32_PRIME2),
+                seed.wrapping_add(XXH32_PRIME2),
+                seed,
+                seed.wrapping_sub(XXH32_PRIME1),
+            ],
+            stripe: [0; XXH32_STRIPE],
+            stripe_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        if self.stripe_len > 0 {
+            let len = data.len().min(XXH32_STRIPE - self.stripe_len);
+            self.stripe[self.stripe_len..self.stripe_len + len]
+                .copy_from_slice(&data[..len]);
+            self.stripe_len += len;
+            data = &data[len..];
+
+            if self.stripe_len < XXH32_STRIPE {
+                return;
+            }
+            let stripe = self.stripe;
+            self.consume(&stripe);
+            self.stripe_len = 0;
+        }
+
+        let mut stripes = data.chunks_exact(XXH32_STRIPE);
+        for stripe in &mut stripes {
+            self.consume(stripe);
+        }
+
+        let rest = stripes.remainder();
+        self.stripe[..rest.len()].copy_from_slice(rest);
+        self.stripe_len = rest.len();
+    }
+
+    pub fn finish(&self) -> u32 {
+        let [a, b, c, d] = self.accumulators;
+        let mut hash = if self.total_len >= XXH32_STRIPE as u64 {
+            a.rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18))
+        } else {
+            self.seed.wrapping_add(XXH32_PRIME5)
+        };
+        hash = hash.wrapping_add(self.total_len as u32);
+
+        let rest = &self.stripe[..self.stripe_len];
+        let mut words = rest.chunks_exact(4);
+        for word in &mut words {
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            hash = hash.wrapping_add(word.wrapping_mul(XXH32_PRIME3));
+            hash = hash.rotate_left(17).wrapping_mul(XXH32_PRIME4);
+        }
+        for byte in words.remainder() {
+            hash = hash.wrapping_add((*byte as u32).wrapping_mul(XXH32_PRIME5));
+            hash = hash.rotate_left(11).wrapping_mul(XXH32_PRIME1);
+        }
+
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(XXH32_PRIME2);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(XXH32_PRIME3);
+        hash ^ (hash >> 16)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl XxHash32 {
+    fn consume(&mut self, stripe: &[u8]) {
+        for (accumulator, word) in
+            self.accumulators.iter_mut().zip(stripe.chunks_exact(4))
+        {
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            *accumulator = accumulator
+                .wrapping_add(word.wrapping_mul(XXH32_PRIME2))
+                .rotate_left(13)
+                .wrapping_mul(XXH32_PRIME1);
+        }
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                CRC32_POLYNOMIAL ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+
+    table
+}
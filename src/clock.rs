@@ -0,0 +1,89 @@
+// Clocks, power domains and the CPU frequency
+//
+// Which clocks and power domains exist depends on the board, so they are associated types of the
+// manager provided by the BSP.
+
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Clock interfaces
+pub mod interface {
+    use super::CpuPolicy;
+
+    // Clock and power domain management functions
+    pub trait ClockManager {
+        type Clock: Copy;
+        type PowerDomain: Copy;
+
+        // 現在のクロック周波数 (Hz)
+        fn rate(&self, clock: Self::Clock) -> Result<u32, &'static str>;
+
+        fn min_rate(&self, clock: Self::Clock) -> Result<u32, &'static str>;
+
+        fn max_rate(&self, clock: Self::Clock) -> Result<u32, &'static str>;
+
+        // 周波数を設定し、実際に設定された周波数を返す
+        fn set_rate(
+            &self,
+            clock: Self::Clock,
+            rate: u32,
+        ) -> Result<u32, &'static str>;
+
+        fn is_powered(
+            &self,
+            domain: Self::PowerDomain,
+        ) -> Result<bool, &'static str>;
+
+        // 電源が安定するまで待つ
+        fn set_power(
+            &self,
+            domain: Self::PowerDomain,
+            on: bool,
+        ) -> Result<(), &'static str>;
+
+        // Power up a device and return the rate of its clock, called by drivers at init
+        fn enable(
+            &self,
+            domain: Self::PowerDomain,
+            clock: Self::Clock,
+        ) -> Result<u32, &'static str> {
+            self.set_power(domain, true)?;
+            self.rate(clock)
+        }
+    }
+
+    // CPU frequency governor functions
+    pub trait CpuGovernor {
+        // `None` as long as the firmware default is in use
+        fn policy(&self) -> Option<CpuPolicy>;
+
+        // Switch the CPU clock, return the new rate in Hz
+        fn set_policy(&self, policy: CpuPolicy) -> Result<u32, &'static str>;
+
+        fn cpu_rate(&self) -> Result<u32, &'static str>;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuPolicy {
+    // lowest CPU clock
+    PowerSave,
+    // highest CPU clock
+    Performance,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for CpuPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuPolicy::PowerSave => write!(f, "powersave"),
+            CpuPolicy::Performance => write!(f, "performance"),
+        }
+    }
+}
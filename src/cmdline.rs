@@ -0,0 +1,211 @@
+// Kernel command line
+//
+// Subsystems declare their parameters as statics, e.g.
+//
+//     pub static BAUD_RATE: cmdline::Param<u32> = cmdline::Param::new("baud", 921_600);
+//
+// and the BSP lists them. `init()` sets them from the `key=value` words of the command line during
+// kernel init, before the drivers come up, and `report()` warns about words that did not match
+// once there is a console to warn on. Later words override earlier ones.
+
+use crate::{
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    warn,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Global Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Longest command line that is kept
+pub const COMMAND_LINE_SIZE: usize = 1024;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct CommandLineBuffer {
+    bytes: [u8; COMMAND_LINE_SIZE],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Command line interfaces
+pub mod interface {
+    // A parameter as seen by the parser
+    pub trait Param {
+        fn key(&self) -> &'static str;
+
+        // true if `value` parses
+        fn accepts(&self, value: &str) -> bool;
+
+        // parse and store `value`, called during kernel init only
+        fn set(&self, value: &str) -> Result<(), &'static str>;
+    }
+
+    // Types a parameter can have
+    pub trait Value: Sized + Copy {
+        fn parse(s: &str) -> Option<Self>;
+    }
+}
+
+// A typed parameter with its default
+pub struct Param<T> {
+    key: &'static str,
+    value: InitStateLock<T>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static COMMAND_LINE: InitStateLock<CommandLineBuffer> =
+    InitStateLock::new(CommandLineBuffer {
+        bytes: [0; COMMAND_LINE_SIZE],
+        len: 0,
+    });
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Keep the command line and set `params` from it. Must be called during kernel init.
+pub fn init(line: &str, params: &[&'static (dyn interface::Param + Sync)]) {
+    COMMAND_LINE.write(|buffer| buffer.set(line));
+
+    with_command_line(|line| {
+        for (key, value) in words(line) {
+            if let Some(param) = find(params, key) {
+                // reported by `report()`
+                let _ = param.set(value);
+            }
+        }
+    });
+}
+
+// Warn about words that are not in `params` or did not parse. Keys starting with one of `ignored`
+// are left alone.
+pub fn report(
+    params: &[&'static (dyn interface::Param + Sync)],
+    ignored: &[&str],
+) {
+    with_command_line(|line| {
+        for (key, value) in words(line) {
+            match find(params, key) {
+                Some(param) if !param.accepts(value) => {
+                    warn!(
+                        "Invalid value for kernel parameter {}: {}",
+                        key, value
+                    )
+                }
+                Some(_) => {}
+                None if ignored
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix)) => {}
+                None => warn!("Unknown kernel parameter: {}", key),
+            }
+        }
+    });
+}
+
+// Call `f` with the command line, empty before `init()`
+pub fn with_command_line<R>(f: impl FnOnce(&str) -> R) -> R {
+    COMMAND_LINE.read(|buffer| f(buffer.as_str()))
+}
+
+impl<T: interface::Value> Param<T> {
+    pub const fn new(key: &'static str, default: T) -> Self {
+        Self {
+            key,
+            value: InitStateLock::new(default),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.value.read(|value| *value)
+    }
+}
+
+impl interface::Value for u32 {
+    fn parse(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+}
+
+impl interface::Value for bool {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "1" | "on" | "yes" | "true" => Some(true),
+            "0" | "off" | "no" | "false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+// For parameters without a default
+impl<T: interface::Value> interface::Value for Option<T> {
+    fn parse(s: &str) -> Option<Self> {
+        T::parse(s).map(Some)
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl<T: interface::Value> interface::Param for Param<T> {
+    fn key(&self) -> &'static str {
+        self.key
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        T::parse(value).is_some()
+    }
+
+    fn set(&self, value: &str) -> Result<(), &'static str> {
+        let value = T::parse(value).ok_or("Invalid parameter value")?;
+        self.value.write(|current| *current = value);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl CommandLineBuffer {
+    // Longer lines are cut at the last whole word
+    fn set(&mut self, line: &str) {
+        let mut len = line.len().min(COMMAND_LINE_SIZE);
+        if len < line.len() {
+            len = line.as_bytes()[..len]
+                .iter()
+                .rposition(|byte| byte.is_ascii_whitespace())
+                .unwrap_or(0);
+        }
+
+        self.bytes[..len].copy_from_slice(&line.as_bytes()[..len]);
+        self.len = len;
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+// `key=value` words, a word without `=` has an empty value
+fn words(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    line.split_ascii_whitespace()
+        .map(|word| word.split_once('=').unwrap_or((word, "")))
+}
+
+fn find(
+    params: &[&'static (dyn interface::Param + Sync)],
+    key: &str,
+) -> Option<&'static (dyn interface::Param + Sync)> {
+    params.iter().find(|param| param.key() == key).copied()
+}
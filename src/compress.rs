@@ -0,0 +1,153 @@
+// Decompressors
+//
+// The compressed input is one slice in memory, e.g. an archive linked into the kernel, and the
+// output is handed to a sink piece by piece, so that nothing larger than the window of the format
+// is buffered. Sink errors are passed through unchanged, decoding errors are converted into the
+// error type of the sink with `From<DecompressError>`.
+//
+// Each decompressor returns the number of input bytes it consumed, so that data following the
+// compressed stream can be found.
+
+pub mod deflate;
+pub mod gzip;
+pub mod lz4;
+
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// The last `N` bytes of output, which back-references copy from. Output goes to the sink whenever
+// the ring buffer has been filled once more.
+struct Window<const N: usize> {
+    buffer: [u8; N],
+    // bytes produced so far
+    total: u64,
+    // of which have gone to the sink
+    flushed: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressError {
+    // the input ends in the middle of the stream
+    Truncated,
+    // not the expected format
+    BadMagic,
+    // a feature of the format that is not implemented, e.g. an LZ4 dictionary
+    Unsupported,
+    // the stream makes no sense
+    Corrupt(&'static str),
+    // the data does not match the checksum stored with it
+    ChecksumMismatch,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::Corrupt(what) => {
+                write!(f, "Corrupt compressed data: {}", what)
+            }
+            _ => f.write_str((*self).into()),
+        }
+    }
+}
+
+impl From<DecompressError> for &'static str {
+    fn from(error: DecompressError) -> Self {
+        match error {
+            DecompressError::Truncated => "Compressed data is truncated",
+            DecompressError::BadMagic => "Unknown compression format",
+            DecompressError::Unsupported => "Unsupported compression feature",
+            DecompressError::Corrupt(what) => what,
+            DecompressError::ChecksumMismatch => "Checksum mismatch",
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<const N: usize> Window<N> {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            total: 0,
+            flushed: 0,
+        }
+    }
+
+    fn push<E>(
+        &mut self,
+        byte: u8,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let at = (self.total % N as u64) as usize;
+        self.buffer[at] = byte;
+        self.total += 1;
+
+        if at == N - 1 {
+            self.flush(sink)?;
+        }
+
+        Ok(())
+    }
+
+    fn push_slice<E>(
+        &mut self,
+        bytes: &[u8],
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for &byte in bytes {
+            self.push(byte, sink)?;
+        }
+
+        Ok(())
+    }
+
+    // Copy `len` bytes from `distance` bytes back, the copy may overlap what it produces
+    fn copy<E: From<DecompressError>>(
+        &mut self,
+        distance: usize,
+        len: usize,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if distance == 0 || distance > N || distance as u64 > self.total {
+            return Err(
+                DecompressError::Corrupt("distance too far back").into()
+            );
+        }
+
+        for _ in 0..len {
+            let from = ((self.total - distance as u64) % N as u64) as usize;
+            self.push(self.buffer[from], sink)?;
+        }
+
+        Ok(())
+    }
+
+    // Hand what has not been flushed yet to the sink
+    fn flush<E>(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.flushed == self.total {
+            return Ok(());
+        }
+
+        let start = (self.flushed % N as u64) as usize;
+        let end = start + (self.total - self.flushed) as usize;
+        self.flushed = self.total;
+
+        sink(&self.buffer[start..end])
+    }
+}
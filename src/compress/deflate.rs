@@ -0,0 +1,379 @@
+// Raw deflate streams
+//
+// Huffman codes are decoded a bit at a time from the code counts, as in zlib's `puff`, which
+// needs no tables beyond the code lengths and is fast enough for unpacking at boot.
+//
+// https://www.rfc-editor.org/rfc/rfc1951
+
+use super::{DecompressError, Window};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Farthest back-reference
+const WINDOW_SIZE: usize = 32768;
+
+const MAX_BITS: usize = 15;
+const MAX_LITERAL_CODES: usize = 288;
+const MAX_DISTANCE_CODES: usize = 30;
+const END_OF_BLOCK: u16 = 256;
+
+// Block types
+const STORED: u32 = 0;
+const FIXED: u32 = 1;
+const DYNAMIC: u32 = 2;
+
+// (base length, extra bits) of the length codes 257..=285
+const LENGTHS: [(u16, u8); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+// (base distance, extra bits) of the distance codes 0..=29
+const DISTANCES: [(u16, u8); MAX_DISTANCE_CODES] = [
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 1),
+    (7, 1),
+    (9, 2),
+    (13, 2),
+    (17, 3),
+    (25, 3),
+    (33, 4),
+    (49, 4),
+    (65, 5),
+    (97, 5),
+    (129, 6),
+    (193, 6),
+    (257, 7),
+    (385, 7),
+    (513, 8),
+    (769, 8),
+    (1025, 9),
+    (1537, 9),
+    (2049, 10),
+    (3073, 10),
+    (4097, 11),
+    (6145, 11),
+    (8193, 12),
+    (12289, 12),
+    (16385, 13),
+    (24577, 13),
+];
+
+// Order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    bits: u32,
+    count: u32,
+}
+
+// A canonical Huffman code, by the number of codes of each length and the symbols in code order
+struct Huffman<const N: usize> {
+    counts: [u16; MAX_BITS + 1],
+    symbols: [u16; N],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Decompress the deflate stream at the start of `input`, returns the number of bytes it took
+pub fn inflate<E: From<DecompressError>>(
+    input: &[u8],
+    mut sink: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<usize, E> {
+    let mut reader = BitReader {
+        input,
+        position: 0,
+        bits: 0,
+        count: 0,
+    };
+    let mut window = Window::<WINDOW_SIZE>::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            STORED => stored(&mut reader, &mut window, &mut sink)?,
+            FIXED => {
+                let (literals, distances) = fixed_codes();
+                codes(
+                    &mut reader,
+                    &mut window,
+                    &mut sink,
+                    &literals,
+                    &distances,
+                )?
+            }
+            DYNAMIC => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                codes(
+                    &mut reader,
+                    &mut window,
+                    &mut sink,
+                    &literals,
+                    &distances,
+                )?
+            }
+            _ => return Err(DecompressError::Corrupt("bad block type").into()),
+        }
+
+        if last {
+            break;
+        }
+    }
+    window.flush(&mut sink)?;
+
+    // the rest of the last byte is padding
+    Ok(reader.position)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl BitReader<'_> {
+    // The next `count` bits, least significant first
+    fn bits(&mut self, count: u32) -> Result<u32, DecompressError> {
+        while self.count < count {
+            let byte = *self
+                .input
+                .get(self.position)
+                .ok_or(DecompressError::Truncated)?;
+            self.bits |= (byte as u32) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+
+        let value = self.bits & ((1 << count) - 1);
+        self.bits >>= count;
+        self.count -= count;
+
+        Ok(value)
+    }
+
+    // Drop the bits up to the next byte boundary
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], DecompressError> {
+        let bytes = self
+            .input
+            .get(self.position..self.position + len)
+            .ok_or(DecompressError::Truncated)?;
+        self.position += len;
+
+        Ok(bytes)
+    }
+}
+
+impl<const N: usize> Huffman<N> {
+    fn new(lengths: &[u8]) -> Result<Self, DecompressError> {
+        let mut code = Self {
+            counts: [0; MAX_BITS + 1],
+            symbols: [0; N],
+        };
+
+        for &len in lengths {
+            code.counts[len as usize] += 1;
+        }
+
+        // more codes of a length than there is room for
+        let mut left: i32 = 1;
+        for len in 1..=MAX_BITS {
+            left = (left << 1) - code.counts[len] as i32;
+            if left < 0 {
+                return Err(DecompressError::Corrupt("over-subscribed code"));
+            }
+        }
+
+        // symbols in order of their codes: by length, then by value
+        let mut offsets = [0; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + code.counts[len];
+        }
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                code.symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(code)
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, DecompressError> {
+        // code, first code and first symbol index of the current length
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(DecompressError::Corrupt("unused code"))
+    }
+}
+
+fn stored<const N: usize, E: From<DecompressError>>(
+    reader: &mut BitReader,
+    window: &mut Window<N>,
+    sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    reader.align();
+
+    let header = reader.bytes(4)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let complement = u16::from_le_bytes([header[2], header[3]]);
+    if len != !complement {
+        return Err(DecompressError::Corrupt("bad stored block length").into());
+    }
+
+    window.push_slice(reader.bytes(len as usize)?, sink)
+}
+
+fn fixed_codes() -> (Huffman<MAX_LITERAL_CODES>, Huffman<MAX_DISTANCE_CODES>) {
+    let mut lengths = [0; MAX_LITERAL_CODES];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    // the fixed codes are complete
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5; MAX_DISTANCE_CODES]).unwrap(),
+    )
+}
+
+fn dynamic_codes(
+    reader: &mut BitReader,
+) -> Result<
+    (Huffman<MAX_LITERAL_CODES>, Huffman<MAX_DISTANCE_CODES>),
+    DecompressError,
+> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > MAX_DISTANCE_CODES {
+        return Err(DecompressError::Corrupt("too many codes"));
+    }
+
+    let mut code_lengths = [0; 19];
+    for &symbol in CODE_LENGTH_ORDER.iter().take(length_count) {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::<19>::new(&code_lengths)?;
+
+    // the literal and distance lengths are one sequence, repeats may cross from one to the other
+    let mut lengths = [0; MAX_LITERAL_CODES + MAX_DISTANCE_CODES];
+    let total = literal_count + distance_count;
+    let mut i = 0;
+    while i < total {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(DecompressError::Corrupt(
+                        "repeat without length",
+                    ));
+                }
+                (lengths[i - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > total {
+            return Err(DecompressError::Corrupt("too many lengths"));
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(DecompressError::Corrupt("no end-of-block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..total])?,
+    ))
+}
+
+// The literals and matches of a Huffman-coded block
+fn codes<const N: usize, E: From<DecompressError>>(
+    reader: &mut BitReader,
+    window: &mut Window<N>,
+    sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    literals: &Huffman<MAX_LITERAL_CODES>,
+    distances: &Huffman<MAX_DISTANCE_CODES>,
+) -> Result<(), E> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        match symbol {
+            0..=255 => window.push(symbol as u8, sink)?,
+            END_OF_BLOCK => return Ok(()),
+            _ => {
+                let (base, extra) = *LENGTHS
+                    .get(symbol as usize - 257)
+                    .ok_or(DecompressError::Corrupt("bad length code"))?;
+                let len = base as usize + reader.bits(extra as u32)? as usize;
+
+                let symbol = distances.decode(reader)?;
+                let (base, extra) = *DISTANCES
+                    .get(symbol as usize)
+                    .ok_or(DecompressError::Corrupt("bad distance code"))?;
+                let distance =
+                    base as usize + reader.bits(extra as u32)? as usize;
+
+                window.copy(distance, len, sink)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// gzip members
+//
+// A member is a header, a deflate stream and a trailer with the CRC-32 and the size of the data.
+// Files with several members decompress to their concatenation, call `decompress` once per member.
+//
+// https://www.rfc-editor.org/rfc/rfc1952
+
+use super::{deflate, DecompressError};
+use crate::checksum::Crc32;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const HEADER_SIZE: usize = 10;
+const TRAILER_SIZE: usize = 8;
+
+const METHOD_DEFLATE: u8 = 8;
+
+// Header flags
+const FLAG_HEADER_CRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const FLAG_RESERVED: u8 = 0xE0;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Decompress the member at the start of `input`, returns the number of bytes it took
+pub fn decompress<E: From<DecompressError>>(
+    input: &[u8],
+    mut sink: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<usize, E> {
+    let start = header_size(input)?;
+
+    let mut crc = Crc32::new();
+    let mut size: u32 = 0;
+    let len = deflate::inflate(&input[start..], |data: &[u8]| {
+        crc.update(data);
+        size = size.wrapping_add(data.len() as u32);
+        sink(data)
+    })?;
+
+    let end = start + len;
+    let trailer = input
+        .get(end..end + TRAILER_SIZE)
+        .ok_or(DecompressError::Truncated)?;
+    // the size is stored modulo 2^32
+    if u32_at(trailer, 0) != crc.finish() || u32_at(trailer, 4) != size {
+        return Err(DecompressError::ChecksumMismatch.into());
+    }
+
+    Ok(end + TRAILER_SIZE)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// Where the deflate stream starts
+fn header_size(input: &[u8]) -> Result<usize, DecompressError> {
+    let header = input.get(..HEADER_SIZE).ok_or(DecompressError::Truncated)?;
+    if header[..2] != MAGIC {
+        return Err(DecompressError::BadMagic);
+    }
+    if header[2] != METHOD_DEFLATE || header[3] & FLAG_RESERVED != 0 {
+        return Err(DecompressError::Unsupported);
+    }
+    let flags = header[3];
+
+    let mut position = HEADER_SIZE;
+    if flags & FLAG_EXTRA != 0 {
+        let len = input
+            .get(position..position + 2)
+            .ok_or(DecompressError::Truncated)?;
+        position += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    // zero-terminated strings
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let len = input
+                .get(position..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or(DecompressError::Truncated)?;
+            position += len + 1;
+        }
+    }
+    if flags & FLAG_HEADER_CRC != 0 {
+        let stored = input
+            .get(position..position + 2)
+            .ok_or(DecompressError::Truncated)?;
+        let mut crc = Crc32::new();
+        crc.update(&input[..position]);
+        if u16::from_le_bytes([stored[0], stored[1]]) != crc.finish() as u16 {
+            return Err(DecompressError::ChecksumMismatch);
+        }
+        position += 2;
+    }
+
+    if position > input.len() {
+        return Err(DecompressError::Truncated);
+    }
+
+    Ok(position)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
@@ -0,0 +1,285 @@
+// LZ4 frames
+//
+// Both the frame format of `lz4` and the legacy format of `lz4 -l`, which is what Linux uses for
+// compressed initramfs images, are read. Skippable frames are skipped. Frames with a dictionary
+// are not supported.
+//
+// https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md
+// https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+
+use super::{DecompressError, Window};
+use crate::checksum::XxHash32;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Farthest back-reference
+const WINDOW_SIZE: usize = 65536;
+
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFF_FFF0;
+
+// Frame descriptor flags
+const VERSION_MASK: u8 = 0xC0;
+const VERSION: u8 = 0x40;
+const FLAG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLAG_CONTENT_SIZE: u8 = 0x08;
+const FLAG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLAG_RESERVED: u8 = 0x02;
+const FLAG_DICTIONARY: u8 = 0x01;
+
+// Block maximum sizes of the `BD` byte, 64 KiB to 4 MiB
+const BLOCK_SIZE_SHIFT: u8 = 4;
+const BLOCK_SIZE_MASK: u8 = 0x70;
+const MIN_BLOCK_SIZE_ID: u8 = 4;
+
+// High bit of a block size: the block is stored uncompressed
+const BLOCK_UNCOMPRESSED: u32 = 0x8000_0000;
+const END_MARK: u32 = 0;
+
+// Legacy blocks decompress to at most 8 MiB
+const LEGACY_BLOCK_SIZE: usize = 8 << 20;
+
+// Block format
+const MIN_MATCH: usize = 4;
+const RUN_MASK: usize = 0x0F;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const MAGIC: u32 = 0x184D_2204;
+pub const LEGACY_MAGIC: u32 = 0x184C_2102;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// The input starts with an LZ4 frame of either format or a skippable frame
+pub fn is_lz4(input: &[u8]) -> bool {
+    match read_u32(input, 0) {
+        Ok(MAGIC | LEGACY_MAGIC) => true,
+        Ok(magic) => magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC,
+        Err(_) => false,
+    }
+}
+
+// Decompress the frame at the start of `input`, returns the number of bytes it took
+pub fn decompress<E: From<DecompressError>>(
+    input: &[u8],
+    mut sink: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<usize, E> {
+    let mut position = 0;
+
+    loop {
+        let magic = read_u32(input, position)?;
+        match magic {
+            MAGIC => return frame(input, position, &mut sink),
+            LEGACY_MAGIC => return legacy_frame(input, position, &mut sink),
+            _ if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC => {
+                position += 8 + read_u32(input, position + 4)? as usize;
+            }
+            _ => return Err(DecompressError::BadMagic.into()),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn frame<E: From<DecompressError>>(
+    input: &[u8],
+    start: usize,
+    sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<usize, E> {
+    let descriptor_start = start + 4;
+    let flags = *input
+        .get(descriptor_start)
+        .ok_or(DecompressError::Truncated)?;
+    let block_descriptor = *input
+        .get(descriptor_start + 1)
+        .ok_or(DecompressError::Truncated)?;
+    if flags & VERSION_MASK != VERSION || flags & FLAG_RESERVED != 0 {
+        return Err(DecompressError::Unsupported.into());
+    }
+    if flags & FLAG_DICTIONARY != 0 {
+        return Err(DecompressError::Unsupported.into());
+    }
+    let size_id = (block_descriptor & BLOCK_SIZE_MASK) >> BLOCK_SIZE_SHIFT;
+    if size_id < MIN_BLOCK_SIZE_ID || block_descriptor & !BLOCK_SIZE_MASK != 0 {
+        return Err(DecompressError::Corrupt("bad block descriptor").into());
+    }
+    let max_block_size = 1 << (8 + 2 * size_id);
+
+    // the header checksum covers the descriptor up to itself
+    let mut position = descriptor_start + 2;
+    if flags & FLAG_CONTENT_SIZE != 0 {
+        position += 8;
+    }
+    let descriptor = input
+        .get(descriptor_start..position + 1)
+        .ok_or(DecompressError::Truncated)?;
+    let mut hash = XxHash32::new(0);
+    hash.update(&descriptor[..descriptor.len() - 1]);
+    if (hash.finish() >> 8) as u8 != descriptor[descriptor.len() - 1] {
+        return Err(DecompressError::ChecksumMismatch.into());
+    }
+    position += 1;
+
+    // blocks may refer to earlier ones unless they are independent, the window allows both
+    let mut window = Window::<WINDOW_SIZE>::new();
+    let mut content = XxHash32::new(0);
+    let mut block_sink = |data: &[u8]| {
+        content.update(data);
+        sink(data)
+    };
+
+    loop {
+        let header = read_u32(input, position)?;
+        position += 4;
+        if header == END_MARK {
+            break;
+        }
+
+        let len = (header & !BLOCK_UNCOMPRESSED) as usize;
+        if len > max_block_size {
+            return Err(DecompressError::Corrupt("block too large").into());
+        }
+        let data = input
+            .get(position..position + len)
+            .ok_or(DecompressError::Truncated)?;
+        position += len;
+
+        if flags & FLAG_BLOCK_CHECKSUM != 0 {
+            let mut hash = XxHash32::new(0);
+            hash.update(data);
+            if read_u32(input, position)? != hash.finish() {
+                return Err(DecompressError::ChecksumMismatch.into());
+            }
+            position += 4;
+        }
+
+        if header & BLOCK_UNCOMPRESSED != 0 {
+            window.push_slice(data, &mut block_sink)?;
+        } else {
+            block(data, max_block_size, &mut window, &mut block_sink)?;
+        }
+    }
+    window.flush(&mut block_sink)?;
+
+    if flags & FLAG_CONTENT_CHECKSUM != 0 {
+        if read_u32(input, position)? != content.finish() {
+            return Err(DecompressError::ChecksumMismatch.into());
+        }
+        position += 4;
+    }
+
+    Ok(position)
+}
+
+// Legacy frames have no end mark, they end with the input or where another frame starts
+fn legacy_frame<E: From<DecompressError>>(
+    input: &[u8],
+    start: usize,
+    sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<usize, E> {
+    let mut window = Window::<WINDOW_SIZE>::new();
+    let mut position = start + 4;
+
+    while let Ok(len) = read_u32(input, position) {
+        // zero padding or the next frame
+        if len == 0 || len == MAGIC || len == LEGACY_MAGIC {
+            break;
+        }
+        position += 4;
+
+        let data = input
+            .get(position..position + len as usize)
+            .ok_or(DecompressError::Truncated)?;
+        position += len as usize;
+
+        block(data, LEGACY_BLOCK_SIZE, &mut window, sink)?;
+    }
+    window.flush(sink)?;
+
+    Ok(position)
+}
+
+// One compressed block: sequences of literals followed by a match, the last one has no match
+fn block<E: From<DecompressError>>(
+    data: &[u8],
+    max_size: usize,
+    window: &mut Window<WINDOW_SIZE>,
+    sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut position = 0;
+    let mut produced = 0;
+
+    while position < data.len() {
+        let token = data[position] as usize;
+        position += 1;
+
+        let literals = token >> 4;
+        let literals =
+            literals + extended_length(data, &mut position, literals)?;
+        let bytes = data
+            .get(position..position + literals)
+            .ok_or(DecompressError::Corrupt("literals past the block"))?;
+        position += literals;
+        produced += literals;
+        window.push_slice(bytes, sink)?;
+
+        if position == data.len() {
+            break;
+        }
+
+        let offset = data
+            .get(position..position + 2)
+            .ok_or(DecompressError::Corrupt("offset past the block"))?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        position += 2;
+
+        let len = token & RUN_MASK;
+        let len = MIN_MATCH + len + extended_length(data, &mut position, len)?;
+        produced += len;
+        if produced > max_size {
+            return Err(DecompressError::Corrupt("block too large").into());
+        }
+        window.copy(offset, len, sink)?;
+    }
+
+    Ok(())
+}
+
+// Bytes of 255 and a final byte below it are added to a length of 15
+fn extended_length(
+    data: &[u8],
+    position: &mut usize,
+    len: usize,
+) -> Result<usize, DecompressError> {
+    if len != RUN_MASK {
+        return Ok(0);
+    }
+
+    let mut extra = 0;
+    loop {
+        let byte = *data
+            .get(*position)
+            .ok_or(DecompressError::Corrupt("length past the block"))?;
+        *position += 1;
+        extra += byte as usize;
+        if byte != 255 {
+            return Ok(extra);
+        }
+    }
+}
+
+fn read_u32(input: &[u8], position: usize) -> Result<u32, DecompressError> {
+    let bytes = input
+        .get(position..position + 4)
+        .ok_or(DecompressError::Truncated)?;
+
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
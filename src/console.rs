@@ -1,3 +1,25 @@
+// Console input
+//
+// What is typed on the serial port and on USB keyboards goes through the key bindings of
+// `screen::vt::filter_input` and ends up in one input buffer, which `interface::Read` of the
+// console reads from. Both sources feed the same buffer, so it does not matter which one is used.
+
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+
+//-------------------------------------------------------------------------------------------------
+// Private Definitions
+//-------------------------------------------------------------------------------------------------
+
+// Characters kept until they are read, more are dropped
+const INPUT_BUFFER_SIZE: usize = 256;
+
+struct InputBufferInner {
+    chars: [char; INPUT_BUFFER_SIZE],
+    // index of the oldest character
+    head: usize,
+    len: usize,
+}
+
 //-------------------------------------------------------------------------------------------------
 // Public Deginitions
 //-------------------------------------------------------------------------------------------------
@@ -32,3 +54,80 @@ pub mod interface {
 
     pub trait All: Read + Write + Statistics {}
 }
+
+// Typed characters waiting to be read
+pub struct InputBuffer {
+    inner: IRQSafeNullLock<InputBufferInner>,
+}
+
+//-------------------------------------------------------------------------------------------------
+// Global instances
+//-------------------------------------------------------------------------------------------------
+
+static INPUT_BUFFER: InputBuffer = InputBuffer::new();
+
+//-------------------------------------------------------------------------------------------------
+// Public Code
+//-------------------------------------------------------------------------------------------------
+
+// Return a reference to the console input buffer
+pub fn input_buffer() -> &'static InputBuffer {
+    &INPUT_BUFFER
+}
+
+impl InputBuffer {
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(InputBufferInner {
+                chars: ['\0'; INPUT_BUFFER_SIZE],
+                head: 0,
+                len: 0,
+            }),
+        }
+    }
+
+    // Add a character, false if the buffer is full and it was dropped
+    pub fn push(&self, c: char) -> bool {
+        self.inner.lock(|inner| {
+            if inner.len == INPUT_BUFFER_SIZE {
+                return false;
+            }
+
+            let tail = (inner.head + inner.len) % INPUT_BUFFER_SIZE;
+            inner.chars[tail] = c;
+            inner.len += 1;
+
+            true
+        })
+    }
+
+    // Take the oldest character
+    pub fn pop(&self) -> Option<char> {
+        self.pop_if(|_| true)
+    }
+
+    // Take the oldest character if `f` accepts it
+    pub fn pop_if(&self, f: impl FnOnce(char) -> bool) -> Option<char> {
+        self.inner.lock(|inner| {
+            if inner.len == 0 || !f(inner.chars[inner.head]) {
+                return None;
+            }
+
+            let c = inner.chars[inner.head];
+            inner.head = (inner.head + 1) % INPUT_BUFFER_SIZE;
+            inner.len -= 1;
+
+            Some(c)
+        })
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock(|inner| inner.len = 0);
+    }
+}
+
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
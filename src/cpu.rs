@@ -8,4 +8,4 @@ pub mod smp;
 //-------------------------------------------------------------------------------------------------
 // Archtectural Public Reexports
 //-------------------------------------------------------------------------------------------------
-pub use arch_cpu::{nop, wait_forever};
+pub use arch_cpu::{clean_and_invalidate_dcache, nop, wait_forever};
@@ -0,0 +1,397 @@
+// Bitmap fonts for the frame buffer console
+//
+// Glyphs are looked up through a fallback chain of fonts. The first font that contains a character
+// renders it into a `GlyphBitmap`, which is kept in a small cache so that the (slow) lookup in PSF2
+// and BDF data only happens once per character.
+
+pub mod bdf;
+pub mod builtin;
+pub mod file;
+pub mod psf2;
+
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+
+//--------------------------------------------------------------------------------------------------
+// Global Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Largest glyph that can be rendered. Enough for a double-width 16px CJK glyph and for 32px fonts.
+pub const MAX_GLYPH_WIDTH: usize = 32;
+pub const MAX_GLYPH_HEIGHT: usize = 32;
+
+// Number of fonts in the fallback chain
+const MAX_FONTS: usize = 4;
+
+// Number of rendered glyphs kept in the cache
+const CACHE_ENTRIES: usize = 64;
+
+// Drawn when no font in the chain has the character
+const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Font interfaces
+pub mod interface {
+    use super::GlyphBitmap;
+
+    pub trait Font {
+        // human readable name of the font
+        fn name(&self) -> &'static str;
+
+        // size of a single-width character cell as (width, height)
+        fn cell_size(&self) -> (usize, usize);
+
+        // return true if the font has a glyph for `c`
+        fn contains(&self, c: char) -> bool;
+
+        // render `c` into `out`, return false if the font has no glyph for it
+        fn render(&self, c: char, out: &mut GlyphBitmap) -> bool;
+    }
+}
+
+// A rendered glyph with one intensity byte per pixel
+#[derive(Clone, Copy)]
+pub struct GlyphBitmap {
+    width: usize,
+    height: usize,
+    pixels: [u8; MAX_GLYPH_WIDTH * MAX_GLYPH_HEIGHT],
+}
+
+// The fonts that can be placed in the fallback chain
+#[derive(Clone, Copy)]
+pub enum FontSource {
+    Builtin(builtin::NotoSansMono),
+    Psf2(psf2::Psf2Font),
+    Bdf(bdf::BdfFont),
+}
+
+pub struct FontManager {
+    inner: IRQSafeNullLock<FontManagerInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// An all-zero entry is unused, so that the cache starts out in .bss
+#[derive(Clone, Copy)]
+struct CacheEntry {
+    used: bool,
+    c: char,
+    last_used: u32,
+    glyph: GlyphBitmap,
+}
+
+struct GlyphCache {
+    entries: [CacheEntry; CACHE_ENTRIES],
+    clock: u32,
+}
+
+struct FontManagerInner {
+    chain: [Option<FontSource>; MAX_FONTS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static FONT_MANAGER: FontManager = FontManager::new();
+
+static GLYPH_CACHE: IRQSafeNullLock<GlyphCache> =
+    IRQSafeNullLock::new(GlyphCache::new_zeroed());
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the font manager
+pub fn font_manager() -> &'static FontManager {
+    &FONT_MANAGER
+}
+
+// East Asian wide characters, which take two console cells
+pub fn is_wide(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x20000..=0x2FFFD
+        | 0x30000..=0x3FFFD)
+}
+
+impl GlyphBitmap {
+    pub const fn empty() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            pixels: [0; MAX_GLYPH_WIDTH * MAX_GLYPH_HEIGHT],
+        }
+    }
+
+    // Clear the bitmap and set its size, clipped to the maximum glyph size
+    pub fn reset(&mut self, width: usize, height: usize) {
+        self.width = width.min(MAX_GLYPH_WIDTH);
+        self.height = height.min(MAX_GLYPH_HEIGHT);
+        self.pixels = [0; MAX_GLYPH_WIDTH * MAX_GLYPH_HEIGHT];
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Number of console cells the glyph occupies, either 1 or 2
+    pub fn cells(&self, cell_width: usize) -> usize {
+        if self.width > cell_width {
+            2
+        } else {
+            1
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+
+        self.pixels[y * MAX_GLYPH_WIDTH + x]
+    }
+
+    // Pixels outside of the bitmap are silently dropped
+    pub fn set(&mut self, x: usize, y: usize, intensity: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        self.pixels[y * MAX_GLYPH_WIDTH + x] = intensity;
+    }
+
+    // An outlined box, drawn for characters that no font knows about
+    fn replacement(&mut self, width: usize, height: usize) {
+        self.reset(width, height);
+
+        // too small to draw a box into
+        if self.width < 4 || self.height < 6 {
+            return;
+        }
+
+        let (right, bottom) = (self.width - 1, self.height - 1);
+        for y in 2..(bottom - 1) {
+            for x in 1..right {
+                let edge =
+                    y == 2 || y == bottom - 2 || x == 1 || x == right - 1;
+                if edge {
+                    self.set(x, y, 0xFF);
+                }
+            }
+        }
+    }
+}
+
+impl FontSource {
+    fn font(&self) -> &dyn interface::Font {
+        match self {
+            FontSource::Builtin(font) => font,
+            FontSource::Psf2(font) => font,
+            FontSource::Bdf(font) => font,
+        }
+    }
+}
+
+impl FontManager {
+    const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(FontManagerInner::new()),
+        }
+    }
+
+    // Replace the whole fallback chain. The first font decides the cell size.
+    pub fn set_fallback_chain(
+        &self,
+        chain: &[FontSource],
+    ) -> Result<(), &'static str> {
+        if chain.is_empty() {
+            return Err("Font chain must not be empty");
+        }
+
+        if chain.len() > MAX_FONTS {
+            return Err("Too many fonts in chain");
+        }
+
+        self.inner.lock(|inner| {
+            inner.chain = [None; MAX_FONTS];
+            for (slot, font) in inner.chain.iter_mut().zip(chain.iter()) {
+                *slot = Some(*font);
+            }
+        });
+        GLYPH_CACHE.lock(|cache| cache.clear());
+
+        Ok(())
+    }
+
+    // Append a font to the end of the fallback chain
+    pub fn push_fallback(&self, font: FontSource) -> Result<(), &'static str> {
+        let added = self.inner.lock(|inner| {
+            match inner.chain.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => {
+                    *slot = Some(font);
+                    true
+                }
+                None => false,
+            }
+        });
+
+        if !added {
+            return Err("Too many fonts in chain");
+        }
+        GLYPH_CACHE.lock(|cache| cache.clear());
+
+        Ok(())
+    }
+
+    // Size of a single-width cell as (width, height)
+    pub fn cell_size(&self) -> (usize, usize) {
+        self.inner.lock(|inner| inner.cell_size())
+    }
+
+    // Call `f` with the glyph for `c`, rendering and caching it if needed
+    pub fn with_glyph<R>(
+        &self,
+        c: char,
+        f: impl FnOnce(&GlyphBitmap) -> R,
+    ) -> R {
+        self.inner
+            .lock(|inner| GLYPH_CACHE.lock(|cache| f(inner.glyph(cache, c))))
+    }
+
+    pub fn print_chain(&self) {
+        use crate::info;
+
+        // printing renders glyphs, so don't hold the lock while doing it
+        let chain = self.inner.lock(|inner| inner.chain);
+        for (i, font) in chain.iter().flatten().enumerate() {
+            info!("      {}. {}", i + 1, font.font().name());
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl GlyphCache {
+    const fn new_zeroed() -> Self {
+        let unused = CacheEntry {
+            used: false,
+            c: '\0',
+            last_used: 0,
+            glyph: GlyphBitmap::empty(),
+        };
+
+        Self {
+            entries: [unused; CACHE_ENTRIES],
+            clock: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.used = false;
+        }
+        self.clock = 0;
+    }
+
+    fn tick(&mut self) -> u32 {
+        self.clock = self.clock.wrapping_add(1);
+        self.clock
+    }
+
+    fn lookup(&mut self, c: char) -> Option<usize> {
+        let now = self.tick();
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.used && entry.c == c)?;
+        self.entries[index].last_used = now;
+
+        Some(index)
+    }
+
+    // Pick a free slot, or evict the least recently used one
+    fn victim(&self) -> usize {
+        if let Some(free) = self.entries.iter().position(|e| !e.used) {
+            return free;
+        }
+
+        self.entries
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, entry)| self.clock.wrapping_sub(entry.last_used))
+            .map_or(0, |(i, _)| i)
+    }
+}
+
+impl FontManagerInner {
+    const fn new() -> Self {
+        let mut chain = [None; MAX_FONTS];
+        chain[0] = Some(FontSource::Builtin(builtin::NotoSansMono));
+
+        Self { chain }
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        use interface::Font;
+
+        match self.chain.iter().flatten().next() {
+            Some(font) => font.font().cell_size(),
+            None => builtin::NotoSansMono.cell_size(),
+        }
+    }
+
+    fn glyph<'a>(&self, cache: &'a mut GlyphCache, c: char) -> &'a GlyphBitmap {
+        if let Some(index) = cache.lookup(c) {
+            return &cache.entries[index].glyph;
+        }
+
+        let mut glyph = GlyphBitmap::empty();
+        let found = self.render(c, &mut glyph)
+            || self.render(REPLACEMENT_CHARACTER, &mut glyph);
+
+        if !found {
+            let (width, height) = self.cell_size();
+            let width = if is_wide(c) { width * 2 } else { width };
+            glyph.replacement(width, height);
+        }
+
+        let index = cache.victim();
+        let last_used = cache.tick();
+        cache.entries[index] = CacheEntry {
+            used: true,
+            c,
+            last_used,
+            glyph,
+        };
+
+        &cache.entries[index].glyph
+    }
+
+    fn render(&self, c: char, out: &mut GlyphBitmap) -> bool {
+        self.chain
+            .iter()
+            .flatten()
+            .any(|font| font.font().contains(c) && font.font().render(c, out))
+    }
+}
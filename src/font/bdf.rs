@@ -0,0 +1,220 @@
+// Glyph Bitmap Distribution Format
+//
+// https://www.adobe.com/content/dam/acom/en/devnet/font/pdfs/5005.BDF_Spec.pdf
+//
+// The font text is kept as is and searched on lookup. The glyph cache in front of the fonts keeps
+// this cheap for characters that are printed repeatedly.
+
+use super::{interface, GlyphBitmap};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy)]
+pub struct BdfFont {
+    name: &'static str,
+    source: &'static str,
+    // FONTBOUNDINGBOX: width, height, x offset, y offset
+    bbox: (i32, i32, i32, i32),
+    ascent: i32,
+    // advance of a half-width character
+    cell_width: i32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct BdfGlyph<'a> {
+    dwidth: i32,
+    // BBX: width, height, x offset, y offset
+    bbx: (i32, i32, i32, i32),
+    bitmap: &'a str,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// Parse up to four whitespace separated integers
+fn numbers(s: &str) -> Option<[i32; 4]> {
+    let mut values = [0; 4];
+    let mut parts = s.split_ascii_whitespace();
+
+    for value in values.iter_mut() {
+        match parts.next() {
+            Some(part) => *value = part.parse().ok()?,
+            None => break,
+        }
+    }
+
+    Some(values)
+}
+
+fn hex_digit(c: u8) -> u32 {
+    match c {
+        b'0'..=b'9' => (c - b'0') as u32,
+        b'a'..=b'f' => (c - b'a' + 10) as u32,
+        b'A'..=b'F' => (c - b'A' + 10) as u32,
+        _ => 0,
+    }
+}
+
+impl<'a> BdfGlyph<'a> {
+    // Parse the lines between `ENCODING` and `ENDCHAR`
+    fn parse(body: &'a str, default_width: i32) -> Option<Self> {
+        let mut glyph = BdfGlyph {
+            dwidth: default_width,
+            bbx: (0, 0, 0, 0),
+            bitmap: "",
+        };
+
+        for (offset, line) in line_offsets(body) {
+            if let Some(rest) = line.strip_prefix("DWIDTH ") {
+                glyph.dwidth = numbers(rest)?[0];
+            } else if let Some(rest) = line.strip_prefix("BBX ") {
+                let [w, h, x, y] = numbers(rest)?;
+                glyph.bbx = (w, h, x, y);
+            } else if line == "BITMAP" {
+                let start = offset + line.len();
+                let end = body.find("ENDCHAR").unwrap_or(body.len());
+                glyph.bitmap = body.get(start..end)?;
+                break;
+            }
+        }
+
+        Some(glyph)
+    }
+}
+
+// Iterate over trimmed lines together with their byte offset
+fn line_offsets(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    s.split('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len() + 1;
+        Some((start, line.trim_end_matches('\r')))
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl BdfFont {
+    pub fn parse(
+        name: &'static str,
+        source: &'static str,
+    ) -> Result<Self, &'static str> {
+        if !source.starts_with("STARTFONT") {
+            return Err("BDF: missing STARTFONT");
+        }
+
+        let mut bbox = None;
+        let mut ascent = None;
+
+        for line in source.lines() {
+            if let Some(rest) = line.strip_prefix("FONTBOUNDINGBOX ") {
+                let [w, h, x, y] =
+                    numbers(rest).ok_or("BDF: bad FONTBOUNDINGBOX")?;
+                bbox = Some((w, h, x, y));
+            } else if let Some(rest) = line.strip_prefix("FONT_ASCENT ") {
+                ascent = numbers(rest).map(|n| n[0]);
+            } else if line.starts_with("CHARS ") {
+                // the properties end before the first glyph
+                break;
+            }
+        }
+
+        let bbox = bbox.ok_or("BDF: missing FONTBOUNDINGBOX")?;
+        if bbox.0 <= 0 || bbox.1 <= 0 {
+            return Err("BDF: empty bounding box");
+        }
+
+        let mut font = Self {
+            name,
+            source,
+            bbox,
+            ascent: ascent.unwrap_or(bbox.1 + bbox.3),
+            cell_width: bbox.0,
+        };
+
+        // CJK fonts have a double-width bounding box, so take the cell width
+        // from a latin character instead
+        if let Some(glyph) = font.find('M') {
+            font.cell_width = glyph.dwidth.clamp(1, bbox.0);
+        }
+
+        Ok(font)
+    }
+
+    fn find(&self, c: char) -> Option<BdfGlyph<'static>> {
+        let mut rest = self.source;
+
+        while let Some(start) = rest.find("\nENCODING ") {
+            let after = &rest[start + "\nENCODING ".len()..];
+            let line_end = after.find('\n').unwrap_or(after.len());
+            let end = after.find("ENDCHAR").unwrap_or(after.len());
+
+            if numbers(&after[..line_end]).map(|n| n[0]) == Some(c as i32) {
+                return BdfGlyph::parse(&after[line_end..end], self.bbox.0);
+            }
+
+            rest = &after[end..];
+        }
+
+        None
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// OS Interface Code
+//--------------------------------------------------------------------------------------------------
+
+impl interface::Font for BdfFont {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        (self.cell_width as usize, self.bbox.1 as usize)
+    }
+
+    fn contains(&self, c: char) -> bool {
+        self.find(c).is_some()
+    }
+
+    fn render(&self, c: char, out: &mut GlyphBitmap) -> bool {
+        let glyph = match self.find(c) {
+            Some(glyph) => glyph,
+            None => return false,
+        };
+
+        let (w, h, x_offset, y_offset) = glyph.bbx;
+        out.reset(glyph.dwidth.max(1) as usize, self.bbox.1 as usize);
+
+        // top row of the glyph relative to the top of the cell
+        let top = self.ascent - (h + y_offset);
+        let left = x_offset - self.bbox.2;
+
+        for (row, line) in glyph.bitmap.split_ascii_whitespace().enumerate() {
+            let y = top + row as i32;
+            if y < 0 || row as i32 >= h {
+                continue;
+            }
+
+            for (i, digit) in line.bytes().enumerate() {
+                let nibble = hex_digit(digit);
+                for bit in 0..4 {
+                    let col = (i * 4 + bit) as i32;
+                    let x = left + col;
+                    if col < w && x >= 0 && nibble & (0x8 >> bit) != 0 {
+                        out.set(x as usize, y as usize, 0xFF);
+                    }
+                }
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,56 @@
+// The font compiled into the kernel, used when nothing else is configured
+
+use super::{interface, GlyphBitmap};
+use noto_sans_mono_bitmap::{get_bitmap, BitmapHeight, FontWeight};
+
+//--------------------------------------------------------------------------------------------------
+// Global Definitions
+//--------------------------------------------------------------------------------------------------
+
+const CELL_WIDTH: usize = 8;
+const CELL_HEIGHT: usize = 16;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Noto Sans Mono, 16px Regular
+#[derive(Clone, Copy)]
+pub struct NotoSansMono;
+
+//--------------------------------------------------------------------------------------------------
+// OS Interface Code
+//--------------------------------------------------------------------------------------------------
+
+impl interface::Font for NotoSansMono {
+    fn name(&self) -> &'static str {
+        "Noto Sans Mono 16px Regular (builtin)"
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        (CELL_WIDTH, CELL_HEIGHT)
+    }
+
+    fn contains(&self, c: char) -> bool {
+        get_bitmap(c, FontWeight::Regular, BitmapHeight::Size16).is_some()
+    }
+
+    fn render(&self, c: char, out: &mut GlyphBitmap) -> bool {
+        let bitmap_char =
+            match get_bitmap(c, FontWeight::Regular, BitmapHeight::Size16) {
+                Some(bitmap_char) => bitmap_char,
+                None => return false,
+            };
+        let bitmap = bitmap_char.bitmap();
+        let width = bitmap.iter().map(|row| row.len()).max().unwrap_or(0);
+
+        out.reset(width.min(CELL_WIDTH), bitmap.len());
+        for (row_i, row) in bitmap.iter().enumerate() {
+            for (col_i, intensity) in row.iter().enumerate() {
+                out.set(col_i, row_i, *intensity);
+            }
+        }
+
+        true
+    }
+}
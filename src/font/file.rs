@@ -0,0 +1,199 @@
+// Fonts from files
+//
+// `font=` lists the fonts of the fallback chain, separated by commas, e.g.
+// `font=builtin,/fonts/unifont.psf` to draw Latin text with the built-in font and CJK characters
+// with unifont. Each entry is `builtin` or the path of a PSF2 or BDF file, usually from the
+// initramfs. The built-in font is added at the end when it is not listed, so that every character
+// has a glyph. The first font decides the size of a console cell.
+//
+// Font files are copied into a static arena once and never freed, the fonts keep pointing into it.
+
+use super::{bdf, builtin, font_manager, psf2, FontSource, MAX_FONTS};
+use crate::{
+    cmdline::{self, interface::Value},
+    fs::vfs::{vfs, Fd, OpenFlags},
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    warn,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Longest `font=` value
+const FONT_LIST_SIZE: usize = 128;
+
+// As big as the root file system
+const ARENA_SIZE: usize = 2 * 1024 * 1024;
+
+const BUILTIN: &str = "builtin";
+
+struct Arena {
+    bytes: [u8; ARENA_SIZE],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// The value of `font=`
+#[derive(Clone, Copy)]
+pub struct FontList {
+    bytes: [u8; FONT_LIST_SIZE],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+pub static FONTS: cmdline::Param<Option<FontList>> =
+    cmdline::Param::new("font", None);
+
+static mut ARENA: Arena = Arena {
+    bytes: [0; ARENA_SIZE],
+};
+
+// Bytes of the arena handed out
+static ARENA_USED: IRQSafeNullLock<usize> = IRQSafeNullLock::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Set up the fallback chain from `font=`, once the file systems are mounted. Fonts that cannot be
+// loaded are warned about and left out.
+pub fn load() {
+    let Some(list) = FONTS.get() else {
+        return;
+    };
+
+    let mut chain = [FontSource::Builtin(builtin::NotoSansMono); MAX_FONTS];
+    let mut len = 0;
+    let mut has_builtin = false;
+
+    for entry in list.as_str().split(',') {
+        let font = if entry == BUILTIN {
+            has_builtin = true;
+            Ok(FontSource::Builtin(builtin::NotoSansMono))
+        } else if len + usize::from(!has_builtin) >= MAX_FONTS {
+            // the built-in font needs a slot of its own
+            Err("Too many fonts in chain")
+        } else {
+            load_file(entry)
+        };
+
+        match font {
+            Ok(_) if len == MAX_FONTS => {
+                warn!("Font {}: too many fonts in chain", entry)
+            }
+            Ok(font) => {
+                chain[len] = font;
+                len += 1;
+            }
+            Err(e) => warn!("Font {}: {}", entry, e),
+        }
+    }
+
+    if !has_builtin {
+        chain[len] = FontSource::Builtin(builtin::NotoSansMono);
+        len += 1;
+    }
+
+    if let Err(e) = font_manager().set_fallback_chain(&chain[..len]) {
+        warn!("Fonts: {}", e);
+    }
+}
+
+impl FontList {
+    pub fn as_str(&self) -> &str {
+        // only valid UTF-8 is stored
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl Value for FontList {
+    fn parse(s: &str) -> Option<Self> {
+        if s.is_empty()
+            || s.len() > FONT_LIST_SIZE
+            || s.split(',').any(|entry| entry.is_empty())
+        {
+            return None;
+        }
+
+        let mut list = Self {
+            bytes: [0; FONT_LIST_SIZE],
+            len: s.len(),
+        };
+        list.bytes[..s.len()].copy_from_slice(s.as_bytes());
+
+        Some(list)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// Read a PSF2 or BDF font, told apart by their contents
+fn load_file(path: &str) -> Result<FontSource, &'static str> {
+    let name = core::str::from_utf8(store(path.as_bytes())?)
+        .map_err(|_| "Invalid font name")?;
+    let data = read_file(path)?;
+
+    if data.starts_with(&psf2::MAGIC) {
+        return psf2::Psf2Font::parse(name, data).map(FontSource::Psf2);
+    }
+
+    let source =
+        core::str::from_utf8(data).map_err(|_| "Unknown font format")?;
+    bdf::BdfFont::parse(name, source).map(FontSource::Bdf)
+}
+
+fn read_file(path: &str) -> Result<&'static [u8], &'static str> {
+    let fd = vfs().open(path, OpenFlags::READ)?;
+    let result = read_open_file(fd);
+    let _ = vfs().close(fd);
+
+    result
+}
+
+fn read_open_file(fd: Fd) -> Result<&'static [u8], &'static str> {
+    let data = allocate(vfs().fstat(fd)?.size as usize)?;
+
+    let mut len = 0;
+    while len < data.len() {
+        match vfs().read(fd, &mut data[len..])? {
+            0 => return Err("File shrank while reading"),
+            read => len += read,
+        }
+    }
+
+    Ok(data)
+}
+
+fn store(bytes: &[u8]) -> Result<&'static [u8], &'static str> {
+    let copy = allocate(bytes.len())?;
+    copy.copy_from_slice(bytes);
+
+    Ok(copy)
+}
+
+// Take `len` bytes of the arena, which are never handed out again
+fn allocate(len: usize) -> Result<&'static mut [u8], &'static str> {
+    let start = ARENA_USED.lock(|used| {
+        let start = *used;
+        if len > ARENA_SIZE - start {
+            return Err("Out of font memory");
+        }
+
+        *used += len;
+        Ok(start)
+    })?;
+
+    // the range belongs to this call alone
+    Ok(unsafe {
+        let arena = core::ptr::addr_of_mut!(ARENA.bytes).cast::<u8>();
+        core::slice::from_raw_parts_mut(arena.add(start), len)
+    })
+}
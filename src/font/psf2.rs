@@ -0,0 +1,179 @@
+// PC Screen Font version 2
+//
+// https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html
+
+use super::{interface, GlyphBitmap};
+
+//--------------------------------------------------------------------------------------------------
+// Global Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const HEADER_SIZE: usize = 32;
+
+// The font has a unicode table after the glyphs
+const HAS_UNICODE_TABLE: u32 = 0x01;
+
+// Unicode table separators
+const SEQUENCE_START: u8 = 0xFE;
+const ENTRY_END: u8 = 0xFF;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy)]
+pub struct Psf2Font {
+    name: &'static str,
+    glyphs: &'static [u8],
+    unicode_table: Option<&'static [u8]>,
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+    width: usize,
+    height: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Psf2Font {
+    // Parse a PSF2 font. `data` usually comes from `include_bytes!` or the initramfs.
+    pub fn parse(
+        name: &'static str,
+        data: &'static [u8],
+    ) -> Result<Self, &'static str> {
+        if data.len() < HEADER_SIZE {
+            return Err("PSF2: file too short");
+        }
+
+        if data[0..4] != MAGIC {
+            return Err("PSF2: bad magic");
+        }
+
+        let field = |i: usize| {
+            let offset = 4 + i * 4;
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+
+        let header_size = field(1) as usize;
+        let flags = field(2);
+        let glyph_count = field(3) as usize;
+        let bytes_per_glyph = field(4) as usize;
+        let height = field(5) as usize;
+        let width = field(6) as usize;
+
+        if width == 0 || height == 0 || glyph_count == 0 {
+            return Err("PSF2: empty font");
+        }
+
+        if bytes_per_glyph != width.div_ceil(8) * height {
+            return Err("PSF2: inconsistent glyph size");
+        }
+
+        let glyphs_end = glyph_count
+            .checked_mul(bytes_per_glyph)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or("PSF2: file too short")?;
+
+        if header_size < HEADER_SIZE || data.len() < glyphs_end {
+            return Err("PSF2: file too short");
+        }
+
+        let unicode_table = if flags & HAS_UNICODE_TABLE != 0 {
+            Some(&data[glyphs_end..])
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name,
+            glyphs: &data[header_size..glyphs_end],
+            unicode_table,
+            glyph_count,
+            bytes_per_glyph,
+            width,
+            height,
+        })
+    }
+
+    // Find the glyph index of `c`
+    fn glyph_index(&self, c: char) -> Option<usize> {
+        let table = match self.unicode_table {
+            // Without a table the glyph index is the code point
+            None => {
+                let index = c as usize;
+                return (index < self.glyph_count).then_some(index);
+            }
+            Some(table) => table,
+        };
+
+        // One entry per glyph: single code points, optionally followed by
+        // sequences introduced with 0xFE, terminated with 0xFF
+        for (index, entry) in table.split(|b| *b == ENTRY_END).enumerate() {
+            if index >= self.glyph_count {
+                break;
+            }
+
+            let singles = match entry.iter().position(|b| *b == SEQUENCE_START)
+            {
+                Some(end) => &entry[..end],
+                None => entry,
+            };
+
+            let found = core::str::from_utf8(singles)
+                .map(|s| s.chars().any(|ch| ch == c))
+                .unwrap_or(false);
+            if found {
+                return Some(index);
+            }
+        }
+
+        None
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// OS Interface Code
+//--------------------------------------------------------------------------------------------------
+
+impl interface::Font for Psf2Font {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn contains(&self, c: char) -> bool {
+        self.glyph_index(c).is_some()
+    }
+
+    fn render(&self, c: char, out: &mut GlyphBitmap) -> bool {
+        let index = match self.glyph_index(c) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let stride = self.width.div_ceil(8);
+        let start = index * self.bytes_per_glyph;
+        let glyph = &self.glyphs[start..start + self.bytes_per_glyph];
+
+        out.reset(self.width, self.height);
+        for (y, row) in glyph.chunks(stride).enumerate() {
+            for x in 0..self.width {
+                if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    out.set(x, y, 0xFF);
+                }
+            }
+        }
+
+        true
+    }
+}
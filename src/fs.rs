@@ -0,0 +1,256 @@
+// File systems
+//
+// File systems work on the block devices of `crate::block` and need no heap: names are returned
+// in fixed buffers and open files are plain values that refer back to their directory entry.
+//
+// Each file system implements `interface::FileSystem`, which names files by inode numbers. The
+// `vfs` module mounts file systems into one tree and keeps the table of open files.
+
+pub mod cpio;
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod initramfs;
+pub mod ramfs;
+pub mod vfs;
+
+use crate::block::BlockError;
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Longest file name in bytes, as on Linux
+pub const MAX_NAME_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidName,
+    NameTooLong,
+    FileTooLarge,
+    NoSpace,
+    ReadOnly,
+    Unsupported,
+    // in use, e.g. a mount point or a pin claimed by a driver
+    Busy,
+    BadFileDescriptor,
+    TooManyOpenFiles,
+    TooManyMounts,
+    InvalidArgument,
+    // the on-disk structures make no sense
+    Corrupt(&'static str),
+    Device(BlockError),
+}
+
+// Number of a file within its file system
+pub type Inode = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    CharDevice,
+    // not followed, reading one gives its target
+    Symlink,
+    // a device node, FIFO or socket of a disk file system, which cannot be opened here
+    Special,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub inode: Inode,
+    pub kind: FileKind,
+    // in bytes, 0 for directories and streams
+    pub size: u64,
+}
+
+// File system interfaces
+pub mod interface {
+    use super::{FileKind, FsError, Inode, Metadata, Name};
+
+    // Operations without a default are needed to read a file system, the others only to change it
+    pub trait FileSystem {
+        // type of the file system, e.g. `fat32`
+        fn name(&self) -> &'static str;
+
+        fn root(&self) -> Inode;
+
+        // the entry `name` in directory `dir`, `.` and `..` are resolved by the caller
+        fn lookup(&self, dir: Inode, name: &str) -> Result<Inode, FsError>;
+
+        fn metadata(&self, inode: Inode) -> Result<Metadata, FsError>;
+
+        // the next entry of `dir` from `position` on, which starts at 0 and is advanced past it
+        fn read_dir(
+            &self,
+            dir: Inode,
+            position: &mut u64,
+            name: &mut Name,
+        ) -> Result<Option<Inode>, FsError>;
+
+        // read from `offset` on, returns the number of bytes read, 0 at the end
+        fn read(
+            &self,
+            inode: Inode,
+            offset: u64,
+            buffer: &mut [u8],
+        ) -> Result<usize, FsError>;
+
+        fn write(
+            &self,
+            _inode: Inode,
+            _offset: u64,
+            _data: &[u8],
+        ) -> Result<usize, FsError> {
+            Err(FsError::ReadOnly)
+        }
+
+        fn truncate(&self, _inode: Inode, _len: u64) -> Result<(), FsError> {
+            Err(FsError::ReadOnly)
+        }
+
+        fn create(
+            &self,
+            _dir: Inode,
+            _name: &str,
+            _kind: FileKind,
+        ) -> Result<Inode, FsError> {
+            Err(FsError::ReadOnly)
+        }
+
+        // remove a file or an empty directory
+        fn remove(&self, _dir: Inode, _name: &str) -> Result<(), FsError> {
+            Err(FsError::ReadOnly)
+        }
+
+        // write cached data to the device
+        fn sync(&self) -> Result<(), FsError> {
+            Ok(())
+        }
+    }
+}
+
+// A file name, at most `MAX_NAME_LEN` bytes of UTF-8
+#[derive(Clone, Copy)]
+pub struct Name {
+    bytes: [u8; MAX_NAME_LEN],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::Device(e) => write!(f, "Device error: {}", e),
+            FsError::Corrupt(what) => {
+                write!(f, "Corrupt file system: {}", what)
+            }
+            _ => f.write_str((*self).into()),
+        }
+    }
+}
+
+impl From<FsError> for &'static str {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound => "No such file or directory",
+            FsError::AlreadyExists => "File exists",
+            FsError::NotADirectory => "Not a directory",
+            FsError::IsADirectory => "Is a directory",
+            FsError::DirectoryNotEmpty => "Directory not empty",
+            FsError::InvalidName => "Invalid file name",
+            FsError::NameTooLong => "File name too long",
+            FsError::FileTooLarge => "File too large",
+            FsError::NoSpace => "No space left on device",
+            FsError::ReadOnly => "Read-only file system",
+            FsError::Unsupported => "Operation not supported",
+            FsError::Busy => "Device or resource busy",
+            FsError::BadFileDescriptor => "Bad file descriptor",
+            FsError::TooManyOpenFiles => "Too many open files",
+            FsError::TooManyMounts => "Too many mounted file systems",
+            FsError::InvalidArgument => "Invalid argument",
+            FsError::Corrupt(what) => what,
+            FsError::Device(e) => e.into(),
+        }
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(error: BlockError) -> Self {
+        FsError::Device(error)
+    }
+}
+
+impl Name {
+    pub const fn empty() -> Self {
+        Self {
+            bytes: [0; MAX_NAME_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn new(name: &str) -> Result<Self, FsError> {
+        let mut result = Self::empty();
+        result.push_str(name)?;
+
+        Ok(result)
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole characters are pushed
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+
+    pub fn push(&mut self, c: char) -> Result<(), FsError> {
+        self.push_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    pub fn push_str(&mut self, s: &str) -> Result<(), FsError> {
+        let end = self.len + s.len();
+        if end > MAX_NAME_LEN {
+            return Err(FsError::NameTooLong);
+        }
+
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+// The components of `path`, without empty ones and `.`
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+}
+
+// Split `path` into its parent directory and its last component
+pub fn split_last(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
@@ -0,0 +1,301 @@
+// cpio archives in the "newc" format
+//
+// The reader is fed the archive in pieces of any size, e.g. as a decompressor produces them, and
+// reports each entry, its data and its end to a handler. Entries are a 110-byte ASCII header, the
+// path and the data, both padded to four bytes. The archive ends with an entry named `TRAILER!!!`.
+//
+// https://man.archlinux.org/man/cpio.5#New_ASCII_Format
+
+use super::FsError;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const HEADER_SIZE: usize = 110;
+const MAGIC: &[u8; 6] = b"070701";
+// the same with a checksum of the data
+const MAGIC_CRC: &[u8; 6] = b"070702";
+
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+// Fields of the header after the magic, eight hex digits each
+const FIELD_MODE: usize = 1;
+const FIELD_FILE_SIZE: usize = 6;
+const FIELD_NAME_SIZE: usize = 11;
+const FIELD_CHECK: usize = 12;
+
+// Type bits of the mode
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_FILE: u32 = 0o100000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    Name,
+    // `u32`s are the bytes left
+    NamePadding(u32),
+    Data(u32),
+    DataPadding(u32),
+    Done,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Longest path of an entry
+pub const MAX_PATH_LEN: usize = 1024;
+
+pub enum Event<'a> {
+    // a new entry, its data follows
+    Entry(Entry<'a>),
+    Data(&'a [u8]),
+    // all data of the entry has been reported
+    EntryEnd,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    // as stored, usually relative and without a leading `/`
+    pub path: &'a str,
+    pub mode: u32,
+    pub size: u32,
+}
+
+pub struct CpioReader {
+    state: State,
+    // the header, then the path
+    buffer: [u8; HEADER_SIZE + MAX_PATH_LEN],
+    filled: usize,
+    mode: u32,
+    size: u32,
+    name_size: usize,
+    // sum of the data bytes for `070702` archives
+    check: Option<u32>,
+    sum: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Entry<'_> {
+    pub fn is_dir(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_DIRECTORY
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_FILE
+    }
+
+    // The data of a symbolic link is its target
+    pub fn is_symlink(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_SYMLINK
+    }
+}
+
+impl CpioReader {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Header,
+            buffer: [0; HEADER_SIZE + MAX_PATH_LEN],
+            filled: 0,
+            mode: 0,
+            size: 0,
+            name_size: 0,
+            check: None,
+            sum: 0,
+        }
+    }
+
+    // The trailer has been read
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Parse the next piece of the archive. Returns the number of bytes used, which is less than
+    // `data.len()` only when the archive ends within `data`.
+    pub fn push<E: From<FsError>>(
+        &mut self,
+        data: &[u8],
+        handler: &mut impl FnMut(Event) -> Result<(), E>,
+    ) -> Result<usize, E> {
+        let mut position = 0;
+
+        while position < data.len() {
+            let rest = &data[position..];
+            match self.state {
+                State::Header => {
+                    let len = rest.len().min(HEADER_SIZE - self.filled);
+                    self.fill(&rest[..len]);
+                    position += len;
+
+                    if self.filled == HEADER_SIZE {
+                        self.parse_header()?;
+                        self.state = State::Name;
+                    }
+                }
+                State::Name => {
+                    let len = rest
+                        .len()
+                        .min(HEADER_SIZE + self.name_size - self.filled);
+                    self.fill(&rest[..len]);
+                    position += len;
+
+                    if self.filled == HEADER_SIZE + self.name_size {
+                        let padding = padding(HEADER_SIZE + self.name_size);
+                        self.state = State::NamePadding(padding);
+                        self.start_entry(handler)?;
+                    }
+                }
+                State::NamePadding(left) | State::DataPadding(left) => {
+                    let len = rest.len().min(left as usize);
+                    position += len;
+                    let left = left - len as u32;
+
+                    self.state = match self.state {
+                        State::NamePadding(_) if left > 0 => {
+                            State::NamePadding(left)
+                        }
+                        State::NamePadding(_) => State::Data(self.size),
+                        _ if left > 0 => State::DataPadding(left),
+                        _ => State::Header,
+                    };
+                    if self.state == State::Data(0) {
+                        self.end_entry(handler)?;
+                    }
+                }
+                State::Data(left) => {
+                    let len = rest.len().min(left as usize);
+                    let bytes = &rest[..len];
+                    position += len;
+
+                    self.sum = bytes
+                        .iter()
+                        .fold(self.sum, |sum, &b| sum.wrapping_add(b as u32));
+                    handler(Event::Data(bytes))?;
+
+                    self.state = State::Data(left - len as u32);
+                    if left as usize == len {
+                        self.end_entry(handler)?;
+                    }
+                }
+                State::Done => break,
+            }
+        }
+
+        Ok(position)
+    }
+
+    // Check that the archive is complete
+    pub fn finish(&self) -> Result<(), FsError> {
+        match self.state {
+            State::Done => Ok(()),
+            _ => Err(FsError::Corrupt("cpio: archive without trailer")),
+        }
+    }
+}
+
+impl Default for CpioReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl CpioReader {
+    fn fill(&mut self, bytes: &[u8]) {
+        self.buffer[self.filled..self.filled + bytes.len()]
+            .copy_from_slice(bytes);
+        self.filled += bytes.len();
+    }
+
+    fn parse_header(&mut self) -> Result<(), FsError> {
+        let magic = &self.buffer[..MAGIC.len()];
+        let has_check = match magic {
+            _ if magic == MAGIC => false,
+            _ if magic == MAGIC_CRC => true,
+            _ => return Err(FsError::Corrupt("cpio: bad magic")),
+        };
+
+        self.mode = self.field(FIELD_MODE)?;
+        self.size = self.field(FIELD_FILE_SIZE)?;
+        self.name_size = self.field(FIELD_NAME_SIZE)? as usize;
+        self.check = match has_check {
+            true => Some(self.field(FIELD_CHECK)?),
+            false => None,
+        };
+        self.sum = 0;
+
+        // the size includes the terminating zero
+        if self.name_size == 0 {
+            return Err(FsError::Corrupt("cpio: entry without a name"));
+        }
+        if self.name_size > MAX_PATH_LEN {
+            return Err(FsError::NameTooLong);
+        }
+
+        Ok(())
+    }
+
+    fn field(&self, index: usize) -> Result<u32, FsError> {
+        let start = MAGIC.len() + index * 8;
+        let digits = core::str::from_utf8(&self.buffer[start..start + 8])
+            .map_err(|_| FsError::Corrupt("cpio: bad header"))?;
+
+        u32::from_str_radix(digits, 16)
+            .map_err(|_| FsError::Corrupt("cpio: bad header"))
+    }
+
+    fn start_entry<E: From<FsError>>(
+        &mut self,
+        handler: &mut impl FnMut(Event) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let name = &self.buffer[HEADER_SIZE..HEADER_SIZE + self.name_size];
+        let (path, terminator) = name.split_at(name.len() - 1);
+        if terminator != [0] {
+            return Err(FsError::Corrupt("cpio: name not terminated").into());
+        }
+
+        if path == TRAILER {
+            self.state = State::Done;
+            return Ok(());
+        }
+
+        let path =
+            core::str::from_utf8(path).map_err(|_| FsError::InvalidName)?;
+        handler(Event::Entry(Entry {
+            path,
+            mode: self.mode,
+            size: self.size,
+        }))
+    }
+
+    fn end_entry<E: From<FsError>>(
+        &mut self,
+        handler: &mut impl FnMut(Event) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.check.is_some_and(|check| check != self.sum) {
+            return Err(FsError::Corrupt("cpio: checksum mismatch").into());
+        }
+
+        self.filled = 0;
+        self.state = match padding(self.size as usize) {
+            0 => State::Header,
+            padding => State::DataPadding(padding),
+        };
+
+        handler(Event::EntryEnd)
+    }
+}
+
+// Bytes of padding after `len` bytes up to a multiple of four
+fn padding(len: usize) -> u32 {
+    (len.next_multiple_of(4) - len) as u32
+}
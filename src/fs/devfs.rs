@@ -0,0 +1,160 @@
+// Device files
+//
+// A flat directory of character devices. Drivers implement `interface::CharDevice` and the BSP
+// lists them with their names, inode `i + 1` is device `i`.
+
+use super::{interface::FileSystem, FileKind, FsError, Inode, Metadata, Name};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const ROOT: Inode = 0;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Device file interfaces
+pub mod interface {
+    use crate::fs::FsError;
+
+    pub trait CharDevice {
+        // read from `offset` on, streams ignore the offset. Returns the number of bytes read.
+        fn read(
+            &self,
+            offset: u64,
+            buffer: &mut [u8],
+        ) -> Result<usize, FsError>;
+
+        // write at `offset`, streams ignore the offset. Returns the number of bytes written.
+        fn write(&self, offset: u64, data: &[u8]) -> Result<usize, FsError>;
+
+        // size in bytes of devices with a fixed size like a frame buffer, 0 for streams
+        fn size(&self) -> u64 {
+            0
+        }
+    }
+}
+
+// A device file
+pub struct Device {
+    pub name: &'static str,
+    pub device: &'static (dyn interface::CharDevice + Sync),
+}
+
+pub struct DevFs<const N: usize> {
+    devices: [Device; N],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<const N: usize> DevFs<N> {
+    pub const fn new(devices: [Device; N]) -> Self {
+        Self { devices }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl<const N: usize> FileSystem for DevFs<N> {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Inode {
+        ROOT
+    }
+
+    fn lookup(&self, dir: Inode, name: &str) -> Result<Inode, FsError> {
+        if dir != ROOT {
+            return Err(FsError::NotADirectory);
+        }
+
+        self.devices
+            .iter()
+            .position(|device| device.name == name)
+            .map(|i| i as Inode + 1)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn metadata(&self, inode: Inode) -> Result<Metadata, FsError> {
+        if inode == ROOT {
+            return Ok(Metadata {
+                inode,
+                kind: FileKind::Directory,
+                size: 0,
+            });
+        }
+
+        Ok(Metadata {
+            inode,
+            kind: FileKind::CharDevice,
+            size: self.device(inode)?.device.size(),
+        })
+    }
+
+    fn read_dir(
+        &self,
+        dir: Inode,
+        position: &mut u64,
+        name: &mut Name,
+    ) -> Result<Option<Inode>, FsError> {
+        if dir != ROOT {
+            return Err(FsError::NotADirectory);
+        }
+
+        match self.devices.get(*position as usize) {
+            Some(device) => {
+                *name = Name::new(device.name)?;
+                *position += 1;
+
+                Ok(Some(*position))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn read(
+        &self,
+        inode: Inode,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        self.device(inode)?.device.read(offset, buffer)
+    }
+
+    fn write(
+        &self,
+        inode: Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, FsError> {
+        self.device(inode)?.device.write(offset, data)
+    }
+
+    // Opening a device for writing truncates it, which means nothing to a device
+    fn truncate(&self, inode: Inode, _len: u64) -> Result<(), FsError> {
+        self.device(inode).map(|_| ())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<const N: usize> DevFs<N> {
+    fn device(&self, inode: Inode) -> Result<&Device, FsError> {
+        match inode {
+            ROOT => Err(FsError::IsADirectory),
+            _ => self
+                .devices
+                .get(inode as usize - 1)
+                .ok_or(FsError::NotFound),
+        }
+    }
+}
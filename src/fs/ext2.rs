@@ -0,0 +1,777 @@
+// ext2, and ext3 and ext4 without a journal to replay, read-only
+//
+// Files are read through the classic block map with its indirect blocks or through the extent
+// tree of ext4. Directories are read linearly, which also works for hashed directories since the
+// hash tree hides in entries that look empty. Nothing is written, so the file system needs no lock
+// and checksums are not verified.
+//
+// The file system block size must be a multiple of the device block size, both at most 4 KiB.
+// Inode numbers are those of the file system, the root directory is inode 2.
+//
+// https://www.nongnu.org/ext2-doc/ext2.html
+// https://docs.kernel.org/filesystems/ext4/index.html
+
+use super::{interface::FileSystem, FileKind, FsError, Inode, Metadata, Name};
+use crate::block::interface::BlockDevice;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+
+const MIN_BLOCK_SIZE: usize = 1024;
+const MAX_BLOCK_SIZE: usize = 4096;
+
+const ROOT_INODE: u32 = 2;
+
+// Revision 0 has fixed inode sizes and no feature flags
+const GOOD_OLD_REVISION: u32 = 0;
+const GOOD_OLD_INODE_SIZE: u32 = 128;
+
+// Size of the inode fields read here
+const INODE_SIZE: usize = 128;
+
+// Group descriptors without and with the 64-bit feature
+const DESC_SIZE: u32 = 32;
+const DESC_SIZE_64BIT: u32 = 64;
+
+// Compatible features, only to tell ext3 from ext2
+const COMPAT_HAS_JOURNAL: u32 = 0x0004;
+
+// Read-only compatible features
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+
+// Incompatible features
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_RECOVER: u32 = 0x0004;
+const INCOMPAT_META_BG: u32 = 0x0010;
+const INCOMPAT_EXTENTS: u32 = 0x0040;
+const INCOMPAT_64BIT: u32 = 0x0080;
+const INCOMPAT_MMP: u32 = 0x0100;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const INCOMPAT_EA_INODE: u32 = 0x0400;
+const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const INCOMPAT_LARGEDIR: u32 = 0x4000;
+// everything else changes how files or names are stored
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE
+    | INCOMPAT_META_BG
+    | INCOMPAT_EXTENTS
+    | INCOMPAT_64BIT
+    | INCOMPAT_MMP
+    | INCOMPAT_FLEX_BG
+    | INCOMPAT_EA_INODE
+    | INCOMPAT_CSUM_SEED
+    | INCOMPAT_LARGEDIR;
+// features that only ext4 has
+const INCOMPAT_EXT4: u32 =
+    INCOMPAT_EXTENTS | INCOMPAT_64BIT | INCOMPAT_FLEX_BG | INCOMPAT_LARGEDIR;
+
+// Type bits of `i_mode`
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_FILE: u16 = 0x8000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_SYMLINK: u16 = 0xA000;
+
+// Inode flags
+const FLAG_EXTENTS: u32 = 0x0008_0000;
+const FLAG_INLINE_DATA: u32 = 0x1000_0000;
+
+// Block map
+const DIRECT_BLOCKS: u64 = 12;
+const INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+
+// Extent tree
+const EXTENT_MAGIC: u16 = 0xF30A;
+const EXTENT_HEADER_SIZE: usize = 12;
+const EXTENT_SIZE: usize = 12;
+const MAX_EXTENT_DEPTH: u16 = 5;
+// longer extents are allocated but not written yet and read as zeros
+const MAX_INITIALIZED_LEN: u16 = 32768;
+
+// Directory entries
+const DIR_ENTRY_HEADER_SIZE: usize = 8;
+
+// Symbolic links shorter than this are stored in `i_block`
+const FAST_SYMLINK_MAX: u64 = 60;
+
+type Block = [u8; MAX_BLOCK_SIZE];
+
+struct Layout {
+    block_size: usize,
+    // device blocks per file system block
+    device_blocks: u64,
+    first_data_block: u64,
+    blocks_per_group: u64,
+    inodes_per_group: u32,
+    inodes_count: u32,
+    group_count: u32,
+    inode_size: u32,
+    desc_size: u32,
+    first_meta_bg: u32,
+    compat: u32,
+    incompat: u32,
+    ro_compat: u32,
+}
+
+// The fields of an inode that are used
+#[derive(Clone, Copy)]
+struct RawInode {
+    mode: u16,
+    size: u64,
+    flags: u32,
+    // block map, extent tree root, inline symbolic link
+    block: [u8; 60],
+    // 512-byte sectors, 0 for a fast symbolic link
+    sectors: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct Ext2FileSystem<'a> {
+    device: &'a (dyn BlockDevice + Sync),
+    layout: Layout,
+    label: Name,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a> Ext2FileSystem<'a> {
+    // Read the superblock of `device` and check that its features can be read
+    pub fn mount(
+        device: &'a (dyn BlockDevice + Sync),
+    ) -> Result<Self, FsError> {
+        let device_block_size = device.block_size();
+        if !device_block_size.is_power_of_two()
+            || device_block_size > MAX_BLOCK_SIZE
+        {
+            return Err(FsError::Unsupported);
+        }
+
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+        read_device(device, SUPERBLOCK_OFFSET, &mut superblock)?;
+        let layout = Layout::parse(&superblock, device)?;
+
+        let label = superblock[120..136].split(|&b| b == 0).next().unwrap();
+        let label = lossy_name(label)?;
+
+        Ok(Self {
+            device,
+            layout,
+            label,
+        })
+    }
+
+    // Volume name, may be empty
+    pub fn label(&self) -> &str {
+        self.label.as_str()
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.layout.block_size
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl FileSystem for Ext2FileSystem<'_> {
+    fn name(&self) -> &'static str {
+        if self.layout.incompat & INCOMPAT_EXT4 != 0 {
+            "ext4"
+        } else if self.layout.compat & COMPAT_HAS_JOURNAL != 0 {
+            "ext3"
+        } else {
+            "ext2"
+        }
+    }
+
+    fn root(&self) -> Inode {
+        ROOT_INODE as Inode
+    }
+
+    fn lookup(&self, dir: Inode, name: &str) -> Result<Inode, FsError> {
+        let dir = self.directory(dir)?;
+
+        let mut position = 0;
+        let mut entry_name = Name::empty();
+        while let Some(inode) =
+            self.next_entry(&dir, &mut position, &mut entry_name)?
+        {
+            if entry_name.as_str() == name {
+                return Ok(inode as Inode);
+            }
+        }
+
+        Err(FsError::NotFound)
+    }
+
+    fn metadata(&self, inode: Inode) -> Result<Metadata, FsError> {
+        let raw = self.inode(inode)?;
+
+        Ok(Metadata {
+            inode,
+            kind: raw.kind(),
+            size: match raw.kind() {
+                FileKind::File | FileKind::Symlink => raw.size,
+                _ => 0,
+            },
+        })
+    }
+
+    fn read_dir(
+        &self,
+        dir: Inode,
+        position: &mut u64,
+        name: &mut Name,
+    ) -> Result<Option<Inode>, FsError> {
+        let dir = self.directory(dir)?;
+
+        Ok(self.next_entry(&dir, position, name)?.map(Inode::from))
+    }
+
+    // A symbolic link reads as its target
+    fn read(
+        &self,
+        inode: Inode,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        let raw = self.inode(inode)?;
+        match raw.kind() {
+            FileKind::Directory => return Err(FsError::IsADirectory),
+            FileKind::Symlink if raw.is_fast_symlink() => {
+                let target = &raw.block[..raw.size as usize];
+                let start = offset.min(raw.size) as usize;
+                let len = buffer.len().min(target.len() - start);
+                buffer[..len].copy_from_slice(&target[start..start + len]);
+
+                return Ok(len);
+            }
+            FileKind::File | FileKind::Symlink => (),
+            _ => return Err(FsError::Unsupported),
+        }
+
+        self.read_data(&raw, offset, buffer)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Layout {
+    fn parse(
+        superblock: &[u8; SUPERBLOCK_SIZE],
+        device: &(dyn BlockDevice + Sync),
+    ) -> Result<Self, FsError> {
+        if u16_at(superblock, 56) != MAGIC {
+            return Err(FsError::Corrupt("ext2: no superblock"));
+        }
+
+        let log_block_size = u32_at(superblock, 24);
+        if log_block_size > 2 {
+            return Err(FsError::Unsupported);
+        }
+        let block_size = MIN_BLOCK_SIZE << log_block_size;
+        if block_size < device.block_size() {
+            return Err(FsError::Unsupported);
+        }
+
+        let revision = u32_at(superblock, 76);
+        let (inode_size, compat, incompat, ro_compat) =
+            if revision == GOOD_OLD_REVISION {
+                (GOOD_OLD_INODE_SIZE, 0, 0, 0)
+            } else {
+                (
+                    u16_at(superblock, 88) as u32,
+                    u32_at(superblock, 92),
+                    u32_at(superblock, 96),
+                    u32_at(superblock, 100),
+                )
+            };
+
+        if incompat & INCOMPAT_RECOVER != 0 {
+            return Err(FsError::Corrupt("ext2: the journal needs recovery"));
+        }
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(FsError::Unsupported);
+        }
+
+        let desc_size = if incompat & INCOMPAT_64BIT != 0 {
+            u16_at(superblock, 254) as u32
+        } else {
+            DESC_SIZE
+        };
+
+        let mut blocks_count = u32_at(superblock, 4) as u64;
+        if incompat & INCOMPAT_64BIT != 0 {
+            blocks_count |= (u32_at(superblock, 0x150) as u64) << 32;
+        }
+
+        let layout = Self {
+            block_size,
+            device_blocks: (block_size / device.block_size()) as u64,
+            first_data_block: u32_at(superblock, 20) as u64,
+            blocks_per_group: u32_at(superblock, 32) as u64,
+            inodes_per_group: u32_at(superblock, 40),
+            inodes_count: u32_at(superblock, 0),
+            group_count: 0,
+            inode_size,
+            desc_size,
+            first_meta_bg: u32_at(superblock, 260),
+            compat,
+            incompat,
+            ro_compat,
+        };
+
+        if layout.blocks_per_group == 0
+            || layout.inodes_per_group == 0
+            || (layout.inode_size as usize) < INODE_SIZE
+            || !layout.inode_size.is_power_of_two()
+            || layout.inode_size as usize > block_size
+            || layout.desc_size < DESC_SIZE
+            || !layout.desc_size.is_power_of_two()
+            || (incompat & INCOMPAT_64BIT != 0
+                && layout.desc_size < DESC_SIZE_64BIT)
+            || layout.first_data_block >= blocks_count
+        {
+            return Err(FsError::Corrupt("ext2: bad superblock"));
+        }
+        if blocks_count * layout.device_blocks > device.block_count() {
+            return Err(FsError::Corrupt("ext2: larger than the device"));
+        }
+
+        let group_count = (blocks_count - layout.first_data_block)
+            .div_ceil(layout.blocks_per_group);
+
+        Ok(Self {
+            group_count: group_count as u32,
+            ..layout
+        })
+    }
+
+    fn descriptors_per_block(&self) -> u64 {
+        (self.block_size as u32 / self.desc_size) as u64
+    }
+
+    fn group_start(&self, group: u64) -> u64 {
+        self.first_data_block + group * self.blocks_per_group
+    }
+
+    // With sparse superblocks, only groups 0, 1 and powers of 3, 5 and 7 keep a copy
+    fn has_superblock(&self, group: u64) -> bool {
+        if self.ro_compat & RO_COMPAT_SPARSE_SUPER == 0 || group <= 1 {
+            return true;
+        }
+
+        [3, 5, 7].iter().any(|&base| {
+            let mut power = base;
+            while power < group {
+                power *= base;
+            }
+            power == group
+        })
+    }
+
+    // Byte offset of the descriptor of `group`
+    fn descriptor_offset(&self, group: u32) -> u64 {
+        let per_block = self.descriptors_per_block();
+        let group = group as u64;
+        let index = group % per_block;
+        let meta_group = group / per_block;
+
+        // Meta block groups keep the descriptors of each run of groups in its first group
+        let block = if self.incompat & INCOMPAT_META_BG != 0
+            && meta_group >= self.first_meta_bg as u64
+        {
+            let first = meta_group * per_block;
+            self.group_start(first) + self.has_superblock(first) as u64
+        } else {
+            self.first_data_block + 1 + meta_group
+        };
+
+        block * self.block_size as u64 + index * self.desc_size as u64
+    }
+}
+
+impl RawInode {
+    fn parse(raw: &[u8; INODE_SIZE], layout: &Layout) -> Self {
+        let mode = u16_at(raw, 0);
+
+        // the upper half is the ACL block of directories before ext4
+        let mut size = u32_at(raw, 4) as u64;
+        if mode & MODE_TYPE_MASK != MODE_DIRECTORY
+            || layout.incompat & INCOMPAT_LARGEDIR != 0
+        {
+            size |= (u32_at(raw, 108) as u64) << 32;
+        }
+
+        let mut block = [0; 60];
+        block.copy_from_slice(&raw[40..100]);
+
+        Self {
+            mode,
+            size,
+            flags: u32_at(raw, 32),
+            block,
+            sectors: u32_at(raw, 28),
+        }
+    }
+
+    fn kind(&self) -> FileKind {
+        match self.mode & MODE_TYPE_MASK {
+            MODE_FILE => FileKind::File,
+            MODE_DIRECTORY => FileKind::Directory,
+            MODE_SYMLINK => FileKind::Symlink,
+            _ => FileKind::Special,
+        }
+    }
+
+    fn is_fast_symlink(&self) -> bool {
+        self.flags & (FLAG_EXTENTS | FLAG_INLINE_DATA) == 0
+            && self.sectors == 0
+            && self.size < FAST_SYMLINK_MAX
+    }
+
+    // Entry `index` of `i_block` as a block map
+    fn block_pointer(&self, index: usize) -> u64 {
+        u32_at(&self.block, index * 4) as u64
+    }
+}
+
+impl Ext2FileSystem<'_> {
+    fn read_bytes(&self, offset: u64, out: &mut [u8]) -> Result<(), FsError> {
+        read_device(self.device, offset, out)
+    }
+
+    // A whole file system block into the start of `buffer`
+    fn read_block(&self, block: u64, buffer: &mut [u8]) -> Result<(), FsError> {
+        let len = self.layout.block_size;
+
+        Ok(self.device.read_blocks(
+            block * self.layout.device_blocks,
+            &mut buffer[..len],
+        )?)
+    }
+
+    fn read_u32(&self, block: u64, index: u64) -> Result<u32, FsError> {
+        let mut bytes = [0; 4];
+        self.read_bytes(
+            block * self.layout.block_size as u64 + index * 4,
+            &mut bytes,
+        )?;
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // Inodes
+
+    fn inode(&self, inode: Inode) -> Result<RawInode, FsError> {
+        let layout = &self.layout;
+        if inode == 0 || inode > layout.inodes_count as Inode {
+            return Err(FsError::NotFound);
+        }
+
+        let index = inode as u32 - 1;
+        let group = index / layout.inodes_per_group;
+        if group >= layout.group_count {
+            return Err(FsError::Corrupt("ext2: inode past the last group"));
+        }
+
+        let mut descriptor = [0; DESC_SIZE_64BIT as usize];
+        let len = (layout.desc_size as usize).min(descriptor.len());
+        self.read_bytes(
+            layout.descriptor_offset(group),
+            &mut descriptor[..len],
+        )?;
+        let mut table = u32_at(&descriptor, 8) as u64;
+        if len >= DESC_SIZE_64BIT as usize {
+            table |= (u32_at(&descriptor, 0x28) as u64) << 32;
+        }
+
+        let offset = table * layout.block_size as u64
+            + (index % layout.inodes_per_group) as u64
+                * layout.inode_size as u64;
+        let mut raw = [0; INODE_SIZE];
+        self.read_bytes(offset, &mut raw)?;
+
+        Ok(RawInode::parse(&raw, layout))
+    }
+
+    fn directory(&self, inode: Inode) -> Result<RawInode, FsError> {
+        let raw = self.inode(inode)?;
+        if raw.kind() != FileKind::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        Ok(raw)
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // File data
+
+    // The device block that holds block `index` of a file, `None` for a hole
+    fn map_block(
+        &self,
+        raw: &RawInode,
+        index: u64,
+    ) -> Result<Option<u64>, FsError> {
+        if raw.flags & FLAG_INLINE_DATA != 0 {
+            return Err(FsError::Unsupported);
+        }
+        if raw.flags & FLAG_EXTENTS != 0 {
+            return self.map_extent(raw, index);
+        }
+
+        let per_block = self.layout.block_size as u64 / 4;
+
+        // the tree to walk and the index within it
+        let (root, levels, mut index) = if index < DIRECT_BLOCKS {
+            return Ok(Some(raw.block_pointer(index as usize))
+                .filter(|&block| block != 0));
+        } else if index - DIRECT_BLOCKS < per_block {
+            (raw.block_pointer(INDIRECT), 1, index - DIRECT_BLOCKS)
+        } else if index - DIRECT_BLOCKS - per_block < per_block * per_block {
+            (
+                raw.block_pointer(DOUBLE_INDIRECT),
+                2,
+                index - DIRECT_BLOCKS - per_block,
+            )
+        } else {
+            (
+                raw.block_pointer(TRIPLE_INDIRECT),
+                3,
+                index - DIRECT_BLOCKS - per_block - per_block * per_block,
+            )
+        };
+        if index >= per_block.pow(levels) {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let mut block = root;
+        for level in (0..levels).rev() {
+            if block == 0 {
+                return Ok(None);
+            }
+            let span = per_block.pow(level);
+            block = self.read_u32(block, index / span)? as u64;
+            index %= span;
+        }
+
+        Ok(Some(block).filter(|&block| block != 0))
+    }
+
+    fn map_extent(
+        &self,
+        raw: &RawInode,
+        index: u64,
+    ) -> Result<Option<u64>, FsError> {
+        // the root node is in the inode, the others fill a block
+        let mut node = [0; MAX_BLOCK_SIZE];
+        node[..raw.block.len()].copy_from_slice(&raw.block);
+        let mut node_size = raw.block.len();
+        let mut expected_depth = None;
+
+        loop {
+            if u16_at(&node, 0) != EXTENT_MAGIC {
+                return Err(FsError::Corrupt("ext4: bad extent header"));
+            }
+            let entries = u16_at(&node, 2) as usize;
+            let depth = u16_at(&node, 6);
+            if depth > MAX_EXTENT_DEPTH
+                || expected_depth.is_some_and(|expected| depth != expected)
+                || EXTENT_HEADER_SIZE + entries * EXTENT_SIZE > node_size
+            {
+                return Err(FsError::Corrupt("ext4: bad extent node"));
+            }
+
+            // the last entry that starts at or before `index`, entries are sorted
+            let entry = (0..entries)
+                .map(|i| EXTENT_HEADER_SIZE + i * EXTENT_SIZE)
+                .take_while(|&at| u32_at(&node, at) as u64 <= index)
+                .last();
+            let at = match entry {
+                Some(at) => at,
+                None => return Ok(None),
+            };
+
+            if depth == 0 {
+                let first = u32_at(&node, at) as u64;
+                let len = u16_at(&node, at + 4);
+                let start = ((u16_at(&node, at + 6) as u64) << 32)
+                    | u32_at(&node, at + 8) as u64;
+
+                // not written yet
+                if len > MAX_INITIALIZED_LEN || index - first >= len as u64 {
+                    return Ok(None);
+                }
+
+                return Ok(Some(start + index - first));
+            }
+
+            let child = ((u16_at(&node, at + 8) as u64) << 32)
+                | u32_at(&node, at + 4) as u64;
+            self.read_block(child, &mut node)?;
+            node_size = self.layout.block_size;
+            expected_depth = Some(depth - 1);
+        }
+    }
+
+    fn read_data(
+        &self,
+        raw: &RawInode,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        let block_size = self.layout.block_size;
+        let end = raw.size.min(offset.saturating_add(buffer.len() as u64));
+        let mut position = offset;
+        let mut block: Block = [0; MAX_BLOCK_SIZE];
+
+        while position < end {
+            let index = position / block_size as u64;
+            let in_block = (position % block_size as u64) as usize;
+            let len = (block_size - in_block).min((end - position) as usize);
+            let out = &mut buffer[(position - offset) as usize
+                ..(position - offset) as usize + len];
+
+            match self.map_block(raw, index)? {
+                // whole blocks go straight to the caller
+                Some(physical) if len == block_size => {
+                    self.read_block(physical, out)?
+                }
+                Some(physical) => {
+                    self.read_block(physical, &mut block)?;
+                    out.copy_from_slice(&block[in_block..in_block + len]);
+                }
+                None => out.fill(0),
+            }
+
+            position += len as u64;
+        }
+
+        Ok((end.max(offset) - offset) as usize)
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // Directories
+
+    // The next entry of `dir` from the byte offset `position` on, without `.` and `..`
+    fn next_entry(
+        &self,
+        dir: &RawInode,
+        position: &mut u64,
+        name: &mut Name,
+    ) -> Result<Option<u32>, FsError> {
+        let block_size = self.layout.block_size as u64;
+        let mut block: Block = [0; MAX_BLOCK_SIZE];
+        let mut loaded = None;
+
+        while *position < dir.size {
+            let index = *position / block_size;
+            if loaded != Some(index) {
+                // directories have no holes, a corrupt size would read on forever
+                let physical = self
+                    .map_block(dir, index)?
+                    .ok_or(FsError::Corrupt("ext2: hole in a directory"))?;
+                self.read_block(physical, &mut block)?;
+                loaded = Some(index);
+            }
+
+            // entries do not cross blocks
+            let at = (*position % block_size) as usize;
+            let rest = block_size as usize - at;
+            if rest < DIR_ENTRY_HEADER_SIZE {
+                return Err(FsError::Corrupt("ext2: bad directory entry"));
+            }
+            let entry = &block[at..at + rest];
+
+            let inode = u32_at(entry, 0);
+            let rec_len = u16_at(entry, 4) as usize;
+            let name_len = if self.layout.incompat & INCOMPAT_FILETYPE != 0 {
+                entry[6] as usize
+            } else {
+                u16_at(entry, 6) as usize
+            };
+            if rec_len < DIR_ENTRY_HEADER_SIZE
+                || rec_len > rest
+                || !rec_len.is_multiple_of(4)
+                || DIR_ENTRY_HEADER_SIZE + name_len > rec_len
+            {
+                return Err(FsError::Corrupt("ext2: bad directory entry"));
+            }
+            *position += rec_len as u64;
+
+            let bytes =
+                &entry[DIR_ENTRY_HEADER_SIZE..DIR_ENTRY_HEADER_SIZE + name_len];
+            if inode == 0 || bytes == b"." || bytes == b".." {
+                continue;
+            }
+
+            *name = lossy_name(bytes)?;
+
+            return Ok(Some(inode));
+        }
+
+        Ok(None)
+    }
+}
+
+// Read `out.len()` bytes from the byte offset `offset` of `device`
+fn read_device(
+    device: &(dyn BlockDevice + Sync),
+    offset: u64,
+    out: &mut [u8],
+) -> Result<(), FsError> {
+    let block_size = device.block_size();
+    let mut buffer: Block = [0; MAX_BLOCK_SIZE];
+    let mut done = 0;
+
+    while done < out.len() {
+        let position = offset + done as u64;
+        let in_block = (position % block_size as u64) as usize;
+        let len = (block_size - in_block).min(out.len() - done);
+
+        device.read_blocks(
+            position / block_size as u64,
+            &mut buffer[..block_size],
+        )?;
+        out[done..done + len]
+            .copy_from_slice(&buffer[in_block..in_block + len]);
+        done += len;
+    }
+
+    Ok(())
+}
+
+// Linux does not care about the encoding of names, show what is not UTF-8 as U+FFFD
+fn lossy_name(bytes: &[u8]) -> Result<Name, FsError> {
+    let mut name = Name::empty();
+    for chunk in bytes.utf8_chunks() {
+        name.push_str(chunk.valid())?;
+        if !chunk.invalid().is_empty() {
+            name.push(char::REPLACEMENT_CHARACTER)?;
+        }
+    }
+
+    Ok(name)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
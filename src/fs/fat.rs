@@ -0,0 +1,1773 @@
+// FAT12, FAT16 and FAT32 with long file names
+//
+// Files and directories are `Entry` values that remember where their directory entry is, so that
+// writes can update the size and first cluster there. The FAT type follows from the cluster count
+// as the specification demands, not from the label in the boot sector.
+//
+// Every operation holds the lock of the file system and goes to the device sector by sector, put a
+// `block::cache::BufferCache` in front of slow devices. As the lock masks IRQs, the device has to
+// work without its interrupt, see `block::interface::BlockDevice`. Only 512-byte sectors are
+// supported.
+//
+// https://download.microsoft.com/download/1/6/1/161ba512-40e2-4cc9-843a-923143f3456c/fatgen103.doc
+
+use super::{
+    components, interface::FileSystem, split_last, FileKind, FsError, Inode,
+    Metadata, Name,
+};
+use crate::{
+    block::interface::BlockDevice,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: u32 = (SECTOR_SIZE / DIR_ENTRY_SIZE) as u32;
+
+// A directory has at most 65536 entries
+const MAX_DIR_ENTRIES: u32 = 65536;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+// Largest cluster counts of FAT12 and FAT16
+const MAX_FAT12_CLUSTERS: u32 = 4084;
+const MAX_FAT16_CLUSTERS: u32 = 65524;
+
+// Cluster numbers
+const FREE_CLUSTER: u32 = 0;
+const FIRST_CLUSTER: u32 = 2;
+const FAT32_CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+
+// Attributes
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 =
+    ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+const ATTR_LONG_NAME_MASK: u8 = ATTR_LONG_NAME | ATTR_DIRECTORY | ATTR_ARCHIVE;
+
+// First byte of a directory entry
+const ENTRY_END: u8 = 0x00;
+const ENTRY_FREE: u8 = 0xE5;
+// a short name starting with 0xE5 is stored with 0x05
+const ENTRY_E5: u8 = 0x05;
+
+// Case of the short name, as written by Windows NT and Linux
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXTENSION: u8 = 0x10;
+
+// Long file name entries
+const LFN_LAST: u8 = 0x40;
+const LFN_ORDINAL_MASK: u8 = 0x1F;
+const LFN_CHARS: usize = 13;
+const MAX_LFN_ENTRIES: usize = 20;
+// byte offsets of the UTF-16 characters in an entry
+const LFN_OFFSETS: [usize; LFN_CHARS] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+// FSInfo sector
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+// 1980-01-01, there is no real time clock to take dates from
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+// Characters that may not appear in any name
+const INVALID_CHARS: &str = "\"*/:<>?\\|";
+// Further characters that may not appear in a short name
+const INVALID_SHORT_CHARS: &str = "+,.;=[] ";
+
+type Sector = [u8; SECTOR_SIZE];
+
+struct Layout {
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    fat_start: u32,
+    fat_sectors: u32,
+    fat_count: u32,
+    // the fixed root directory of FAT12 and FAT16
+    root_start: u32,
+    root_sectors: u32,
+    data_start: u32,
+    cluster_count: u32,
+    // the root directory of FAT32
+    root_cluster: u32,
+    fsinfo_sector: Option<u32>,
+}
+
+struct FatInner<'a> {
+    device: &'a (dyn BlockDevice + Sync),
+    layout: Layout,
+    // `None` until the FAT has been counted
+    free_count: Option<u32>,
+    // where the search for a free cluster starts
+    next_free: u32,
+    fsinfo_dirty: bool,
+}
+
+// Where a directory entry is. `dir` is the first cluster of the directory, 0 for the fixed root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location {
+    dir: u32,
+    // the short entry, the long name entries are right before it
+    index: u32,
+    long_entries: u32,
+}
+
+// Long name entries seen before a short entry
+struct LongName {
+    units: [u16; MAX_LFN_ENTRIES * LFN_CHARS],
+    checksum: u8,
+    // ordinal of the next entry, 0 when complete
+    next: u8,
+    count: u8,
+    valid: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+// A file or directory
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    cluster: u32,
+    size: u32,
+    attributes: u8,
+    // `None` for the root directory
+    location: Option<Location>,
+}
+
+pub struct FatFileSystem<'a> {
+    inner: IRQSafeNullLock<FatInner<'a>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.location.is_none()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.attributes & ATTR_READ_ONLY != 0
+    }
+
+    // Size in bytes, 0 for directories
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+
+    // Number that stays the same as long as the entry exists
+    pub fn id(&self) -> u64 {
+        match self.location {
+            Some(location) => {
+                (((location.dir as u64) << 16) | location.index as u64) + 1
+            }
+            None => 0,
+        }
+    }
+}
+
+impl<'a> FatFileSystem<'a> {
+    // Read the boot sector of `device` and check that it holds a FAT file system
+    pub fn mount(
+        device: &'a (dyn BlockDevice + Sync),
+    ) -> Result<Self, FsError> {
+        if device.block_size() != SECTOR_SIZE {
+            return Err(FsError::Unsupported);
+        }
+
+        let mut boot = [0; SECTOR_SIZE];
+        device.read_blocks(0, &mut boot)?;
+        let layout = Layout::parse(&boot, device.block_count())?;
+
+        let mut inner = FatInner {
+            device,
+            layout,
+            free_count: None,
+            next_free: FIRST_CLUSTER,
+            fsinfo_dirty: false,
+        };
+        inner.read_fsinfo()?;
+
+        Ok(Self {
+            inner: IRQSafeNullLock::new(inner),
+        })
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.inner.lock(|fs| fs.layout.fat_type)
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.inner.lock(|fs| fs.layout.cluster_size())
+    }
+
+    // Free space in bytes
+    pub fn free_space(&self) -> Result<u64, FsError> {
+        self.inner.lock(|fs| {
+            let clusters = fs.free_clusters()?;
+
+            Ok(clusters as u64 * fs.layout.cluster_size() as u64)
+        })
+    }
+
+    pub fn root(&self) -> Entry {
+        self.inner.lock(|fs| fs.root())
+    }
+
+    // The entry at `path`, relative to the root directory
+    pub fn lookup(&self, path: &str) -> Result<Entry, FsError> {
+        self.inner.lock(|fs| fs.resolve(path))
+    }
+
+    // The next entry of `dir` from `position` on, its name goes to `name`. `position` starts at 0
+    // and is advanced past the entry.
+    pub fn read_dir(
+        &self,
+        dir: &Entry,
+        position: &mut u32,
+        name: &mut Name,
+    ) -> Result<Option<Entry>, FsError> {
+        if !dir.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        self.inner
+            .lock(|fs| fs.next_entry(fs.dir_cluster(dir), position, name))
+    }
+
+    // Read from `offset` on, returns the number of bytes read, 0 at the end of the file
+    pub fn read(
+        &self,
+        file: &Entry,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        if file.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+
+        self.inner.lock(|fs| fs.read(file, offset, buffer))
+    }
+
+    // Write at `offset`, filling a gap after the end of the file with zeros
+    pub fn write(
+        &self,
+        file: &mut Entry,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, FsError> {
+        if file.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+        if file.is_read_only() {
+            return Err(FsError::ReadOnly);
+        }
+
+        self.inner.lock(|fs| fs.write(file, offset, data))
+    }
+
+    // Cut the file to `len` bytes or extend it with zeros
+    pub fn truncate(&self, file: &mut Entry, len: u64) -> Result<(), FsError> {
+        if file.is_dir() {
+            return Err(FsError::IsADirectory);
+        }
+
+        self.inner.lock(|fs| fs.truncate(file, len))
+    }
+
+    pub fn create_file(&self, path: &str) -> Result<Entry, FsError> {
+        self.inner.lock(|fs| fs.create(path, false))
+    }
+
+    pub fn create_dir(&self, path: &str) -> Result<Entry, FsError> {
+        self.inner.lock(|fs| fs.create(path, true))
+    }
+
+    // Remove a file or an empty directory
+    pub fn remove(&self, path: &str) -> Result<(), FsError> {
+        self.inner.lock(|fs| fs.remove(path))
+    }
+
+    // Write the FSInfo sector and flush the device
+    pub fn sync(&self) -> Result<(), FsError> {
+        self.inner.lock(|fs| fs.sync())
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+// Inode numbers are `Entry::id()`, they stay valid until the entry is removed
+impl FileSystem for FatFileSystem<'_> {
+    fn name(&self) -> &'static str {
+        match self.fat_type() {
+            FatType::Fat12 => "fat12",
+            FatType::Fat16 => "fat16",
+            FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> Inode {
+        0
+    }
+
+    fn lookup(&self, dir: Inode, name: &str) -> Result<Inode, FsError> {
+        self.inner.lock(|fs| {
+            let dir = fs.entry_by_id(dir)?;
+            if !dir.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+
+            Ok(fs.lookup_in(&dir, name)?.id())
+        })
+    }
+
+    fn metadata(&self, inode: Inode) -> Result<Metadata, FsError> {
+        let entry = self.inner.lock(|fs| fs.entry_by_id(inode))?;
+
+        Ok(Metadata {
+            inode,
+            kind: if entry.is_dir() {
+                FileKind::Directory
+            } else {
+                FileKind::File
+            },
+            size: entry.size(),
+        })
+    }
+
+    fn read_dir(
+        &self,
+        dir: Inode,
+        position: &mut u64,
+        name: &mut Name,
+    ) -> Result<Option<Inode>, FsError> {
+        let dir = self.inner.lock(|fs| fs.entry_by_id(dir))?;
+        let mut index = (*position).min(MAX_DIR_ENTRIES as u64) as u32;
+        let entry = FatFileSystem::read_dir(self, &dir, &mut index, name)?;
+        *position = index as u64;
+
+        Ok(entry.map(|entry| entry.id()))
+    }
+
+    fn read(
+        &self,
+        inode: Inode,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        let file = self.inner.lock(|fs| fs.entry_by_id(inode))?;
+
+        FatFileSystem::read(self, &file, offset, buffer)
+    }
+
+    fn write(
+        &self,
+        inode: Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, FsError> {
+        let mut file = self.inner.lock(|fs| fs.entry_by_id(inode))?;
+
+        FatFileSystem::write(self, &mut file, offset, data)
+    }
+
+    fn truncate(&self, inode: Inode, len: u64) -> Result<(), FsError> {
+        let mut file = self.inner.lock(|fs| fs.entry_by_id(inode))?;
+
+        FatFileSystem::truncate(self, &mut file, len)
+    }
+
+    fn create(
+        &self,
+        dir: Inode,
+        name: &str,
+        kind: FileKind,
+    ) -> Result<Inode, FsError> {
+        let directory = match kind {
+            FileKind::File => false,
+            FileKind::Directory => true,
+            _ => return Err(FsError::Unsupported),
+        };
+
+        self.inner.lock(|fs| {
+            let dir = fs.entry_by_id(dir)?;
+
+            Ok(fs.create_in(&dir, name, directory)?.id())
+        })
+    }
+
+    fn remove(&self, dir: Inode, name: &str) -> Result<(), FsError> {
+        self.inner.lock(|fs| {
+            let dir = fs.entry_by_id(dir)?;
+
+            fs.remove_in(&dir, name)
+        })
+    }
+
+    fn sync(&self) -> Result<(), FsError> {
+        FatFileSystem::sync(self)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Layout {
+    fn parse(boot: &Sector, device_sectors: u64) -> Result<Self, FsError> {
+        if boot[510..512] != BOOT_SIGNATURE || !matches!(boot[0], 0xEB | 0xE9) {
+            return Err(FsError::Corrupt("no FAT boot sector"));
+        }
+
+        if u16_at(boot, 11) as usize != SECTOR_SIZE {
+            return Err(FsError::Unsupported);
+        }
+
+        let sectors_per_cluster = boot[13] as u32;
+        let reserved_sectors = u16_at(boot, 14) as u32;
+        let fat_count = boot[16] as u32;
+        let root_entries = u16_at(boot, 17) as u32;
+        let fat_sectors = match u16_at(boot, 22) as u32 {
+            0 => u32_at(boot, 36),
+            sectors => sectors,
+        };
+        let total_sectors = match u16_at(boot, 19) as u32 {
+            0 => u32_at(boot, 32),
+            sectors => sectors,
+        };
+
+        if !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_sectors == 0
+        {
+            return Err(FsError::Corrupt("bad BIOS parameter block"));
+        }
+        if total_sectors as u64 > device_sectors {
+            return Err(FsError::Corrupt("file system larger than the device"));
+        }
+
+        let root_sectors =
+            (root_entries * DIR_ENTRY_SIZE as u32).div_ceil(SECTOR_SIZE as u32);
+        let fat_start = reserved_sectors;
+        let root_start = fat_start + fat_count * fat_sectors;
+        let data_start = root_start + root_sectors;
+        let cluster_count = total_sectors
+            .checked_sub(data_start)
+            .ok_or(FsError::Corrupt("bad BIOS parameter block"))?
+            / sectors_per_cluster;
+
+        let fat_type = if cluster_count <= MAX_FAT12_CLUSTERS {
+            FatType::Fat12
+        } else if cluster_count <= MAX_FAT16_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        let mut layout = Self {
+            fat_type,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors,
+            fat_count,
+            root_start,
+            root_sectors,
+            data_start,
+            cluster_count,
+            root_cluster: 0,
+            fsinfo_sector: None,
+        };
+
+        if fat_type == FatType::Fat32 {
+            layout.root_cluster = u32_at(boot, 44);
+            if !layout.is_valid_cluster(layout.root_cluster) {
+                return Err(FsError::Corrupt("bad root cluster"));
+            }
+
+            let fsinfo = u16_at(boot, 48) as u32;
+            if fsinfo != 0 && fsinfo < reserved_sectors {
+                layout.fsinfo_sector = Some(fsinfo);
+            }
+        } else if root_sectors == 0 {
+            return Err(FsError::Corrupt("no root directory"));
+        }
+
+        // the FAT must have an entry for every cluster
+        let fat_bits = match fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        };
+        if (cluster_count as u64 + 2) * fat_bits
+            > fat_sectors as u64 * SECTOR_SIZE as u64 * 8
+        {
+            return Err(FsError::Corrupt("FAT too small"));
+        }
+
+        Ok(layout)
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..self.cluster_count + FIRST_CLUSTER).contains(&cluster)
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - FIRST_CLUSTER) * self.sectors_per_cluster
+    }
+
+    // Smallest FAT entry that ends a chain
+    fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+}
+
+impl<'a> FatInner<'a> {
+    fn read_sector(
+        &self,
+        sector: u32,
+        buffer: &mut Sector,
+    ) -> Result<(), FsError> {
+        Ok(self.device.read_blocks(sector as u64, buffer)?)
+    }
+
+    fn write_sector(
+        &self,
+        sector: u32,
+        buffer: &Sector,
+    ) -> Result<(), FsError> {
+        Ok(self.device.write_blocks(sector as u64, buffer)?)
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // FSInfo
+
+    fn read_fsinfo(&mut self) -> Result<(), FsError> {
+        let sector = match self.layout.fsinfo_sector {
+            Some(sector) => sector,
+            None => return Ok(()),
+        };
+
+        let mut buffer = [0; SECTOR_SIZE];
+        self.read_sector(sector, &mut buffer)?;
+        if !is_fsinfo(&buffer) {
+            self.layout.fsinfo_sector = None;
+            return Ok(());
+        }
+
+        // the values are hints and may be unknown or stale
+        let free_count = u32_at(&buffer, 488);
+        if free_count != FSINFO_UNKNOWN
+            && free_count <= self.layout.cluster_count
+        {
+            self.free_count = Some(free_count);
+        }
+        let next_free = u32_at(&buffer, 492);
+        if self.layout.is_valid_cluster(next_free) {
+            self.next_free = next_free;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), FsError> {
+        if let (true, Some(sector)) =
+            (self.fsinfo_dirty, self.layout.fsinfo_sector)
+        {
+            let mut buffer = [0; SECTOR_SIZE];
+            self.read_sector(sector, &mut buffer)?;
+
+            if is_fsinfo(&buffer) {
+                let free_count = self.free_count.unwrap_or(FSINFO_UNKNOWN);
+                buffer[488..492].copy_from_slice(&free_count.to_le_bytes());
+                buffer[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+                self.write_sector(sector, &buffer)?;
+            }
+        }
+        self.fsinfo_dirty = false;
+
+        Ok(self.device.flush()?)
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // File allocation table
+
+    // Read bytes of the first FAT from byte `offset` on
+    fn read_fat(&self, offset: u32, bytes: &mut [u8]) -> Result<(), FsError> {
+        let mut buffer = [0; SECTOR_SIZE];
+        let mut loaded = None;
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let offset = offset as usize + i;
+            let sector = self.layout.fat_start + (offset / SECTOR_SIZE) as u32;
+            if loaded != Some(sector) {
+                self.read_sector(sector, &mut buffer)?;
+                loaded = Some(sector);
+            }
+            *byte = buffer[offset % SECTOR_SIZE];
+        }
+
+        Ok(())
+    }
+
+    // Write bytes to every FAT from byte `offset` on
+    fn write_fat(&self, offset: u32, bytes: &[u8]) -> Result<(), FsError> {
+        let mut buffer = [0; SECTOR_SIZE];
+
+        for copy in 0..self.layout.fat_count {
+            let fat_start =
+                self.layout.fat_start + copy * self.layout.fat_sectors;
+            let mut i = 0;
+
+            // the bytes of a FAT12 entry may be in two sectors
+            while i < bytes.len() {
+                let position = offset as usize + i;
+                let sector = fat_start + (position / SECTOR_SIZE) as u32;
+                let start = position % SECTOR_SIZE;
+                let len = (bytes.len() - i).min(SECTOR_SIZE - start);
+
+                self.read_sector(sector, &mut buffer)?;
+                buffer[start..start + len].copy_from_slice(&bytes[i..i + len]);
+                self.write_sector(sector, &buffer)?;
+                i += len;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fat_entry(&self, cluster: u32) -> Result<u32, FsError> {
+        match self.layout.fat_type {
+            FatType::Fat12 => {
+                let mut bytes = [0; 2];
+                self.read_fat(cluster + cluster / 2, &mut bytes)?;
+                let value = u16::from_le_bytes(bytes) as u32;
+
+                Ok(if cluster & 1 == 1 {
+                    value >> 4
+                } else {
+                    value & 0xFFF
+                })
+            }
+            FatType::Fat16 => {
+                let mut bytes = [0; 2];
+                self.read_fat(cluster * 2, &mut bytes)?;
+
+                Ok(u16::from_le_bytes(bytes) as u32)
+            }
+            FatType::Fat32 => {
+                let mut bytes = [0; 4];
+                self.read_fat(cluster * 4, &mut bytes)?;
+
+                Ok(u32::from_le_bytes(bytes) & FAT32_CLUSTER_MASK)
+            }
+        }
+    }
+
+    fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        match self.layout.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster + cluster / 2;
+                let mut bytes = [0; 2];
+                self.read_fat(offset, &mut bytes)?;
+
+                let old = u16::from_le_bytes(bytes);
+                let value = value as u16 & 0xFFF;
+                let new = if cluster & 1 == 1 {
+                    (old & 0x000F) | (value << 4)
+                } else {
+                    (old & 0xF000) | value
+                };
+                self.write_fat(offset, &new.to_le_bytes())
+            }
+            FatType::Fat16 => {
+                self.write_fat(cluster * 2, &(value as u16).to_le_bytes())
+            }
+            FatType::Fat32 => {
+                // the top four bits are reserved
+                let mut bytes = [0; 4];
+                self.read_fat(cluster * 4, &mut bytes)?;
+
+                let old = u32::from_le_bytes(bytes);
+                let new =
+                    (old & !FAT32_CLUSTER_MASK) | (value & FAT32_CLUSTER_MASK);
+                self.write_fat(cluster * 4, &new.to_le_bytes())
+            }
+        }
+    }
+
+    // The cluster after `cluster`, `None` at the end of the chain
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FsError> {
+        let next = self.fat_entry(cluster)?;
+
+        if next >= self.layout.end_of_chain() {
+            Ok(None)
+        } else if self.layout.is_valid_cluster(next) {
+            Ok(Some(next))
+        } else {
+            Err(FsError::Corrupt("bad cluster chain"))
+        }
+    }
+
+    fn check_cluster(&self, cluster: u32) -> Result<u32, FsError> {
+        if self.layout.is_valid_cluster(cluster) {
+            Ok(cluster)
+        } else {
+            Err(FsError::Corrupt("bad cluster number"))
+        }
+    }
+
+    // The `n`th cluster of the chain from `first` on
+    fn nth_cluster(&self, first: u32, n: u32) -> Result<Option<u32>, FsError> {
+        let mut cluster = self.check_cluster(first)?;
+
+        for _ in 0..n {
+            match self.next_cluster(cluster)? {
+                Some(next) => cluster = next,
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(cluster))
+    }
+
+    fn free_clusters(&mut self) -> Result<u32, FsError> {
+        if let Some(count) = self.free_count {
+            return Ok(count);
+        }
+
+        let mut count = 0;
+        for cluster in FIRST_CLUSTER..self.layout.cluster_count + FIRST_CLUSTER
+        {
+            if self.fat_entry(cluster)? == FREE_CLUSTER {
+                count += 1;
+            }
+        }
+        self.free_count = Some(count);
+
+        Ok(count)
+    }
+
+    // Take a free cluster as the end of a chain and append it to `previous`
+    fn allocate(&mut self, previous: Option<u32>) -> Result<u32, FsError> {
+        let count = self.layout.cluster_count;
+        let start = self.next_free - FIRST_CLUSTER;
+
+        for i in 0..count {
+            let cluster = (start + i) % count + FIRST_CLUSTER;
+            if self.fat_entry(cluster)? != FREE_CLUSTER {
+                continue;
+            }
+
+            self.set_fat_entry(cluster, FAT32_CLUSTER_MASK)?;
+            if let Some(previous) = previous {
+                self.set_fat_entry(previous, cluster)?;
+            }
+
+            self.next_free = if cluster + 1 < count + FIRST_CLUSTER {
+                cluster + 1
+            } else {
+                FIRST_CLUSTER
+            };
+            self.free_count =
+                self.free_count.map(|free| free.saturating_sub(1));
+            self.fsinfo_dirty = true;
+
+            return Ok(cluster);
+        }
+
+        Err(FsError::NoSpace)
+    }
+
+    fn free_chain(&mut self, first: u32) -> Result<(), FsError> {
+        let mut cluster = Some(self.check_cluster(first)?);
+        let mut freed = 0;
+
+        while let Some(current) = cluster {
+            if freed > self.layout.cluster_count {
+                return Err(FsError::Corrupt("cluster chain loops"));
+            }
+
+            cluster = self.next_cluster(current)?;
+            self.set_fat_entry(current, FREE_CLUSTER)?;
+            freed += 1;
+        }
+
+        self.free_count = self.free_count.map(|free| free + freed);
+        self.fsinfo_dirty = true;
+
+        Ok(())
+    }
+
+    fn zero_cluster(&self, cluster: u32) -> Result<(), FsError> {
+        let sector = self.layout.cluster_sector(cluster);
+        let zeros = [0; SECTOR_SIZE];
+
+        for i in 0..self.layout.sectors_per_cluster {
+            self.write_sector(sector + i, &zeros)?;
+        }
+
+        Ok(())
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // Directories
+
+    fn root(&self) -> Entry {
+        Entry {
+            cluster: self.layout.root_cluster,
+            size: 0,
+            attributes: ATTR_DIRECTORY,
+            location: None,
+        }
+    }
+
+    // The first cluster of a directory, 0 for the fixed root directory
+    fn dir_cluster(&self, dir: &Entry) -> u32 {
+        if dir.is_root() {
+            self.layout.root_cluster
+        } else {
+            dir.cluster
+        }
+    }
+
+    // Sector and byte offset of entry `index` of a directory. With `extend` a directory that is too
+    // short gets another cluster.
+    fn entry_sector(
+        &mut self,
+        dir: u32,
+        index: u32,
+        extend: bool,
+    ) -> Result<Option<(u32, usize)>, FsError> {
+        if index >= MAX_DIR_ENTRIES {
+            return Ok(None);
+        }
+
+        let sector = index / ENTRIES_PER_SECTOR;
+        let offset = (index % ENTRIES_PER_SECTOR) as usize * DIR_ENTRY_SIZE;
+
+        if dir == 0 {
+            return Ok((sector < self.layout.root_sectors)
+                .then(|| (self.layout.root_start + sector, offset)));
+        }
+
+        let mut cluster = self.check_cluster(dir)?;
+        for _ in 0..sector / self.layout.sectors_per_cluster {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None if extend => {
+                    let next = self.allocate(Some(cluster))?;
+                    self.zero_cluster(next)?;
+                    next
+                }
+                None => return Ok(None),
+            };
+        }
+
+        Ok(Some((
+            self.layout.cluster_sector(cluster)
+                + sector % self.layout.sectors_per_cluster,
+            offset,
+        )))
+    }
+
+    fn read_dir_entry(
+        &mut self,
+        dir: u32,
+        index: u32,
+    ) -> Result<[u8; DIR_ENTRY_SIZE], FsError> {
+        let (sector, offset) = self
+            .entry_sector(dir, index, false)?
+            .ok_or(FsError::Corrupt("directory entry out of range"))?;
+        let mut buffer = [0; SECTOR_SIZE];
+        self.read_sector(sector, &mut buffer)?;
+
+        Ok(buffer[offset..offset + DIR_ENTRY_SIZE].try_into().unwrap())
+    }
+
+    fn write_dir_entry(
+        &mut self,
+        dir: u32,
+        index: u32,
+        raw: &[u8; DIR_ENTRY_SIZE],
+    ) -> Result<(), FsError> {
+        let (sector, offset) = self
+            .entry_sector(dir, index, false)?
+            .ok_or(FsError::Corrupt("directory entry out of range"))?;
+        let mut buffer = [0; SECTOR_SIZE];
+        self.read_sector(sector, &mut buffer)?;
+        buffer[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(raw);
+
+        self.write_sector(sector, &buffer)
+    }
+
+    // The next file or directory from `*index` on, skipping free entries and the volume label
+    fn next_entry(
+        &mut self,
+        dir: u32,
+        index: &mut u32,
+        name: &mut Name,
+    ) -> Result<Option<Entry>, FsError> {
+        let mut long_name = LongName::new();
+        let mut buffer = [0; SECTOR_SIZE];
+        let mut loaded = None;
+
+        loop {
+            let (sector, offset) =
+                match self.entry_sector(dir, *index, false)? {
+                    Some(position) => position,
+                    None => return Ok(None),
+                };
+            if loaded != Some(sector) {
+                self.read_sector(sector, &mut buffer)?;
+                loaded = Some(sector);
+            }
+
+            let raw = &buffer[offset..offset + DIR_ENTRY_SIZE];
+            let current = *index;
+
+            match raw[0] {
+                ENTRY_END => return Ok(None),
+                ENTRY_FREE => {
+                    long_name.reset();
+                    *index += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            *index += 1;
+
+            let attributes = raw[11];
+            if attributes & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+                long_name.push(raw);
+                continue;
+            }
+            if attributes & ATTR_VOLUME_ID != 0 {
+                long_name.reset();
+                continue;
+            }
+
+            let short: &[u8; 11] = raw[0..11].try_into().unwrap();
+            let long_entries = if long_name.decode(checksum(short), name) {
+                long_name.count as u32
+            } else {
+                short_name(short, raw[12], name);
+                0
+            };
+
+            let location = Location {
+                dir,
+                index: current,
+                long_entries,
+            };
+            return Ok(Some(self.parse_entry(raw, location)));
+        }
+    }
+
+    fn parse_entry(&self, raw: &[u8], location: Location) -> Entry {
+        let high = match self.layout.fat_type {
+            FatType::Fat32 => u16_at(raw, 20) as u32,
+            _ => 0,
+        };
+
+        Entry {
+            cluster: high << 16 | u16_at(raw, 26) as u32,
+            size: u32_at(raw, 28),
+            attributes: raw[11],
+            location: Some(location),
+        }
+    }
+
+    // The entry with `Entry::id()` `id`
+    fn entry_by_id(&mut self, id: u64) -> Result<Entry, FsError> {
+        if id == 0 {
+            return Ok(self.root());
+        }
+
+        let dir = ((id - 1) >> 16) as u32;
+        let index = ((id - 1) & 0xFFFF) as u32;
+        if dir != 0 && !self.layout.is_valid_cluster(dir) {
+            return Err(FsError::NotFound);
+        }
+
+        // the entry may have been removed since
+        let raw = self.read_dir_entry(dir, index)?;
+        if matches!(raw[0], ENTRY_END | ENTRY_FREE)
+            || raw[11] & ATTR_VOLUME_ID != 0
+        {
+            return Err(FsError::NotFound);
+        }
+
+        let location = Location {
+            dir,
+            index,
+            long_entries: 0,
+        };
+        Ok(self.directory_or_root(self.parse_entry(&raw, location)))
+    }
+
+    // `..` of a directory in the root directory points to cluster 0
+    fn directory_or_root(&self, entry: Entry) -> Entry {
+        if entry.is_dir() && entry.cluster == 0 {
+            self.root()
+        } else {
+            entry
+        }
+    }
+
+    // The entry called `name` in `dir`, compared without regard to ASCII case as FAT does
+    fn find(&mut self, dir: u32, name: &str) -> Result<Option<Entry>, FsError> {
+        let mut index = 0;
+        let mut entry_name = Name::empty();
+
+        while let Some(entry) =
+            self.next_entry(dir, &mut index, &mut entry_name)?
+        {
+            if entry_name.as_str().eq_ignore_ascii_case(name) {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn resolve(&mut self, path: &str) -> Result<Entry, FsError> {
+        let mut entry = self.root();
+
+        for component in components(path) {
+            if !entry.is_dir() {
+                return Err(FsError::NotADirectory);
+            }
+            if component == ".." && entry.is_root() {
+                continue;
+            }
+
+            entry = self.lookup_in(&entry, component)?;
+        }
+
+        Ok(entry)
+    }
+
+    fn lookup_in(&mut self, dir: &Entry, name: &str) -> Result<Entry, FsError> {
+        let entry = self
+            .find(self.dir_cluster(dir), name)?
+            .ok_or(FsError::NotFound)?;
+
+        Ok(self.directory_or_root(entry))
+    }
+
+    // Index of the first of `count` free entries in a row, the directory is extended when needed
+    fn free_entries(&mut self, dir: u32, count: u32) -> Result<u32, FsError> {
+        let mut run_start = 0;
+        let mut run = 0;
+        let mut index = 0;
+
+        while run < count {
+            let (sector, offset) = self
+                .entry_sector(dir, index, dir != 0)?
+                .ok_or(FsError::NoSpace)?;
+            let mut buffer = [0; SECTOR_SIZE];
+            self.read_sector(sector, &mut buffer)?;
+
+            if matches!(buffer[offset], ENTRY_END | ENTRY_FREE) {
+                if run == 0 {
+                    run_start = index;
+                }
+                run += 1;
+            } else {
+                run = 0;
+            }
+            index += 1;
+        }
+
+        Ok(run_start)
+    }
+
+    fn short_name_exists(
+        &mut self,
+        dir: u32,
+        short: &[u8; 11],
+    ) -> Result<bool, FsError> {
+        let mut index = 0;
+        let mut name = Name::empty();
+
+        while let Some(entry) = self.next_entry(dir, &mut index, &mut name)? {
+            let location = entry.location.unwrap();
+            let raw = self.read_dir_entry(dir, location.index)?;
+            if raw[0..11] == short[..] {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    // A short name for `name` that is not in `dir` yet, and whether a long name is needed
+    fn make_short_name(
+        &mut self,
+        dir: u32,
+        name: &str,
+    ) -> Result<([u8; 11], u8, bool), FsError> {
+        if let Some((short, case)) = exact_short_name(name) {
+            if self.short_name_exists(dir, &short)? {
+                return Err(FsError::AlreadyExists);
+            }
+            return Ok((short, case, false));
+        }
+
+        let (base, extension) = short_name_basis(name);
+        let base_len = base.iter().position(|&c| c == b' ').unwrap_or(8);
+
+        for n in 1..1_000_000u32 {
+            let mut tail = [0; 7];
+            let tail_len = {
+                let mut digits = [0; 6];
+                let mut len = 0;
+                let mut value = n;
+                while value > 0 {
+                    digits[len] = b'0' + (value % 10) as u8;
+                    value /= 10;
+                    len += 1;
+                }
+                tail[0] = b'~';
+                for i in 0..len {
+                    tail[1 + i] = digits[len - 1 - i];
+                }
+                len + 1
+            };
+
+            let mut short = [b' '; 11];
+            let keep = base_len.min(8 - tail_len);
+            short[..keep].copy_from_slice(&base[..keep]);
+            short[keep..keep + tail_len].copy_from_slice(&tail[..tail_len]);
+            short[8..11].copy_from_slice(&extension);
+
+            if !self.short_name_exists(dir, &short)? {
+                return Ok((short, 0, true));
+            }
+        }
+
+        Err(FsError::AlreadyExists)
+    }
+
+    fn create(
+        &mut self,
+        path: &str,
+        directory: bool,
+    ) -> Result<Entry, FsError> {
+        let (parent_path, name) = split_last(path);
+        let parent = self.resolve(parent_path)?;
+
+        self.create_in(&parent, name, directory)
+    }
+
+    fn create_in(
+        &mut self,
+        parent: &Entry,
+        name: &str,
+        directory: bool,
+    ) -> Result<Entry, FsError> {
+        check_name(name)?;
+        if !parent.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+        let dir = self.dir_cluster(parent);
+        if self.find(dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let (short, case, long) = self.make_short_name(dir, name)?;
+        let mut units = [0xFFFF; MAX_LFN_ENTRIES * LFN_CHARS];
+        let long_entries = if long {
+            let mut len = 0;
+            for unit in name.encode_utf16() {
+                units[len] = unit;
+                len += 1;
+            }
+            if len < units.len() {
+                units[len] = 0;
+            }
+            len.div_ceil(LFN_CHARS) as u32
+        } else {
+            0
+        };
+
+        let first = self.free_entries(dir, long_entries + 1)?;
+
+        let (cluster, attributes) = if directory {
+            let cluster = self.allocate(None)?;
+            self.zero_cluster(cluster)?;
+
+            // `..` of a directory in the root directory is 0, also on FAT32
+            let parent_cluster = if parent.is_root() { 0 } else { dir };
+            let dot =
+                short_entry(b".          ", 0, ATTR_DIRECTORY, cluster, 0);
+            let dot_dot = short_entry(
+                b"..         ",
+                0,
+                ATTR_DIRECTORY,
+                parent_cluster,
+                0,
+            );
+            self.write_dir_entry(cluster, 0, &dot)?;
+            self.write_dir_entry(cluster, 1, &dot_dot)?;
+
+            (cluster, ATTR_DIRECTORY)
+        } else {
+            (0, ATTR_ARCHIVE)
+        };
+
+        let sum = checksum(&short);
+        for i in 0..long_entries {
+            // the entry with the last part of the name comes first
+            let ordinal = long_entries - i;
+            let mut raw = [0; DIR_ENTRY_SIZE];
+            raw[0] = ordinal as u8 | if i == 0 { LFN_LAST } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = sum;
+
+            let part =
+                &units[(ordinal as usize - 1) * LFN_CHARS..][..LFN_CHARS];
+            for (offset, unit) in LFN_OFFSETS.iter().zip(part) {
+                raw[*offset..*offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            self.write_dir_entry(dir, first + i, &raw)?;
+        }
+
+        let index = first + long_entries;
+        let raw = short_entry(&short, case, attributes, cluster, 0);
+        self.write_dir_entry(dir, index, &raw)?;
+
+        Ok(Entry {
+            cluster,
+            size: 0,
+            attributes,
+            location: Some(Location {
+                dir,
+                index,
+                long_entries,
+            }),
+        })
+    }
+
+    fn remove(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent_path, name) = split_last(path);
+        let parent = self.resolve(parent_path)?;
+
+        self.remove_in(&parent, name)
+    }
+
+    fn remove_in(&mut self, parent: &Entry, name: &str) -> Result<(), FsError> {
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(FsError::InvalidName);
+        }
+        if !parent.is_dir() {
+            return Err(FsError::NotADirectory);
+        }
+
+        let entry = self
+            .find(self.dir_cluster(parent), name)?
+            .ok_or(FsError::NotFound)?;
+        let location = entry.location.ok_or(FsError::InvalidName)?;
+
+        if entry.is_dir() {
+            let mut index = 0;
+            let mut child = Name::empty();
+            while self
+                .next_entry(entry.cluster, &mut index, &mut child)?
+                .is_some()
+            {
+                if child.as_str() != "." && child.as_str() != ".." {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+            }
+        }
+
+        for index in location.index - location.long_entries..=location.index {
+            let mut raw = self.read_dir_entry(location.dir, index)?;
+            raw[0] = ENTRY_FREE;
+            self.write_dir_entry(location.dir, index, &raw)?;
+        }
+
+        if entry.cluster != 0 {
+            self.free_chain(entry.cluster)?;
+        }
+
+        Ok(())
+    }
+
+    //----------------------------------------------------------------------------------------------
+    // Files
+
+    fn read(
+        &mut self,
+        file: &Entry,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        if offset >= file.size() || buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buffer.len().min((file.size() - offset) as usize);
+        let cluster_size = self.layout.cluster_size() as u64;
+        let mut cluster = self
+            .nth_cluster(file.cluster, (offset / cluster_size) as u32)?
+            .ok_or(FsError::Corrupt("cluster chain shorter than the file"))?;
+        let mut position = offset;
+        let mut done = 0;
+        let mut sector_buffer = [0; SECTOR_SIZE];
+
+        while done < len {
+            let in_cluster = (position % cluster_size) as usize;
+            let sector = self.layout.cluster_sector(cluster)
+                + (in_cluster / SECTOR_SIZE) as u32;
+            let start = in_cluster % SECTOR_SIZE;
+            let count = (len - done).min(SECTOR_SIZE - start);
+
+            self.read_sector(sector, &mut sector_buffer)?;
+            buffer[done..done + count]
+                .copy_from_slice(&sector_buffer[start..start + count]);
+            done += count;
+            position += count as u64;
+
+            if done < len && position.is_multiple_of(cluster_size) {
+                cluster = self.next_cluster(cluster)?.ok_or(
+                    FsError::Corrupt("cluster chain shorter than the file"),
+                )?;
+            }
+        }
+
+        Ok(len)
+    }
+
+    fn write(
+        &mut self,
+        file: &mut Entry,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, FsError> {
+        if offset + data.len() as u64 > u32::MAX as u64 {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let zeros = [0; SECTOR_SIZE];
+        while file.size() < offset {
+            let len = (offset - file.size()).min(SECTOR_SIZE as u64) as usize;
+            self.write_at(file, file.size(), &zeros[..len])?;
+        }
+
+        self.write_at(file, offset, data)?;
+
+        Ok(data.len())
+    }
+
+    fn write_at(
+        &mut self,
+        file: &mut Entry,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let cluster_size = self.layout.cluster_size() as u64;
+        let mut cluster =
+            self.cluster_for_write(file, (offset / cluster_size) as u32)?;
+        let mut position = offset;
+        let mut done = 0;
+        let mut sector_buffer = [0; SECTOR_SIZE];
+
+        while done < data.len() {
+            let in_cluster = (position % cluster_size) as usize;
+            let sector = self.layout.cluster_sector(cluster)
+                + (in_cluster / SECTOR_SIZE) as u32;
+            let start = in_cluster % SECTOR_SIZE;
+            let count = (data.len() - done).min(SECTOR_SIZE - start);
+
+            // whole sectors need not be read first
+            if count < SECTOR_SIZE {
+                self.read_sector(sector, &mut sector_buffer)?;
+            }
+            sector_buffer[start..start + count]
+                .copy_from_slice(&data[done..done + count]);
+            self.write_sector(sector, &sector_buffer)?;
+            done += count;
+            position += count as u64;
+
+            if done < data.len() && position.is_multiple_of(cluster_size) {
+                cluster = match self.next_cluster(cluster)? {
+                    Some(next) => next,
+                    None => match self.allocate(Some(cluster)) {
+                        Ok(next) => next,
+                        Err(e) => {
+                            // keep what fitted, the chain ends right after it
+                            self.grow(file, position)?;
+                            return Err(e);
+                        }
+                    },
+                };
+            }
+        }
+
+        self.grow(file, position)
+    }
+
+    // Record that the file has data up to `end`
+    fn grow(&mut self, file: &mut Entry, end: u64) -> Result<(), FsError> {
+        if end > file.size() {
+            file.size = end as u32;
+        }
+
+        self.update_entry(file)
+    }
+
+    // The `n`th cluster of a file, allocating the clusters up to it
+    fn cluster_for_write(
+        &mut self,
+        file: &mut Entry,
+        n: u32,
+    ) -> Result<u32, FsError> {
+        if file.cluster == 0 {
+            file.cluster = self.allocate(None)?;
+            self.update_entry(file)?;
+        }
+
+        let mut cluster = self.check_cluster(file.cluster)?;
+        for _ in 0..n {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => self.allocate(Some(cluster))?,
+            };
+        }
+
+        Ok(cluster)
+    }
+
+    fn truncate(&mut self, file: &mut Entry, len: u64) -> Result<(), FsError> {
+        if len >= file.size() {
+            return self.write(file, len, &[]).map(|_| ());
+        }
+
+        let cluster_size = self.layout.cluster_size() as u64;
+        let keep = len.div_ceil(cluster_size) as u32;
+
+        if keep == 0 {
+            if file.cluster != 0 {
+                self.free_chain(file.cluster)?;
+                file.cluster = 0;
+            }
+        } else {
+            let last = self.nth_cluster(file.cluster, keep - 1)?.ok_or(
+                FsError::Corrupt("cluster chain shorter than the file"),
+            )?;
+            if let Some(rest) = self.next_cluster(last)? {
+                self.set_fat_entry(last, FAT32_CLUSTER_MASK)?;
+                self.free_chain(rest)?;
+            }
+        }
+
+        file.size = len as u32;
+        self.update_entry(file)
+    }
+
+    // Write the first cluster and size of a file to its directory entry
+    fn update_entry(&mut self, file: &Entry) -> Result<(), FsError> {
+        let location = match file.location {
+            Some(location) => location,
+            None => return Ok(()),
+        };
+
+        let mut raw = self.read_dir_entry(location.dir, location.index)?;
+        raw[11] |= ATTR_ARCHIVE;
+        raw[20..22]
+            .copy_from_slice(&((file.cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(file.cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&file.size.to_le_bytes());
+
+        self.write_dir_entry(location.dir, location.index, &raw)
+    }
+}
+
+impl LongName {
+    fn new() -> Self {
+        Self {
+            units: [0; MAX_LFN_ENTRIES * LFN_CHARS],
+            checksum: 0,
+            next: 0,
+            count: 0,
+            valid: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.valid = false;
+    }
+
+    // Entries come last part first, with descending ordinals down to 1
+    fn push(&mut self, raw: &[u8]) {
+        let ordinal = raw[0] & LFN_ORDINAL_MASK;
+
+        if raw[0] & LFN_LAST != 0 {
+            self.valid = ordinal != 0 && ordinal as usize <= MAX_LFN_ENTRIES;
+            self.checksum = raw[13];
+            self.next = ordinal;
+            self.count = ordinal;
+        }
+
+        if !self.valid
+            || ordinal == 0
+            || ordinal != self.next
+            || raw[13] != self.checksum
+        {
+            self.valid = false;
+            return;
+        }
+
+        let part =
+            &mut self.units[(ordinal as usize - 1) * LFN_CHARS..][..LFN_CHARS];
+        for (unit, offset) in part.iter_mut().zip(LFN_OFFSETS) {
+            *unit = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        }
+        self.next -= 1;
+    }
+
+    // Decode the long name if it belongs to the short entry with `checksum`
+    fn decode(&mut self, checksum: u8, name: &mut Name) -> bool {
+        let complete =
+            self.valid && self.next == 0 && self.checksum == checksum;
+        self.valid = false;
+        if !complete {
+            return false;
+        }
+
+        let len = self.count as usize * LFN_CHARS;
+        let end = self.units[..len]
+            .iter()
+            .position(|&u| u == 0)
+            .unwrap_or(len);
+
+        *name = Name::empty();
+        for c in char::decode_utf16(self.units[..end].iter().copied()) {
+            if name.push(c.unwrap_or(char::REPLACEMENT_CHARACTER)).is_err() {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn is_fsinfo(sector: &Sector) -> bool {
+    u32_at(sector, 0) == FSINFO_LEAD_SIGNATURE
+        && u32_at(sector, 484) == FSINFO_STRUCT_SIGNATURE
+        && u32_at(sector, 508) == FSINFO_TRAIL_SIGNATURE
+}
+
+// Checksum of a short name, stored in its long name entries
+fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+// `NAME.EXT` of a short entry, in the case recorded in `case`
+fn short_name(short: &[u8; 11], case: u8, name: &mut Name) {
+    let push = |name: &mut Name, bytes: &[u8], lower: bool| {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let byte = if i == 0 && byte == ENTRY_E5 {
+                ENTRY_FREE
+            } else {
+                byte
+            };
+            let c = if lower {
+                byte.to_ascii_lowercase()
+            } else {
+                byte
+            };
+            // the OEM code page is unknown, bytes above 0x7F are taken as Latin-1
+            let _ = name.push(c as char);
+        }
+    };
+    let trim = |bytes: &[u8]| {
+        bytes.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1)
+    };
+
+    *name = Name::empty();
+    let base_len = trim(&short[0..8]);
+    let extension_len = trim(&short[8..11]);
+
+    push(name, &short[..base_len], case & NTRES_LOWER_BASE != 0);
+    if extension_len > 0 {
+        let _ = name.push('.');
+        push(
+            name,
+            &short[8..8 + extension_len],
+            case & NTRES_LOWER_EXTENSION != 0,
+        );
+    }
+}
+
+fn short_entry(
+    short: &[u8; 11],
+    case: u8,
+    attributes: u8,
+    cluster: u32,
+    size: u32,
+) -> [u8; DIR_ENTRY_SIZE] {
+    let mut raw = [0; DIR_ENTRY_SIZE];
+
+    raw[0..11].copy_from_slice(short);
+    raw[11] = attributes;
+    raw[12] = case;
+    raw[16..18].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    raw[18..20].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[24..26].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+
+    raw
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FsError::InvalidName);
+    }
+    if name.len() > super::MAX_NAME_LEN
+        || name.encode_utf16().count() > MAX_LFN_ENTRIES * LFN_CHARS - 5
+    {
+        return Err(FsError::NameTooLong);
+    }
+    // Windows drops trailing dots and spaces, such names could not be found again
+    if name.ends_with('.')
+        || name.ends_with(' ')
+        || name.chars().any(|c| c < ' ' || INVALID_CHARS.contains(c))
+    {
+        return Err(FsError::InvalidName);
+    }
+
+    Ok(())
+}
+
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_graphic()
+        && !INVALID_CHARS.contains(c)
+        && !INVALID_SHORT_CHARS.contains(c)
+}
+
+// The short name of a name that fits 8.3 with only the case of each part to record
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+
+    if base.is_empty()
+        || base.len() > 8
+        || extension.len() > 3
+        || !base.chars().chain(extension.chars()).all(is_short_char)
+    {
+        return None;
+    }
+
+    let mut case = 0;
+    for (part, flag) in
+        [(base, NTRES_LOWER_BASE), (extension, NTRES_LOWER_EXTENSION)]
+    {
+        let lower = part.chars().any(|c| c.is_ascii_lowercase());
+        let upper = part.chars().any(|c| c.is_ascii_uppercase());
+        match (lower, upper) {
+            (true, true) => return None,
+            (true, false) => case |= flag,
+            _ => {}
+        }
+    }
+
+    let mut short = [b' '; 11];
+    for (i, byte) in base.bytes().enumerate() {
+        short[i] = byte.to_ascii_uppercase();
+    }
+    for (i, byte) in extension.bytes().enumerate() {
+        short[8 + i] = byte.to_ascii_uppercase();
+    }
+    if short[0] == ENTRY_FREE {
+        short[0] = ENTRY_E5;
+    }
+
+    Some((short, case))
+}
+
+// Base and extension of a generated short name, before the numeric tail is added
+fn short_name_basis(name: &str) -> ([u8; 8], [u8; 3]) {
+    let name = name.trim_start_matches('.');
+    let (base, extension) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+
+    let convert = |part: &str, out: &mut [u8]| {
+        let chars = part.chars().filter(|&c| c != ' ' && c != '.');
+        for (byte, c) in out.iter_mut().zip(chars) {
+            *byte = if is_short_char(c) {
+                c.to_ascii_uppercase() as u8
+            } else {
+                b'_'
+            };
+        }
+    };
+
+    let mut short_base = [b' '; 8];
+    let mut short_extension = [b' '; 3];
+    convert(base, &mut short_base);
+    convert(extension, &mut short_extension);
+    if short_base[0] == b' ' {
+        short_base[0] = b'_';
+    }
+
+    (short_base, short_extension)
+}
@@ -0,0 +1,311 @@
+// Initial RAM file system
+//
+// The initramfs is a cpio "newc" archive that ships with the kernel and is unpacked into a mounted
+// file system at boot. Like on Linux, it may consist of several segments, each a plain archive or
+// a gzip or LZ4 compressed one, separated by zero padding. A decompressed segment may hold several
+// archives and an archive may span several segments. Later entries replace earlier files of the
+// same path.
+//
+// Regular files and directories are created, symbolic links and device nodes are skipped because
+// the RAM file system has no place for them.
+
+use super::{
+    cpio::{CpioReader, Entry, Event, MAX_PATH_LEN},
+    vfs::{vfs, Fd, OpenFlags},
+    FileKind, FsError,
+};
+use crate::compress::{gzip, lz4, DecompressError};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Start of a plain archive, the last digit tells whether it has checksums
+const CPIO_MAGIC: &[u8] = b"07070";
+
+// Where the entries go while an archive is read
+struct Unpacker<'a> {
+    root: &'a str,
+    // the path of the current entry below `root`
+    path: [u8; MAX_PATH_LEN * 2],
+    path_len: usize,
+    // the file being written
+    file: Option<Fd>,
+    summary: Summary,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// What has been unpacked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub directories: usize,
+    pub files: usize,
+    // the size of all files
+    pub bytes: u64,
+    // symbolic links, device nodes and paths leaving the root
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitramfsError {
+    Decompress(DecompressError),
+    Fs(FsError),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Unpack `archive` into the directory `root` of the virtual file system
+pub fn unpack(archive: &[u8], root: &str) -> Result<Summary, InitramfsError> {
+    let mut unpacker = Unpacker {
+        root: root.trim_end_matches('/'),
+        path: [0; MAX_PATH_LEN * 2],
+        path_len: 0,
+        file: None,
+        summary: Summary::default(),
+    };
+
+    let result = unpacker.unpack(archive);
+    // a file left open by an error
+    if let Some(fd) = unpacker.file.take() {
+        let _ = vfs().close(fd);
+    }
+
+    result.map(|_| unpacker.summary)
+}
+
+impl fmt::Display for InitramfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitramfsError::Decompress(e) => fmt::Display::fmt(e, f),
+            InitramfsError::Fs(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl From<InitramfsError> for &'static str {
+    fn from(error: InitramfsError) -> Self {
+        match error {
+            InitramfsError::Decompress(e) => e.into(),
+            InitramfsError::Fs(e) => e.into(),
+        }
+    }
+}
+
+impl From<DecompressError> for InitramfsError {
+    fn from(error: DecompressError) -> Self {
+        InitramfsError::Decompress(error)
+    }
+}
+
+impl From<FsError> for InitramfsError {
+    fn from(error: FsError) -> Self {
+        InitramfsError::Fs(error)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Unpacker<'_> {
+    fn unpack(&mut self, archive: &[u8]) -> Result<(), InitramfsError> {
+        // an archive may continue in the next segment, e.g. a gzip member
+        let mut reader = CpioReader::new();
+        let mut position = 0;
+
+        loop {
+            // padding between segments
+            match archive[position..].iter().position(|&b| b != 0) {
+                Some(len) => position += len,
+                None if position == 0 => return Ok(()),
+                None => return Ok(reader.finish()?),
+            }
+
+            let segment = &archive[position..];
+            position += if segment.starts_with(&gzip::MAGIC) {
+                gzip::decompress(segment, |data: &[u8]| {
+                    self.push_stream(&mut reader, data)
+                })?
+            } else if lz4::is_lz4(segment) {
+                lz4::decompress(segment, |data: &[u8]| {
+                    self.push_stream(&mut reader, data)
+                })?
+            } else if segment.starts_with(CPIO_MAGIC) {
+                if reader.is_done() {
+                    reader = CpioReader::new();
+                }
+                let len =
+                    reader.push(segment, &mut |event| self.handle(event))?;
+                // the archive ends with the input
+                if !reader.is_done() {
+                    return Ok(reader.finish()?);
+                }
+                len
+            } else {
+                return Err(DecompressError::BadMagic.into());
+            };
+        }
+    }
+
+    // Decompressed data, which may hold several archives separated by zero padding
+    fn push_stream(
+        &mut self,
+        reader: &mut CpioReader,
+        mut data: &[u8],
+    ) -> Result<(), InitramfsError> {
+        while !data.is_empty() {
+            if reader.is_done() {
+                match data.iter().position(|&b| b != 0) {
+                    Some(len) => data = &data[len..],
+                    None => return Ok(()),
+                }
+                *reader = CpioReader::new();
+            }
+
+            let len = reader.push(data, &mut |event| self.handle(event))?;
+            data = &data[len..];
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, event: Event) -> Result<(), InitramfsError> {
+        match event {
+            Event::Entry(entry) => self.start(entry),
+            Event::Data(data) => self.write(data),
+            Event::EntryEnd => match self.file.take() {
+                Some(fd) => Ok(vfs().close(fd)?),
+                None => Ok(()),
+            },
+        }
+    }
+
+    fn start(&mut self, entry: Entry) -> Result<(), InitramfsError> {
+        if !self.set_path(entry.path)? {
+            self.summary.skipped += 1;
+            return Ok(());
+        }
+        // `.`, which exists
+        if self.path_len == self.root.len() {
+            return Ok(());
+        }
+
+        if entry.is_dir() {
+            self.create_dir()?;
+            self.summary.directories += 1;
+        } else if entry.is_file() {
+            let flags =
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+            let fd = match vfs().open(self.path(), flags) {
+                Err(FsError::NotFound) => {
+                    self.create_parents()?;
+                    vfs().open(self.path(), flags)?
+                }
+                result => result?,
+            };
+            self.file = Some(fd);
+            self.summary.files += 1;
+            self.summary.bytes += entry.size as u64;
+        } else {
+            self.summary.skipped += 1;
+        }
+
+        Ok(())
+    }
+
+    // Data of a skipped entry is dropped
+    fn write(&mut self, mut data: &[u8]) -> Result<(), InitramfsError> {
+        let Some(fd) = self.file else {
+            return Ok(());
+        };
+
+        while !data.is_empty() {
+            match vfs().write(fd, data)? {
+                0 => return Err(FsError::NoSpace.into()),
+                len => data = &data[len..],
+            }
+        }
+
+        Ok(())
+    }
+
+    // Join the root and the path of an entry, returns false for paths that would leave the root
+    fn set_path(&mut self, path: &str) -> Result<bool, FsError> {
+        self.path_len = 0;
+        self.append(self.root)?;
+
+        for component in path.split('/') {
+            match component {
+                "" | "." => continue,
+                ".." => return Ok(false),
+                _ => {
+                    self.append("/")?;
+                    self.append(component)?;
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn append(&mut self, s: &str) -> Result<(), FsError> {
+        let end = self.path_len + s.len();
+        if end > self.path.len() {
+            return Err(FsError::NameTooLong);
+        }
+
+        self.path[self.path_len..end].copy_from_slice(s.as_bytes());
+        self.path_len = end;
+
+        Ok(())
+    }
+
+    fn path(&self) -> &str {
+        // built from `&str`s split at ASCII characters
+        core::str::from_utf8(&self.path[..self.path_len]).unwrap()
+    }
+
+    // Create the directory at the current path unless there is one
+    fn create_dir(&self) -> Result<(), FsError> {
+        let path = self.path();
+        let result = match vfs().create_dir(path) {
+            Err(FsError::NotFound) => {
+                self.create_parents()?;
+                vfs().create_dir(path)
+            }
+            result => result,
+        };
+
+        match result {
+            Err(FsError::AlreadyExists) => match vfs().metadata(path)?.kind {
+                FileKind::Directory => Ok(()),
+                _ => Err(FsError::NotADirectory),
+            },
+            result => result,
+        }
+    }
+
+    // Archives need not list the directories of their files
+    fn create_parents(&self) -> Result<(), FsError> {
+        let path = self.path();
+
+        for (i, _) in path.match_indices('/').skip(1) {
+            // the root itself exists
+            if i <= self.root.len() {
+                continue;
+            }
+            match vfs().create_dir(&path[..i]) {
+                Ok(()) | Err(FsError::AlreadyExists) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+// File system in memory
+//
+// A fixed number of nodes and data blocks, so that a `RamFs` can be a static. The blocks of a file
+// are chained in a table like the FAT of a FAT file system, node 0 is the root directory.
+//
+// An empty file system is all zeros, so that a static one goes to .bss instead of the kernel
+// image. Hence block numbers start at 1, 0 marks free blocks and empty files, and the root
+// directory is not marked in its node.
+
+use super::{interface::FileSystem, FileKind, FsError, Inode, Metadata, Name};
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Values in the block chain table besides the number of the next block
+const FREE_BLOCK: u16 = 0;
+const END_OF_CHAIN: u16 = 0xFFFF;
+
+// `first_block` of an empty file
+const NO_BLOCK: u16 = 0;
+
+const ROOT: usize = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    Unused,
+    File,
+    Directory,
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    kind: NodeKind,
+    parent: usize,
+    name: Name,
+    size: u64,
+    first_block: u16,
+}
+
+struct RamFsInner<const NODES: usize, const BLOCKS: usize> {
+    nodes: [Node; NODES],
+    // next block of block `i + 1`
+    next_block: [u16; BLOCKS],
+    data: [[u8; BLOCK_SIZE]; BLOCKS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const BLOCK_SIZE: usize = 512;
+
+// At most `NODES` files and directories, including the root, in `BLOCKS` blocks of data
+pub struct RamFs<const NODES: usize, const BLOCKS: usize> {
+    inner: IRQSafeNullLock<RamFsInner<NODES, BLOCKS>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<const NODES: usize, const BLOCKS: usize> RamFs<NODES, BLOCKS> {
+    pub const fn new() -> Self {
+        assert!(NODES > 0 && BLOCKS < END_OF_CHAIN as usize);
+
+        Self {
+            inner: IRQSafeNullLock::new(RamFsInner::new()),
+        }
+    }
+
+    // Free space in bytes
+    pub fn free_space(&self) -> u64 {
+        self.inner.lock(|fs| {
+            let free = fs
+                .next_block
+                .iter()
+                .filter(|&&next| next == FREE_BLOCK)
+                .count();
+
+            (free * BLOCK_SIZE) as u64
+        })
+    }
+}
+
+impl<const NODES: usize, const BLOCKS: usize> Default for RamFs<NODES, BLOCKS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl<const NODES: usize, const BLOCKS: usize> FileSystem
+    for RamFs<NODES, BLOCKS>
+{
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Inode {
+        ROOT as Inode
+    }
+
+    fn lookup(&self, dir: Inode, name: &str) -> Result<Inode, FsError> {
+        self.inner.lock(|fs| {
+            let dir = fs.directory(dir)?;
+
+            fs.find(dir, name)
+                .map(|node| node as Inode)
+                .ok_or(FsError::NotFound)
+        })
+    }
+
+    fn metadata(&self, inode: Inode) -> Result<Metadata, FsError> {
+        self.inner.lock(|fs| {
+            let kind = fs.kind(inode)?;
+
+            Ok(Metadata {
+                inode,
+                kind,
+                size: fs.nodes[inode as usize].size,
+            })
+        })
+    }
+
+    fn read_dir(
+        &self,
+        dir: Inode,
+        position: &mut u64,
+        name: &mut Name,
+    ) -> Result<Option<Inode>, FsError> {
+        self.inner.lock(|fs| {
+            let dir = fs.directory(dir)?;
+
+            let start = (*position).min(NODES as u64) as usize;
+            match (start..NODES).find(|&i| fs.is_child(i, dir)) {
+                Some(i) => {
+                    *name = fs.nodes[i].name;
+                    *position = i as u64 + 1;
+
+                    Ok(Some(i as Inode))
+                }
+                None => {
+                    *position = NODES as u64;
+
+                    Ok(None)
+                }
+            }
+        })
+    }
+
+    fn read(
+        &self,
+        inode: Inode,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        self.inner
+            .lock(|fs| fs.read(fs.file(inode)?, offset, buffer))
+    }
+
+    fn write(
+        &self,
+        inode: Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, FsError> {
+        self.inner
+            .lock(|fs| fs.write(fs.file(inode)?, offset, data))
+    }
+
+    fn truncate(&self, inode: Inode, len: u64) -> Result<(), FsError> {
+        self.inner.lock(|fs| fs.truncate(fs.file(inode)?, len))
+    }
+
+    fn create(
+        &self,
+        dir: Inode,
+        name: &str,
+        kind: FileKind,
+    ) -> Result<Inode, FsError> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/')
+        {
+            return Err(FsError::InvalidName);
+        }
+        let kind = match kind {
+            FileKind::File => NodeKind::File,
+            FileKind::Directory => NodeKind::Directory,
+            _ => return Err(FsError::Unsupported),
+        };
+        let name = Name::new(name)?;
+
+        self.inner.lock(|fs| {
+            let dir = fs.directory(dir)?;
+            if fs.find(dir, name.as_str()).is_some() {
+                return Err(FsError::AlreadyExists);
+            }
+
+            let free = (0..NODES)
+                .find(|&i| i != ROOT && fs.nodes[i].kind == NodeKind::Unused)
+                .ok_or(FsError::NoSpace)?;
+            fs.nodes[free] = Node {
+                kind,
+                parent: dir,
+                name,
+                size: 0,
+                first_block: NO_BLOCK,
+            };
+
+            Ok(free as Inode)
+        })
+    }
+
+    fn remove(&self, dir: Inode, name: &str) -> Result<(), FsError> {
+        self.inner.lock(|fs| {
+            let dir = fs.directory(dir)?;
+            let node = fs.find(dir, name).ok_or(FsError::NotFound)?;
+
+            if (0..NODES).any(|i| fs.is_child(i, node)) {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+
+            let first = fs.nodes[node].first_block;
+            if first != NO_BLOCK {
+                fs.free_chain(first);
+            }
+            fs.nodes[node] = Node::UNUSED;
+
+            Ok(())
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Node {
+    const UNUSED: Node = Node {
+        kind: NodeKind::Unused,
+        parent: ROOT,
+        name: Name::empty(),
+        size: 0,
+        first_block: NO_BLOCK,
+    };
+}
+
+impl<const NODES: usize, const BLOCKS: usize> RamFsInner<NODES, BLOCKS> {
+    const fn new() -> Self {
+        Self {
+            nodes: [Node::UNUSED; NODES],
+            next_block: [FREE_BLOCK; BLOCKS],
+            data: [[0; BLOCK_SIZE]; BLOCKS],
+        }
+    }
+
+    fn kind(&self, inode: Inode) -> Result<FileKind, FsError> {
+        if inode == ROOT as Inode {
+            return Ok(FileKind::Directory);
+        }
+
+        match self.nodes.get(inode as usize).map(|node| node.kind) {
+            Some(NodeKind::File) => Ok(FileKind::File),
+            Some(NodeKind::Directory) => Ok(FileKind::Directory),
+            _ => Err(FsError::NotFound),
+        }
+    }
+
+    fn directory(&self, inode: Inode) -> Result<usize, FsError> {
+        match self.kind(inode)? {
+            FileKind::Directory => Ok(inode as usize),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn file(&self, inode: Inode) -> Result<usize, FsError> {
+        match self.kind(inode)? {
+            FileKind::Directory => Err(FsError::IsADirectory),
+            _ => Ok(inode as usize),
+        }
+    }
+
+    fn is_child(&self, node: usize, dir: usize) -> bool {
+        node != ROOT
+            && self.nodes[node].kind != NodeKind::Unused
+            && self.nodes[node].parent == dir
+    }
+
+    fn find(&self, dir: usize, name: &str) -> Option<usize> {
+        (0..NODES).find(|&i| {
+            self.is_child(i, dir) && self.nodes[i].name.as_str() == name
+        })
+    }
+
+    fn next(&self, block: u16) -> u16 {
+        self.next_block[block as usize - 1]
+    }
+
+    fn set_next(&mut self, block: u16, next: u16) {
+        self.next_block[block as usize - 1] = next;
+    }
+
+    fn data(&mut self, block: u16) -> &mut [u8; BLOCK_SIZE] {
+        &mut self.data[block as usize - 1]
+    }
+
+    // The `n`th block of a file, `None` past the end of its chain
+    fn nth_block(&self, node: usize, n: u64) -> Option<u16> {
+        let mut block = self.nodes[node].first_block;
+        if block == NO_BLOCK {
+            return None;
+        }
+
+        for _ in 0..n {
+            match self.next(block) {
+                END_OF_CHAIN => return None,
+                next => block = next,
+            }
+        }
+
+        Some(block)
+    }
+
+    // Take a free block, zeroed, as the end of a chain and append it to `previous`
+    fn allocate(&mut self, previous: Option<u16>) -> Result<u16, FsError> {
+        let block = self
+            .next_block
+            .iter()
+            .position(|&next| next == FREE_BLOCK)
+            .ok_or(FsError::NoSpace)? as u16
+            + 1;
+
+        self.set_next(block, END_OF_CHAIN);
+        self.data(block).fill(0);
+        if let Some(previous) = previous {
+            self.set_next(previous, block);
+        }
+
+        Ok(block)
+    }
+
+    fn free_chain(&mut self, first: u16) {
+        let mut block = first;
+
+        loop {
+            let next = self.next(block);
+            self.set_next(block, FREE_BLOCK);
+            if next == END_OF_CHAIN {
+                break;
+            }
+            block = next;
+        }
+    }
+
+    fn read(
+        &mut self,
+        node: usize,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, FsError> {
+        let size = self.nodes[node].size;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = buffer.len().min((size - offset) as usize);
+        let mut block = self
+            .nth_block(node, offset / BLOCK_SIZE as u64)
+            .ok_or(FsError::Corrupt("ramfs: file shorter than its size"))?;
+        let mut done = 0;
+
+        while done < len {
+            let start = (offset as usize + done) % BLOCK_SIZE;
+            let count = (len - done).min(BLOCK_SIZE - start);
+
+            buffer[done..done + count]
+                .copy_from_slice(&self.data(block)[start..start + count]);
+            done += count;
+            if done < len {
+                block = self.next(block);
+            }
+        }
+
+        Ok(len)
+    }
+
+    // Write as much as fits, a gap between the end of the file and `offset` reads as zeros
+    fn write(
+        &mut self,
+        node: usize,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<usize, FsError> {
+        let mut done = 0;
+
+        while done < data.len() {
+            let position = offset + done as u64;
+            let block = match self
+                .block_for_write(node, position / BLOCK_SIZE as u64)
+            {
+                Ok(block) => block,
+                Err(e) if done == 0 => return Err(e),
+                Err(_) => break,
+            };
+
+            let start = (position % BLOCK_SIZE as u64) as usize;
+            let count = (data.len() - done).min(BLOCK_SIZE - start);
+            self.data(block)[start..start + count]
+                .copy_from_slice(&data[done..done + count]);
+            done += count;
+        }
+
+        let end = offset + done as u64;
+        if end > self.nodes[node].size {
+            self.nodes[node].size = end;
+        }
+
+        Ok(done)
+    }
+
+    // The `n`th block of a file, allocating the blocks up to it
+    fn block_for_write(&mut self, node: usize, n: u64) -> Result<u16, FsError> {
+        let mut block = self.nodes[node].first_block;
+        if block == NO_BLOCK {
+            block = self.allocate(None)?;
+            self.nodes[node].first_block = block;
+        }
+
+        for _ in 0..n {
+            block = match self.next(block) {
+                END_OF_CHAIN => self.allocate(Some(block))?,
+                next => next,
+            };
+        }
+
+        Ok(block)
+    }
+
+    fn truncate(&mut self, node: usize, len: u64) -> Result<(), FsError> {
+        let keep = len.div_ceil(BLOCK_SIZE as u64);
+
+        if len >= self.nodes[node].size {
+            if keep > 0 {
+                self.block_for_write(node, keep - 1)?;
+            }
+        } else if keep == 0 {
+            let first = self.nodes[node].first_block;
+            if first != NO_BLOCK {
+                self.free_chain(first);
+                self.nodes[node].first_block = NO_BLOCK;
+            }
+        } else {
+            let last = self
+                .nth_block(node, keep - 1)
+                .ok_or(FsError::Corrupt("ramfs: file shorter than its size"))?;
+            let rest = self.next(last);
+            if rest != END_OF_CHAIN {
+                self.free_chain(rest);
+                self.set_next(last, END_OF_CHAIN);
+            }
+
+            // a later extension must read zeros
+            let end = (len % BLOCK_SIZE as u64) as usize;
+            if end != 0 {
+                self.data(last)[end..].fill(0);
+            }
+        }
+
+        self.nodes[node].size = len;
+
+        Ok(())
+    }
+}
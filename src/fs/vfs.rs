@@ -0,0 +1,511 @@
+// Virtual file system
+//
+// File systems are mounted on directories of one tree, the first one on `/`. A place in the tree
+// is a `Dentry`, a mount and an inode of its file system. Paths are resolved one component at a
+// time, stepping into a file system where one is mounted and keeping the way back for `..`.
+//
+// Open files are slots of a fixed table and are referred to by their `Fd`. File system calls are
+// made without holding the lock of the table, since reading a device may wait.
+
+use super::{
+    components, interface::FileSystem, split_last, FileKind, FsError, Inode,
+    Metadata, Name,
+};
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+use core::ops;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAX_MOUNTS: usize = 8;
+
+// Deepest path that `..` can go back from
+const MAX_DEPTH: usize = 32;
+
+// The file system mounted on `/`
+const ROOT_MOUNT: usize = 0;
+
+// A file or directory in the tree
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Dentry {
+    mount: usize,
+    inode: Inode,
+}
+
+#[derive(Clone, Copy)]
+struct Mount {
+    fs: &'static (dyn FileSystem + Sync),
+    // the directory it is mounted on, `None` for the root file system
+    point: Option<Dentry>,
+}
+
+#[derive(Clone, Copy)]
+struct OpenFile {
+    dentry: Dentry,
+    flags: OpenFlags,
+    // position in the file, or in the directory for `read_dir`
+    offset: u64,
+}
+
+struct VfsInner {
+    mounts: [Option<Mount>; MAX_MOUNTS],
+    // where each file system is mounted, for `print_mounts`
+    mount_paths: [Name; MAX_MOUNTS],
+    files: [Option<OpenFile>; MAX_OPEN_FILES],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const MAX_OPEN_FILES: usize = 32;
+
+// Number of an open file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fd(pub usize);
+
+// How a file is opened, combined with `|`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+pub struct Vfs {
+    inner: IRQSafeNullLock<VfsInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static VFS: Vfs = Vfs::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the virtual file system
+pub fn vfs() -> &'static Vfs {
+    &VFS
+}
+
+impl OpenFlags {
+    pub const READ: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    pub const READ_WRITE: Self = Self(Self::READ.0 | Self::WRITE.0);
+    // create the file if it does not exist
+    pub const CREATE: Self = Self(1 << 2);
+    // empty the file when it is opened for writing
+    pub const TRUNCATE: Self = Self(1 << 3);
+    // write at the end of the file
+    pub const APPEND: Self = Self(1 << 4);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl Vfs {
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(VfsInner {
+                mounts: [None; MAX_MOUNTS],
+                mount_paths: [Name::empty(); MAX_MOUNTS],
+                files: [None; MAX_OPEN_FILES],
+            }),
+        }
+    }
+
+    // Mount `fs` on the directory `path`, the first file system on `/`
+    pub fn mount(
+        &self,
+        path: &str,
+        fs: &'static (dyn FileSystem + Sync),
+    ) -> Result<(), FsError> {
+        let path_name = Name::new(path)?;
+
+        let point =
+            if self.inner.lock(|inner| inner.mounts[ROOT_MOUNT].is_none()) {
+                if components(path).next().is_some() {
+                    return Err(FsError::NotFound);
+                }
+                None
+            } else {
+                let dentry = self.resolve(path)?;
+                if self.fs(dentry.mount)?.metadata(dentry.inode)?.kind
+                    != FileKind::Directory
+                {
+                    return Err(FsError::NotADirectory);
+                }
+                // the root of a mounted file system is a mount point already
+                if self.is_mount_root(dentry)? {
+                    return Err(FsError::Busy);
+                }
+                Some(dentry)
+            };
+
+        self.inner.lock(|inner| {
+            let slot = match point {
+                None => ROOT_MOUNT,
+                Some(_) => inner
+                    .mounts
+                    .iter()
+                    .position(|mount| mount.is_none())
+                    .ok_or(FsError::TooManyMounts)?,
+            };
+
+            inner.mounts[slot] = Some(Mount { fs, point });
+            inner.mount_paths[slot] = path_name;
+
+            Ok(())
+        })
+    }
+
+    // Unmount the file system mounted on `path` after syncing it
+    pub fn unmount(&self, path: &str) -> Result<(), FsError> {
+        let dentry = self.resolve(path)?;
+        if !self.is_mount_root(dentry)? {
+            return Err(FsError::InvalidArgument);
+        }
+        let mount = dentry.mount;
+        if mount == ROOT_MOUNT {
+            return Err(FsError::Busy);
+        }
+
+        let fs = self.inner.lock(|inner| {
+            let in_use = inner
+                .files
+                .iter()
+                .flatten()
+                .any(|file| file.dentry.mount == mount)
+                || inner.mounts.iter().flatten().any(|other| {
+                    other.point.is_some_and(|point| point.mount == mount)
+                });
+            if in_use {
+                return Err(FsError::Busy);
+            }
+
+            let fs = inner.mounts[mount].map(|mount| mount.fs);
+            inner.mounts[mount] = None;
+
+            fs.ok_or(FsError::InvalidArgument)
+        })?;
+
+        fs.sync()
+    }
+
+    pub fn open(&self, path: &str, flags: OpenFlags) -> Result<Fd, FsError> {
+        let dentry = match self.resolve(path) {
+            Ok(dentry) => dentry,
+            Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+                self.create(path, FileKind::File)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        let fs = self.fs(dentry.mount)?;
+        let metadata = fs.metadata(dentry.inode)?;
+        let writing = flags.contains(OpenFlags::WRITE);
+        if metadata.kind == FileKind::Directory && writing {
+            return Err(FsError::IsADirectory);
+        }
+        if writing && flags.contains(OpenFlags::TRUNCATE) {
+            fs.truncate(dentry.inode, 0)?;
+        }
+
+        self.inner.lock(|inner| {
+            let fd = inner
+                .files
+                .iter()
+                .position(|file| file.is_none())
+                .ok_or(FsError::TooManyOpenFiles)?;
+            inner.files[fd] = Some(OpenFile {
+                dentry,
+                flags,
+                offset: 0,
+            });
+
+            Ok(Fd(fd))
+        })
+    }
+
+    pub fn close(&self, fd: Fd) -> Result<(), FsError> {
+        self.inner.lock(|inner| {
+            match inner.files.get_mut(fd.0).and_then(|file| file.take()) {
+                Some(_) => Ok(()),
+                None => Err(FsError::BadFileDescriptor),
+            }
+        })
+    }
+
+    // Read from the position of `fd` and advance it, returns 0 at the end of the file
+    pub fn read(&self, fd: Fd, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let (file, fs) = self.file(fd)?;
+        if !file.flags.contains(OpenFlags::READ) {
+            return Err(FsError::BadFileDescriptor);
+        }
+
+        let len = fs.read(file.dentry.inode, file.offset, buffer)?;
+        self.set_offset(fd, file.offset + len as u64);
+
+        Ok(len)
+    }
+
+    // Write at the position of `fd`, or at the end of the file for `OpenFlags::APPEND`
+    pub fn write(&self, fd: Fd, data: &[u8]) -> Result<usize, FsError> {
+        let (file, fs) = self.file(fd)?;
+        if !file.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::BadFileDescriptor);
+        }
+
+        let offset = if file.flags.contains(OpenFlags::APPEND) {
+            fs.metadata(file.dentry.inode)?.size
+        } else {
+            file.offset
+        };
+        let len = fs.write(file.dentry.inode, offset, data)?;
+        self.set_offset(fd, offset + len as u64);
+
+        Ok(len)
+    }
+
+    // Move the position of `fd`, returns the new position
+    pub fn seek(&self, fd: Fd, to: SeekFrom) -> Result<u64, FsError> {
+        let (file, fs) = self.file(fd)?;
+
+        let offset = match to {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => file.offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => fs
+                .metadata(file.dentry.inode)?
+                .size
+                .checked_add_signed(delta),
+        }
+        .ok_or(FsError::InvalidArgument)?;
+        self.set_offset(fd, offset);
+
+        Ok(offset)
+    }
+
+    // The next entry of the directory open as `fd`, its name goes to `name`
+    pub fn read_dir(
+        &self,
+        fd: Fd,
+        name: &mut Name,
+    ) -> Result<Option<Metadata>, FsError> {
+        let (file, fs) = self.file(fd)?;
+
+        let mut position = file.offset;
+        let inode = fs.read_dir(file.dentry.inode, &mut position, name)?;
+        self.set_offset(fd, position);
+
+        match inode {
+            Some(inode) => {
+                // a mount point shows the root of the mounted file system
+                let dentry = self.enter_mount(Dentry {
+                    mount: file.dentry.mount,
+                    inode,
+                });
+
+                Ok(Some(self.fs(dentry.mount)?.metadata(dentry.inode)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn fstat(&self, fd: Fd) -> Result<Metadata, FsError> {
+        let (file, fs) = self.file(fd)?;
+
+        fs.metadata(file.dentry.inode)
+    }
+
+    pub fn metadata(&self, path: &str) -> Result<Metadata, FsError> {
+        let dentry = self.resolve(path)?;
+
+        self.fs(dentry.mount)?.metadata(dentry.inode)
+    }
+
+    pub fn create_dir(&self, path: &str) -> Result<(), FsError> {
+        self.create(path, FileKind::Directory).map(|_| ())
+    }
+
+    // Remove a file or an empty directory that is neither open nor a mount point
+    pub fn remove(&self, path: &str) -> Result<(), FsError> {
+        let (parent_path, name) = split_last(path);
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(FsError::InvalidName);
+        }
+
+        let dentry = self.resolve(path)?;
+        if self.is_mount_root(dentry)? {
+            return Err(FsError::Busy);
+        }
+        let open = self.inner.lock(|inner| {
+            inner
+                .files
+                .iter()
+                .flatten()
+                .any(|file| file.dentry == dentry)
+        });
+        if open {
+            return Err(FsError::Busy);
+        }
+
+        let parent = self.resolve(parent_path)?;
+        self.fs(parent.mount)?.remove(parent.inode, name)
+    }
+
+    // Write cached data of all file systems to their devices
+    pub fn sync(&self) -> Result<(), FsError> {
+        let mounts = self.inner.lock(|inner| inner.mounts);
+
+        let mut result = Ok(());
+        for mount in mounts.iter().flatten() {
+            if let Err(e) = mount.fs.sync() {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    pub fn print_mounts(&self) {
+        use crate::info;
+
+        let (mounts, paths) =
+            self.inner.lock(|inner| (inner.mounts, inner.mount_paths));
+        for (mount, path) in mounts.iter().zip(paths.iter()) {
+            if let Some(mount) = mount {
+                info!("      {} on {}", mount.fs.name(), path);
+            }
+        }
+    }
+}
+
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Vfs {
+    fn fs(
+        &self,
+        mount: usize,
+    ) -> Result<&'static (dyn FileSystem + Sync), FsError> {
+        self.inner.lock(|inner| {
+            inner.mounts[mount]
+                .map(|mount| mount.fs)
+                .ok_or(FsError::NotFound)
+        })
+    }
+
+    fn file(
+        &self,
+        fd: Fd,
+    ) -> Result<(OpenFile, &'static (dyn FileSystem + Sync)), FsError> {
+        self.inner.lock(|inner| {
+            let file = inner
+                .files
+                .get(fd.0)
+                .copied()
+                .flatten()
+                .ok_or(FsError::BadFileDescriptor)?;
+            let mount = inner.mounts[file.dentry.mount]
+                .ok_or(FsError::BadFileDescriptor)?;
+
+            Ok((file, mount.fs))
+        })
+    }
+
+    fn set_offset(&self, fd: Fd, offset: u64) {
+        self.inner.lock(|inner| {
+            if let Some(Some(file)) = inner.files.get_mut(fd.0) {
+                file.offset = offset;
+            }
+        });
+    }
+
+    // The root of the file system mounted on `dentry`, or `dentry` itself
+    fn enter_mount(&self, dentry: Dentry) -> Dentry {
+        self.inner.lock(|inner| {
+            inner
+                .mounts
+                .iter()
+                .enumerate()
+                .find_map(|(i, mount)| match mount {
+                    Some(mount) if mount.point == Some(dentry) => {
+                        Some(Dentry {
+                            mount: i,
+                            inode: mount.fs.root(),
+                        })
+                    }
+                    _ => None,
+                })
+                .unwrap_or(dentry)
+        })
+    }
+
+    fn is_mount_root(&self, dentry: Dentry) -> Result<bool, FsError> {
+        Ok(self.fs(dentry.mount)?.root() == dentry.inode)
+    }
+
+    fn resolve(&self, path: &str) -> Result<Dentry, FsError> {
+        let root = Dentry {
+            mount: ROOT_MOUNT,
+            inode: self.fs(ROOT_MOUNT)?.root(),
+        };
+        // the directories on the way, for `..`
+        let mut way = [root; MAX_DEPTH];
+        let mut depth = 1;
+
+        for component in components(path) {
+            if component == ".." {
+                depth = (depth - 1).max(1);
+                continue;
+            }
+            if depth == MAX_DEPTH {
+                return Err(FsError::NameTooLong);
+            }
+
+            let dir = way[depth - 1];
+            let inode = self.fs(dir.mount)?.lookup(dir.inode, component)?;
+            way[depth] = self.enter_mount(Dentry {
+                mount: dir.mount,
+                inode,
+            });
+            depth += 1;
+        }
+
+        Ok(way[depth - 1])
+    }
+
+    fn create(&self, path: &str, kind: FileKind) -> Result<Dentry, FsError> {
+        let (parent_path, name) = split_last(path);
+        let parent = self.resolve(parent_path)?;
+        let inode = self.fs(parent.mount)?.create(parent.inode, name, kind)?;
+
+        Ok(Dentry {
+            mount: parent.mount,
+            inode,
+        })
+    }
+}
@@ -0,0 +1,224 @@
+// Input events
+//
+// Input devices push typed events into one queue: keys going down and up, the characters they
+// type, buttons and pointer motion. Every consumer subscribes on its own and sees every event
+// pushed after it subscribed, so the consoles, a shell and a GUI can all follow the same input.
+//
+// The queue is a ring of the last `QUEUE_SIZE` events. Events are pushed from IRQ handlers as well
+// as from the main loop. A subscriber that falls more than a ring behind loses the oldest events,
+// which it can tell from `Subscriber::lost()`.
+
+use crate::{
+    cpu,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+    usb,
+};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const QUEUE_SIZE: usize = 128;
+
+struct InputQueueInner {
+    // event `n` lives in `events[n % QUEUE_SIZE]`
+    events: [InputEvent; QUEUE_SIZE],
+    // number of events pushed so far
+    next: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Modifier keys held with a key, bits as in USB HID boot reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(pub u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Serial,
+    // the address of the device
+    Usb(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // `key` is the USB HID usage on the keyboard page, also for the modifier keys 0xE0-0xE7.
+    // Held keys repeat as characters only.
+    KeyDown {
+        key: u8,
+        modifiers: Modifiers,
+    },
+    KeyUp {
+        key: u8,
+        modifiers: Modifiers,
+    },
+    // What was typed, escape sequences one character at a time
+    Character(char),
+    // Buttons numbered from 0, the left mouse button first
+    Button {
+        button: u8,
+        pressed: bool,
+    },
+    RelativePointer {
+        dx: i16,
+        dy: i16,
+        wheel: i8,
+    },
+    // In units of the device, up to `max_x` and `max_y`
+    AbsolutePointer {
+        x: u16,
+        y: u16,
+        max_x: u16,
+        max_y: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    // uptime when it was pushed
+    pub time: Duration,
+    pub source: Source,
+    pub event: Event,
+}
+
+pub struct InputQueue {
+    inner: IRQSafeNullLock<InputQueueInner>,
+}
+
+// A consumer of the queue, reading from where it is
+pub struct Subscriber {
+    queue: &'static InputQueue,
+    position: u64,
+    lost: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static INPUT_QUEUE: InputQueue = InputQueue::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the input event queue
+pub fn input_queue() -> &'static InputQueue {
+    &INPUT_QUEUE
+}
+
+impl Modifiers {
+    // left and right
+    pub const CTRL: u8 = 0x11;
+    pub const SHIFT: u8 = 0x22;
+    pub const ALT: u8 = 0x44;
+    pub const GUI: u8 = 0x88;
+
+    pub fn ctrl(self) -> bool {
+        self.0 & Self::CTRL != 0
+    }
+
+    pub fn shift(self) -> bool {
+        self.0 & Self::SHIFT != 0
+    }
+
+    pub fn alt(self) -> bool {
+        self.0 & Self::ALT != 0
+    }
+
+    pub fn gui(self) -> bool {
+        self.0 & Self::GUI != 0
+    }
+}
+
+impl InputQueue {
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(InputQueueInner {
+                events: [InputEvent {
+                    time: Duration::ZERO,
+                    source: Source::Serial,
+                    event: Event::Character('\0'),
+                }; QUEUE_SIZE],
+                next: 0,
+            }),
+        }
+    }
+
+    // Add an event, callable from IRQ handlers. The oldest event is overwritten when the ring is
+    // full.
+    pub fn push(&self, source: Source, event: Event) {
+        let event = InputEvent {
+            time: time::time_manager().uptime(),
+            source,
+            event,
+        };
+
+        self.inner.lock(|inner| {
+            inner.events[(inner.next % QUEUE_SIZE as u64) as usize] = event;
+            inner.next += 1;
+        });
+    }
+
+    // Start reading at the next event that is pushed
+    pub fn subscribe(&'static self) -> Subscriber {
+        Subscriber {
+            queue: self,
+            position: self.inner.lock(|inner| inner.next),
+            lost: 0,
+        }
+    }
+}
+
+impl Default for InputQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Subscriber {
+    // The next event, `None` if there is none yet
+    pub fn try_next(&mut self) -> Option<InputEvent> {
+        self.queue.inner.lock(|inner| {
+            // skip what has been overwritten
+            let oldest = inner.next.saturating_sub(QUEUE_SIZE as u64);
+            if self.position < oldest {
+                self.lost += oldest - self.position;
+                self.position = oldest;
+            }
+
+            if self.position == inner.next {
+                return None;
+            }
+
+            let event =
+                inner.events[(self.position % QUEUE_SIZE as u64) as usize];
+            self.position += 1;
+
+            Some(event)
+        })
+    }
+
+    // Wait for the next event. The main loop stops while it waits here, so the USB devices are
+    // polled here instead, the serial port pushes from its IRQ handler.
+    pub fn wait(&mut self) -> InputEvent {
+        loop {
+            if let Some(event) = self.try_next() {
+                return event;
+            }
+
+            // the drivers keep to the poll interval of their devices
+            usb::usb().poll();
+            cpu::nop();
+        }
+    }
+
+    // Events missed because the subscriber fell behind
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+}
@@ -6,16 +6,25 @@
 mod panic_wait;
 mod synchronization;
 
+pub mod block;
 pub mod bsp;
+pub mod checksum;
+pub mod clock;
+pub mod cmdline;
+pub mod compress;
 pub mod console;
 pub mod cpu;
 pub mod driver;
 pub mod exception;
+pub mod font;
+pub mod fs;
+pub mod input;
 pub mod memory;
 pub mod print;
 pub mod screen;
 pub mod state;
 pub mod time;
+pub mod usb;
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
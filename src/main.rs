@@ -2,8 +2,19 @@
 #![no_main]
 #![no_std]
 
+use clock::{interface::CpuGovernor, CpuPolicy};
 use exception::asynchronous::interface::IRQManager;
-use libkernel::{bsp, driver, exception, info, memory, state, time, warn};
+use libkernel::{
+    block, bsp, clock, cmdline, console, driver, exception, font, fs, info,
+    memory, screen, state, time, usb, warn,
+};
+
+// The file systems go to the SD card sector by sector
+type SdCache<'a> = block::cache::BufferCache<
+    'a,
+    64,
+    { bsp::device_driver::SDHCI::BLOCK_SIZE },
+>;
 
 //-------------------------------------------------------------------------------------------------
 // Kernel code
@@ -20,6 +31,9 @@ unsafe fn kernel_init() -> ! {
 
     exception::handling_init();
 
+    // before the drivers, which read their parameters at init
+    let cmdline = bsp::cmdline::init();
+
     // Initialize all device
     for (_, i) in bsp::driver::driver_manager()
         .all_device_drivers()
@@ -33,6 +47,20 @@ unsafe fn kernel_init() -> ! {
         }
     }
     bsp::driver::driver_manager().post_device_driver_init();
+
+    match cmdline {
+        Ok(()) => bsp::cmdline::report(),
+        Err(e) => warn!("Reading the kernel command line failed: {}", e),
+    }
+
+    if let Err(e) = bsp::board::init() {
+        warn!("Board identification failed: {}", e);
+    }
+    if let Err(e) =
+        bsp::clock::cpu_governor().set_policy(CpuPolicy::Performance)
+    {
+        warn!("Setting the CPU clock failed: {}", e);
+    }
     // println! is usable from here on
     // Trasmit from unsafe to safe
     // Let device drivers register and enable their handlers with the interrupt controller.
@@ -52,6 +80,7 @@ unsafe fn kernel_init() -> ! {
 }
 
 fn kernel_main() -> ! {
+    use console::interface::Write;
     use core::time::Duration;
     use driver::interface::DriverManager;
     use time::interface::TimeManager;
@@ -62,6 +91,19 @@ fn kernel_main() -> ! {
         env!("CARGO_PKG_VERSION")
     );
     info!("Booting on: {}", bsp::board_name());
+    cmdline::with_command_line(|line| info!("Command line: {}", line));
+    if let Some(board_info) = bsp::board::board_info() {
+        info!("Board:");
+        board_info.print();
+    }
+    match bsp::clock::cpu_governor().cpu_rate() {
+        Ok(rate) => info!("CPU clock: {} MHz", rate / 1_000_000),
+        Err(e) => warn!("CPU clock: {}", e),
+    }
+    if let Some(policy) = bsp::clock::cpu_governor().policy() {
+        info!("CPU frequency policy: {}", policy);
+    }
+    info!("Frame buffer mode: {}", bsp::driver::FRAMEBUFFER.mode());
 
     let (_, privilege_level) = exception::current_privilege_level();
     info!("Current privilege level: {}", privilege_level);
@@ -86,6 +128,56 @@ fn kernel_main() -> ! {
     info!("Registered IRQ handlers:");
     bsp::exception::asynchronous::irq_manager().print_handler();
 
+    match bsp::fs::init() {
+        Ok(()) => {
+            info!("Mounted file systems:");
+            fs::vfs::vfs().print_mounts();
+        }
+        Err(e) => warn!("Mounting the file systems failed: {}", e),
+    }
+
+    // fonts from `font=` usually come from the initramfs
+    font::file::load();
+    screen::vt::console(screen::vt::active()).redraw();
+    info!("Font fallback chain:");
+    font::font_manager().print_chain();
+
+    match block::partition::PartitionTable::scan(bsp::driver::sd_card()) {
+        Ok(table) => {
+            info!("SD card partitions:");
+            for partition in table.iter() {
+                info!("      {}", partition);
+            }
+
+            if let Some(partition) = table.iter().find(|p| p.kind().is_fat()) {
+                let cache = SdCache::new(partition);
+                match fs::fat::FatFileSystem::mount(&cache) {
+                    Ok(fs) => list_root(partition, &fs),
+                    Err(e) => warn!("{}: {}", partition.name(), e),
+                }
+            }
+            if let Some(partition) = table
+                .iter()
+                .find(|p| p.kind() == block::partition::PartitionKind::Linux)
+            {
+                let cache = SdCache::new(partition);
+                match fs::ext2::Ext2FileSystem::mount(&cache) {
+                    Ok(fs) => list_root(partition, &fs),
+                    Err(e) => warn!("{}: {}", partition.name(), e),
+                }
+            }
+        }
+        Err(e) => warn!("SD card partitions: {}", e),
+    }
+
+    match usb::usb().init(bsp::driver::usb_host()) {
+        Ok(()) => {
+            info!("USB devices:");
+            usb::usb().print_devices();
+        }
+        Err(e) => warn!("USB: {}", e),
+    }
+
     // Test a failing timer case.
     time::time_manager().spin_for(Duration::from_nanos(1));
 
@@ -103,7 +195,33 @@ fn kernel_main() -> ! {
     // }
 
     loop {
-        info!("Spinning for 1 second");
-        time::time_manager().spin_for(Duration::from_secs(1));
+        bsp::thermal::thermal_monitor().poll();
+        usb::usb().poll();
+        screen::vt::take_screenshot(|c| bsp::console::console().write_char(c));
+        // often enough for keyboards
+        time::time_manager().spin_for(Duration::from_millis(10));
+    }
+}
+
+// Log the root directory of the file system on a partition
+fn list_root(
+    partition: &block::partition::Partition,
+    fs: &dyn fs::interface::FileSystem,
+) {
+    use fs::{FileKind, Name};
+
+    info!("{}: {}", partition.name(), fs.name());
+
+    let mut position = 0;
+    let mut name = Name::empty();
+    while let Ok(Some(inode)) = fs.read_dir(fs.root(), &mut position, &mut name)
+    {
+        match fs.metadata(inode) {
+            Ok(metadata) if metadata.kind == FileKind::Directory => {
+                info!("      {}/", name)
+            }
+            Ok(metadata) => info!("      {} ({} bytes)", name, metadata.size),
+            Err(e) => warn!("      {}: {}", name, e),
+        }
     }
 }
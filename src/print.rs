@@ -1,10 +1,71 @@
-use crate::{bsp, screen};
+use crate::cmdline::{self, interface::Value};
+use crate::screen::{self, vt};
+use crate::{bsp, console};
 use core::fmt;
 
 //-------------------------------------------------------------------------------------------------
 // Public Deginitions
 //-------------------------------------------------------------------------------------------------
 
+// Messages printed with `loglevel=`, each level includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    None,
+    Warn,
+    Info,
+}
+
+// Where the kernel log goes, selected with `console=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleOutput {
+    Uart,
+    FrameBuffer,
+    Both,
+}
+
+//-------------------------------------------------------------------------------------------------
+// Global instances
+//-------------------------------------------------------------------------------------------------
+
+pub static LOGLEVEL: cmdline::Param<LogLevel> =
+    cmdline::Param::new("loglevel", LogLevel::Info);
+
+pub static CONSOLE: cmdline::Param<ConsoleOutput> =
+    cmdline::Param::new("console", ConsoleOutput::FrameBuffer);
+
+//-------------------------------------------------------------------------------------------------
+// Public Code
+//-------------------------------------------------------------------------------------------------
+
+impl Value for LogLevel {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(LogLevel::None),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            _ => None,
+        }
+    }
+}
+
+impl Value for ConsoleOutput {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "uart" => Some(ConsoleOutput::Uart),
+            "fb" => Some(ConsoleOutput::FrameBuffer),
+            "both" => Some(ConsoleOutput::Both),
+            _ => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, args: fmt::Arguments) {
+    if level <= LOGLEVEL.get() {
+        _print(args);
+    }
+}
+
 // #[doc(hidden)]
 // pub fn _print(args: fmt::Arguments) {
 //     use console::interface::Write;
@@ -16,10 +77,21 @@ use core::fmt;
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    use screen::interface::Write;
-    bsp::frame_buffer::screen()
-        .write_fmt(args)
-        .expect("screen print panic!!")
+    let output = CONSOLE.get();
+
+    if output != ConsoleOutput::Uart {
+        use screen::interface::Write;
+        vt::console(vt::KERNEL_LOG)
+            .write_fmt(args)
+            .expect("screen print panic!!")
+    }
+
+    if output != ConsoleOutput::FrameBuffer {
+        use console::interface::Write;
+        bsp::console::console()
+            .write_fmt(args)
+            .expect("console print panic!!");
+    }
 }
 
 #[macro_export]
@@ -45,12 +117,15 @@ macro_rules! info {
         let timestamp = $crate::time::time_manager().uptime();
         let timestamp_subsec_us = timestamp.subsec_micros();
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
+        $crate::print::_log(
+            $crate::print::LogLevel::Info,
+            format_args_nl!(
+                concat!("[  {:>3}.{:03}{:03}] ", $string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000
+            ),
+        );
     });
     ($format_string:expr, $($arg:tt)*) => ({
         #[allow(unused_imports)]
@@ -59,13 +134,16 @@ macro_rules! info {
         let timestamp = $crate::time::time_manager().uptime();
         let timestamp_subsec_us = timestamp.subsec_micros();
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
+        $crate::print::_log(
+            $crate::print::LogLevel::Info,
+            format_args_nl!(
+                concat!("[  {:>3}.{:03}{:03}] ", $format_string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000,
+                $($arg)*
+            ),
+        );
     })
 }
 
@@ -79,12 +157,15 @@ macro_rules! warn {
         let timestamp = $crate::time::time_manager().uptime();
         let timestamp_subsec_us = timestamp.subsec_micros();
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
+        $crate::print::_log(
+            $crate::print::LogLevel::Warn,
+            format_args_nl!(
+                concat!("[W {:>3}.{:03}{:03}] ", $string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000
+            ),
+        );
     });
     ($format_string:expr, $($arg:tt)*) => ({
         #[allow(unused_imports)]
@@ -93,20 +174,23 @@ macro_rules! warn {
         let timestamp = $crate::time::time_manager().uptime();
         let timestamp_subsec_us = timestamp.subsec_micros();
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
+        $crate::print::_log(
+            $crate::print::LogLevel::Warn,
+            format_args_nl!(
+                concat!("[W {:>3}.{:03}{:03}] ", $format_string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000,
+                $($arg)*
+            ),
+        );
     })
 }
 
 #[doc(hidden)]
 pub fn _buffer_print(args: fmt::Arguments) {
     use screen::interface::Write;
-    bsp::frame_buffer::screen()
+    vt::console(vt::KERNEL_LOG)
         .write_fmt(args)
         .expect("screen print panic!!")
 }
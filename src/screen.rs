@@ -1,8 +1,100 @@
+mod png;
+pub mod screenshot;
+pub mod vt;
+
+//-------------------------------------------------------------------------------------------------
+// Public Deginitions
+//-------------------------------------------------------------------------------------------------
+
+// The 16 colors of a text console
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+}
+
+// Foreground and background color of a character cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attribute {
+    pub fg: Color,
+    pub bg: Color,
+}
+
 pub mod interface {
     pub use core::fmt;
+
+    use super::Attribute;
+
     pub trait Write {
         fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result;
     }
 
+    // A display that shows a grid of character cells
+    pub trait TextDisplay {
+        // size of the grid as (columns, rows), (0, 0) while the display is not ready
+        fn text_size(&self) -> (usize, usize);
+
+        // number of cells `c` occupies, 2 for wide characters
+        fn char_cells(&self, c: char) -> usize;
+
+        // draw `c` with its top left corner in the given cell
+        fn draw_char(
+            &self,
+            row: usize,
+            column: usize,
+            c: char,
+            attr: Attribute,
+        );
+
+        // fill `count` cells with the background color
+        fn clear_cells(
+            &self,
+            row: usize,
+            column: usize,
+            count: usize,
+            attr: Attribute,
+        );
+    }
+
+    // A display whose pixels can be read back
+    pub trait ReadPixels {
+        // size in pixels as (width, height), (0, 0) while the display is not ready
+        fn pixel_size(&self) -> (usize, usize);
+
+        // read pixels of row `y` from column `x` on into `out` as RGB bytes,
+        // return the number of pixels read
+        fn read_pixels(&self, y: usize, x: usize, out: &mut [u8]) -> usize;
+    }
+
     pub trait All: Write {}
 }
+
+//-------------------------------------------------------------------------------------------------
+// Public Code
+//-------------------------------------------------------------------------------------------------
+
+impl Attribute {
+    pub const fn new(fg: Color, bg: Color) -> Self {
+        Self { fg, bg }
+    }
+}
+
+impl Default for Attribute {
+    fn default() -> Self {
+        Self::new(Color::White, Color::Black)
+    }
+}
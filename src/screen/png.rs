@@ -0,0 +1,314 @@
+// Streaming PNG encoder for 8-bit RGB images
+//
+// The image is fed in pixel by pixel and written out in IDAT chunks as it goes, so nothing larger
+// than a chunk is buffered. Rows use the Sub filter, which turns areas of one color into runs of
+// zeros, and the deflate stream is a single fixed-Huffman block that only encodes runs of equal
+// bytes. That is cheap to produce and still shrinks a text console to a small fraction of its raw
+// size.
+
+use crate::checksum::{Adler32, Crc32};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// IHDR values: 8 bits per sample, truecolor, deflate, adaptive filtering, no interlace
+const BIT_DEPTH: u8 = 8;
+const COLOR_TYPE_RGB: u8 = 2;
+
+const BYTES_PER_PIXEL: usize = 3;
+
+// Largest width or height allowed by the specification
+const MAX_DIMENSION: usize = 0x7FFF_FFFF;
+
+// Filter type written in front of every row
+const FILTER_SUB: u8 = 1;
+
+// Compressed bytes collected before an IDAT chunk is written
+const IDAT_SIZE: usize = 4096;
+
+// zlib header: deflate with a 32K window, no preset dictionary, check bits
+const ZLIB_HEADER: [u8; 2] = [0x78, 0x01];
+
+// Deflate block header: last block, fixed Huffman codes
+const BLOCK_FINAL: u32 = 1;
+const BLOCK_FIXED: u32 = 1;
+
+const END_OF_BLOCK: u16 = 256;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+// (base length, extra bits) of the length codes 257..=285
+const LENGTH_CODES: [(usize, u32); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Encoder writing the PNG file to `out` in pieces
+pub struct PngEncoder<W: FnMut(&[u8])> {
+    out: W,
+    width: usize,
+    height: usize,
+    // position of the next pixel
+    row: usize,
+    column: usize,
+    // the pixel to the left of the next one, used by the Sub filter
+    left: [u8; BYTES_PER_PIXEL],
+    adler: Adler32,
+    // a byte followed by `run_length` copies of itself that are not written yet
+    run_byte: Option<u8>,
+    run_length: usize,
+    // deflate output not yet written to `idat`, LSB first
+    bit_buffer: u32,
+    bit_count: u32,
+    idat: [u8; IDAT_SIZE],
+    idat_len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<W: FnMut(&[u8])> PngEncoder<W> {
+    // Write the file header and get ready for `width * height` pixels
+    pub fn new(
+        width: usize,
+        height: usize,
+        out: W,
+    ) -> Result<Self, &'static str> {
+        if width == 0 || height == 0 {
+            return Err("Image size is zero");
+        }
+
+        if width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err("Image size is too large");
+        }
+
+        let mut encoder = Self {
+            out,
+            width,
+            height,
+            row: 0,
+            column: 0,
+            left: [0; BYTES_PER_PIXEL],
+            adler: Adler32::new(),
+            run_byte: None,
+            run_length: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+            idat: [0; IDAT_SIZE],
+            idat_len: 0,
+        };
+
+        let mut header = [0u8; 13];
+        header[0..4].copy_from_slice(&(width as u32).to_be_bytes());
+        header[4..8].copy_from_slice(&(height as u32).to_be_bytes());
+        header[8] = BIT_DEPTH;
+        header[9] = COLOR_TYPE_RGB;
+
+        (encoder.out)(&SIGNATURE);
+        write_chunk(&mut encoder.out, b"IHDR", &header);
+
+        for byte in ZLIB_HEADER.iter() {
+            encoder.push_idat(*byte);
+        }
+        encoder.put_bits(BLOCK_FINAL, 1);
+        encoder.put_bits(BLOCK_FIXED, 2);
+
+        Ok(encoder)
+    }
+
+    // Add pixels given as RGB bytes. Rows are filled from left to right, top to bottom.
+    pub fn write_pixels(&mut self, rgb: &[u8]) -> Result<(), &'static str> {
+        if !rgb.len().is_multiple_of(BYTES_PER_PIXEL) {
+            return Err("Partial pixel");
+        }
+
+        for pixel in rgb.chunks_exact(BYTES_PER_PIXEL) {
+            if self.row >= self.height {
+                return Err("More pixels than the image holds");
+            }
+
+            if self.column == 0 {
+                self.deflate_byte(FILTER_SUB);
+            }
+
+            for (sample, left) in pixel.iter().zip(self.left) {
+                self.deflate_byte(sample.wrapping_sub(left));
+            }
+            self.left.copy_from_slice(pixel);
+
+            self.column += 1;
+            if self.column == self.width {
+                self.column = 0;
+                self.row += 1;
+                self.left = [0; BYTES_PER_PIXEL];
+            }
+        }
+
+        Ok(())
+    }
+
+    // Close the deflate stream and write the trailing chunks
+    pub fn finish(mut self) -> Result<(), &'static str> {
+        if self.row != self.height {
+            return Err("Image is incomplete");
+        }
+
+        self.flush_run();
+        self.put_symbol(END_OF_BLOCK);
+
+        // pad the last partial byte
+        if self.bit_count > 0 {
+            self.put_bits(0, 8 - self.bit_count);
+        }
+
+        for byte in self.adler.finish().to_be_bytes() {
+            self.push_idat(byte);
+        }
+        self.flush_idat();
+        write_chunk(&mut self.out, b"IEND", &[]);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// Length, type, data and CRC of the type and data
+fn write_chunk(out: &mut impl FnMut(&[u8]), kind: &[u8; 4], data: &[u8]) {
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+
+    out(&(data.len() as u32).to_be_bytes());
+    out(kind);
+    out(data);
+    out(&crc.finish().to_be_bytes());
+}
+
+impl<W: FnMut(&[u8])> PngEncoder<W> {
+    fn push_idat(&mut self, byte: u8) {
+        self.idat[self.idat_len] = byte;
+        self.idat_len += 1;
+
+        if self.idat_len == IDAT_SIZE {
+            self.flush_idat();
+        }
+    }
+
+    fn flush_idat(&mut self) {
+        let len = core::mem::take(&mut self.idat_len);
+        if len > 0 {
+            write_chunk(&mut self.out, b"IDAT", &self.idat[..len]);
+        }
+    }
+
+    // Feed one byte of the filtered image into the deflate stream
+    fn deflate_byte(&mut self, byte: u8) {
+        self.adler.update(&[byte]);
+
+        if self.run_byte == Some(byte) {
+            if self.run_length == MAX_MATCH {
+                self.flush_run();
+            }
+            self.run_length += 1;
+            return;
+        }
+
+        self.flush_run();
+        self.put_symbol(byte as u16);
+        self.run_byte = Some(byte);
+    }
+
+    // Write the pending repeats, as a match at distance 1 if long enough
+    fn flush_run(&mut self) {
+        let length = core::mem::take(&mut self.run_length);
+
+        if length >= MIN_MATCH {
+            self.put_match(length);
+        } else if let Some(byte) = self.run_byte {
+            for _ in 0..length {
+                self.put_symbol(byte as u16);
+            }
+        }
+    }
+
+    fn put_match(&mut self, length: usize) {
+        let index = LENGTH_CODES
+            .iter()
+            .rposition(|(base, _)| *base <= length)
+            .unwrap_or(0);
+        let (base, extra_bits) = LENGTH_CODES[index];
+
+        self.put_symbol(257 + index as u16);
+        self.put_bits((length - base) as u32, extra_bits);
+
+        // distance code 0 is a distance of 1, with a fixed 5-bit code
+        self.put_code(0, 5);
+    }
+
+    // Literal/length symbol with the fixed Huffman code of RFC 1951, 3.2.6
+    fn put_symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        let (code, len) = match symbol {
+            0..=143 => (0x30 + symbol, 8),
+            144..=255 => (0x190 + symbol - 144, 9),
+            256..=279 => (symbol - 256, 7),
+            _ => (0xC0 + symbol - 280, 8),
+        };
+
+        self.put_code(code, len);
+    }
+
+    // Huffman codes are packed starting with their most significant bit
+    fn put_code(&mut self, code: u32, len: u32) {
+        self.put_bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn put_bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += count;
+
+        while self.bit_count >= 8 {
+            self.push_idat(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// Screenshots of the display, streamed as text
+//
+// The picture is encoded as PNG and sent as lines of base64, so it can share the serial line with
+// other output. `tools/receive_screenshot.py` picks the lines out and writes the file:
+//
+//     @@SCREENSHOT BEGIN <width>x<height> png
+//     @@<line number> <base64 data>
+//     ...
+//     @@SCREENSHOT END <file size> <crc32>
+//
+// Line numbers count the data lines from 0 in hexadecimal, and the CRC-32 covers the decoded file,
+// so the receiver can tell when characters were lost. A transfer that fails ends with
+// `@@SCREENSHOT ABORT <reason>` instead.
+
+use super::{interface, png};
+use crate::checksum::Crc32;
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Bytes per data line, 76 characters of base64
+const LINE_BYTES: usize = 57;
+
+// Pixels read from the display at a time
+const READ_PIXELS: usize = 256;
+
+const BASE64: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Splits the file into numbered lines and keeps the totals for the trailer
+struct FramedWriter<F: FnMut(char)> {
+    out: F,
+    line: [u8; LINE_BYTES],
+    len: usize,
+    sequence: u32,
+    size: usize,
+    crc: Crc32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Stream the current contents of `display` to `out` as a framed PNG file.
+// Return the size of the file.
+pub fn capture(
+    display: &impl interface::ReadPixels,
+    out: impl FnMut(char),
+) -> Result<usize, &'static str> {
+    let mut writer = FramedWriter::new(out);
+
+    let (width, height) = display.pixel_size();
+    if width == 0 || height == 0 {
+        writer.abort("Display is not ready");
+        return Err("Display is not ready");
+    }
+
+    writer.begin(width, height);
+    match encode(display, width, height, &mut writer) {
+        Ok(()) => Ok(writer.end()),
+        Err(e) => {
+            writer.abort(e);
+            Err(e)
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn encode(
+    display: &impl interface::ReadPixels,
+    width: usize,
+    height: usize,
+    writer: &mut FramedWriter<impl FnMut(char)>,
+) -> Result<(), &'static str> {
+    let mut encoder =
+        png::PngEncoder::new(width, height, |data| writer.write(data))?;
+    let mut pixels = [0u8; READ_PIXELS * 3];
+
+    for y in 0..height {
+        let mut x = 0;
+        while x < width {
+            let count = display.read_pixels(y, x, &mut pixels);
+            if count == 0 {
+                return Err("Display read failed");
+            }
+
+            encoder.write_pixels(&pixels[..count * 3])?;
+            x += count;
+        }
+    }
+
+    encoder.finish()
+}
+
+impl<F: FnMut(char)> FramedWriter<F> {
+    fn new(out: F) -> Self {
+        Self {
+            out,
+            line: [0; LINE_BYTES],
+            len: 0,
+            sequence: 0,
+            size: 0,
+            crc: Crc32::new(),
+        }
+    }
+
+    fn begin(&mut self, width: usize, height: usize) {
+        let _ = fmt::write(
+            self,
+            format_args!("\n@@SCREENSHOT BEGIN {}x{} png\n", width, height),
+        );
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.crc.update(data);
+        self.size += data.len();
+
+        for byte in data.iter() {
+            self.line[self.len] = *byte;
+            self.len += 1;
+
+            if self.len == LINE_BYTES {
+                self.flush_line();
+            }
+        }
+    }
+
+    // Write the last data line and the trailer, return the file size
+    fn end(mut self) -> usize {
+        self.flush_line();
+
+        let (size, crc) = (self.size, self.crc.finish());
+        let _ = fmt::write(
+            &mut self,
+            format_args!("@@SCREENSHOT END {} {:08x}\n", size, crc),
+        );
+
+        size
+    }
+
+    fn abort(&mut self, reason: &str) {
+        let _ =
+            fmt::write(self, format_args!("\n@@SCREENSHOT ABORT {}\n", reason));
+    }
+
+    fn flush_line(&mut self) {
+        let len = core::mem::take(&mut self.len);
+        if len == 0 {
+            return;
+        }
+
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        let _ = fmt::write(self, format_args!("@@{:08x} ", sequence));
+
+        for group in self.line[..len].chunks(3) {
+            let bits =
+                group.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+                    bits | ((*byte as u32) << (16 - 8 * i))
+                });
+
+            // one character per 6 bits, padded with `=`
+            for i in 0..4 {
+                let c = if i <= group.len() {
+                    BASE64[((bits >> (18 - 6 * i)) & 0x3F) as usize] as char
+                } else {
+                    '='
+                };
+                (self.out)(c);
+            }
+        }
+        (self.out)('\n');
+    }
+}
+
+// Text of the frame lines
+impl<F: FnMut(char)> fmt::Write for FramedWriter<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars().for_each(&mut self.out);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,721 @@
+// Virtual consoles on the frame buffer
+//
+// Every console keeps its text in a ring of character cells, together with its own cursor and
+// attribute. The ring holds the visible screen and a scrollback history. Only the active console is
+// drawn on the display, always from its cells: switching consoles, scrolling and paging through the
+// history redraw the screen instead of copying pixels.
+//
+// Key bindings on the input device:
+//
+// - ESC 1..3:          switch console, what most terminals send for Alt+<number>
+// - PageUp / PageDown: page through the scrollback of the active console
+// - ESC d:             dump the text on the screen to the input device
+// - ESC s:             send a screenshot to the serial port, see `screenshot` and `take_screenshot`
+
+use super::{interface, screenshot, Attribute, Color};
+use crate::{
+    bsp,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Global Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub const NUM_CONSOLES: usize = 3;
+
+// Well known consoles
+pub const KERNEL_LOG: usize = 0;
+pub const SHELL: usize = 1;
+pub const USER: usize = 2;
+
+// Largest text grid, enough for 1920x1080 with an 8x16 font
+pub const MAX_COLUMNS: usize = 240;
+pub const MAX_ROWS: usize = 68;
+
+// Lines kept per console, including the visible ones
+const BUFFER_LINES: usize = 512;
+
+// Largest scrollback that fits next to a full screen
+pub const MAX_SCROLLBACK: usize = BUFFER_LINES - MAX_ROWS;
+
+// Geometry used while the display is not ready yet
+const DEFAULT_COLUMNS: usize = 80;
+const DEFAULT_ROWS: usize = 25;
+
+const TAB_WIDTH: usize = 8;
+const ESC: char = '\x1b';
+
+// Longest escape sequence the input filter buffers, `ESC [ 5 ; 2 ~`
+const MAX_SEQUENCE: usize = 8;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// An all-zero cell is blank, so that the cell buffers start out in .bss
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub attr: Attribute,
+    // right half of a wide character
+    pub continuation: bool,
+}
+
+pub struct VirtualConsole {
+    name: &'static str,
+    index: usize,
+    // show characters typed while this console is active
+    echo_input: bool,
+    inner: IRQSafeNullLock<VirtualConsoleInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+type Line = [Cell; MAX_COLUMNS];
+
+// Text of a console, line `n` lives in `lines[n % BUFFER_LINES]`
+struct CellBuffer {
+    lines: [Line; BUFFER_LINES],
+}
+
+struct VirtualConsoleInner {
+    columns: usize,
+    rows: usize,
+    // absolute line numbers
+    cursor_line: usize,
+    screen_top: usize,
+    cursor_column: usize,
+    attr: Attribute,
+    // number of history lines that can be paged back to
+    scrollback: usize,
+    // number of lines the view is scrolled back from the screen, 0 when live
+    view_offset: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InputState {
+    Normal,
+    Escape,
+    ControlSequence,
+}
+
+// Bytes received after ESC while looking for a key binding
+struct InputFilter {
+    state: InputState,
+    sequence: [char; MAX_SEQUENCE],
+    len: usize,
+}
+
+enum InputAction {
+    None,
+    Forward([char; MAX_SEQUENCE], usize),
+    Switch(usize),
+    PageUp,
+    PageDown,
+    Dump,
+    Screenshot,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static CONSOLES: [VirtualConsole; NUM_CONSOLES] = [
+    VirtualConsole::new(
+        "kernel log",
+        KERNEL_LOG,
+        false,
+        Attribute::new(Color::White, Color::Black),
+    ),
+    VirtualConsole::new(
+        "shell",
+        SHELL,
+        true,
+        Attribute::new(Color::BrightWhite, Color::Black),
+    ),
+    VirtualConsole::new(
+        "user",
+        USER,
+        true,
+        Attribute::new(Color::BrightGreen, Color::Black),
+    ),
+];
+
+// Kept apart from the consoles, which have non-zero initial values
+static CELL_BUFFERS: [IRQSafeNullLock<CellBuffer>; NUM_CONSOLES] = [
+    IRQSafeNullLock::new(CellBuffer::new_zeroed()),
+    IRQSafeNullLock::new(CellBuffer::new_zeroed()),
+    IRQSafeNullLock::new(CellBuffer::new_zeroed()),
+];
+
+static ACTIVE: AtomicUsize = AtomicUsize::new(KERNEL_LOG);
+
+static INPUT_FILTER: IRQSafeNullLock<InputFilter> =
+    IRQSafeNullLock::new(InputFilter::new());
+
+// Set by ESC s until the main loop takes the screenshot
+static SCREENSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to a console
+pub fn console(index: usize) -> &'static VirtualConsole {
+    &CONSOLES[index]
+}
+
+// Index of the console shown on the display
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+// Show another console and redraw the display from its cells
+pub fn switch_to(index: usize) -> Result<(), &'static str> {
+    if index >= NUM_CONSOLES {
+        return Err("No such virtual console");
+    }
+
+    ACTIVE.store(index, Ordering::Relaxed);
+    CONSOLES[index].redraw();
+
+    Ok(())
+}
+
+// Look for key bindings in the input stream.
+//
+// Characters that are not part of a binding go to the console input buffer, are echoed to `out`
+// if `echo` is set, and to the active console if it wants them. Screen dumps are written to `out`.
+// Screenshots take seconds to send, so they are only noted here and left to `take_screenshot()`.
+pub fn filter_input(c: char, echo: bool, mut out: impl FnMut(char)) {
+    let console = &CONSOLES[active()];
+
+    match INPUT_FILTER.lock(|filter| filter.feed(c)) {
+        InputAction::None => {}
+        InputAction::Forward(sequence, len) => {
+            // typing returns to the live screen
+            console.scroll_to_bottom();
+
+            for c in sequence[..len].iter().copied() {
+                // dropped while nobody reads
+                let _ = crate::console::input_buffer().push(c);
+                if echo {
+                    out(c);
+                }
+
+                if console.echo_input {
+                    console.write_char(c);
+                }
+            }
+        }
+        InputAction::Switch(index) => {
+            let _ = switch_to(index);
+        }
+        InputAction::PageUp => console.page_up(),
+        InputAction::PageDown => console.page_down(),
+        InputAction::Dump => console.dump(out),
+        InputAction::Screenshot => {
+            SCREENSHOT_REQUESTED.store(true, Ordering::Relaxed)
+        }
+    }
+}
+
+// Send the screenshot asked for with ESC s to `out`, if there is one. Called from the main loop,
+// where taking seconds does not hold up interrupts.
+pub fn take_screenshot(out: impl FnMut(char)) {
+    if SCREENSHOT_REQUESTED.swap(false, Ordering::Relaxed) {
+        // the outcome is reported in the stream itself
+        let _ = screenshot::capture(bsp::frame_buffer::pixels(), out);
+    }
+}
+
+impl Cell {
+    pub const fn blank(attr: Attribute) -> Self {
+        Self {
+            c: ' ',
+            attr,
+            continuation: false,
+        }
+    }
+
+    pub fn is_blank(&self) -> bool {
+        !self.continuation && (self.c == ' ' || self.c == '\0')
+    }
+}
+
+impl VirtualConsole {
+    const fn new(
+        name: &'static str,
+        index: usize,
+        echo_input: bool,
+        attr: Attribute,
+    ) -> Self {
+        Self {
+            name,
+            index,
+            echo_input,
+            inner: IRQSafeNullLock::new(VirtualConsoleInner::new(attr)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_active(&self) -> bool {
+        active() == self.index
+    }
+
+    // Attribute used for the following output
+    pub fn set_attribute(&self, attr: Attribute) {
+        self.inner.lock(|inner| inner.attr = attr);
+    }
+
+    // Number of history lines kept for paging, clipped to `MAX_SCROLLBACK`.
+    // Return the value that is used.
+    pub fn set_scrollback(&self, lines: usize) -> usize {
+        self.inner.lock(|inner| {
+            inner.scrollback = lines.min(MAX_SCROLLBACK);
+            inner.view_offset = inner.view_offset.min(inner.max_view_offset());
+            inner.scrollback
+        })
+    }
+
+    pub fn write_char(&self, c: char) {
+        let active = self.is_active();
+        self.lock(|inner, cells| {
+            inner.sync_geometry(cells, active);
+            inner.put_char(cells, c, active);
+        });
+    }
+
+    // Clear the console and move the cursor to the top left corner
+    pub fn clear(&self) {
+        let active = self.is_active();
+        self.lock(|inner, cells| {
+            // lines after the cursor are blanked when the cursor gets there
+            inner.cursor_line = 0;
+            inner.screen_top = 0;
+            inner.cursor_column = 0;
+            inner.view_offset = 0;
+            cells.lines[0] = [Cell::blank(inner.attr); MAX_COLUMNS];
+            if active {
+                inner.redraw(cells);
+            }
+        });
+    }
+
+    // Draw every visible cell on the display
+    pub fn redraw(&self) {
+        self.lock(|inner, cells| {
+            inner.sync_geometry(cells, false);
+            inner.redraw(cells);
+        });
+    }
+
+    // Scroll the view one page back into the history
+    pub fn page_up(&self) {
+        self.scroll_view(|inner| {
+            let page = inner.rows.saturating_sub(1).max(1);
+            (inner.view_offset + page).min(inner.max_view_offset())
+        });
+    }
+
+    // Scroll the view one page towards the live screen
+    pub fn page_down(&self) {
+        self.scroll_view(|inner| {
+            let page = inner.rows.saturating_sub(1).max(1);
+            inner.view_offset.saturating_sub(page)
+        });
+    }
+
+    pub fn scroll_to_bottom(&self) {
+        self.scroll_view(|_| 0);
+    }
+
+    // Write the text in view, one line per row, to `out`
+    pub fn dump(&self, mut out: impl FnMut(char)) {
+        let mut emit = |s: &str| s.chars().for_each(&mut out);
+
+        self.lock(|inner, cells| {
+            let mut header = HeaderWriter(&mut emit);
+            let _ = fmt::write(
+                &mut header,
+                format_args!(
+                    "\n--- vt{} ({}) {}x{} ---\n",
+                    self.index + 1,
+                    self.name,
+                    inner.columns,
+                    inner.rows
+                ),
+            );
+
+            let view_top = inner.view_top();
+            for row in 0..inner.rows {
+                let columns = inner.columns;
+                let line = cells.line(view_top + row);
+                let used = line[..columns]
+                    .iter()
+                    .rposition(|cell| !cell.is_blank())
+                    .map_or(0, |last| last + 1);
+
+                for cell in line[..used].iter() {
+                    if cell.is_blank() {
+                        emit(" ");
+                    } else if !cell.continuation {
+                        let mut buf = [0; 4];
+                        emit(cell.c.encode_utf8(&mut buf));
+                    }
+                }
+                emit("\n");
+            }
+
+            emit("--- end ---\n");
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// Adapter for `fmt::write` that forwards to a closure
+struct HeaderWriter<'a, F: FnMut(&str)>(&'a mut F);
+
+impl<F: FnMut(&str)> fmt::Write for HeaderWriter<'_, F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (self.0)(s);
+        Ok(())
+    }
+}
+
+impl CellBuffer {
+    const fn new_zeroed() -> Self {
+        let blank = Cell {
+            c: '\0',
+            attr: Attribute::new(Color::Black, Color::Black),
+            continuation: false,
+        };
+
+        Self {
+            lines: [[blank; MAX_COLUMNS]; BUFFER_LINES],
+        }
+    }
+
+    fn line(&self, line: usize) -> &Line {
+        &self.lines[line % BUFFER_LINES]
+    }
+
+    fn line_mut(&mut self, line: usize) -> &mut Line {
+        &mut self.lines[line % BUFFER_LINES]
+    }
+}
+
+impl VirtualConsole {
+    // Lock the console state together with its cells
+    fn lock<R>(
+        &self,
+        f: impl FnOnce(&mut VirtualConsoleInner, &mut CellBuffer) -> R,
+    ) -> R {
+        self.inner.lock(|inner| {
+            CELL_BUFFERS[self.index].lock(|cells| f(inner, cells))
+        })
+    }
+
+    fn scroll_view(&self, offset: impl FnOnce(&VirtualConsoleInner) -> usize) {
+        let active = self.is_active();
+        self.lock(|inner, cells| {
+            let offset = offset(inner);
+            if offset == inner.view_offset {
+                return;
+            }
+
+            inner.view_offset = offset;
+            if active {
+                inner.redraw(cells);
+            }
+        });
+    }
+}
+
+impl VirtualConsoleInner {
+    const fn new(attr: Attribute) -> Self {
+        Self {
+            columns: DEFAULT_COLUMNS,
+            rows: DEFAULT_ROWS,
+            cursor_line: 0,
+            screen_top: 0,
+            cursor_column: 0,
+            attr,
+            scrollback: MAX_SCROLLBACK,
+            view_offset: 0,
+        }
+    }
+
+    // The oldest line that is still in the buffer
+    fn oldest_line(&self) -> usize {
+        (self.cursor_line + 1).saturating_sub(BUFFER_LINES)
+    }
+
+    fn max_view_offset(&self) -> usize {
+        let history_top = self
+            .screen_top
+            .saturating_sub(self.scrollback)
+            .max(self.oldest_line());
+
+        self.screen_top - history_top
+    }
+
+    fn view_top(&self) -> usize {
+        self.screen_top - self.view_offset
+    }
+
+    // Output is only drawn while the live screen is in view
+    fn is_drawn(&self, active: bool) -> bool {
+        active && self.view_offset == 0
+    }
+
+    // Follow the size of the display, which is only known once it is initialized
+    fn sync_geometry(&mut self, cells: &mut CellBuffer, active: bool) {
+        use interface::TextDisplay;
+
+        let (columns, rows) = match bsp::frame_buffer::screen().text_size() {
+            (0, _) | (_, 0) => return,
+            (columns, rows) => (columns.min(MAX_COLUMNS), rows.min(MAX_ROWS)),
+        };
+
+        if (columns, rows) == (self.columns, self.rows) {
+            return;
+        }
+
+        self.columns = columns;
+        self.rows = rows;
+        self.cursor_column = self.cursor_column.min(columns - 1);
+        self.screen_top = (self.cursor_line + 1)
+            .saturating_sub(rows)
+            .max(self.oldest_line());
+        self.view_offset = 0;
+
+        if active {
+            self.redraw(cells);
+        }
+    }
+
+    fn put_char(&mut self, cells: &mut CellBuffer, c: char, active: bool) {
+        use interface::TextDisplay;
+
+        match c {
+            '\n' => self.new_line(cells, active),
+            '\r' => self.cursor_column = 0,
+            '\t' => {
+                let next = (self.cursor_column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.cursor_column < next.min(self.columns) {
+                    self.put_char(cells, ' ', active);
+                }
+            }
+            '\x08' => self.cursor_column = self.cursor_column.saturating_sub(1),
+            _ => {
+                let width = bsp::frame_buffer::screen().char_cells(c);
+                if self.cursor_column + width > self.columns {
+                    self.new_line(cells, active);
+                }
+
+                let (line, column, attr) =
+                    (self.cursor_line, self.cursor_column, self.attr);
+                cells.line_mut(line)[column] = Cell {
+                    c,
+                    attr,
+                    continuation: false,
+                };
+                if width == 2 {
+                    cells.line_mut(line)[column + 1] = Cell {
+                        c,
+                        attr,
+                        continuation: true,
+                    };
+                }
+
+                if self.is_drawn(active) {
+                    bsp::frame_buffer::screen().draw_char(
+                        line - self.screen_top,
+                        column,
+                        c,
+                        attr,
+                    );
+                }
+
+                self.cursor_column += width;
+            }
+        }
+    }
+
+    fn new_line(&mut self, cells: &mut CellBuffer, active: bool) {
+        self.cursor_line += 1;
+        self.cursor_column = 0;
+        *cells.line_mut(self.cursor_line) =
+            [Cell::blank(self.attr); MAX_COLUMNS];
+
+        if self.cursor_line < self.screen_top + self.rows {
+            if self.is_drawn(active) {
+                self.draw_row(cells, self.cursor_line - self.screen_top);
+            }
+            return;
+        }
+
+        self.screen_top = self.cursor_line + 1 - self.rows;
+
+        if self.view_offset > 0 {
+            // keep the history that is in view where it is
+            self.view_offset =
+                (self.view_offset + 1).min(self.max_view_offset());
+        } else if active {
+            self.redraw(cells);
+        }
+    }
+
+    fn redraw(&self, cells: &CellBuffer) {
+        for row in 0..self.rows {
+            self.draw_row(cells, row);
+        }
+    }
+
+    // Draw a row of the view. Runs of blank cells are cleared in one go.
+    fn draw_row(&self, cells: &CellBuffer, row: usize) {
+        use interface::TextDisplay;
+
+        let display = bsp::frame_buffer::screen();
+        let line = self.view_top() + row;
+        if line > self.cursor_line {
+            display.clear_cells(row, 0, self.columns, self.attr);
+            return;
+        }
+
+        let line = &cells.line(line)[..self.columns];
+        let mut column = 0;
+        while column < line.len() {
+            let cell = line[column];
+            if cell.is_blank() {
+                let run = line[column..]
+                    .iter()
+                    .take_while(|other| **other == cell)
+                    .count();
+                display.clear_cells(row, column, run, cell.attr);
+                column += run;
+                continue;
+            }
+
+            if !cell.continuation {
+                display.draw_char(row, column, cell.c, cell.attr);
+            }
+            column += 1;
+        }
+    }
+}
+
+impl InputFilter {
+    const fn new() -> Self {
+        Self {
+            state: InputState::Normal,
+            sequence: [ESC; MAX_SEQUENCE],
+            len: 0,
+        }
+    }
+
+    fn feed(&mut self, c: char) -> InputAction {
+        match self.state {
+            InputState::Normal if c == ESC => {
+                self.start(c, InputState::Escape);
+                InputAction::None
+            }
+            InputState::Normal => InputAction::Forward([c; MAX_SEQUENCE], 1),
+            InputState::Escape => {
+                self.state = InputState::Normal;
+                match c {
+                    '1'..='9' => {
+                        let index = c as usize - '1' as usize;
+                        if index < NUM_CONSOLES {
+                            InputAction::Switch(index)
+                        } else {
+                            self.push_and_forward(c)
+                        }
+                    }
+                    'd' => InputAction::Dump,
+                    's' => InputAction::Screenshot,
+                    '[' => {
+                        self.push(c);
+                        self.state = InputState::ControlSequence;
+                        InputAction::None
+                    }
+                    _ => self.push_and_forward(c),
+                }
+            }
+            InputState::ControlSequence => {
+                // parameter bytes, the sequence ends with a final byte
+                if ('0'..='?').contains(&c) && self.len + 1 < MAX_SEQUENCE {
+                    self.push(c);
+                    return InputAction::None;
+                }
+
+                self.state = InputState::Normal;
+                let parameters = &self.sequence[2..self.len];
+                match (parameters.first(), c) {
+                    (Some('5'), '~') => InputAction::PageUp,
+                    (Some('6'), '~') => InputAction::PageDown,
+                    _ => self.push_and_forward(c),
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, c: char, state: InputState) {
+        self.sequence[0] = c;
+        self.len = 1;
+        self.state = state;
+    }
+
+    fn push(&mut self, c: char) {
+        if self.len < MAX_SEQUENCE {
+            self.sequence[self.len] = c;
+            self.len += 1;
+        }
+    }
+
+    // Give up on the sequence and forward everything received so far
+    fn push_and_forward(&mut self, c: char) -> InputAction {
+        self.push(c);
+        let len = core::mem::take(&mut self.len);
+
+        InputAction::Forward(self.sequence, len)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// OS Interface Code
+//--------------------------------------------------------------------------------------------------
+
+impl interface::Write for VirtualConsole {
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        struct Writer<'a>(&'a VirtualConsole);
+
+        impl fmt::Write for Writer<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for c in s.chars() {
+                    self.0.write_char(c);
+                }
+
+                Ok(())
+            }
+        }
+
+        fmt::Write::write_fmt(&mut Writer(self), args)
+    }
+}
+
+impl interface::All for VirtualConsole {}
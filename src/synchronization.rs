@@ -79,7 +79,7 @@ impl<T> interface::ReadWriteEx for InitStateLock<T> {
         );
 
         assert!(
-            !exception::asynchronous::is_local_irq_masked(),
+            exception::asynchronous::is_local_irq_masked(),
             "InitStateLock::write called with IRQs unmasked"
         );
 
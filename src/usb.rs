@@ -0,0 +1,734 @@
+// USB host stack
+//
+// The host controller driver moves transfers, this module enumerates the devices behind it: a new
+// device gets an address, its descriptors are read, its first configuration is selected and its
+// interfaces are offered to the registered class drivers. Hubs are a class driver of their own in
+// `hub`, which attaches and detaches the devices on its ports. Keyboards are driven by `keyboard`.
+//
+// Nothing is interrupt driven. `Usb::poll()`, called from the main loop, lets the class drivers
+// check their interrupt endpoints, e.g. the status change endpoint of a hub.
+//
+// USB 2.0 specification, chapters 5, 8 and 9
+
+pub mod descriptor;
+pub mod hub;
+pub mod keyboard;
+
+use crate::{
+    info,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+};
+use core::{fmt, time::Duration};
+use descriptor::{ConfigurationDescriptor, DeviceDescriptor, Interface};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Addresses 1 to MAX_DEVICES are handed out
+const MAX_DEVICES: usize = 16;
+const MAX_DRIVERS: usize = 8;
+
+// Configurations with more interfaces and endpoints than fit are cut off
+const CONFIGURATION_BUFFER_SIZE: usize = 512;
+
+// Longest product name kept, in bytes of UTF-8
+const PRODUCT_NAME_SIZE: usize = 32;
+
+// Time a device may take to switch to its new address
+const SET_ADDRESS_RECOVERY: Duration = Duration::from_millis(10);
+
+// US English, asked for product names
+const LANGUAGE_ID: u16 = 0x0409;
+
+struct UsbInner {
+    controller: Option<&'static (dyn interface::HostController + Sync)>,
+    devices: [Option<Device>; MAX_DEVICES],
+    drivers:
+        [Option<&'static (dyn interface::ClassDriver + Sync)>; MAX_DRIVERS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Standard requests
+pub const GET_STATUS: u8 = 0;
+pub const CLEAR_FEATURE: u8 = 1;
+pub const SET_FEATURE: u8 = 3;
+pub const SET_ADDRESS: u8 = 5;
+pub const GET_DESCRIPTOR: u8 = 6;
+pub const SET_CONFIGURATION: u8 = 9;
+pub const SET_INTERFACE: u8 = 11;
+
+// bmRequestType of a setup packet
+pub const REQUEST_IN: u8 = 0x80;
+pub const REQUEST_CLASS: u8 = 0x20;
+pub const REQUEST_TO_INTERFACE: u8 = 0x01;
+// a port of a hub
+pub const REQUEST_TO_OTHER: u8 = 0x03;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbError {
+    // nothing on the port or at the address
+    NoDevice,
+    // no host controller has been set up
+    NoController,
+    // the device did not answer in time or kept answering NAK
+    Timeout,
+    // the endpoint refused the request
+    Stall,
+    // CRC, bit stuffing or handshake errors on the bus
+    TransactionError,
+    // the device sent more than a packet
+    Babble,
+    DataToggleError,
+    // the controller could not reach memory
+    DmaError,
+    // all channels of the controller are busy
+    NoChannel,
+    // a descriptor is too short or makes no sense
+    InvalidDescriptor,
+    TooManyDevices,
+    TooManyDrivers,
+    // e.g. isochronous endpoints
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    // 1.5 Mbit/s
+    Low,
+    // 12 Mbit/s
+    Full,
+    // 480 Mbit/s
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    // host to device
+    Out,
+    // device to host
+    In,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+// A port of a hub, numbered from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Port {
+    pub hub: u8,
+    pub port: u8,
+}
+
+// Where a transfer goes, kept by the user of the endpoint between transfers
+#[derive(Debug, Clone, Copy)]
+pub struct Endpoint {
+    pub device: u8,
+    pub number: u8,
+    pub direction: Direction,
+    pub transfer_type: TransferType,
+    pub max_packet_size: u16,
+    // bInterval, in frames or as an exponent of microframes depending on the speed
+    pub interval: u8,
+    pub speed: Speed,
+    // the high-speed hub port a full- or low-speed device is behind, whose transaction
+    // translator takes split transactions
+    pub translator: Option<Port>,
+    // DATA1 is next, flipped by the controller
+    pub toggle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    // of the data stage
+    pub length: u16,
+}
+
+// A configured device
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    pub address: u8,
+    pub speed: Speed,
+    // `None` on the root port
+    pub parent: Option<Port>,
+    pub translator: Option<Port>,
+    pub descriptor: DeviceDescriptor,
+    pub configuration: u8,
+    max_packet_size: u8,
+    product: [u8; PRODUCT_NAME_SIZE],
+    product_len: usize,
+}
+
+// USB interfaces
+pub mod interface {
+    use super::{Device, Endpoint, Interface, SetupPacket, Speed, UsbError};
+
+    // Host controller functions. Transfers block until they are done.
+    pub trait HostController {
+        // Power the root port, reset the device on it and return its speed
+        fn reset_root_port(&self) -> Result<Speed, UsbError>;
+
+        // Run a control transfer, the data stage reads into or writes from `data` depending on
+        // the direction in `setup`. Returns the length of the data stage.
+        fn control(
+            &self,
+            endpoint: &mut Endpoint,
+            setup: SetupPacket,
+            data: &mut [u8],
+        ) -> Result<usize, UsbError>;
+
+        // Run a bulk transfer in the direction of `endpoint`, returns the length moved
+        fn bulk(
+            &self,
+            endpoint: &mut Endpoint,
+            data: &mut [u8],
+        ) -> Result<usize, UsbError>;
+
+        // Try one transaction on an interrupt endpoint, `None` if the device had nothing to send
+        // or could not take the data yet
+        fn interrupt(
+            &self,
+            endpoint: &mut Endpoint,
+            data: &mut [u8],
+        ) -> Result<Option<usize>, UsbError>;
+    }
+
+    // Class driver functions
+    pub trait ClassDriver {
+        fn name(&self) -> &'static str;
+
+        // Offer an interface of a newly configured device, true if the driver takes it
+        fn probe(&self, device: &Device, interface: &Interface) -> bool;
+
+        // Check the endpoints of the claimed interfaces, called from the main loop
+        fn poll(&self) {}
+
+        // The device at `address` is gone
+        fn disconnect(&self, _address: u8) {}
+    }
+}
+
+pub struct Usb {
+    inner: IRQSafeNullLock<UsbInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static USB: Usb = Usb::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the USB host stack
+pub fn usb() -> &'static Usb {
+    &USB
+}
+
+impl fmt::Display for UsbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str((*self).into())
+    }
+}
+
+impl From<UsbError> for &'static str {
+    fn from(error: UsbError) -> Self {
+        match error {
+            UsbError::NoDevice => "No USB device",
+            UsbError::NoController => "No USB host controller",
+            UsbError::Timeout => "USB transfer timeout",
+            UsbError::Stall => "USB endpoint stalled",
+            UsbError::TransactionError => "USB transaction error",
+            UsbError::Babble => "USB babble",
+            UsbError::DataToggleError => "USB data toggle error",
+            UsbError::DmaError => "USB DMA error",
+            UsbError::NoChannel => "No free USB channel",
+            UsbError::InvalidDescriptor => "Invalid USB descriptor",
+            UsbError::TooManyDevices => "Too many USB devices",
+            UsbError::TooManyDrivers => "Too many USB class drivers",
+            UsbError::Unsupported => "USB feature not supported",
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Speed::Low => "low speed",
+            Speed::Full => "full speed",
+            Speed::High => "high speed",
+        })
+    }
+}
+
+impl Endpoint {
+    // Endpoint 0 of a device, before its descriptor is known
+    pub const fn control(
+        device: u8,
+        speed: Speed,
+        translator: Option<Port>,
+        max_packet_size: u16,
+    ) -> Self {
+        Self {
+            device,
+            number: 0,
+            direction: Direction::Out,
+            transfer_type: TransferType::Control,
+            max_packet_size,
+            interval: 0,
+            speed,
+            translator,
+            toggle: false,
+        }
+    }
+
+    // How often an interrupt endpoint wants to be polled
+    pub fn poll_interval(&self) -> Duration {
+        match self.speed {
+            // 2^(bInterval - 1) microframes of 125 us
+            Speed::High => {
+                let exponent = self.interval.clamp(1, 16) - 1;
+                Duration::from_micros(125 << exponent)
+            }
+            _ => Duration::from_millis(self.interval.max(1) as u64),
+        }
+    }
+}
+
+impl SetupPacket {
+    // A standard GET_DESCRIPTOR request for the device
+    pub const fn get_descriptor(kind: u8, index: u8, length: u16) -> Self {
+        Self {
+            request_type: REQUEST_IN,
+            request: GET_DESCRIPTOR,
+            value: (kind as u16) << 8 | index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    pub fn direction(&self) -> Direction {
+        if self.request_type & REQUEST_IN != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+
+    // As sent in the setup stage
+    pub fn to_bytes(self) -> [u8; 8] {
+        let value = self.value.to_le_bytes();
+        let index = self.index.to_le_bytes();
+        let length = self.length.to_le_bytes();
+
+        [
+            self.request_type,
+            self.request,
+            value[0],
+            value[1],
+            index[0],
+            index[1],
+            length[0],
+            length[1],
+        ]
+    }
+}
+
+impl Device {
+    // The product string of the device, empty if it has none
+    pub fn product(&self) -> &str {
+        core::str::from_utf8(&self.product[..self.product_len]).unwrap_or("")
+    }
+
+    // An endpoint of the device, as described in its configuration
+    pub fn endpoint(
+        &self,
+        descriptor: &descriptor::EndpointDescriptor,
+    ) -> Endpoint {
+        Endpoint {
+            device: self.address,
+            number: descriptor.number(),
+            direction: descriptor.direction(),
+            transfer_type: descriptor.transfer_type(),
+            max_packet_size: descriptor.max_packet_size & 0x7FF,
+            interval: descriptor.interval,
+            speed: self.speed,
+            translator: self.translator,
+            toggle: false,
+        }
+    }
+
+    fn control_endpoint(&self) -> Endpoint {
+        Endpoint::control(
+            self.address,
+            self.speed,
+            self.translator,
+            self.max_packet_size as u16,
+        )
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:04x}:{:04x} class {:02x}, {}",
+            self.address,
+            self.descriptor.vendor_id,
+            self.descriptor.product_id,
+            self.descriptor.class,
+            self.speed
+        )?;
+        if let Some(parent) = self.parent {
+            write!(f, ", hub {} port {}", parent.hub, parent.port)?;
+        }
+        if !self.product().is_empty() {
+            write!(f, " ({})", self.product())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Usb {
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(UsbInner {
+                controller: None,
+                devices: [None; MAX_DEVICES],
+                drivers: [None; MAX_DRIVERS],
+            }),
+        }
+    }
+
+    // Class drivers are offered the interfaces of the devices attached afterwards
+    pub fn register_driver(
+        &self,
+        driver: &'static (dyn interface::ClassDriver + Sync),
+    ) -> Result<(), UsbError> {
+        self.inner.lock(|inner| {
+            let slot = inner
+                .drivers
+                .iter_mut()
+                .find(|slot| slot.is_none())
+                .ok_or(UsbError::TooManyDrivers)?;
+            *slot = Some(driver);
+
+            Ok(())
+        })
+    }
+
+    // Take the host controller into use and enumerate the devices on its root port, hubs
+    // included
+    pub fn init(
+        &self,
+        controller: &'static (dyn interface::HostController + Sync),
+    ) -> Result<(), UsbError> {
+        self.inner.lock(|inner| inner.controller = Some(controller));
+        self.register_driver(hub::hub_driver())?;
+        self.register_driver(keyboard::keyboard_driver())?;
+
+        let speed = controller.reset_root_port()?;
+        self.attach(None, speed).map(|_| ())
+    }
+
+    // Enumerate a device that has just been reset, returns its address
+    pub fn attach(
+        &self,
+        parent: Option<Port>,
+        speed: Speed,
+    ) -> Result<u8, UsbError> {
+        let controller = self.controller()?;
+        let translator = match parent {
+            None => None,
+            Some(port) => {
+                let hub = self.device(port.hub).ok_or(UsbError::NoDevice)?;
+                if hub.speed == Speed::High && speed != Speed::High {
+                    Some(port)
+                } else {
+                    hub.translator
+                }
+            }
+        };
+
+        // the first 8 bytes of the device descriptor hold the packet size of endpoint 0
+        let initial_packet_size = if speed == Speed::Low { 8 } else { 64 };
+        let mut control =
+            Endpoint::control(0, speed, translator, initial_packet_size);
+        let mut bytes = [0; DeviceDescriptor::SIZE];
+        controller.control(
+            &mut control,
+            SetupPacket::get_descriptor(descriptor::DEVICE, 0, 8),
+            &mut bytes[..8],
+        )?;
+        let max_packet_size = bytes[7];
+        if !matches!(max_packet_size, 8 | 16 | 32 | 64) {
+            return Err(UsbError::InvalidDescriptor);
+        }
+        control.max_packet_size = max_packet_size as u16;
+
+        let address = self.free_address()?;
+        controller.control(
+            &mut control,
+            SetupPacket {
+                request_type: 0,
+                request: SET_ADDRESS,
+                value: address as u16,
+                index: 0,
+                length: 0,
+            },
+            &mut [],
+        )?;
+        time::time_manager().spin_for(SET_ADDRESS_RECOVERY);
+        control.device = address;
+
+        controller.control(
+            &mut control,
+            SetupPacket::get_descriptor(
+                descriptor::DEVICE,
+                0,
+                DeviceDescriptor::SIZE as u16,
+            ),
+            &mut bytes,
+        )?;
+        let device_descriptor = DeviceDescriptor::parse(&bytes)?;
+
+        let mut configuration = [0; CONFIGURATION_BUFFER_SIZE];
+        controller.control(
+            &mut control,
+            SetupPacket::get_descriptor(
+                descriptor::CONFIGURATION,
+                0,
+                ConfigurationDescriptor::SIZE as u16,
+            ),
+            &mut configuration[..ConfigurationDescriptor::SIZE],
+        )?;
+        let configuration_descriptor =
+            ConfigurationDescriptor::parse(&configuration)?;
+        let len = (configuration_descriptor.total_length as usize)
+            .min(CONFIGURATION_BUFFER_SIZE);
+        let len = controller.control(
+            &mut control,
+            SetupPacket::get_descriptor(
+                descriptor::CONFIGURATION,
+                0,
+                len as u16,
+            ),
+            &mut configuration[..len],
+        )?;
+
+        controller.control(
+            &mut control,
+            SetupPacket {
+                request_type: 0,
+                request: SET_CONFIGURATION,
+                value: configuration_descriptor.value as u16,
+                index: 0,
+                length: 0,
+            },
+            &mut [],
+        )?;
+
+        let mut device = Device {
+            address,
+            speed,
+            parent,
+            translator,
+            descriptor: device_descriptor,
+            configuration: configuration_descriptor.value,
+            max_packet_size,
+            product: [0; PRODUCT_NAME_SIZE],
+            product_len: 0,
+        };
+        // a device without a name works all the same
+        if device_descriptor.product != 0 {
+            let _ = self.read_product(controller, &mut control, &mut device);
+        }
+
+        self.inner.lock(|inner| {
+            inner.devices[address as usize - 1] = Some(device);
+        });
+        info!("USB: {}", device);
+
+        // class drivers may attach further devices, e.g. a hub those on its ports
+        let drivers = self.inner.lock(|inner| inner.drivers);
+        for interface in descriptor::interfaces(&configuration[..len]) {
+            if interface.descriptor.alternate_setting != 0 {
+                continue;
+            }
+            for driver in drivers.iter().flatten() {
+                if driver.probe(&device, &interface) {
+                    info!(
+                        "USB: {} interface {}: {}",
+                        address,
+                        interface.descriptor.number,
+                        driver.name()
+                    );
+                    break;
+                }
+            }
+        }
+
+        Ok(address)
+    }
+
+    // Forget a device that has been unplugged and the devices behind it if it is a hub
+    pub fn detach(&self, address: u8) {
+        let Some(device) = self.inner.lock(|inner| {
+            inner
+                .devices
+                .get_mut((address as usize).wrapping_sub(1))
+                .and_then(|slot| slot.take())
+        }) else {
+            return;
+        };
+        info!("USB: {} disconnected", device.address);
+
+        let drivers = self.inner.lock(|inner| inner.drivers);
+        for driver in drivers.iter().flatten() {
+            driver.disconnect(address);
+        }
+
+        let children = self.inner.lock(|inner| {
+            inner.devices.map(|slot| {
+                slot.filter(|child| {
+                    child.parent.is_some_and(|port| port.hub == address)
+                })
+                .map_or(0, |child| child.address)
+            })
+        });
+        for child in children.into_iter().filter(|&child| child != 0) {
+            self.detach(child);
+        }
+    }
+
+    pub fn device(&self, address: u8) -> Option<Device> {
+        self.inner.lock(|inner| {
+            inner
+                .devices
+                .get((address as usize).wrapping_sub(1))
+                .copied()
+                .flatten()
+        })
+    }
+
+    // Run a control transfer on endpoint 0 of a device
+    pub fn control(
+        &self,
+        address: u8,
+        setup: SetupPacket,
+        data: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        let device = self.device(address).ok_or(UsbError::NoDevice)?;
+
+        self.controller()?
+            .control(&mut device.control_endpoint(), setup, data)
+    }
+
+    pub fn bulk(
+        &self,
+        endpoint: &mut Endpoint,
+        data: &mut [u8],
+    ) -> Result<usize, UsbError> {
+        self.controller()?.bulk(endpoint, data)
+    }
+
+    pub fn interrupt(
+        &self,
+        endpoint: &mut Endpoint,
+        data: &mut [u8],
+    ) -> Result<Option<usize>, UsbError> {
+        self.controller()?.interrupt(endpoint, data)
+    }
+
+    // Let the class drivers check their devices, called from the main loop
+    pub fn poll(&self) {
+        let drivers = self.inner.lock(|inner| inner.drivers);
+
+        for driver in drivers.iter().flatten() {
+            driver.poll();
+        }
+    }
+
+    pub fn print_devices(&self) {
+        let devices = self.inner.lock(|inner| inner.devices);
+
+        for device in devices.iter().flatten() {
+            info!("      {}", device);
+        }
+    }
+}
+
+impl Default for Usb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Usb {
+    fn controller(
+        &self,
+    ) -> Result<&'static (dyn interface::HostController + Sync), UsbError> {
+        self.inner
+            .lock(|inner| inner.controller)
+            .ok_or(UsbError::NoController)
+    }
+
+    fn free_address(&self) -> Result<u8, UsbError> {
+        self.inner.lock(|inner| {
+            inner
+                .devices
+                .iter()
+                .position(|slot| slot.is_none())
+                .map(|index| index as u8 + 1)
+                .ok_or(UsbError::TooManyDevices)
+        })
+    }
+
+    // The product string in US English
+    fn read_product(
+        &self,
+        controller: &(dyn interface::HostController + Sync),
+        control: &mut Endpoint,
+        device: &mut Device,
+    ) -> Result<(), UsbError> {
+        let mut bytes = [0; 255];
+        let len = controller.control(
+            control,
+            SetupPacket {
+                index: LANGUAGE_ID,
+                ..SetupPacket::get_descriptor(
+                    descriptor::STRING,
+                    device.descriptor.product,
+                    bytes.len() as u16,
+                )
+            },
+            &mut bytes,
+        )?;
+
+        let name =
+            descriptor::decode_string(&bytes[..len], &mut device.product)?;
+        device.product_len = name.len();
+
+        Ok(())
+    }
+}
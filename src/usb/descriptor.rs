@@ -0,0 +1,316 @@
+// USB descriptors
+//
+// Descriptors are read from the device as little-endian byte strings and decoded here. A
+// configuration descriptor is followed by the descriptors of its interfaces, each followed by its
+// class-specific descriptors and its endpoints, which `interfaces()` walks.
+//
+// USB 2.0 specification, chapter 9.6
+
+use super::{Direction, TransferType, UsbError};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Descriptor types
+pub const DEVICE: u8 = 1;
+pub const CONFIGURATION: u8 = 2;
+pub const STRING: u8 = 3;
+pub const INTERFACE: u8 = 4;
+pub const ENDPOINT: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    // BCD, e.g. 0x0200 for USB 2.0
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: u16,
+    // indices of string descriptors, 0 if there is none
+    pub manufacturer: u8,
+    pub product: u8,
+    pub serial_number: u8,
+    pub configurations: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigurationDescriptor {
+    // of the configuration descriptor and all that follow it
+    pub total_length: u16,
+    pub interfaces: u8,
+    // argument of SET_CONFIGURATION
+    pub value: u8,
+    pub attributes: u8,
+    // in units of 2 mA
+    pub max_power: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub endpoints: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    // number and direction
+    pub address: u8,
+    pub attributes: u8,
+    // bits 10-0, high-bandwidth endpoints have more transactions per microframe in bits 12-11
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+// An interface of a configuration and the descriptors that follow it
+#[derive(Clone, Copy)]
+pub struct Interface<'a> {
+    pub descriptor: InterfaceDescriptor,
+    // class-specific and endpoint descriptors up to the next interface
+    pub extra: &'a [u8],
+}
+
+// Walks a sequence of descriptors, yielding the type and the bytes of each
+pub struct Descriptors<'a> {
+    bytes: &'a [u8],
+}
+
+// Walks the interfaces of a configuration
+pub struct Interfaces<'a> {
+    descriptors: Descriptors<'a>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DeviceDescriptor {
+    pub const SIZE: usize = 18;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        let bytes = check(bytes, DEVICE, Self::SIZE)?;
+
+        Ok(Self {
+            usb_version: u16_at(bytes, 2),
+            class: bytes[4],
+            subclass: bytes[5],
+            protocol: bytes[6],
+            max_packet_size: bytes[7],
+            vendor_id: u16_at(bytes, 8),
+            product_id: u16_at(bytes, 10),
+            device_version: u16_at(bytes, 12),
+            manufacturer: bytes[14],
+            product: bytes[15],
+            serial_number: bytes[16],
+            configurations: bytes[17],
+        })
+    }
+}
+
+impl ConfigurationDescriptor {
+    pub const SIZE: usize = 9;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        let bytes = check(bytes, CONFIGURATION, Self::SIZE)?;
+
+        Ok(Self {
+            total_length: u16_at(bytes, 2),
+            interfaces: bytes[4],
+            value: bytes[5],
+            attributes: bytes[7],
+            max_power: bytes[8],
+        })
+    }
+}
+
+impl InterfaceDescriptor {
+    pub const SIZE: usize = 9;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        let bytes = check(bytes, INTERFACE, Self::SIZE)?;
+
+        Ok(Self {
+            number: bytes[2],
+            alternate_setting: bytes[3],
+            endpoints: bytes[4],
+            class: bytes[5],
+            subclass: bytes[6],
+            protocol: bytes[7],
+        })
+    }
+}
+
+impl EndpointDescriptor {
+    pub const SIZE: usize = 7;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        let bytes = check(bytes, ENDPOINT, Self::SIZE)?;
+
+        Ok(Self {
+            address: bytes[2],
+            attributes: bytes[3],
+            max_packet_size: u16_at(bytes, 4),
+            interval: bytes[6],
+        })
+    }
+
+    pub fn number(&self) -> u8 {
+        self.address & 0x0F
+    }
+
+    pub fn direction(&self) -> Direction {
+        if self.address & 0x80 != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0b11 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+}
+
+impl<'a> Interface<'a> {
+    // The endpoints of the interface
+    pub fn endpoints(&self) -> impl Iterator<Item = EndpointDescriptor> + 'a {
+        Descriptors::new(self.extra)
+            .filter(|(kind, _)| *kind == ENDPOINT)
+            .filter_map(|(_, bytes)| EndpointDescriptor::parse(bytes).ok())
+    }
+
+    // The first endpoint of a type and direction
+    pub fn endpoint(
+        &self,
+        transfer_type: TransferType,
+        direction: Direction,
+    ) -> Option<EndpointDescriptor> {
+        self.endpoints().find(|endpoint| {
+            endpoint.transfer_type() == transfer_type
+                && endpoint.direction() == direction
+        })
+    }
+
+    // The first class-specific descriptor of a type, e.g. the HID descriptor
+    pub fn class_descriptor(&self, kind: u8) -> Option<&'a [u8]> {
+        Descriptors::new(self.extra)
+            .find(|(found, _)| *found == kind)
+            .map(|(_, bytes)| bytes)
+    }
+}
+
+impl<'a> Descriptors<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the length includes itself and the type, anything shorter ends the walk
+        let len = *self.bytes.first()? as usize;
+        if len < 2 || len > self.bytes.len() {
+            self.bytes = &[];
+            return None;
+        }
+
+        let (descriptor, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Some((descriptor[1], descriptor))
+    }
+}
+
+// The interfaces of a configuration descriptor and what follows it
+pub fn interfaces(configuration: &[u8]) -> Interfaces<'_> {
+    Interfaces {
+        descriptors: Descriptors::new(configuration),
+    }
+}
+
+impl<'a> Iterator for Interfaces<'a> {
+    type Item = Interface<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let descriptor = loop {
+            let (kind, bytes) = self.descriptors.next()?;
+            if kind == INTERFACE {
+                if let Ok(descriptor) = InterfaceDescriptor::parse(bytes) {
+                    break descriptor;
+                }
+            }
+        };
+
+        // up to the next interface
+        let rest = self.descriptors.bytes;
+        let mut len = 0;
+        for (kind, bytes) in Descriptors::new(rest) {
+            if kind == INTERFACE {
+                break;
+            }
+            len += bytes.len();
+        }
+        self.descriptors.bytes = &rest[len..];
+
+        Some(Interface {
+            descriptor,
+            extra: &rest[..len],
+        })
+    }
+}
+
+// Decode a string descriptor, UTF-16LE, into `buffer` as UTF-8. Characters that do not fit are
+// dropped.
+pub fn decode_string<'b>(
+    bytes: &[u8],
+    buffer: &'b mut [u8],
+) -> Result<&'b str, UsbError> {
+    let bytes = check(bytes, STRING, 2)?;
+
+    let units = bytes[2..]
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+    let mut len = 0;
+    for c in char::decode_utf16(units) {
+        let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+        if len + c.len_utf8() > buffer.len() {
+            break;
+        }
+        len += c.encode_utf8(&mut buffer[len..]).len();
+    }
+
+    // only whole characters were written
+    Ok(core::str::from_utf8(&buffer[..len]).unwrap())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// The descriptor at the start of `bytes`, if it has the type and is at least `size` bytes long
+fn check(bytes: &[u8], kind: u8, size: usize) -> Result<&[u8], UsbError> {
+    let len = *bytes.first().ok_or(UsbError::InvalidDescriptor)? as usize;
+    if len < size || len > bytes.len() || bytes[1] != kind {
+        return Err(UsbError::InvalidDescriptor);
+    }
+
+    Ok(&bytes[..len])
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
@@ -0,0 +1,392 @@
+// USB hubs
+//
+// When a hub is configured its ports are powered and the devices on them are reset and attached.
+// Afterwards its status change endpoint is polled, so devices can be plugged in and out. A
+// high-speed hub translates for the full- and low-speed devices on its ports, see
+// `Endpoint::translator`.
+//
+// USB 2.0 specification, chapter 11.24
+
+use super::{
+    descriptor::Interface, interface::ClassDriver, usb, Device, Direction,
+    Endpoint, Port, SetupPacket, Speed, TransferType, UsbError, CLEAR_FEATURE,
+    GET_DESCRIPTOR, GET_STATUS, REQUEST_CLASS, REQUEST_IN, REQUEST_TO_OTHER,
+    SET_FEATURE,
+};
+use crate::{
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+    warn,
+};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAX_HUBS: usize = 8;
+// Ports beyond are left unpowered
+const MAX_PORTS: usize = 15;
+
+const CLASS_HUB: u8 = 9;
+const DESCRIPTOR_HUB: u8 = 0x29;
+
+// Port features
+const PORT_RESET: u16 = 4;
+const PORT_POWER: u16 = 8;
+const C_PORT_CONNECTION: u16 = 16;
+const C_PORT_ENABLE: u16 = 17;
+const C_PORT_SUSPEND: u16 = 18;
+const C_PORT_OVER_CURRENT: u16 = 19;
+const C_PORT_RESET: u16 = 20;
+
+// wPortStatus bits
+const STATUS_CONNECTION: u16 = 1 << 0;
+const STATUS_ENABLE: u16 = 1 << 1;
+const STATUS_RESET: u16 = 1 << 4;
+const STATUS_LOW_SPEED: u16 = 1 << 9;
+const STATUS_HIGH_SPEED: u16 = 1 << 10;
+
+// wPortChange bits, one per C_PORT_* feature
+const CHANGE_CONNECTION: u16 = 1 << 0;
+const CHANGE_RESET: u16 = 1 << 4;
+
+// Time a connection must be stable before the port is reset
+const DEBOUNCE: Duration = Duration::from_millis(100);
+const RESET_TIMEOUT: Duration = Duration::from_millis(500);
+const RESET_RECOVERY: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy)]
+struct Hub {
+    address: u8,
+    ports: u8,
+    // the status change endpoint
+    status: Endpoint,
+    next_poll: Duration,
+    // addresses of the devices on the ports, 0 for none
+    children: [u8; MAX_PORTS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct HubDriver {
+    hubs: IRQSafeNullLock<[Option<Hub>; MAX_HUBS]>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static HUB_DRIVER: HubDriver = HubDriver::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the hub class driver
+pub fn hub_driver() -> &'static HubDriver {
+    &HUB_DRIVER
+}
+
+impl HubDriver {
+    pub const fn new() -> Self {
+        Self {
+            hubs: IRQSafeNullLock::new([None; MAX_HUBS]),
+        }
+    }
+}
+
+impl Default for HubDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl ClassDriver for HubDriver {
+    fn name(&self) -> &'static str {
+        "hub"
+    }
+
+    fn probe(&self, device: &Device, interface: &Interface) -> bool {
+        if interface.descriptor.class != CLASS_HUB {
+            return false;
+        }
+
+        let hub = match self.power_up(device, interface) {
+            Ok(hub) => hub,
+            Err(e) => {
+                warn!("USB: hub {}: {}", device.address, e);
+                return false;
+            }
+        };
+
+        let added = self.hubs.lock(|hubs| {
+            match hubs.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => {
+                    *slot = Some(hub);
+                    true
+                }
+                None => false,
+            }
+        });
+        if !added {
+            warn!("USB: hub {}: too many hubs", device.address);
+            return false;
+        }
+
+        // devices that were plugged in before
+        for port in 1..=hub.ports {
+            self.port_changed(hub.address, port);
+        }
+
+        true
+    }
+
+    fn poll(&self) {
+        let now = time::time_manager().uptime();
+
+        for index in 0..MAX_HUBS {
+            let Some(mut hub) = self.hubs.lock(|hubs| hubs[index]) else {
+                continue;
+            };
+            if now < hub.next_poll {
+                continue;
+            }
+
+            // bit 0 is the hub itself, then one bit per port
+            let mut changes = [0; (MAX_PORTS + 1).div_ceil(8)];
+            let len = (hub.ports as usize + 1).div_ceil(8);
+            let result = usb().interrupt(&mut hub.status, &mut changes[..len]);
+
+            // the hub may have gone in the meantime
+            hub.next_poll = now + hub.status.poll_interval();
+            self.hubs.lock(|hubs| {
+                if let Some(slot) = hubs[index]
+                    .as_mut()
+                    .filter(|slot| slot.address == hub.address)
+                {
+                    slot.status = hub.status;
+                    slot.next_poll = hub.next_poll;
+                }
+            });
+
+            // errors are retried at the next poll
+            if let Ok(Some(_)) = result {
+                for port in 1..=hub.ports {
+                    let port_index = port as usize;
+                    if changes[port_index / 8] & (1 << (port_index % 8)) != 0 {
+                        self.port_changed(hub.address, port);
+                    }
+                }
+            }
+        }
+    }
+
+    fn disconnect(&self, address: u8) {
+        self.hubs.lock(|hubs| {
+            for slot in hubs.iter_mut() {
+                if slot.is_some_and(|hub| hub.address == address) {
+                    *slot = None;
+                }
+            }
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl HubDriver {
+    // Read the hub descriptor and power the ports
+    fn power_up(
+        &self,
+        device: &Device,
+        interface: &Interface,
+    ) -> Result<Hub, UsbError> {
+        let status = interface
+            .endpoint(TransferType::Interrupt, Direction::In)
+            .ok_or(UsbError::InvalidDescriptor)?;
+
+        let mut descriptor = [0; 8];
+        let len = usb().control(
+            device.address,
+            SetupPacket {
+                request_type: REQUEST_IN | REQUEST_CLASS,
+                request: GET_DESCRIPTOR,
+                value: (DESCRIPTOR_HUB as u16) << 8,
+                index: 0,
+                length: descriptor.len() as u16,
+            },
+            &mut descriptor,
+        )?;
+        if len < 7 || descriptor[1] != DESCRIPTOR_HUB {
+            return Err(UsbError::InvalidDescriptor);
+        }
+        let ports = descriptor[2].min(MAX_PORTS as u8);
+        // in units of 2 ms
+        let power_on_delay = Duration::from_millis(descriptor[5] as u64 * 2);
+
+        for port in 1..=ports {
+            set_port_feature(device.address, port, PORT_POWER)?;
+        }
+        time::time_manager().spin_for(power_on_delay.max(DEBOUNCE));
+
+        Ok(Hub {
+            address: device.address,
+            ports,
+            status: device.endpoint(&status),
+            next_poll: Duration::ZERO,
+            children: [0; MAX_PORTS],
+        })
+    }
+
+    // Attach or detach the device on a port after its status has changed
+    fn port_changed(&self, hub: u8, port: u8) {
+        let (status, change) = match port_status(hub, port) {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("USB: hub {} port {}: {}", hub, port, e);
+                return;
+            }
+        };
+
+        // acknowledge all changes, a connection change is handled by looking at the status
+        for (bit, feature) in [
+            (CHANGE_CONNECTION, C_PORT_CONNECTION),
+            (1 << 1, C_PORT_ENABLE),
+            (1 << 2, C_PORT_SUSPEND),
+            (1 << 3, C_PORT_OVER_CURRENT),
+            (CHANGE_RESET, C_PORT_RESET),
+        ] {
+            if change & bit != 0 {
+                let _ = clear_port_feature(hub, port, feature);
+            }
+        }
+
+        let child = self.set_child(hub, port, 0);
+        let connected = status & STATUS_CONNECTION != 0;
+        // a device that is still enabled has not been replaced
+        if child != 0 && connected && change & CHANGE_CONNECTION == 0 {
+            self.set_child(hub, port, child);
+            return;
+        }
+        if child != 0 {
+            usb().detach(child);
+        }
+
+        if connected {
+            match self.connect(hub, port) {
+                Ok(address) => {
+                    self.set_child(hub, port, address);
+                }
+                Err(e) => warn!("USB: hub {} port {}: {}", hub, port, e),
+            }
+        }
+    }
+
+    // Reset the port and enumerate the device on it
+    fn connect(&self, hub: u8, port: u8) -> Result<u8, UsbError> {
+        time::time_manager().spin_for(DEBOUNCE);
+        set_port_feature(hub, port, PORT_RESET)?;
+
+        let deadline = time::time_manager().uptime() + RESET_TIMEOUT;
+        let status = loop {
+            let (status, change) = port_status(hub, port)?;
+            if change & CHANGE_RESET != 0 && status & STATUS_RESET == 0 {
+                break status;
+            }
+            if time::time_manager().uptime() > deadline {
+                return Err(UsbError::Timeout);
+            }
+            time::time_manager().spin_for(Duration::from_millis(10));
+        };
+        clear_port_feature(hub, port, C_PORT_RESET)?;
+        time::time_manager().spin_for(RESET_RECOVERY);
+
+        if status & STATUS_CONNECTION == 0 || status & STATUS_ENABLE == 0 {
+            return Err(UsbError::NoDevice);
+        }
+        let speed = if status & STATUS_LOW_SPEED != 0 {
+            Speed::Low
+        } else if status & STATUS_HIGH_SPEED != 0 {
+            Speed::High
+        } else {
+            Speed::Full
+        };
+
+        usb().attach(Some(Port { hub, port }), speed)
+    }
+
+    // Record the device on a port, returns the one before
+    fn set_child(&self, hub: u8, port: u8, address: u8) -> u8 {
+        self.hubs.lock(|hubs| {
+            hubs.iter_mut()
+                .flatten()
+                .find(|found| found.address == hub)
+                .map_or(0, |found| {
+                    let child = &mut found.children[port as usize - 1];
+                    core::mem::replace(child, address)
+                })
+        })
+    }
+}
+
+// wPortStatus and wPortChange
+fn port_status(hub: u8, port: u8) -> Result<(u16, u16), UsbError> {
+    let mut status = [0; 4];
+    let len = usb().control(
+        hub,
+        SetupPacket {
+            request_type: REQUEST_IN | REQUEST_CLASS | REQUEST_TO_OTHER,
+            request: GET_STATUS,
+            value: 0,
+            index: port as u16,
+            length: status.len() as u16,
+        },
+        &mut status,
+    )?;
+    if len < status.len() {
+        return Err(UsbError::InvalidDescriptor);
+    }
+
+    Ok((
+        u16::from_le_bytes([status[0], status[1]]),
+        u16::from_le_bytes([status[2], status[3]]),
+    ))
+}
+
+fn set_port_feature(hub: u8, port: u8, feature: u16) -> Result<(), UsbError> {
+    port_request(hub, port, SET_FEATURE, feature)
+}
+
+fn clear_port_feature(hub: u8, port: u8, feature: u16) -> Result<(), UsbError> {
+    port_request(hub, port, CLEAR_FEATURE, feature)
+}
+
+fn port_request(
+    hub: u8,
+    port: u8,
+    request: u8,
+    feature: u16,
+) -> Result<(), UsbError> {
+    usb()
+        .control(
+            hub,
+            SetupPacket {
+                request_type: REQUEST_CLASS | REQUEST_TO_OTHER,
+                request,
+                value: feature,
+                index: port as u16,
+                length: 0,
+            },
+            &mut [],
+        )
+        .map(|_| ())
+}
@@ -0,0 +1,444 @@
+// USB keyboards
+//
+// Keyboards are driven in the boot protocol, whose 8-byte reports hold the modifier keys and up to
+// six pressed keys. Reports are compared with the one before to find the keys that went down,
+// which are turned into characters with the layout from `keymap=`, and into xterm escape
+// sequences for the cursor and function keys. The last key pressed repeats while it is held.
+//
+// Keys going down and up and the typed characters are pushed as input events. The characters also
+// go through the key bindings of the virtual consoles into the console input buffer, the same way
+// as the characters from the serial port.
+//
+// Device Class Definition for HID 1.11, appendix B
+
+pub mod keymap;
+
+use super::{
+    descriptor::Interface, interface::ClassDriver, usb, Device, Direction,
+    Endpoint, SetupPacket, TransferType, UsbError, REQUEST_CLASS,
+    REQUEST_TO_INTERFACE,
+};
+use crate::{
+    bsp,
+    console::interface::Write,
+    input::{self, Event, Modifiers, Source},
+    screen::vt,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+    time::{self, interface::TimeManager},
+    warn,
+};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAX_KEYBOARDS: usize = 4;
+
+const CLASS_HID: u8 = 3;
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+
+// HID class requests
+const SET_REPORT: u8 = 0x09;
+const SET_IDLE: u8 = 0x0A;
+const SET_PROTOCOL: u8 = 0x0B;
+
+const BOOT_PROTOCOL: u16 = 0;
+const REPORT_OUTPUT: u16 = 2;
+
+// Byte 0 holds the modifiers, byte 1 is reserved, then the pressed keys
+const REPORT_SIZE: usize = 8;
+const FIRST_KEY: usize = 2;
+
+// Sent in every key slot when too many keys are pressed
+const USAGE_ERROR_ROLL_OVER: u8 = 0x01;
+const USAGE_CAPS_LOCK: u8 = 0x39;
+// the first modifier key, bit 0 of the modifiers
+const USAGE_LEFT_CTRL: u8 = 0xE0;
+
+// Output report bits
+const LED_NUM_LOCK: u8 = 1 << 0;
+const LED_CAPS_LOCK: u8 = 1 << 1;
+
+const REPEAT_DELAY: Duration = Duration::from_millis(500);
+const REPEAT_INTERVAL: Duration = Duration::from_millis(33);
+
+const ESC: char = '\x1b';
+
+#[derive(Clone, Copy)]
+struct Keyboard {
+    address: u8,
+    interface: u8,
+    // the boot report endpoint
+    report: Endpoint,
+    next_poll: Duration,
+    last_report: [u8; REPORT_SIZE],
+    caps_lock: bool,
+    // the key that repeats and when it does next
+    repeat: Option<(u8, Duration)>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+pub struct KeyboardDriver {
+    keyboards: IRQSafeNullLock<[Option<Keyboard>; MAX_KEYBOARDS]>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static KEYBOARD_DRIVER: KeyboardDriver = KeyboardDriver::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// Return a reference to the keyboard class driver
+pub fn keyboard_driver() -> &'static KeyboardDriver {
+    &KEYBOARD_DRIVER
+}
+
+impl KeyboardDriver {
+    pub const fn new() -> Self {
+        Self {
+            keyboards: IRQSafeNullLock::new([None; MAX_KEYBOARDS]),
+        }
+    }
+}
+
+impl Default for KeyboardDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl ClassDriver for KeyboardDriver {
+    fn name(&self) -> &'static str {
+        "keyboard"
+    }
+
+    fn probe(&self, device: &Device, interface: &Interface) -> bool {
+        let descriptor = interface.descriptor;
+        if descriptor.class != CLASS_HID
+            || descriptor.subclass != SUBCLASS_BOOT
+            || descriptor.protocol != PROTOCOL_KEYBOARD
+        {
+            return false;
+        }
+
+        let keyboard = match set_up(device, interface) {
+            Ok(keyboard) => keyboard,
+            Err(e) => {
+                warn!("USB: keyboard {}: {}", device.address, e);
+                return false;
+            }
+        };
+
+        let added = self.keyboards.lock(|keyboards| {
+            match keyboards.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => {
+                    *slot = Some(keyboard);
+                    true
+                }
+                None => false,
+            }
+        });
+        if !added {
+            warn!("USB: keyboard {}: too many keyboards", device.address);
+        }
+
+        added
+    }
+
+    fn poll(&self) {
+        let now = time::time_manager().uptime();
+
+        for index in 0..MAX_KEYBOARDS {
+            let Some(mut keyboard) =
+                self.keyboards.lock(|keyboards| keyboards[index])
+            else {
+                continue;
+            };
+
+            if now >= keyboard.next_poll {
+                let mut report = [0; REPORT_SIZE];
+                let result = usb().interrupt(&mut keyboard.report, &mut report);
+                keyboard.next_poll = now + keyboard.report.poll_interval();
+
+                // NAK while nothing changes, errors are retried at the next poll
+                if let Ok(Some(len)) = result {
+                    if len > FIRST_KEY {
+                        keyboard.report_received(&report, now);
+                    }
+                }
+            }
+
+            if let Some((usage, at)) = keyboard.repeat {
+                if now >= at {
+                    keyboard.repeat = Some((usage, now + REPEAT_INTERVAL));
+                    keyboard.type_key(usage, keyboard.last_report[0]);
+                }
+            }
+
+            // the keyboard may have gone in the meantime
+            self.keyboards.lock(|keyboards| {
+                if let Some(slot) = keyboards[index]
+                    .as_mut()
+                    .filter(|slot| slot.address == keyboard.address)
+                {
+                    *slot = keyboard;
+                }
+            });
+        }
+    }
+
+    fn disconnect(&self, address: u8) {
+        self.keyboards.lock(|keyboards| {
+            for slot in keyboards.iter_mut() {
+                if slot.is_some_and(|keyboard| keyboard.address == address) {
+                    *slot = None;
+                }
+            }
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// Switch the keyboard to the boot protocol and have it report changes only
+fn set_up(
+    device: &Device,
+    interface: &Interface,
+) -> Result<Keyboard, UsbError> {
+    let report = interface
+        .endpoint(TransferType::Interrupt, Direction::In)
+        .ok_or(UsbError::InvalidDescriptor)?;
+    let number = interface.descriptor.number;
+
+    class_request(
+        device.address,
+        number,
+        SET_PROTOCOL,
+        BOOT_PROTOCOL,
+        &mut [],
+    )?;
+    // not all keyboards know it, repeating is done here anyway
+    let _ = class_request(device.address, number, SET_IDLE, 0, &mut []);
+
+    let keyboard = Keyboard {
+        address: device.address,
+        interface: number,
+        report: device.endpoint(&report),
+        next_poll: Duration::ZERO,
+        last_report: [0; REPORT_SIZE],
+        caps_lock: false,
+        repeat: None,
+    };
+    keyboard.set_leds();
+
+    Ok(keyboard)
+}
+
+fn class_request(
+    address: u8,
+    interface: u8,
+    request: u8,
+    value: u16,
+    data: &mut [u8],
+) -> Result<(), UsbError> {
+    usb()
+        .control(
+            address,
+            SetupPacket {
+                request_type: REQUEST_CLASS | REQUEST_TO_INTERFACE,
+                request,
+                value,
+                index: interface as u16,
+                length: data.len() as u16,
+            },
+            data,
+        )
+        .map(|_| ())
+}
+
+impl Keyboard {
+    fn report_received(&mut self, report: &[u8; REPORT_SIZE], now: Duration) {
+        // the keys are unknown, keep the ones from before
+        if report[FIRST_KEY] == USAGE_ERROR_ROLL_OVER {
+            return;
+        }
+
+        let modifiers = report[0];
+        let before = self.last_report;
+        self.last_report = *report;
+
+        if let Some((usage, _)) = self.repeat {
+            if !report[FIRST_KEY..].contains(&usage) {
+                self.repeat = None;
+            }
+        }
+
+        for &usage in before[FIRST_KEY..].iter().filter(|&&usage| usage != 0) {
+            if !report[FIRST_KEY..].contains(&usage) {
+                self.push(Event::KeyUp {
+                    key: usage,
+                    modifiers: Modifiers(modifiers),
+                });
+            }
+        }
+
+        // the modifier keys are reported as bits
+        for bit in 0..8 {
+            let mask = 1 << bit;
+            if (before[0] ^ modifiers) & mask == 0 {
+                continue;
+            }
+
+            let key = USAGE_LEFT_CTRL + bit;
+            let modifiers = Modifiers(modifiers);
+            self.push(if modifiers.0 & mask != 0 {
+                Event::KeyDown { key, modifiers }
+            } else {
+                Event::KeyUp { key, modifiers }
+            });
+        }
+
+        for &usage in report[FIRST_KEY..].iter().filter(|&&usage| usage != 0) {
+            if before[FIRST_KEY..].contains(&usage) {
+                continue;
+            }
+
+            self.push(Event::KeyDown {
+                key: usage,
+                modifiers: Modifiers(modifiers),
+            });
+
+            if usage == USAGE_CAPS_LOCK {
+                self.caps_lock = !self.caps_lock;
+                self.set_leds();
+            } else {
+                self.repeat = Some((usage, now + REPEAT_DELAY));
+                self.type_key(usage, modifiers);
+            }
+        }
+    }
+
+    // Type what a key sends
+    fn type_key(&self, usage: u8, modifiers: u8) {
+        let modifiers = Modifiers(modifiers);
+        let mut shift = modifiers.shift();
+        // Caps Lock only affects letters
+        if self.caps_lock && (0x04..=0x1D).contains(&usage) {
+            shift = !shift;
+        }
+
+        if let Some(sequence) = escape_sequence(usage) {
+            for c in sequence.chars() {
+                self.type_char(c);
+            }
+            return;
+        }
+
+        let Some(mut c) = keymap::KEYMAP
+            .get()
+            .character(usage, shift)
+            .or_else(|| keypad_character(usage))
+        else {
+            return;
+        };
+
+        if modifiers.ctrl() && ('@'..='~').contains(&c) {
+            c = (c as u8 & 0x1F) as char;
+        }
+        if modifiers.alt() {
+            self.type_char(ESC);
+        }
+        self.type_char(c);
+    }
+
+    // Publish a character and hand it to the console input without echo, screen dumps and
+    // screenshots go to the serial port
+    fn type_char(&self, c: char) {
+        self.push(Event::Character(c));
+        vt::filter_input(c, false, |c| bsp::console::console().write_char(c));
+    }
+
+    fn push(&self, event: Event) {
+        input::input_queue().push(Source::Usb(self.address), event);
+    }
+
+    fn set_leds(&self) {
+        // the keypad always types digits
+        let mut leds = LED_NUM_LOCK;
+        if self.caps_lock {
+            leds |= LED_CAPS_LOCK;
+        }
+
+        // keyboards without LEDs may refuse
+        let _ = class_request(
+            self.address,
+            self.interface,
+            SET_REPORT,
+            REPORT_OUTPUT << 8,
+            &mut [leds],
+        );
+    }
+}
+
+// What xterm sends for the cursor, editing and function keys
+fn escape_sequence(usage: u8) -> Option<&'static str> {
+    let sequence = match usage {
+        0x3A => "\x1bOP",
+        0x3B => "\x1bOQ",
+        0x3C => "\x1bOR",
+        0x3D => "\x1bOS",
+        0x3E => "\x1b[15~",
+        0x3F => "\x1b[17~",
+        0x40 => "\x1b[18~",
+        0x41 => "\x1b[19~",
+        0x42 => "\x1b[20~",
+        0x43 => "\x1b[21~",
+        0x44 => "\x1b[23~",
+        0x45 => "\x1b[24~",
+        0x49 => "\x1b[2~",
+        0x4A => "\x1b[H",
+        0x4B => "\x1b[5~",
+        0x4C => "\x1b[3~",
+        0x4D => "\x1b[F",
+        0x4E => "\x1b[6~",
+        0x4F => "\x1b[C",
+        0x50 => "\x1b[D",
+        0x51 => "\x1b[B",
+        0x52 => "\x1b[A",
+        _ => return None,
+    };
+
+    Some(sequence)
+}
+
+// The keypad, the same on every layout
+fn keypad_character(usage: u8) -> Option<char> {
+    let c = match usage {
+        0x54 => '/',
+        0x55 => '*',
+        0x56 => '-',
+        0x57 => '+',
+        0x58 => '\n',
+        0x59..=0x61 => (b'1' + usage - 0x59) as char,
+        0x62 => '0',
+        0x63 => '.',
+        _ => return None,
+    };
+
+    Some(c)
+}
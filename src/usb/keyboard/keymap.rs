@@ -0,0 +1,103 @@
+// Keyboard layouts
+//
+// Map the usages of the character keys to what they type, without and with Shift. Keys that type
+// the same on every layout, like the keypad, and keys that send escape sequences are handled by
+// the keyboard driver.
+//
+// HID Usage Tables 1.12, chapter 10
+
+use crate::cmdline::{self, interface::Value};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// The character keys, from A to /
+const FIRST_USAGE: u8 = 0x04;
+const LAST_USAGE: u8 = 0x38;
+const NUM_KEYS: usize = (LAST_USAGE - FIRST_USAGE + 1) as usize;
+
+// Characters of the keys from `FIRST_USAGE` on, 0 where a key types nothing. Enter, Escape,
+// Backspace, Tab and Space sit between the digits and the punctuation.
+struct Keymap {
+    normal: &'static [u8; NUM_KEYS],
+    shift: &'static [u8; NUM_KEYS],
+    // keys outside the range: usage, normal, with Shift
+    extra: &'static [(u8, u8, u8)],
+}
+
+static US: Keymap = Keymap {
+    normal: b"abcdefghijklmnopqrstuvwxyz1234567890\n\x1b\x7f\t -=[]\\#;'`,./",
+    shift: b"ABCDEFGHIJKLMNOPQRSTUVWXYZ!@#$%^&*()\n\x1b\x7f\t _+{}|~:\"~<>?",
+    // the key next to the left Shift of ISO keyboards
+    extra: &[(0x64, b'\\', b'|')],
+};
+
+// Hankaku/Zenkaku at 0x35 and Shift+0 type nothing. ] is the non-US # key, 0x31 is not used.
+static JP106: Keymap = Keymap {
+    normal: b"abcdefghijklmnopqrstuvwxyz1234567890\n\x1b\x7f\t -^@[]];:\0,./",
+    shift: b"ABCDEFGHIJKLMNOPQRSTUVWXYZ!\"#$%&'()\0\n\x1b\x7f\t =~`{}}+*\0<>?",
+    // Ro and Yen
+    extra: &[(0x87, b'\\', b'_'), (0x89, b'\\', b'|')],
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Selected with `keymap=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Jp106,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+pub static KEYMAP: cmdline::Param<Layout> =
+    cmdline::Param::new("keymap", Layout::Us);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Layout {
+    // The character a key types, `None` for keys that are not character keys
+    pub fn character(self, usage: u8, shift: bool) -> Option<char> {
+        let keymap = match self {
+            Layout::Us => &US,
+            Layout::Jp106 => &JP106,
+        };
+
+        let c = if (FIRST_USAGE..=LAST_USAGE).contains(&usage) {
+            let index = (usage - FIRST_USAGE) as usize;
+            if shift {
+                keymap.shift[index]
+            } else {
+                keymap.normal[index]
+            }
+        } else {
+            let &(_, normal, shifted) =
+                keymap.extra.iter().find(|key| key.0 == usage)?;
+            if shift {
+                shifted
+            } else {
+                normal
+            }
+        };
+
+        (c != 0).then_some(c as char)
+    }
+}
+
+impl Value for Layout {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "us" => Some(Layout::Us),
+            "jp" | "jp106" => Some(Layout::Jp106),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,37 @@
+#!/bin/sh
+# Boot the Pi 3 kernel under QEMU with an SD card and check that the card is identified
+#
+#     $ tools/check_sd_boot.sh [image]
+#
+# Without an image a blank 64 MiB one is used, QEMU wants a power of two. The kernel log goes to
+# the serial port and is kept in ./img/sd_boot.log. Exits with 0 once the log shows the card.
+
+set -eu
+
+TIMEOUT=${TIMEOUT:-30}
+
+mkdir -p ./img
+
+image=${1:-./img/sd.img}
+if [ $# -eq 0 ]; then
+    truncate -s 64M "$image"
+fi
+
+cargo objcopy --bin kernel --release --no-default-features --features bsp_rpi3 \
+    -- --strip-all -O binary ./img/kernel8.img
+
+# the kernel keeps running, so it is stopped once the log is in
+timeout "$TIMEOUT" qemu-system-aarch64 -M raspi3b -display none \
+    -serial stdio -monitor none \
+    -kernel ./img/kernel8.img -append "console=uart" \
+    -drive if=sd,format=raw,file="$image" \
+    > ./img/sd_boot.log 2>&1 || true
+
+if grep -q "SD card: [0-9]* MiB" ./img/sd_boot.log; then
+    grep "SD card:" ./img/sd_boot.log
+    exit 0
+fi
+
+echo "SD card was not identified, see ./img/sd_boot.log" >&2
+grep "SD card:" ./img/sd_boot.log >&2 || true
+exit 1
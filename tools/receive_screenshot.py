@@ -0,0 +1,176 @@
+#!/usr/bin/env python3
+"""Receive a screenshot sent by the kernel over the serial line.
+
+Press ESC s on the serial console to make the kernel send a screenshot of the
+display. It arrives as framed lines of base64 (see src/screen/screenshot.rs):
+
+    @@SCREENSHOT BEGIN <width>x<height> png
+    @@<line number> <base64 data>
+    @@SCREENSHOT END <file size> <crc32>
+
+Other output is passed through to stdout, so this can stay attached to the
+serial port like a terminal. Only the Python standard library is needed.
+
+    $ tools/receive_screenshot.py /dev/ttyUSB0
+    $ tools/receive_screenshot.py serial.log -o board.png
+"""
+
+import argparse
+import base64
+import binascii
+import datetime
+import os
+import sys
+import termios
+import tty
+import zlib
+
+PREFIX = "@@"
+BEGIN = "@@SCREENSHOT BEGIN "
+END = "@@SCREENSHOT END "
+ABORT = "@@SCREENSHOT ABORT"
+
+BAUD_RATES = {
+    115200: termios.B115200,
+    230400: termios.B230400,
+    460800: getattr(termios, "B460800", None),
+    921600: getattr(termios, "B921600", None),
+}
+
+
+class Transfer:
+    def __init__(self, header):
+        self.header = header
+        self.data = bytearray()
+        self.next_line = 0
+        self.errors = []
+
+    def add(self, number, payload):
+        if number != self.next_line:
+            self.errors.append(
+                "expected line {:#x}, got {:#x}".format(self.next_line, number)
+            )
+        self.next_line = number + 1
+
+        try:
+            self.data += base64.b64decode(payload, validate=True)
+        except binascii.Error:
+            self.errors.append("bad base64 in line {:#x}".format(number))
+
+    def check(self, size, crc):
+        if len(self.data) != size:
+            self.errors.append(
+                "got {} bytes, expected {}".format(len(self.data), size)
+            )
+        actual = zlib.crc32(self.data)
+        if actual != crc:
+            self.errors.append(
+                "CRC is {:08x}, expected {:08x}".format(actual, crc)
+            )
+        return not self.errors
+
+
+def open_source(path, baud):
+    if path == "-":
+        return sys.stdin.buffer
+
+    source = open(path, "rb", buffering=0)
+    if os.isatty(source.fileno()):
+        speed = BAUD_RATES.get(baud)
+        if speed is None:
+            sys.exit("unsupported baud rate: {}".format(baud))
+
+        tty.setraw(source.fileno())
+        attrs = termios.tcgetattr(source.fileno())
+        attrs[4] = attrs[5] = speed
+        termios.tcsetattr(source.fileno(), termios.TCSANOW, attrs)
+
+    return source
+
+
+def read_lines(source):
+    pending = b""
+    while True:
+        chunk = source.read(4096)
+        if not chunk:
+            break
+        pending += chunk
+        *lines, pending = pending.split(b"\n")
+        for line in lines:
+            yield line.rstrip(b"\r").decode("utf-8", "replace")
+    if pending:
+        yield pending.decode("utf-8", "replace")
+
+
+def output_name(pattern, count):
+    name = datetime.datetime.now().strftime(pattern)
+    # a fixed name would be overwritten by the next screenshot
+    if count > 0 and "%" not in pattern:
+        root, ext = os.path.splitext(name)
+        name = "{}-{}{}".format(root, count, ext)
+    return name
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("source", help="serial device, log file or - for stdin")
+    parser.add_argument("-b", "--baud", type=int, default=921600,
+                        help="baud rate of a serial device (default 921600)")
+    parser.add_argument("-o", "--output", default="screenshot-%Y%m%d-%H%M%S.png",
+                        help="file name, strftime patterns are expanded")
+    parser.add_argument("-k", "--keep-going", action="store_true",
+                        help="keep receiving after the first screenshot")
+    parser.add_argument("-q", "--quiet", action="store_true",
+                        help="do not echo other output")
+    args = parser.parse_args()
+
+    transfer = None
+    received = 0
+
+    for line in read_lines(open_source(args.source, args.baud)):
+        if line.startswith(BEGIN):
+            if transfer is not None:
+                print("screenshot: restarted before the end", file=sys.stderr)
+            transfer = Transfer(line[len(BEGIN):])
+            print("screenshot: receiving {}".format(transfer.header),
+                  file=sys.stderr)
+        elif line.startswith(ABORT):
+            print("screenshot: aborted by the kernel:{}".format(line[len(ABORT):]),
+                  file=sys.stderr)
+            transfer = None
+        elif line.startswith(END) and transfer is not None:
+            fields = line[len(END):].split()
+            ok = len(fields) == 2 and transfer.check(int(fields[0]), int(fields[1], 16))
+            if not ok:
+                for error in transfer.errors or ["malformed trailer"]:
+                    print("screenshot: {}".format(error), file=sys.stderr)
+                name = output_name(args.output, received) + ".broken"
+            else:
+                name = output_name(args.output, received)
+
+            with open(name, "wb") as f:
+                f.write(transfer.data)
+            print("screenshot: wrote {} ({} bytes)".format(name, len(transfer.data)),
+                  file=sys.stderr)
+
+            transfer = None
+            received += 1
+            if not args.keep_going:
+                return 0 if ok else 1
+        elif line.startswith(PREFIX) and transfer is not None:
+            number, _, payload = line[len(PREFIX):].partition(" ")
+            try:
+                transfer.add(int(number, 16), payload)
+            except ValueError:
+                transfer.errors.append("malformed line: {!r}".format(line))
+        elif not args.quiet:
+            print(line)
+
+    if transfer is not None:
+        print("screenshot: input ended during a transfer", file=sys.stderr)
+        return 1
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())